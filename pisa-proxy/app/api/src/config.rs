@@ -24,6 +24,32 @@ pub struct Admin {
 
     #[serde(default = "default_log_level")]
     pub log_level: String,
+
+    // Export the spans of queries by OTLP, it is disabled when not set.
+    #[serde(default)]
+    pub tracing: Option<Tracing>,
+
+    // The bearer token of the api which changes the nodes, only the loopback clients are allowed
+    // to call it when not set.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Tracing {
+    // e.g. `http://127.0.0.1:4317` for grpc, `http://127.0.0.1:4318/v1/traces` for http
+    pub otlp_endpoint: String,
+
+    // `grpc` or `http`
+    #[serde(default = "default_tracing_protocol")]
+    pub protocol: String,
+
+    #[serde(default = "default_tracing_service_name")]
+    pub service_name: String,
+
+    // The ratio of traces to be sampled, the trace propagated from the client follows its sampled flag.
+    #[serde(default = "default_tracing_sample_ratio")]
+    pub sample_ratio: f64,
 }
 
 fn default_admin_host() -> String {
@@ -37,3 +63,15 @@ fn default_admin_port() -> u32 {
 fn default_log_level() -> String {
     "ERROR".into()
 }
+
+fn default_tracing_protocol() -> String {
+    "grpc".into()
+}
+
+fn default_tracing_service_name() -> String {
+    "pisa-proxy".into()
+}
+
+fn default_tracing_sample_ratio() -> f64 {
+    1.0
+}
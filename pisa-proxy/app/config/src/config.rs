@@ -17,16 +17,25 @@ use std::{env, fs::File, io::prelude::*};
 
 use api::config::Admin;
 use clap::{value_parser, Arg, Command};
-use proxy::proxy::{MySQLNode, MySQLNodes, ProxiesConfig, ProxyConfig};
+use proxy::{
+    proxy::{MySQLNode, MySQLNodes, ProxiesConfig, ProxyConfig},
+    reload::ReloadConfig,
+};
 use strategy::config::NodeGroup;
 use serde::{Deserialize, Serialize};
 use tracing::trace;
 
-use crate::env_const::*;
+use crate::{
+    env_const::*,
+    source::{interpolate_env, ConfigFormat},
+    validate::ConfigError,
+};
 
 #[derive(Default, Clone)]
 pub struct PisaProxyConfigBuilder {
     pub _local: bool,
+    // Only validate the config file, used by the `validate` subcommand
+    pub _validate: bool,
     pub _config_path: String,
     pub _http_path: String,
 
@@ -41,6 +50,9 @@ pub struct PisaProxyConfigBuilder {
     pub _pisa_controller_svc: String,
     pub _pisa_controller_ns: String,
 
+    // Seconds between checks of the config source, 0 disables reloading
+    pub _reload_interval: u64,
+
     pub _git_tag: String,
     pub _git_commit: String,
     pub _git_branch: String,
@@ -52,28 +64,43 @@ impl PisaProxyConfigBuilder {
     }
 
     pub fn build_from_file(self, path: String) -> PisaProxyConfig {
-        let config: PisaProxyConfig;
-        let mut file = match File::open(path) {
+        match Self::load_from_file(&path) {
             Err(e) => {
-                eprintln!("{:?}", e);
+                eprintln!("{}", e);
                 std::process::exit(-1);
             }
-            Ok(file) => file,
-        };
+            Ok(config) => config,
+        }
+    }
 
+    // Same as `build_from_file`, but the error is returned, it is used when validating and reloading.
+    // The file is toml or yaml by the extension, `${NAME}` is replaced by the environment variable.
+    pub fn load_from_file(path: &str) -> Result<PisaProxyConfig, ConfigError> {
         let mut config_str = String::new();
-        file.read_to_string(&mut config_str).unwrap();
-        config = toml::from_str(&config_str).unwrap();
-        config
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut config_str))
+            .map_err(|e| ConfigError::Io(path.to_string(), e))?;
+
+        let format = ConfigFormat::from_path(path);
+        let config_str = interpolate_env(&config_str, format)?;
+        let mut config = format.parse(path, &config_str)?;
+        config.resolve_secrets()?;
+        Ok(config)
     }
 
-    pub fn build_from_http(
-        self,
-        path: String,
-    ) -> Result<PisaProxyConfig, Box<dyn std::error::Error>> {
-        let resp = reqwest::blocking::get(path)?.json::<PisaProxyConfig>()?;
+    pub fn build_from_http(self, path: String) -> Result<PisaProxyConfig, ConfigError> {
+        let mut config = reqwest::blocking::get(&path)
+            .and_then(|resp| resp.json::<PisaProxyConfig>())
+            .map_err(|e| ConfigError::Http(path, e))?;
+        config.resolve_secrets()?;
+        Ok(config)
+    }
 
-        Ok(resp)
+    pub fn http_path(&self) -> String {
+        format!(
+            "http://{}/apis/configs.database-mesh.io/v1alpha1/namespaces/{}/proxyconfigs/{}",
+            self._pisa_controller_host, self._deployed_ns, self._deployed_name
+        )
     }
 
     pub fn collect_from_cmd(mut self) -> Self {
@@ -122,6 +149,16 @@ impl PisaProxyConfigBuilder {
                             .takes_value(true),
                     ),
             )
+            .subcommand(
+                Command::new("validate").about("validate the config file and exit").arg(
+                    Arg::new("config")
+                        .short('c')
+                        .long("config")
+                        .help("Config path")
+                        .default_value(DEFAULT_LOCAL_CONFIG)
+                        .takes_value(true),
+                ),
+            )
             .subcommand(
                 Command::new("daemon").about("used for standalone mode").arg(
                     Arg::new("config")
@@ -153,6 +190,15 @@ impl PisaProxyConfigBuilder {
                     .env(ENV_PISA_PROXY_ADMIN_LISTEN_PORT)
                     .takes_value(true),
             )
+            .arg(
+                Arg::new("reload-interval")
+                    .long("reload-interval")
+                    .help("Seconds between config reload checks, 0 disables reloading")
+                    .default_value(DEFAULT_PISA_PROXY_CONFIG_RELOAD_INTERVAL)
+                    .value_parser(value_parser!(u64))
+                    .env(ENV_PISA_PROXY_CONFIG_RELOAD_INTERVAL)
+                    .takes_value(true),
+            )
             .arg(
                 Arg::new("loglevel")
                     .short('l')
@@ -169,6 +215,7 @@ impl PisaProxyConfigBuilder {
         self._host = matches.get_one::<String>("host").unwrap().to_string();
         self._port = matches.get_one::<String>("port").unwrap().to_string();
         self._log_level = matches.get_one::<String>("loglevel").unwrap().to_string();
+        self._reload_interval = *matches.get_one::<u64>("reload-interval").unwrap();
 
         let (name, cmd) = matches.remove_subcommand().expect("required");
         match (name.as_str(), cmd) {
@@ -176,6 +223,11 @@ impl PisaProxyConfigBuilder {
                 self._config_path = cmd.value_of("config").unwrap().to_string();
                 self._local = true;
             }
+            ("validate", cmd) => {
+                self._config_path = cmd.value_of("config").unwrap().to_string();
+                self._local = true;
+                self._validate = true;
+            }
             ("sidecar", cmd) => {
                 self._pisa_controller_svc =
                     cmd.value_of("pisa-controller-service").unwrap().to_string();
@@ -211,16 +263,25 @@ impl PisaProxyConfigBuilder {
         self
     }
 
+    // Load and validate the config file, all problems are printed.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        Self::load_from_file(&self._config_path)?.validate()
+    }
+
     pub fn build(self) -> PisaProxyConfig {
         let builder = PisaProxyConfigBuilder::new();
-        let mut config: PisaProxyConfig = if self._local {
-            builder.build_from_file(self._config_path)
+        let config = if self._local {
+            Ok(builder.build_from_file(self._config_path))
         } else {
-            let http_path = format!(
-                "http://{}/apis/configs.database-mesh.io/v1alpha1/namespaces/{}/proxyconfigs/{}",
-                self._pisa_controller_host, self._deployed_ns, self._deployed_name
-            );
-            builder.build_from_http(http_path).unwrap_or_default()
+            builder.build_from_http(self.http_path())
+        };
+
+        let mut config = match config.and_then(|x| x.validate().map(|_| x)) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(-1);
+            }
         };
 
         if !self._log_level.is_empty() {
@@ -273,6 +334,15 @@ impl PisaProxyConfig {
     pub fn get_shardingsphere_proxy(&self) -> &Vec<MySQLNode> {
         &self.shardingsphere_proxy.as_ref().unwrap().node.as_ref().unwrap()
     }
+
+    // The part of config which is applied to the running proxies when it is reloaded.
+    pub fn get_reload_config(&self) -> ReloadConfig {
+        ReloadConfig {
+            proxies: self.proxy.as_ref().and_then(|x| x.config.clone()).unwrap_or_default(),
+            mysql_nodes: self.mysql.as_ref().and_then(|x| x.node.clone()).unwrap_or_default(),
+            node_group: self.node_group.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
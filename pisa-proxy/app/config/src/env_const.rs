@@ -21,6 +21,7 @@ pub const DEFAULT_PISA_CONTROLLER_NAMESPACE: &str = "pisa-system";
 pub const DEFAULT_PISA_CONTROLLER_SERVICE: &str = "pisa-controller";
 pub const DEFAULT_PISA_DEPLOYED_NAMESPACE: &str = "default";
 pub const DEFAULT_PISA_DEPLOYED_NAME: &str = "default";
+pub const DEFAULT_PISA_PROXY_CONFIG_RELOAD_INTERVAL: &str = "5";
 
 pub const ENV_PISA_PROXY_ADMIN_LISTEN_HOST: &str = "PISA_PROXY_ADMIN_LISTEN_HOST";
 pub const ENV_PISA_PROXY_ADMIN_LISTEN_PORT: &str = "PISA_PROXY_ADMIN_LISTEN_PORT";
@@ -30,6 +31,7 @@ pub const ENV_PISA_CONTROLLER_NAMESPACE: &str = "PISA_CONTROLLER_NAMESPACE";
 pub const ENV_PISA_CONTROLLER_SERVICE: &str = "PISA_CONTROLLER_SERVICE";
 pub const ENV_PISA_DEPLOYED_NAMESPACE: &str = "PISA_DEPLOYED_NAMESPACE";
 pub const ENV_PISA_DEPLOYED_NAME: &str = "PISA_DEPLOYED_NAME";
+pub const ENV_PISA_PROXY_CONFIG_RELOAD_INTERVAL: &str = "PISA_PROXY_CONFIG_RELOAD_INTERVAL";
 
 pub const ENV_GIT_TAG: &str = "GIT_TAG";
 pub const ENV_GIT_BRANCH: &str = "GIT_BRANCH";
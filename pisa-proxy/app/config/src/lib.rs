@@ -14,3 +14,6 @@
 
 pub mod config;
 pub mod env_const;
+pub mod source;
+pub mod validate;
+pub mod watcher;
@@ -0,0 +1,338 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{env, fs, path::Path};
+
+use crate::{
+    config::PisaProxyConfig,
+    validate::{ConfigError, Problems},
+};
+
+/// The format of config file, it is decided by the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|x| x.to_str()) {
+            Some("yaml") | Some("yml") => Self::Yaml,
+            _ => Self::Toml,
+        }
+    }
+
+    pub fn parse(&self, path: &str, content: &str) -> Result<PisaProxyConfig, ConfigError> {
+        match self {
+            Self::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+            Self::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| ConfigError::Parse(path.to_string(), e))
+    }
+}
+
+// Replace `${NAME}` with the environment variable, `${NAME:-default}` falls back to `default`
+// when it is not set. `$${` is an escaped `${`, the comments are kept as they are.
+pub fn interpolate_env(content: &str, format: ConfigFormat) -> Result<String, ConfigError> {
+    interpolate(content, format, |name| env::var(name).ok())
+}
+
+fn interpolate(
+    content: &str,
+    format: ConfigFormat,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, ConfigError> {
+    let mut output = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        let (text, comment) = line.split_at(comment_start(line, format).unwrap_or(line.len()));
+        interpolate_text(&mut output, text, &lookup)?;
+        output.push_str(comment);
+    }
+    Ok(output)
+}
+
+// The index where the comment of the line starts, `#` in quoted strings is not a comment. A quote
+// starts a string only at the beginning of a value, e.g. the quote of `it's` in YAML does not.
+fn comment_start(line: &str, format: ConfigFormat) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let prev = if i == 0 { b' ' } else { bytes[i - 1] };
+        match quote {
+            Some(q) if c == b'\\' && q == b'"' => i += 1,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                b'"' | b'\'' if prev.is_ascii_whitespace() || b"=:[{,".contains(&prev) => {
+                    quote = Some(c)
+                }
+                b'#' if format == ConfigFormat::Toml || prev.is_ascii_whitespace() => {
+                    return Some(i)
+                }
+                // The default of `${NAME:-default}` may contain `#`.
+                b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                    if let Some(end) = line[i..].find('}') {
+                        i += end;
+                    }
+                }
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+    None
+}
+
+fn interpolate_text(
+    output: &mut String,
+    text: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<(), ConfigError> {
+    let mut rest = text;
+
+    while let Some(idx) = rest.find('$') {
+        output.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        if rest.starts_with("$${") {
+            output.push_str("${");
+            rest = &rest[3..];
+            continue;
+        }
+
+        let end = match rest.find('}') {
+            Some(end) if rest.starts_with("${") => end,
+            _ => {
+                output.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let expr = &rest[2..end];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+        match lookup(name).or_else(|| default.map(|x| x.to_string())) {
+            Some(value) => output.push_str(&value),
+            None => return Err(ConfigError::Env(name.to_string())),
+        }
+        rest = &rest[end + 1..];
+    }
+
+    output.push_str(rest);
+    Ok(())
+}
+
+// Read the password referenced by `password_file` or `password_env`, the trailing newline of
+// the file is trimmed. Exactly one of them and `password` is required, an empty `password` is allowed.
+fn resolve_password(
+    problems: &mut Problems,
+    path: &str,
+    password: &mut Option<String>,
+    password_file: &Option<String>,
+    password_env: &Option<String>,
+) {
+    let sources = [password.is_some(), password_file.is_some(), password_env.is_some()];
+    if sources.iter().filter(|x| **x).count() != 1 {
+        problems.add(path, "exactly one of password, password_file and password_env must be set");
+        return;
+    }
+
+    match (password_file, password_env) {
+        (Some(file), _) => match fs::read_to_string(file) {
+            Ok(content) => {
+                *password = Some(content.trim_end_matches(&['\r', '\n'][..]).to_string())
+            }
+            Err(e) => {
+                problems.add(format!("{}.password_file", path), format!("read {}: {}", file, e))
+            }
+        },
+        (_, Some(name)) => match env::var(name) {
+            Ok(value) => *password = Some(value),
+            Err(_) => problems.add(
+                format!("{}.password_env", path),
+                format!("environment variable {:?} is not set", name),
+            ),
+        },
+        (None, None) => {}
+    }
+}
+
+impl PisaProxyConfig {
+    // Resolve the password references of backend nodes and discovery, so that the secrets
+    // can be mounted instead of written in the config.
+    pub fn resolve_secrets(&mut self) -> Result<(), ConfigError> {
+        let mut problems = Problems::default();
+
+        let nodes = [
+            ("mysql.node", &mut self.mysql),
+            ("shardingsphere_proxy.node", &mut self.shardingsphere_proxy),
+        ];
+        for (path, nodes) in nodes {
+            let nodes = nodes.as_mut().and_then(|x| x.node.as_mut());
+            for (i, node) in nodes.into_iter().flatten().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                resolve_password(
+                    &mut problems,
+                    &path,
+                    &mut node.password,
+                    &node.password_file,
+                    &node.password_env,
+                );
+            }
+        }
+
+        let proxies = self.proxy.as_mut().and_then(|x| x.config.as_mut());
+        for (i, proxy) in proxies.into_iter().flatten().enumerate() {
+            let discovery = proxy
+                .read_write_splitting
+                .as_mut()
+                .and_then(|x| x.dynamic.as_mut())
+                .map(|x| &mut x.discovery);
+            if let Some(strategy::config::Discovery::Mha(mha)) = discovery {
+                let path = format!("proxy.config[{}].read_write_splitting.dynamic.discovery", i);
+                resolve_password(
+                    &mut problems,
+                    &path,
+                    &mut mha.password,
+                    &mha.password_file,
+                    &mha.password_env,
+                );
+            }
+        }
+
+        if problems.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        let lookup = |name: &str| (name == "DB_HOST").then(|| "10.0.0.1".to_string());
+
+        let toml = ConfigFormat::Toml;
+        let content =
+            interpolate("host = \"${DB_HOST}\"\nport = ${DB_PORT:-3306}", toml, lookup).unwrap();
+        assert_eq!(content, "host = \"10.0.0.1\"\nport = 3306");
+        assert_eq!(
+            interpolate("regex = [\"^select$\", \"$x\", \"a$$b\", \"$${x}\"]", toml, lookup).unwrap(),
+            "regex = [\"^select$\", \"$x\", \"a$$b\", \"${x}\"]"
+        );
+        assert!(
+            matches!(interpolate("${DB_USER}", toml, lookup), Err(ConfigError::Env(x)) if x == "DB_USER")
+        );
+
+        // The comments are not interpolated.
+        let content = "# ${DB_USER}\nhost = \"#${DB_HOST}\" # ${DB_USER}\nport = ${DB_PORT:-33#06}#${DB_USER}";
+        assert_eq!(
+            interpolate(content, toml, lookup).unwrap(),
+            "# ${DB_USER}\nhost = \"#10.0.0.1\" # ${DB_USER}\nport = 33#06#${DB_USER}"
+        );
+        let content = "user: it's#${DB_HOST} # ${DB_USER}\nhost: '${DB_HOST} # a'";
+        assert_eq!(
+            interpolate(content, ConfigFormat::Yaml, lookup).unwrap(),
+            "user: it's#10.0.0.1 # ${DB_USER}\nhost: '10.0.0.1 # a'"
+        );
+    }
+
+    #[test]
+    fn test_resolve_secrets() {
+        let file = env::temp_dir().join("pisa_test_resolve_secrets");
+        fs::write(&file, "s3cret\n").unwrap();
+        env::set_var("PISA_TEST_MHA_PASSWORD", "mha");
+
+        let yaml = format!(
+            r#"
+admin: {{}}
+proxy:
+  config:
+    - name: proxy-1
+      backend_type: mysql
+      read_write_splitting:
+        dynamic:
+          default_target: readwrite
+          rule: []
+          discovery:
+            type: mha
+            user: monitor
+            password_env: PISA_TEST_MHA_PASSWORD
+mysql:
+  node:
+    - name: ds001
+      db: test
+      user: root
+      password_file: {}
+      role: readwrite
+"#,
+            file.display()
+        );
+        let mut config = ConfigFormat::Yaml.parse("config.yaml", &yaml).unwrap();
+        config.resolve_secrets().unwrap();
+        assert_eq!(config.get_mysql()[0].password.as_deref(), Some("s3cret"));
+        let rw = config.get_proxy()[0].read_write_splitting.as_ref().unwrap();
+        let strategy::config::Discovery::Mha(mha) = &rw.dynamic.as_ref().unwrap().discovery;
+        assert_eq!(mha.password.as_deref(), Some("mha"));
+
+        // The resolved mha password is kept, its reference is cleared before resolving again.
+        let rw = config.proxy.as_mut().unwrap().config.as_mut().unwrap()[0]
+            .read_write_splitting
+            .as_mut()
+            .unwrap();
+        let strategy::config::Discovery::Mha(mha) = &mut rw.dynamic.as_mut().unwrap().discovery;
+        mha.password_env = None;
+
+        let node = &mut config.mysql.as_mut().unwrap().node.as_mut().unwrap()[0];
+        node.password = None;
+        node.password_file = None;
+        node.password_env = Some("PISA_TEST_UNSET".to_string());
+        env::remove_var("PISA_TEST_UNSET");
+        match config.resolve_secrets() {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(
+                problems[0].to_string(),
+                "mysql.node[0].password_env: environment variable \"PISA_TEST_UNSET\" is not set"
+            ),
+            _ => unreachable!(),
+        }
+
+        // An empty password is set.
+        let node = &mut config.mysql.as_mut().unwrap().node.as_mut().unwrap()[0];
+        node.password = Some(String::new());
+        node.password_env = None;
+        config.resolve_secrets().unwrap();
+        assert_eq!(config.get_mysql()[0].password.as_deref(), Some(""));
+
+        config.mysql.as_mut().unwrap().node.as_mut().unwrap()[0].password = None;
+        match config.resolve_secrets() {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(
+                problems[0].to_string(),
+                "mysql.node[0]: exactly one of password, password_file and password_env must be set"
+            ),
+            _ => unreachable!(),
+        }
+
+        fs::remove_file(file).unwrap();
+    }
+}
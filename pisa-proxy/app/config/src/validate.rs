@@ -0,0 +1,508 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{collections::HashSet, fmt};
+
+use proxy::proxy::{MySQLNode, ProxyConfig};
+use strategy::config::{ReadWriteSplittingRule, Sharding, StrategyType};
+
+use crate::config::PisaProxyConfig;
+
+/// A problem of config, `path` locates the field, e.g. `proxy.config[0].sharding[1].actual_datanodes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, String),
+    Http(String, reqwest::Error),
+    // The environment variable referenced by `${NAME}` is not set.
+    Env(String),
+    // All problems found by validating, they are reported at once.
+    Invalid(Vec<ConfigProblem>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "read config {}: {}", path, e),
+            Self::Parse(path, e) => write!(f, "parse config {}: {}", path, e),
+            Self::Http(path, e) => write!(f, "fetch config {}: {}", path, e),
+            Self::Env(name) => write!(f, "environment variable {:?} is not set", name),
+            Self::Invalid(problems) => {
+                write!(f, "invalid config, {} problem(s) found", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Default)]
+pub(crate) struct Problems(pub(crate) Vec<ConfigProblem>);
+
+impl Problems {
+    pub(crate) fn add(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigProblem { path: path.into(), message: message.into() })
+    }
+}
+
+impl PisaProxyConfig {
+    // Check the cross references of config, it is used by the `validate` command, at startup and when reloading.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Problems::default();
+
+        let mysql_nodes = self.mysql.as_ref().and_then(|x| x.node.as_deref()).unwrap_or_default();
+        let ssp_nodes =
+            self.shardingsphere_proxy.as_ref().and_then(|x| x.node.as_deref()).unwrap_or_default();
+        check_nodes(&mut problems, "mysql.node", mysql_nodes);
+        check_nodes(&mut problems, "shardingsphere_proxy.node", ssp_nodes);
+
+        let mut groups = HashSet::new();
+        if let Some(node_group) = &self.node_group {
+            for (i, member) in node_group.members.iter().enumerate() {
+                let path = format!("node_group.member[{}]", i);
+                if !groups.insert(member.name.as_str()) {
+                    problems
+                        .add(format!("{}.name", path), format!("duplicate name {:?}", member.name));
+                }
+                if !has_node(mysql_nodes, &member.readwrite) {
+                    problems.add(format!("{}.readwrite", path), unknown_node(&member.readwrite));
+                }
+                for (j, read) in member.reads.iter().enumerate() {
+                    if !has_node(mysql_nodes, read) {
+                        problems.add(format!("{}.reads[{}]", path, j), unknown_node(read));
+                    }
+                }
+            }
+        }
+
+        if let Some(tracing) = &self.admin.tracing {
+            if !matches!(tracing.protocol.as_str(), "grpc" | "http") {
+                problems.add(
+                    "admin.tracing.protocol",
+                    format!("unsupported protocol {:?}", tracing.protocol),
+                );
+            }
+            if !(0.0..=1.0).contains(&tracing.sample_ratio) {
+                problems.add("admin.tracing.sample_ratio", "must be between 0 and 1");
+            }
+        }
+
+        let proxies = self.proxy.as_ref().and_then(|x| x.config.as_deref()).unwrap_or_default();
+        let mut names = HashSet::new();
+        for (i, proxy) in proxies.iter().enumerate() {
+            let path = format!("proxy.config[{}]", i);
+            if !names.insert(proxy.name.as_str()) {
+                problems.add(format!("{}.name", path), format!("duplicate name {:?}", proxy.name));
+            }
+
+            let nodes = match proxy.backend_type.as_str() {
+                "mysql" => mysql_nodes,
+                "shardingsphere-proxy" => ssp_nodes,
+                x => {
+                    problems.add(
+                        format!("{}.backend_type", path),
+                        format!("unsupported backend type {:?}", x),
+                    );
+                    continue;
+                }
+            };
+            if nodes.is_empty() {
+                problems.add(
+                    path.clone(),
+                    format!("no backend nodes for backend type {:?}", proxy.backend_type),
+                );
+            }
+
+            check_proxy(&mut problems, &path, proxy, nodes, &groups);
+        }
+
+        if problems.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems.0))
+        }
+    }
+}
+
+fn has_node(nodes: &[MySQLNode], name: &str) -> bool {
+    nodes.iter().any(|x| x.name == name)
+}
+
+fn unknown_node(name: &str) -> String {
+    format!("node {:?} is not found", name)
+}
+
+fn check_nodes(problems: &mut Problems, path: &str, nodes: &[MySQLNode]) {
+    let mut names = HashSet::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if !names.insert(node.name.as_str()) {
+            problems
+                .add(format!("{}[{}].name", path, i), format!("duplicate name {:?}", node.name));
+        }
+    }
+}
+
+fn check_proxy(
+    problems: &mut Problems,
+    path: &str,
+    proxy: &ProxyConfig,
+    nodes: &[MySQLNode],
+    groups: &HashSet<&str>,
+) {
+    match &proxy.simple_loadbalance {
+        Some(lb) => {
+            for (i, name) in lb.nodes.iter().enumerate() {
+                if !has_node(nodes, name) {
+                    problems.add(
+                        format!("{}.simple_loadbalance.nodes[{}]", path, i),
+                        unknown_node(name),
+                    );
+                }
+            }
+        }
+        None if proxy.read_write_splitting.is_none() => {
+            problems.add(path, "one of simple_loadbalance and read_write_splitting is required")
+        }
+        None => {}
+    }
+
+    if let Some(rw) = &proxy.read_write_splitting {
+        let rules = match (&rw.statics, &rw.dynamic) {
+            (Some(x), None) => Some(("static", &x.rules)),
+            (None, Some(x)) => Some(("dynamic", &x.rules)),
+            _ => {
+                problems.add(
+                    format!("{}.read_write_splitting", path),
+                    "exactly one of static and dynamic is required",
+                );
+                None
+            }
+        };
+
+        if let Some((kind, rules)) = rules {
+            for (i, rule) in rules.iter().enumerate() {
+                let path = format!("{}.read_write_splitting.{}.rule[{}]", path, kind, i);
+                let node_group_name = match rule {
+                    ReadWriteSplittingRule::Regex(rule) => {
+                        check_regex(problems, &path, &rule.regex);
+                        &rule.node_group_name
+                    }
+                    ReadWriteSplittingRule::Generic(rule) => &rule.node_group_name,
+                };
+                for (j, name) in node_group_name.iter().enumerate() {
+                    if !groups.contains(name.as_str()) {
+                        problems.add(
+                            format!("{}.node_group_name[{}]", path, j),
+                            format!("node group {:?} is not found", name),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(plugin) = &proxy.plugin {
+        for (i, x) in plugin.concurrency_control.iter().flatten().enumerate() {
+            check_regex(problems, &format!("{}.plugin.concurrency_control[{}]", path, i), &x.regex);
+        }
+        for (i, x) in plugin.circuit_break.iter().flatten().enumerate() {
+            check_regex(problems, &format!("{}.plugin.circuit_break[{}]", path, i), &x.regex);
+        }
+    }
+
+    // The data nodes are node group names when read write splitting is enabled.
+    let has_rw = proxy.read_write_splitting.is_some();
+    for (i, rule) in proxy.sharding.iter().flatten().enumerate() {
+        let path = format!("{}.sharding[{}]", path, i);
+        check_sharding(problems, &path, rule);
+        for (j, name) in rule.actual_datanodes.iter().enumerate() {
+            let found = if has_rw { groups.contains(name.as_str()) } else { has_node(nodes, name) };
+            if !found {
+                problems.add(format!("{}.actual_datanodes[{}]", path, j), unknown_node(name));
+            }
+        }
+    }
+
+    if let Some(slow_log) = &proxy.slow_log {
+        if slow_log.output.is_empty() {
+            problems.add(format!("{}.slow_log.output", path), "must not be empty");
+        }
+        if slow_log.output != "stdout" && slow_log.max_size == 0 {
+            problems.add(format!("{}.slow_log.max_size", path), "must be greater than 0");
+        }
+    }
+
+    if let Some(users) = &proxy.users {
+        for (i, user) in users.iter().enumerate() {
+            let secrets = [
+                user.password.is_some(),
+                user.native_password_hash.is_some(),
+                user.caching_sha2_password_hash.is_some(),
+            ];
+            if !secrets.contains(&true) {
+                problems.add(
+                    format!("{}.users[{}]", path, i),
+                    "one of password, native_password_hash and caching_sha2_password_hash is required",
+                );
+            }
+            for (j, backend_user) in user.backend_users.iter().enumerate() {
+                match &backend_user.node {
+                    Some(name) if !has_node(nodes, name) => problems.add(
+                        format!("{}.users[{}].backend_users[{}].node", path, i, j),
+                        unknown_node(name),
+                    ),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn check_regex(problems: &mut Problems, path: &str, regex: &[String]) {
+    for (i, x) in regex.iter().enumerate() {
+        if let Err(e) = regex::Regex::new(x) {
+            problems.add(format!("{}.regex[{}]", path, i), e.to_string());
+        }
+    }
+}
+
+fn check_sharding(problems: &mut Problems, path: &str, rule: &Sharding) {
+    if rule.actual_datanodes.is_empty() {
+        problems.add(format!("{}.actual_datanodes", path), "at least one data node is required");
+    }
+
+    let strategies = [
+        rule.database_strategy.is_some(),
+        rule.table_strategy.is_some(),
+        rule.database_table_strategy.is_some(),
+    ];
+    if strategies.iter().filter(|x| **x).count() > 1 {
+        problems.add(
+            path,
+            "only one of database_strategy, table_strategy and database_table_strategy can be set",
+        );
+    }
+
+    // The variants of `StrategyType` are untagged, so only the counts are checked here.
+    if let Some(StrategyType::TableStrategyConfig(x)) = &rule.table_strategy {
+        if x.sharding_count == 0 {
+            problems
+                .add(format!("{}.table_strategy.sharding_count", path), "must be greater than 0");
+        }
+        // The tables are sharded in the first data node.
+        if rule.actual_datanodes.len() > 1 {
+            problems.add(
+                format!("{}.actual_datanodes", path),
+                "table_strategy shards tables in a single data node",
+            );
+        }
+    }
+
+    if let Some(StrategyType::DatabaseStrategyConfig(x)) = &rule.database_strategy {
+        match x.sharding_count {
+            Some(count) if count as usize != rule.actual_datanodes.len() => problems.add(
+                format!("{}.database_strategy.sharding_count", path),
+                format!("{} does not match {} data node(s)", count, rule.actual_datanodes.len()),
+            ),
+            _ => {}
+        }
+    }
+
+    if let Some(StrategyType::DatabaseTableStrategyConfig(x)) = &rule.database_table_strategy {
+        if x.shading_count == 0 {
+            problems.add(
+                format!("{}.database_table_strategy.shading_count", path),
+                "must be greater than 0",
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[admin]
+
+[[proxy.config]]
+name = "proxy-1"
+backend_type = "mysql"
+
+[proxy.config.simple_loadbalance]
+nodes = ["ds001"]
+
+[[proxy.config.sharding]]
+table_name = "sbtest"
+actual_datanodes = ["ds001"]
+
+[proxy.config.sharding.table_strategy]
+table_sharding_algorithm_name = "mod"
+table_sharding_column = "id"
+sharding_count = 4
+
+[[mysql.node]]
+name = "ds001"
+db = "test"
+user = "root"
+password = "12345678"
+role = "readwrite"
+"#;
+
+    fn problems(config: &str) -> Vec<String> {
+        let config: PisaProxyConfig = toml::from_str(config).unwrap();
+        match config.validate() {
+            Ok(_) => vec![],
+            Err(ConfigError::Invalid(problems)) => problems.iter().map(|x| x.to_string()).collect(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(problems(CONFIG).is_empty());
+
+        let config = CONFIG
+            .replace(r#"actual_datanodes = ["ds001"]"#, r#"actual_datanodes = ["ds001", "ds003"]"#)
+            .replace(
+                r#"
+nodes = ["ds001"]"#,
+                r#"
+nodes = ["ds001", "ds002"]"#,
+            )
+            .replace("sharding_count = 4", "sharding_count = 0");
+        assert_eq!(
+            problems(&config),
+            vec![
+                r#"proxy.config[0].simple_loadbalance.nodes[1]: node "ds002" is not found"#,
+                "proxy.config[0].sharding[0].table_strategy.sharding_count: must be greater than 0",
+                "proxy.config[0].sharding[0].actual_datanodes: table_strategy shards tables in a single data node",
+                r#"proxy.config[0].sharding[0].actual_datanodes[1]: node "ds003" is not found"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_database_strategy() {
+        let config = CONFIG.replace(
+            r#"[proxy.config.sharding.table_strategy]
+table_sharding_algorithm_name = "mod"
+table_sharding_column = "id"
+sharding_count = 4"#,
+            r#"[proxy.config.sharding.database_strategy]
+database_sharding_algorithm_name = "mod"
+database_sharding_column = "id"
+sharding_count = 2"#,
+        );
+        assert_eq!(
+            problems(&config),
+            vec!["proxy.config[0].sharding[0].database_strategy.sharding_count: 2 does not match 1 data node(s)"]
+        );
+        assert!(problems(&config.replace("sharding_count = 2", "sharding_count = 1")).is_empty());
+    }
+
+    #[test]
+    fn test_validate_rw_and_plugin() {
+        let config = format!(
+            "{}{}",
+            CONFIG.replace(
+                r#"backend_type = "mysql""#,
+                "backend_type = \"mysql\"\n[[proxy.config.plugin.circuit_break]]\nregex = [\"(\"]"
+            ),
+            r#"
+[[mysql.node]]
+name = "ds001"
+db = "test"
+user = "root"
+password = "12345678"
+role = "read"
+
+[[node_group.member]]
+name = "group-1"
+readwrite = "ds001"
+reads = ["ds002"]
+"#
+        );
+        let problems = problems(&config);
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("mysql.node[1].name: duplicate name"));
+        assert_eq!(problems[1], r#"node_group.member[0].reads[0]: node "ds002" is not found"#);
+        assert!(problems[2]
+            .starts_with("proxy.config[0].plugin.circuit_break[0].regex[0]: regex parse error"));
+    }
+
+    #[test]
+    fn test_validate_tracing() {
+        let config =
+            CONFIG.replace("[admin]", "[admin.tracing]\notlp_endpoint = \"http://127.0.0.1:4317\"");
+        assert!(problems(&config).is_empty());
+
+        let config = config.replace("4317\"", "4317\"\nprotocol = \"udp\"\nsample_ratio = 2.0");
+        assert_eq!(
+            problems(&config),
+            vec![
+                r#"admin.tracing.protocol: unsupported protocol "udp""#,
+                "admin.tracing.sample_ratio: must be between 0 and 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_slow_log() {
+        let config = CONFIG.replace(
+            "[[proxy.config.sharding]]",
+            "[proxy.config.slow_log]\nthreshold = 500\n\n[[proxy.config.sharding]]",
+        );
+        assert!(problems(&config).is_empty());
+
+        let config = config.replace("threshold = 500", "output = \"/tmp/slow.log\"\nmax_size = 0");
+        assert_eq!(
+            problems(&config),
+            vec!["proxy.config[0].slow_log.max_size: must be greater than 0"]
+        );
+    }
+
+    #[test]
+    fn test_validate_users() {
+        let config = CONFIG.replace(
+            "[[proxy.config.sharding]]",
+            "[[proxy.config.users]]\nuser = \"app\"\npassword = \"app\"\n\n[[proxy.config.sharding]]",
+        );
+        assert!(problems(&config).is_empty());
+
+        let config = config.replace("password = \"app\"\n", "");
+        assert_eq!(
+            problems(&config),
+            vec![
+                "proxy.config[0].users[0]: one of password, native_password_hash and caching_sha2_password_hash is required"
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use proxy::reload::{is_changed, ReloadConfig, ReloadSender};
+use tracing::{error, info};
+
+use crate::{
+    config::{PisaProxyConfig, PisaProxyConfigBuilder},
+    validate::ConfigError,
+};
+
+/// Watch the config source, which is the config file in daemon mode or the pisa-controller
+/// in sidecar mode, and send the changed config to the proxies.
+///
+/// The config file is watched by polling its mtime instead of the file system events. There is
+/// no notify crate in the dependencies, and the metadata follows the symlink, so that the file
+/// replaced by swapping the symlink, e.g. a mounted ConfigMap, is detected as well, while the
+/// inotify watch of the old file misses it.
+pub struct ConfigWatcher {
+    builder: PisaProxyConfigBuilder,
+    current: ReloadConfig,
+}
+
+impl ConfigWatcher {
+    pub fn new(builder: PisaProxyConfigBuilder, current: ReloadConfig) -> Self {
+        Self { builder, current }
+    }
+
+    pub fn spawn(self, tx: ReloadSender) {
+        let interval = self.builder._reload_interval;
+        if interval == 0 {
+            return;
+        }
+
+        tokio::spawn(async move { self.run(tx, interval).await });
+    }
+
+    async fn run(mut self, tx: ReloadSender, interval: u64) {
+        let path = self.builder._config_path.clone();
+        let mut last_modified = modified(&path);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        // The first tick completes immediately.
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let config = if self.builder._local {
+                let modified = modified(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                PisaProxyConfigBuilder::load_from_file(&path).map_err(|e| e.to_string())
+            } else {
+                self.fetch().await.map_err(|e| e.to_string())
+            };
+
+            let config =
+                match config.and_then(|x| x.validate().map_err(|e| e.to_string()).map(|_| x)) {
+                    Ok(config) => config.get_reload_config(),
+                    Err(e) => {
+                        error!("reload config error: {}, the running config is kept", e);
+                        continue;
+                    }
+                };
+
+            if !is_changed(&self.current, &config) {
+                continue;
+            }
+
+            info!("config is changed, reloading");
+            self.current = config.clone();
+            if tx.send(Arc::new(config)).is_err() {
+                return;
+            }
+        }
+    }
+
+    // Poll the proxyconfigs endpoint of pisa-controller.
+    async fn fetch(&self) -> Result<PisaProxyConfig, ConfigError> {
+        let path = self.builder.http_path();
+        let resp = reqwest::get(&path).await.map_err(|e| ConfigError::Http(path.clone(), e))?;
+        let mut config =
+            resp.json::<PisaProxyConfig>().await.map_err(|e| ConfigError::Http(path, e))?;
+        config.resolve_secrets()?;
+        Ok(config)
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
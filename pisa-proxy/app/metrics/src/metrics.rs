@@ -33,5 +33,14 @@ impl MetricsManager {
         self.server.registry().register(Box::new(SQL_PROCESSED_TOTAL.clone())).unwrap();
         self.server.registry().register(Box::new(SQL_PROCESSED_DURATION.clone())).unwrap();
         self.server.registry().register(Box::new(SQL_UNDER_PROCESSING.clone())).unwrap();
+        self.server.registry().register(Box::new(CLIENT_CONNECTIONS_TOTAL.clone())).unwrap();
+        self.server.registry().register(Box::new(CLIENT_CONNECTIONS_ACTIVE.clone())).unwrap();
+        self.server.registry().register(Box::new(CLIENT_HANDSHAKE_FAILURES_TOTAL.clone())).unwrap();
+        self.server.registry().register(Box::new(POOL_WAIT_DURATION.clone())).unwrap();
+        self.server.registry().register(Box::new(ROUTE_DECISIONS_TOTAL.clone())).unwrap();
+        self.server.registry().register(Box::new(SHARD_FANOUT.clone())).unwrap();
+        self.server.registry().register(Box::new(REWRITE_ERRORS_TOTAL.clone())).unwrap();
+        self.server.registry().register(Box::new(DISCOVERY_ROLE_CHANGES_TOTAL.clone())).unwrap();
+        self.server.registry().register(Box::new(ProxyStatsCollector::new())).unwrap();
     }
 }
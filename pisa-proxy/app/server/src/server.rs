@@ -16,16 +16,24 @@ use config::config::PisaProxyConfig;
 use proxy::{
     factory::{Proxy, ProxyFactory, ProxyKind},
     proxy::ProxyConfig,
+    reload::ReloadReceiver,
+    shutdown::Shutdown,
 };
 
 pub struct PisaProxyFactory {
     pub proxy_config: ProxyConfig,
     pub pisa_config: PisaProxyConfig,
+    pub reload: Option<ReloadReceiver>,
 }
 
 impl PisaProxyFactory {
     pub fn new(proxy_config: ProxyConfig, pisa_config: PisaProxyConfig) -> Self {
-        Self { proxy_config, pisa_config }
+        Self { proxy_config, pisa_config, reload: None }
+    }
+
+    pub fn with_reload(mut self, reload: ReloadReceiver) -> Self {
+        self.reload = Some(reload);
+        self
     }
 }
 
@@ -38,6 +46,7 @@ impl ProxyFactory for PisaProxyFactory {
                 mysql_nodes: self.pisa_config.get_mysql().to_vec(),
                 node_group: self.pisa_config.node_group.clone(),
                 pisa_version: self.pisa_config.get_version().to_string(),
+                reload: self.reload.clone(),
                 // mysql_nodes: self.pisa_config.mysql.as_ref().unwrap().node.as_ref().unwrap().to_vec(),
                 // pisa_version: self.pisa_config.version.as_ref().unwrap().to_string(),
             }),
@@ -58,6 +67,6 @@ impl ProxyFactory for PisaProxyFactory {
     }
 }
 
-pub async fn new_proxy_server(mut s: Box<dyn proxy::factory::Proxy + Send>) {
-    s.start().await.unwrap();
+pub async fn new_proxy_server(mut s: Box<dyn proxy::factory::Proxy + Send>, shutdown: Shutdown) {
+    s.start(shutdown).await.unwrap();
 }
@@ -19,21 +19,62 @@ use std::str::FromStr;
 
 use tokio::runtime::{Builder, Runtime};
 use tracing::{error, info, warn, Level};
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    prelude::*,
+};
 extern crate tokio;
 
-use config::config::PisaProxyConfigBuilder;
+use config::{config::PisaProxyConfigBuilder, watcher::ConfigWatcher};
 use http::http::{new_http_server, HttpFactory, HttpServerKind, PisaHttpServerFactory};
 use pisa_metrics::metrics::MetricsManager;
-use proxy::factory::{ProxyFactory, ProxyKind};
+use proxy::{
+    factory::{ProxyFactory, ProxyKind},
+    reload::reload_channel,
+    shutdown::shutdown_channel,
+};
+use runtime_mysql::telemetry;
 use server::{
     backend_const::{BACKEND_TYPE_MYSQL, BACKEND_TYPE_SHARDINGSPHERE_PROXY},
     server::{new_proxy_server, PisaProxyFactory},
 };
 
 fn main() {
-    let config = PisaProxyConfigBuilder::new().collect_from_cmd().build();
-    tracing_subscriber::fmt()
-        .with_max_level(Level::from_str(config.get_admin().log_level.as_str()).ok())
+    let builder = PisaProxyConfigBuilder::new().collect_from_cmd();
+    if builder._validate {
+        match builder.validate() {
+            Ok(_) => println!("{} is valid", builder._config_path),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let config = builder.clone().build();
+    let runtime = build_runtime();
+
+    // The spans are exported on the runtime when tracing is enabled.
+    let tracer = config.get_admin().tracing.as_ref().and_then(|x| {
+        let _guard = runtime.enter();
+        match telemetry::init_tracer(x) {
+            Ok(tracer) => Some(tracer),
+            Err(e) => {
+                eprintln!("init tracer error: {}", e);
+                None
+            }
+        }
+    });
+    let tracing_enabled = tracer.is_some();
+    let level = LevelFilter::from(Level::from_str(config.get_admin().log_level.as_str()).ok());
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(level))
+        .with(tracer.map(|x| {
+            // Only the spans of queries are exported.
+            let targets = Targets::new().with_target("runtime_mysql", Level::INFO);
+            tracing_opentelemetry::layer().with_tracer(x).with_filter(targets)
+        }))
         .init();
 
     info!("Pisa-Proxy {}", config.get_version());
@@ -44,34 +85,55 @@ fn main() {
             let http_server = PisaHttpServerFactory::new(config.clone(), MetricsManager::new())
                 .build_http_server(HttpServerKind::Rocket);
 
-            build_runtime().block_on(async move {
+            runtime.block_on(async move {
+                let (trigger, shutdown) = shutdown_channel();
+                tokio::spawn(async move {
+                    wait_for_signal().await;
+                    info!("Pisa-Proxy is shutting down");
+                    trigger.shutdown();
+                });
+
+                let (reload_tx, reload_rx) = reload_channel(config.get_reload_config());
+                ConfigWatcher::new(builder, config.get_reload_config()).spawn(reload_tx);
+
                 for proxy_config in config.get_proxy() {
                     let cfg = proxy_config;
-                    let factory = PisaProxyFactory::new(cfg.to_owned(), config.clone());
+                    let factory = PisaProxyFactory::new(cfg.to_owned(), config.clone())
+                        .with_reload(reload_rx.clone());
                     match proxy_config.backend_type.as_str() {
                         BACKEND_TYPE_MYSQL => servers.push(tokio::spawn(new_proxy_server(
                             factory.build_proxy(ProxyKind::MySQL),
+                            shutdown.clone(),
                         ))),
                         BACKEND_TYPE_SHARDINGSPHERE_PROXY => servers.push(tokio::spawn(
-                            new_proxy_server(factory.build_proxy(ProxyKind::ShardingSphereProxy)),
+                            new_proxy_server(factory.build_proxy(ProxyKind::ShardingSphereProxy), shutdown.clone()),
                         )),
                         &_ => {}
                     }
                 }
 
-                servers.push(tokio::spawn(new_http_server(http_server)));
+                let http_server = tokio::spawn(new_http_server(http_server));
 
                 for server in servers {
                     if let Err(e) = server.await {
                         error!("{:?}", e)
                     }
                 }
+
+                // The proxies are drained, the http server is stopped with the runtime.
+                if shutdown.is_shutdown() {
+                    return;
+                }
+
+                if let Err(e) = http_server.await {
+                    error!("{:?}", e)
+                }
             });
         }
         None => {
             let http_server = PisaHttpServerFactory::new(config.clone(), MetricsManager::new())
                 .build_http_server(HttpServerKind::Rocket);
-            build_runtime().block_on(async move {
+            runtime.block_on(async move {
                 if let Err(e) = tokio::spawn(new_http_server(http_server)).await {
                         error!("{:?}", e)
                     }
@@ -79,6 +141,35 @@ fn main() {
             );
         }
     }
+
+    // Flush the spans which are not exported, the exporter is driven by the runtime.
+    if tracing_enabled {
+        let _ = runtime.block_on(runtime.spawn_blocking(telemetry::shutdown_tracer));
+    }
+}
+
+// Wait for SIGTERM or ctrl-c.
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                warn!("listen SIGTERM error {:?}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
 /// build runtime, build Tokio runtime
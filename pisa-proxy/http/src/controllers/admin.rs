@@ -0,0 +1,243 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{sync::Arc, time::Duration};
+
+use config::config::PisaProxyConfig;
+use plugin::stats::PluginStats;
+use proxy::proxy::MySQLNodes;
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    serde::json::Json,
+    State,
+};
+use runtime_mysql::{
+    registry::{
+        self, AdminError, BackendsView, DrainView, EffectiveConfig, NodeView, PoolView,
+        ProxyRuntime, SessionView,
+    },
+    server::slow_log::redact,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Serialize)]
+pub struct ProxyView {
+    pub name: String,
+    pub listen_addr: String,
+    pub sessions: usize,
+}
+
+// The config loaded at startup overlaid with the reloaded parts, the passwords, password hashes
+// and the token are redacted.
+#[get("/config")]
+pub fn get_config(
+    _auth: AdminAuth,
+    pisa_config: &State<PisaProxyConfig>,
+) -> Result<Json<Value>, Status> {
+    let mut config = pisa_config.inner().clone();
+    let runtimes = registry::get_proxies();
+
+    if let Some(proxies) = config.proxy.as_mut().and_then(|x| x.config.as_mut()) {
+        for proxy in proxies.iter_mut() {
+            if let Some(runtime) = runtimes.iter().find(|x| x.name == proxy.name) {
+                *proxy = runtime.get_config().proxy_config;
+            }
+        }
+    }
+
+    // All proxies are reloaded with the same nodes.
+    if let Some(EffectiveConfig { mysql_nodes, node_group, .. }) =
+        runtimes.first().map(|x| x.get_config())
+    {
+        config.mysql = Some(MySQLNodes { node: Some(mysql_nodes) });
+        config.node_group = node_group;
+    }
+
+    let mut value = serde_json::to_value(config).map_err(|_| Status::InternalServerError)?;
+    redact_password(&mut value);
+    Ok(Json(value))
+}
+
+#[get("/proxies")]
+pub fn get_proxies(_auth: AdminAuth) -> Json<Vec<ProxyView>> {
+    let proxies = registry::get_proxies()
+        .iter()
+        .map(|x| ProxyView {
+            name: x.name.clone(),
+            listen_addr: x.get_config().proxy_config.listen_addr,
+            sessions: x.sessions.get_all().len(),
+        })
+        .collect();
+    Json(proxies)
+}
+
+#[get("/proxies/<name>/backends")]
+pub fn get_backends(_auth: AdminAuth, name: &str) -> Option<Json<BackendsView>> {
+    registry::get_proxy(name).map(|x| Json(x.get_backends()))
+}
+
+#[get("/proxies/<name>/pools")]
+pub fn get_pools(_auth: AdminAuth, name: &str) -> Option<Json<Vec<PoolView>>> {
+    registry::get_proxy(name).map(|x| Json(x.get_pools()))
+}
+
+// The literals of the running statements are redacted, they may contain secrets.
+#[get("/proxies/<name>/sessions")]
+pub fn get_sessions(_auth: AdminAuth, name: &str) -> Option<Json<Vec<SessionView>>> {
+    registry::get_proxy(name).map(|x| {
+        let mut sessions = x.get_sessions();
+        sessions.iter_mut().for_each(|x| x.info = x.info.as_deref().map(redact));
+        Json(sessions)
+    })
+}
+
+#[get("/proxies/<name>/rules")]
+pub fn get_rules(_auth: AdminAuth, name: &str) -> Option<Json<PluginStats>> {
+    registry::get_proxy(name).map(|x| Json(x.get_rules()))
+}
+
+// The default seconds to wait for the transactions pinned on a draining node.
+const DEFAULT_DRAIN_TIMEOUT: u64 = 30;
+
+type AdminResult<T> = Result<Json<T>, (Status, String)>;
+
+fn find_proxy(name: &str) -> Result<Arc<ProxyRuntime>, (Status, String)> {
+    registry::get_proxy(name).ok_or((Status::NotFound, format!("proxy {:?} is not found", name)))
+}
+
+fn into_response<T>(res: Result<T, AdminError>) -> AdminResult<T> {
+    res.map(Json).map_err(|e| match e {
+        AdminError::NodeNotFound(_) => (Status::NotFound, e.to_string()),
+        _ => (Status::BadRequest, e.to_string()),
+    })
+}
+
+// The guard of the admin api, `Authorization: Bearer <admin.token>` is required.
+// Only the clients on the loopback address are allowed when the token is not set.
+pub struct AdminAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminAuth {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token =
+            req.rocket().state::<PisaProxyConfig>().and_then(|x| x.get_admin().token.as_ref());
+        match token {
+            Some(token) => {
+                let bearer =
+                    req.headers().get_one("Authorization").and_then(|x| x.strip_prefix("Bearer "));
+                if bearer.map_or(false, |x| is_token_eq(x, token)) {
+                    Outcome::Success(AdminAuth)
+                } else {
+                    Outcome::Failure((Status::Unauthorized, "invalid admin token"))
+                }
+            }
+            // The peer addr is checked, the forwarded headers can be forged.
+            None if req.remote().map_or(false, |x| x.ip().is_loopback()) => Outcome::Success(AdminAuth),
+            None => Outcome::Failure((Status::Forbidden, "admin.token is required for remote clients")),
+        }
+    }
+}
+
+// Compare in constant time, so that the token can not be guessed by the response time.
+fn is_token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[post("/proxies/<name>/nodes/<node>/disable")]
+pub fn disable_node(_auth: AdminAuth, name: &str, node: &str) -> AdminResult<NodeView> {
+    into_response(find_proxy(name)?.disable_node(node))
+}
+
+#[post("/proxies/<name>/nodes/<node>/enable")]
+pub fn enable_node(_auth: AdminAuth, name: &str, node: &str) -> AdminResult<NodeView> {
+    into_response(find_proxy(name)?.enable_node(node))
+}
+
+#[post("/proxies/<name>/nodes/<node>/drain?<timeout>")]
+pub async fn drain_node(
+    _auth: AdminAuth,
+    name: &str,
+    node: &str,
+    timeout: Option<u64>,
+) -> AdminResult<DrainView> {
+    let timeout = Duration::from_secs(timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT));
+    into_response(find_proxy(name)?.drain_node(node, timeout).await)
+}
+
+#[post("/proxies/<name>/nodes/<node>/weight?<weight>")]
+pub fn set_node_weight(
+    _auth: AdminAuth,
+    name: &str,
+    node: &str,
+    weight: i64,
+) -> AdminResult<NodeView> {
+    into_response(find_proxy(name)?.set_node_weight(node, weight))
+}
+
+// Every key containing `password` is redacted, e.g. `native_password_hash` of the users.
+fn redact_password(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                if (k.contains("password") || k == "token") && v.is_string() {
+                    *v = Value::String("******".to_string());
+                } else {
+                    redact_password(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_password),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_redact_password() {
+        let mut value = json!({
+            "mysql": { "node": [{ "name": "ds001", "password": "root", "password_env": "DS_PWD" }] },
+            "users": [
+                { "user": "root", "password": "secret" },
+                { "user": "app", "native_password_hash": "*6BB4837EB74329105EE4568DDA7DC67ED2CA2AD9" },
+                { "user": "ops", "caching_sha2_password_hash": "$A$005$hash" }
+            ],
+            "admin": { "token": "change-me" }
+        });
+        redact_password(&mut value);
+        assert_eq!(value["mysql"]["node"][0]["password"], "******");
+        assert_eq!(value["mysql"]["node"][0]["password_env"], "******");
+        assert_eq!(value["mysql"]["node"][0]["name"], "ds001");
+        assert_eq!(value["users"][0]["password"], "******");
+        assert_eq!(value["users"][1]["native_password_hash"], "******");
+        assert_eq!(value["users"][2]["caching_sha2_password_hash"], "******");
+        assert_eq!(value["users"][2]["user"], "ops");
+        assert_eq!(value["admin"]["token"], "******");
+    }
+
+    #[test]
+    fn test_is_token_eq() {
+        assert!(is_token_eq("s3cret", "s3cret"));
+        assert!(!is_token_eq("s3cret", "s3creT"));
+        assert!(!is_token_eq("s3cret", "s3cre"));
+    }
+}
@@ -16,7 +16,14 @@ use config::config::PisaProxyConfig;
 use pisa_error::error::*;
 use pisa_metrics::metrics::MetricsManager;
 
-use crate::controllers::{healthz::healthz, version::version};
+use crate::controllers::{
+    admin::{
+        disable_node, drain_node, enable_node, get_backends, get_config, get_pools, get_proxies,
+        get_rules, get_sessions, set_node_weight,
+    },
+    healthz::healthz,
+    version::version,
+};
 
 #[async_trait::async_trait]
 pub trait HttpServer {
@@ -70,7 +77,23 @@ impl HttpServer for RocketServer {
 
         return rocket::Rocket::custom(figment)
             .attach(self.metrics_manager.get_server())
+            .manage(self.pisa_config.clone())
             .mount("/", routes![healthz, version])
+            .mount(
+                "/api",
+                routes![
+                    get_config,
+                    get_proxies,
+                    get_backends,
+                    get_pools,
+                    get_sessions,
+                    get_rules,
+                    disable_node,
+                    enable_node,
+                    drain_node,
+                    set_node_weight
+                ],
+            )
             .mount("/metrics", self.metrics_manager.get_server())
             .launch()
             .await
@@ -20,10 +20,8 @@ use crate::ast::api::*;
 #[allow(dead_code)]
 const MYSQL_START_TRANS_OPT_WITH_CONS_SNAPSHOT: u8 = 1;
 // READ ONLY option
-#[allow(dead_code)]
 const MYSQL_START_TRANS_OPT_READ_ONLY: u8 = 2;
 // READ WRITE option
-#[allow(dead_code)]
 const MYSQL_START_TRANS_OPT_READ_WRITE: u8 = 4;
 
 #[derive(Debug, Clone)]
@@ -33,6 +31,16 @@ pub struct Start {
 }
 
 impl Start {
+    // Whether `START TRANSACTION READ ONLY`
+    pub fn is_read_only(&self) -> bool {
+        self.transaction_opts & MYSQL_START_TRANS_OPT_READ_ONLY != 0
+    }
+
+    // Whether `START TRANSACTION READ WRITE`
+    pub fn is_read_write(&self) -> bool {
+        self.transaction_opts & MYSQL_START_TRANS_OPT_READ_WRITE != 0
+    }
+
     pub fn format(&self) -> String {
         let mut val = String::from("START TRANSACTION ");
         match self.transaction_opts {
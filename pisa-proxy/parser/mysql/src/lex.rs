@@ -372,9 +372,14 @@ impl<'a> Scanner<'a> {
                 }
 
                 k if k.starts_with("session.") => {
-                    lexemes.push(Ok(DefaultLexeme::new(T_GLOBAL, self.pos, 7)));
+                    lexemes.push(Ok(DefaultLexeme::new(T_SESSION, self.pos, 7)));
                     self.next_n(7);
                 }
+
+                k if k.starts_with("local.") => {
+                    lexemes.push(Ok(DefaultLexeme::new(T_LOCAL, self.pos, 5)));
+                    self.next_n(5);
+                }
                 _ => {}
             }
             ch = self.peek();
@@ -123,6 +123,7 @@ mod test {
             "SET character_set_client = \"gbk\";",
             "SET @@GLOBAL.character_set_client = gbk;",
             "SET @@SESSION.character_set_client = gbk;",
+            "SET @@LOCAL.sql_mode = '', @@session.time_zone = '+08:00';",
             "SELECT * from mysql.select;",
             "select * from test.test limit 1",
             "select * from test.1test limit 1",
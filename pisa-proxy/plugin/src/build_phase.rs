@@ -18,6 +18,7 @@ use crate::{
     config,
     err::PluginError,
     layer::*,
+    stats::PluginStats,
 };
 
 /// concurrency control service, some logic may be added in the future, eg: metrics...
@@ -49,4 +50,11 @@ impl PluginPhase {
 
         PluginPhase { concurrency_control, circuit_break }
     }
+
+    pub fn stats(&self) -> PluginStats {
+        PluginStats {
+            concurrency_control: self.concurrency_control.stats(),
+            circuit_break: self.circuit_break.stats(),
+        }
+    }
 }
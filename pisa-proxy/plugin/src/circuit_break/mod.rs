@@ -18,6 +18,7 @@ use crate::{
     config,
     err::{BoxError, PluginError},
     layer::{Layer, Service},
+    stats::{RuleCounter, RuleStats},
 };
 
 #[derive(Clone)]
@@ -33,6 +34,7 @@ pub struct CircuitBreakConfig {
 #[derive(Clone)]
 pub struct CircuitBreakInstance {
     regex: Vec<Regex>,
+    counter: RuleCounter,
 }
 
 impl CircuitBreakLayer {
@@ -49,7 +51,7 @@ impl CircuitBreakLayer {
             let mut instances = Vec::with_capacity(config.len());
             for c in config {
                 let regex = c.regex.iter().map(|r| Regex::new(r).unwrap()).collect::<Vec<Regex>>();
-                instances.push(CircuitBreakInstance { regex })
+                instances.push(CircuitBreakInstance { regex, counter: RuleCounter::default() })
             }
             return Some(instances);
         }
@@ -79,6 +81,8 @@ impl<S> CircuitBreak<S> {
         if let Some(instances) = &self.instances {
             for c in instances {
                 if c.regex.iter().any(|r| r.is_match(input)) {
+                    c.counter.hit();
+                    c.counter.reject();
                     return false;
                 }
             }
@@ -88,6 +92,16 @@ impl<S> CircuitBreak<S> {
     }
 }
 
+impl<S> CircuitBreak<S> {
+    pub fn stats(&self) -> Vec<RuleStats> {
+        self.instances
+            .iter()
+            .flatten()
+            .map(|c| c.counter.stats(c.regex.iter().map(|r| r.to_string()).collect()))
+            .collect()
+    }
+}
+
 impl<S, Input> Service<Input> for CircuitBreak<S>
 where
     S: Service<Input>,
@@ -130,6 +144,13 @@ mod test {
             .build(service_fn(test_service));
 
         let res = wrap_svc.handle("abc");
-        assert_eq!(res.is_err(), true)
+        assert_eq!(res.is_err(), true);
+
+        // The counters are shared by clones.
+        let mut cloned = wrap_svc.clone();
+        assert!(cloned.handle("123").is_ok());
+        assert!(cloned.handle("def").is_err());
+        let stats = wrap_svc.stats();
+        assert_eq!((stats[0].hits, stats[0].rejects), (2, 2));
     }
 }
@@ -25,6 +25,7 @@ use crate::{
     config,
     err::{BoxError, PluginError},
     layer::{Layer, Service},
+    stats::{RuleCounter, RuleStats},
 };
 
 #[derive(Clone)]
@@ -49,6 +50,7 @@ pub struct ConcurrencyControlInstance {
     // and duration `duration`
     duration: Duration,
     start_at: Option<Instant>,
+    counter: RuleCounter,
 }
 
 impl ConcurrencyControlLayer {
@@ -73,6 +75,7 @@ impl ConcurrencyControlLayer {
                     semaphore,
                     duration: c.duration,
                     start_at: None,
+                    counter: RuleCounter::default(),
                 });
             }
             return Some(instances);
@@ -112,6 +115,7 @@ impl<S> ConcurrencyControl<S> {
                 if !c.regex.iter().any(|r| r.is_match(input)) {
                     continue;
                 }
+                c.counter.hit();
 
                 if c.start_at.is_none() {
                     // first match, set start_at
@@ -119,6 +123,7 @@ impl<S> ConcurrencyControl<S> {
                     let permit = c.semaphore.clone().try_acquire_owned();
 
                     if permit.is_err() {
+                        c.counter.reject();
                         return (Some(idx), false);
                     }
                     permit.unwrap().forget();
@@ -133,6 +138,7 @@ impl<S> ConcurrencyControl<S> {
                     } else {
                         let permit = c.clone().semaphore.try_acquire_owned();
                         if permit.is_err() {
+                            c.counter.reject();
                             return (Some(idx), false);
                         }
                         permit.unwrap().forget();
@@ -145,6 +151,17 @@ impl<S> ConcurrencyControl<S> {
         (None, true)
     }
 
+    pub fn stats(&self) -> Vec<RuleStats> {
+        match &self.instances {
+            Some(instances) => instances
+                .lock()
+                .iter()
+                .map(|c| c.counter.stats(c.regex.iter().map(|r| r.to_string()).collect()))
+                .collect(),
+            None => vec![],
+        }
+    }
+
     pub fn add_permits(&mut self, idx: usize) {
         let instances = self.instances.as_mut().unwrap().lock();
         instances[idx].semaphore.add_permits(1)
@@ -227,6 +244,10 @@ mod test {
             }
         }
 
-        assert_eq!(count, 3)
+        assert_eq!(count, 3);
+
+        let stats = wrap_svc.stats();
+        assert_eq!(stats[0].hits, 5);
+        assert_eq!(stats[0].rejects, 2);
     }
 }
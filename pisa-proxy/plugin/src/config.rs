@@ -22,6 +22,18 @@ pub struct Plugin {
     pub circuit_break: Option<Vec<CircuitBreak>>,
 }
 
+impl Plugin {
+    // Check the regex of rules, the layers unwrap them when building.
+    pub fn check(&self) -> Result<(), regex::Error> {
+        let concurrency_control = self.concurrency_control.iter().flatten().flat_map(|x| x.regex.iter());
+        let circuit_break = self.circuit_break.iter().flatten().flat_map(|x| x.regex.iter());
+        for regex in concurrency_control.chain(circuit_break) {
+            regex::Regex::new(regex)?;
+        }
+        Ok(())
+    }
+}
+
 #[serde_with::serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConcurrencyControl {
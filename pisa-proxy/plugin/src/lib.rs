@@ -18,6 +18,7 @@ pub mod concurrency_control;
 pub mod config;
 pub mod err;
 pub mod layer;
+pub mod stats;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,60 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use serde::Serialize;
+
+/// Counters of a plugin rule, they are shared by the clones of the plugin in sessions.
+#[derive(Debug, Clone, Default)]
+pub struct RuleCounter {
+    hits: Arc<AtomicU64>,
+    rejects: Arc<AtomicU64>,
+}
+
+impl RuleCounter {
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reject(&self) {
+        self.rejects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self, regex: Vec<String>) -> RuleStats {
+        RuleStats {
+            regex,
+            hits: self.hits.load(Ordering::Relaxed),
+            rejects: self.rejects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RuleStats {
+    pub regex: Vec<String>,
+    // The statements matched by the rule
+    pub hits: u64,
+    // The statements rejected by the rule
+    pub rejects: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Default, PartialEq)]
+pub struct PluginStats {
+    pub concurrency_control: Vec<RuleStats>,
+    pub circuit_break: Vec<RuleStats>,
+}
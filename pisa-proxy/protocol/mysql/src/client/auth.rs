@@ -24,7 +24,9 @@ use sha1::Sha1;
 use tokio_util::codec::{Decoder, Encoder, Framed};
 
 use super::{codec::ClientCodec, stream::LocalStream};
-use crate::{charset::*, err::ProtocolError, mysql_const::*, util::*};
+use crate::{
+    charset::*, compress::Compression, err::ProtocolError, mysql_const::*, session::SessionVars, util::*,
+};
 
 lazy_static! {
     static ref RE: Regex = Regex::new(r"^(?P<major>\d+)\.(?P<minor>\d+)\.(?P<patch>\d+)").unwrap();
@@ -84,6 +86,10 @@ pub struct ClientAuth {
     pub seq: u8,
     pub server_version: ServerVersion,
     pub auotcommit: Option<String>,
+    // The session variables which are set on the conn, they are replayed by `ConnAttrMut::init`.
+    pub vars: SessionVars,
+    // Requested compression, it is reset to `None` when the server doesn't support it.
+    pub compression: Option<Compression>,
 }
 
 impl ClientAuth {
@@ -92,7 +98,11 @@ impl ClientAuth {
             next_state: HandshakeState::InitialHandshake,
             connection_id: 0,
             salt: Vec::with_capacity(21),
-            client_capability: 0,
+            // Multi-statements and local infile are checked by the proxy, the backend conn always enables them.
+            client_capability: CLIENT_MULTI_STATEMENTS
+                | CLIENT_MULTI_RESULTS
+                | CLIENT_PS_MULTI_RESULTS
+                | CLIENT_LOCAL_FILES,
             capability: 0,
             status: 0,
             auth_plugin_name: "".to_string(),
@@ -104,6 +114,8 @@ impl ClientAuth {
             seq: 0,
             server_version: ServerVersion::default(),
             auotcommit: None,
+            vars: SessionVars::new(),
+            compression: None,
         }
     }
 
@@ -232,19 +244,36 @@ impl ClientAuth {
             | CLIENT_LONG_PASSWORD
             | CLIENT_TRANSACTIONS
             | CLIENT_PLUGIN_AUTH
-            | self.capability & CLIENT_LONG_FLAG;
+            | self.capability & CLIENT_LONG_FLAG
+            // Session state changes are tracked by the OK packet when the server supports it.
+            | self.capability & CLIENT_SESSION_TRACK;
 
         capability |= self.client_capability & CLIENT_FOUND_ROWS
             | self.client_capability & CLIENT_IGNORE_SPACE
             | self.client_capability & CLIENT_MULTI_STATEMENTS
             | self.client_capability & CLIENT_MULTI_RESULTS
             | self.client_capability & CLIENT_PS_MULTI_RESULTS
+            | self.client_capability & CLIENT_LOCAL_FILES
             | self.client_capability & CLIENT_CONNECT_ATTRS;
 
         if self.tls_config.is_some() {
             capability |= CLIENT_SSL
         }
 
+        self.compression = match self.compression {
+            Some(Compression::Zstd(level))
+                if self.capability & CLIENT_ZSTD_COMPRESSION_ALGORITHM != 0 =>
+            {
+                capability |= CLIENT_ZSTD_COMPRESSION_ALGORITHM;
+                Some(Compression::Zstd(level))
+            }
+            Some(Compression::Zlib(level)) if self.capability & CLIENT_COMPRESS != 0 => {
+                capability |= CLIENT_COMPRESS;
+                Some(Compression::Zlib(level))
+            }
+            _ => None,
+        };
+
         let auth_data = match self.gen_auth_response(&self.salt.clone()) {
             Err(err) => return Err(err),
             Ok(auth_data) => auth_data,
@@ -310,6 +339,14 @@ impl ClientAuth {
         data.put_slice(self.auth_plugin_name.as_bytes());
         data.put_u8(0x00);
 
+        if let Some(Compression::Zstd(level)) = self.compression {
+            // Empty connection attributes
+            if capability & CLIENT_CONNECT_ATTRS != 0 {
+                data.put_u8(0x00);
+            }
+            data.put_u8(level as u8);
+        }
+
         Ok(self.tls_config.is_some())
     }
 
@@ -632,7 +669,13 @@ pub async fn handshake(
                 framed.send(passwd).await?
             }
 
-            Some(Ok(HandshakeDecoderReturn::AuthSuccess)) => return Ok((framed, true, vec![])),
+            Some(Ok(HandshakeDecoderReturn::AuthSuccess)) => {
+                // Packets after the OK packet are compressed.
+                if let Some(compression) = framed.codec().compression {
+                    framed.get_mut().make_compressed(compression);
+                }
+                return Ok((framed, true, vec![]));
+            }
 
             Some(Err(e)) => return Err(e),
 
@@ -20,7 +20,7 @@ use std::{
 };
 
 use bytes::{Buf, BufMut, BytesMut};
-use futures::{stream::Fuse, Stream};
+use futures::{stream::Fuse, SinkExt, Stream};
 use pin_project::pin_project;
 use protocol_codegen::mysql_codec_convert;
 use tokio::io::Interest;
@@ -28,11 +28,12 @@ use tokio_util::codec::{Decoder, Encoder, Framed};
 
 use super::{
     auth::ClientAuth,
-    resultset::{write_command_binary, ResultsetCodec},
+    resultset::{write_command_binary, ResultSendCommand, ResultsetCodec},
     stmt::Stmt,
     stream::LocalStream,
 };
 use crate::{
+    common_packet::SessionState,
     err::ProtocolError,
     row::{RowData, RowDataTyp},
     util::{get_length, is_eof}, client::resultset::DecodeResultsetState,
@@ -74,6 +75,15 @@ impl DerefMut for ClientCodec {
 }
 
 impl ClientCodec {
+    pub fn get_stream_mut(&mut self) -> &mut LocalStream {
+        match self {
+            Self::ClientAuth(framed) => framed.get_mut(),
+            Self::Resultset(framed) => framed.get_mut(),
+            Self::Stmt(framed) => framed.get_mut(),
+            Self::Common(framed) => framed.get_mut(),
+        }
+    }
+
     pub async fn is_ready(&self) -> bool {
         let local_stream = match self {
             Self::ClientAuth(framed) => framed.get_ref(),
@@ -241,6 +251,38 @@ impl<'a> ResultsetStream<'a> {
     pub fn is_binary(&self) -> bool {
         self.framed.codec().is_binary
     }
+
+    // Whether another resultset follows the completed one, e.g. multi-statements or CALL.
+    pub fn has_more_results(&self) -> bool {
+        self.framed.codec().more_results
+    }
+
+    // The server status of the completed resultset, it is sent to client in the EOF packet.
+    pub fn status(&self) -> u16 {
+        self.framed.codec().status
+    }
+
+    // Send the file content of LOCAL INFILE request, an empty `data` terminates the file,
+    // then the OK or ERR packet is returned by the stream.
+    pub async fn send_local_infile(&mut self, data: &[u8]) -> Result<(), ProtocolError> {
+        self.framed.send(ResultSendCommand::LocalInfile(data)).await
+    }
+
+    // Take the session state changes tracked by the OK packets, they are applied to the conn already.
+    pub fn take_session_changes(&mut self) -> Vec<SessionState> {
+        std::mem::take(&mut self.framed.codec_mut().state_changes)
+    }
+
+    // Record the variable which is set on the conn, e.g. user variable which is not tracked by the server.
+    // `None` means the variable is reset to default.
+    pub fn set_session_var(&mut self, name: &str, value: Option<String>) {
+        if let Some(auth_info) = self.framed.codec_mut().auth_info.as_mut() {
+            match value {
+                Some(value) => auth_info.vars.insert(name.to_lowercase(), value),
+                None => auth_info.vars.remove(&name.to_lowercase()),
+            };
+        }
+    }
 }
 
 impl<'a> Stream for ResultsetStream<'a> {
@@ -338,7 +380,7 @@ impl Decoder for CommonCodec {
     type Error = ProtocolError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.is_empty() {
+        if src.len() < 4 {
             return Ok(None);
         }
 
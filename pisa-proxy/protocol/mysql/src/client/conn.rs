@@ -25,16 +25,18 @@ use tokio_util::codec::Framed;
 use super::{
     auth::{handshake, ClientAuth},
     codec::{ClientCodec, CommonStream, QueryResultStream, ResultsetStream},
-    resultset::ResultSendCommand,
+    resultset::{DecodeResultsetState, ResultSendCommand},
     stmt::Stmt,
     stream::LocalStream,
 };
 use crate::{
     column::{Column, ColumnInfo},
+    compress::Compression,
     err::ProtocolError,
     mysql_const::*,
     row::{RowDataText, RowDataTyp},
-    util::{is_ok_header, BufExt},
+    session::{build_set_vars, SessionVars},
+    util::{get_length, is_eof, is_ok_header, BufExt},
 };
 
 #[derive(Debug, Default)]
@@ -43,6 +45,7 @@ pub struct ClientConn {
     user: String,
     password: String,
     endpoint: String,
+    compression: Option<Compression>,
 }
 
 impl ClientConn {
@@ -50,6 +53,12 @@ impl ClientConn {
         ClientConn { user, password, endpoint, ..Default::default() }
     }
 
+    // Request the compressed protocol, it is used only when the server supports it.
+    pub fn with_compression(mut self, compression: Option<Compression>) -> ClientConn {
+        self.compression = compression;
+        self
+    }
+
     #[cfg(test)]
     pub async fn test_conn(
         user: String,
@@ -70,6 +79,7 @@ impl ClientConn {
         auth_codec.user = self.user.clone();
         auth_codec.password = self.password.clone();
         auth_codec.tls_config = None;
+        auth_codec.compression = self.compression;
 
         let mut framed = Some(Box::new(ClientCodec::ClientAuth(Framed::with_capacity(
             local_stream,
@@ -80,12 +90,22 @@ impl ClientConn {
         let res = handshake(*(framed.take().unwrap())).await?;
         let framed = Some(Box::new(ClientCodec::ClientAuth(res.0)));
 
-        Ok(ClientConn {
+        let mut conn = ClientConn {
             user: self.user.clone(),
             password: self.password.clone(),
             endpoint: self.endpoint.clone(),
+            compression: self.compression,
             framed,
-        })
+        };
+
+        // Track all session variables, so that the variables changed by the query are replayed on other conns.
+        if conn.framed.as_ref().unwrap().capability & CLIENT_SESSION_TRACK > 0 {
+            let _ = conn
+                .send_query_discard_result("SET SESSION session_track_system_variables = '*'")
+                .await;
+        }
+
+        Ok(conn)
     }
 
     pub async fn handshake(&mut self) -> Result<(bool, Vec<u8>), ProtocolError> {
@@ -99,7 +119,7 @@ impl ClientConn {
         &'a mut self,
         val: &'a [u8],
     ) -> Result<ResultsetStream<'a>, ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
 
         let mut resultset_codec = framed.into_resultset();
 
@@ -111,7 +131,7 @@ impl ClientConn {
     }
 
     pub async fn send_prepare<'a>(&'a mut self, val: &[u8]) -> Result<Stmt, ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
 
         let mut stmt_codec = framed.into_stmt();
         stmt_codec.send((COM_STMT_PREPARE, val)).await?;
@@ -152,7 +172,7 @@ impl ClientConn {
         &'a mut self,
         val: &'a [u8],
     ) -> Result<ResultsetStream<'a>, ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
         let mut resultset_codec = framed.into_resultset();
         resultset_codec.codec_mut().with_binary(true);
 
@@ -163,11 +183,43 @@ impl ClientConn {
         Ok(ResultsetStream::new(self.framed.as_mut()))
     }
 
+    // Send COM_STMT_FETCH, returns binary rows and the EOF packet, or an ERR packet.
+    pub async fn send_fetch(&mut self, val: &[u8]) -> Result<Vec<BytesMut>, ProtocolError> {
+        let framed = self.take_framed();
+        let mut resultset_codec = framed.into_resultset();
+        resultset_codec.codec_mut().with_binary(true);
+
+        let res = resultset_codec.send(ResultSendCommand::Binary((COM_STMT_FETCH, val))).await;
+        resultset_codec.codec_mut().next_state = DecodeResultsetState::RowBinary;
+
+        let mut packets = vec![];
+        let res = match res {
+            Ok(_) => loop {
+                match resultset_codec.next().await {
+                    Some(Ok((data, _))) => {
+                        let is_end = is_eof(&data) || data[4] == ERR_HEADER;
+                        packets.push(data);
+                        if is_end {
+                            break Ok(packets);
+                        }
+                    }
+                    Some(Err(e)) => break Err(e),
+                    None => break Err(ProtocolError::Io(std::io::ErrorKind::UnexpectedEof.into())),
+                }
+            },
+            Err(e) => Err(e),
+        };
+
+        resultset_codec.codec_mut().next_state = DecodeResultsetState::Complete;
+        self.framed = Some(Box::new(ClientCodec::Resultset(resultset_codec)));
+        res
+    }
+
     pub async fn send_use_db<'a>(
         &'a mut self,
         val: &str,
     ) -> Result<(BytesMut, bool), ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
         let mut common_codec = framed.into_common();
 
         common_codec.send((COM_INIT_DB, val.as_bytes())).await?;
@@ -192,7 +244,7 @@ impl ClientConn {
     }
 
     pub async fn send_ping(&mut self) -> Result<bool, ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
         let mut common_codec = framed.into_common();
 
         common_codec.send((COM_PING, &[])).await?;
@@ -219,7 +271,7 @@ impl ClientConn {
         code: u8,
         val: &'a [u8],
     ) -> Result<CommonStream<'a>, ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
         let mut common_codec = framed.into_common();
 
         common_codec.send((code, val)).await?;
@@ -229,6 +281,91 @@ impl ClientConn {
         Ok(CommonStream::new(self.framed.as_mut()))
     }
 
+    // Send STMT_RESET, RESET_CONNECTION, STATISTICS, SET_OPTION,etc... command,
+    // the server responds with only one packet.
+    pub async fn send_single_response_command(
+        &mut self,
+        code: u8,
+        val: &[u8],
+    ) -> Result<BytesMut, ProtocolError> {
+        let framed = self.take_framed();
+        let mut common_codec = framed.into_common();
+
+        let res = match common_codec.send((code, val)).await {
+            Ok(_) => match common_codec.next().await {
+                Some(Ok(data)) => Ok(data.0),
+                Some(Err(e)) => Err(e),
+                None => Err(ProtocolError::Io(std::io::ErrorKind::UnexpectedEof.into())),
+            },
+            Err(e) => Err(e),
+        };
+
+        self.framed = Some(Box::new(ClientCodec::Common(common_codec)));
+        res
+    }
+
+    // Send RESET_CONNECTION command, the cached charset, autocommit and variables are cleared so that
+    // they are set again by `ConnAttrMut::init`.
+    pub async fn send_reset_connection(&mut self) -> Result<bool, ProtocolError> {
+        let data = self.send_single_response_command(COM_RESET_CONNECTION, &[]).await?;
+        if !is_ok_header(data[4]) {
+            return Ok(false);
+        }
+
+        let codec = self.framed.as_mut().unwrap();
+        codec.charset = String::new();
+        codec.auotcommit = None;
+        codec.vars.clear();
+
+        // The variables are not tracked after the session is reset.
+        if codec.capability & CLIENT_SESSION_TRACK > 0 {
+            self.send_query_discard_result("SET SESSION session_track_system_variables = '*'").await?;
+        }
+
+        Ok(true)
+    }
+
+    // Send STMT_SEND_LONG_DATA,etc... command, the server does not respond.
+    pub async fn send_no_response_command(
+        &mut self,
+        code: u8,
+        val: &[u8],
+    ) -> Result<(), ProtocolError> {
+        let framed = self.take_framed();
+        let mut common_codec = framed.into_common();
+
+        let res = common_codec.send((code, val)).await;
+
+        self.framed = Some(Box::new(ClientCodec::Common(common_codec)));
+        res
+    }
+
+    // Read a packet of the binlog stream after COM_BINLOG_DUMP or COM_BINLOG_DUMP_GTID is sent by
+    // `send_no_response_command`, the packets of MAX_PAYLOAD_LEN are merged. Returns the payload,
+    // `None` when the conn is closed.
+    pub async fn read_binlog_packet(&mut self) -> Option<Result<BytesMut, ProtocolError>> {
+        let framed = match self.framed.as_mut().map(|x| x.as_mut()) {
+            Some(ClientCodec::Common(framed)) => framed,
+            _ => return Some(Err(ProtocolError::Default)),
+        };
+
+        let mut payload = BytesMut::new();
+        loop {
+            let mut data = match framed.next().await? {
+                Ok(data) => data.0,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let length = get_length(&data) as usize;
+            let _ = data.split_to(4);
+            payload.extend_from_slice(&data);
+
+            if length < MAX_PAYLOAD_LEN {
+                return Some(Ok(payload));
+            }
+        }
+    }
+
     pub async fn query_result<'a>(
         &'a mut self,
         val: &'a [u8],
@@ -287,11 +424,24 @@ impl ClientConn {
         Ok(())
     }
 
+    // Returns true when the server responds OK, the result is discarded.
+    pub async fn send_query_check_ok(&mut self, val: &str) -> Result<bool, ProtocolError> {
+        let mut stream = self.send_common_command(COM_QUERY, val.as_bytes()).await?;
+
+        let mut is_ok = None;
+        while let Some(data) = stream.next().await {
+            let data = data?;
+            is_ok.get_or_insert(data.get(4) == Some(&OK_HEADER));
+        }
+
+        Ok(is_ok.unwrap_or(false))
+    }
+
     pub async fn send_query_without_stream<'a>(
         &'a mut self,
         val: &'a [u8],
     ) -> Result<(), ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
 
         let mut resultset_codec = framed.into_resultset();
 
@@ -306,7 +456,7 @@ impl ClientConn {
         &'a mut self,
         val: &'a [u8],
     ) -> Result<(), ProtocolError> {
-        let framed = self.framed.take().unwrap();
+        let framed = self.take_framed();
         let mut resultset_codec = framed.into_resultset();
         resultset_codec.codec_mut().with_binary(true);
 
@@ -317,6 +467,13 @@ impl ClientConn {
         Ok(())
     }
 
+    // Take the framed to send a new command, the sequence id of compressed packet starts from 0.
+    fn take_framed(&mut self) -> Box<ClientCodec> {
+        let mut framed = self.framed.take().unwrap();
+        framed.get_stream_mut().reset_seq();
+        framed
+    }
+
     pub async fn is_ready(&self) -> bool {
         self.framed.as_ref().unwrap().is_ready().await
     }
@@ -325,6 +482,11 @@ impl ClientConn {
         Some(self.endpoint.clone())
     }
 
+    // The thread id of the connection on the server side
+    pub fn get_connection_id(&self) -> Option<u32> {
+        self.framed.as_ref().map(|x| x.connection_id)
+    }
+
     pub fn set_charset(&mut self, name: &str) {
         self.framed.as_mut().unwrap().charset = name.to_string()
     }
@@ -332,6 +494,14 @@ impl ClientConn {
     pub fn set_autocommit(&mut self, status: &str) {
         self.framed.as_mut().unwrap().auotcommit = Some(status.to_string())
     }
+
+    pub fn get_vars(&self) -> SessionVars {
+        self.framed.as_ref().map(|codec| codec.vars.clone()).unwrap_or_default()
+    }
+
+    pub fn set_vars(&mut self, vars: SessionVars) {
+        self.framed.as_mut().unwrap().vars = vars
+    }
 }
 
 impl Clone for ClientConn {
@@ -340,6 +510,7 @@ impl Clone for ClientConn {
             user: self.user.clone(),
             password: self.password.clone(),
             endpoint: self.endpoint.clone(),
+            compression: self.compression,
             framed: None,
         }
     }
@@ -414,6 +585,7 @@ pub enum SessionAttr {
     DB(Option<String>),
     Charset(String),
     Autocommit(Option<String>),
+    Vars(SessionVars),
 }
 
 #[async_trait]
@@ -445,6 +617,14 @@ impl ConnAttrMut for ClientConn {
                         }
                     }
                 }
+                SessionAttr::Vars(vars) => {
+                    // The vars are recorded when they are set, they are set again by the next init otherwise.
+                    if let Some(sql) = build_set_vars(&self.get_vars(), vars) {
+                        if let Ok(true) = self.send_query_check_ok(&sql).await {
+                            self.set_vars(vars.clone());
+                        }
+                    }
+                }
             }
         }
     }
@@ -17,9 +17,14 @@ use tokio_util::codec::{Decoder, Encoder};
 
 use super::{auth::ClientAuth, codec::*};
 use crate::{
+    common_packet::{untrack_ok_packet, SessionState},
     err::ProtocolError,
-    mysql_const::ERR_HEADER,
-    util::{get_length, is_eof, is_ok, BufExt},
+    mysql_const::{
+        CLIENT_SESSION_TRACK, ERR_HEADER, LOCAL_IN_FILE_HEADER, MAX_PAYLOAD_LEN,
+        SERVER_MORE_RESULTS_EXISTS, SERVER_STATUS_CURSOR_EXISTS,
+    },
+    session::{is_tracked_var, quote_var_value},
+    util::{get_eof_status, get_length, get_ok_status, is_eof, is_ok, BufExt},
 };
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -51,6 +56,12 @@ pub struct ResultsetCodec {
     pub is_binary: bool,
     pub seq: u8,
     pub auth_info: Option<ClientAuth>,
+    // SERVER_MORE_RESULTS_EXISTS is set by the last resultset, the next one is decoded then.
+    pub more_results: bool,
+    // The server status of the EOF or OK packet which completes the last resultset.
+    pub status: u16,
+    // The session state changes of OK packets, they are taken by `ResultsetStream`.
+    pub state_changes: Vec<SessionState>,
 }
 
 impl ResultsetCodec {
@@ -61,6 +72,9 @@ impl ResultsetCodec {
             is_binary: false,
             seq: 0,
             auth_info: None,
+            more_results: false,
+            status: 0,
+            state_changes: vec![],
         }
     }
 
@@ -90,17 +104,36 @@ impl ResultsetCodec {
         }
     }
 
+    // The resultset is completed, decode the next one when SERVER_MORE_RESULTS_EXISTS is set.
+    fn decode_complete(&mut self, status: u16) {
+        self.status = status;
+        self.more_results = status & SERVER_MORE_RESULTS_EXISTS > 0;
+        if self.more_results {
+            self.next_state = DecodeResultsetState::ColumnCount
+        } else {
+            self.next_state = DecodeResultsetState::Complete
+        }
+    }
+
     fn decode_column_count(&mut self, length: usize, data: &mut BytesMut) -> (BytesMut, bool) {
         if data[4] == ERR_HEADER {
             //return Err(ProtocolError::PacketError(data.split().to_vec()))
+            self.more_results = false;
             self.next_state = DecodeResultsetState::Complete;
             return (data.split(), true);
         }
 
+        // LOAD DATA LOCAL INFILE request, the OK or ERR packet is returned after the file is sent.
+        if data[4] == LOCAL_IN_FILE_HEADER {
+            self.next_state = DecodeResultsetState::Complete;
+            return (data.split_to(4 + length), true);
+        }
+
         let is_ok = self.decode_try_ok(length, data);
 
-        if let Some(data) = is_ok {
-            self.next_state = DecodeResultsetState::Complete;
+        if let Some(mut data) = is_ok {
+            self.decode_complete(get_ok_status(&data));
+            self.untrack_session_state(&mut data);
             return (data, true);
         }
 
@@ -117,9 +150,43 @@ impl ResultsetCodec {
         (payload_clone, is_null)
     }
 
+    // The session state changes are applied to the conn, the OK packet is rewritten without them
+    // since the client never enables CLIENT_SESSION_TRACK.
+    fn untrack_session_state(&mut self, data: &mut BytesMut) {
+        let auth_info = match self.auth_info.as_mut() {
+            Some(auth_info) if auth_info.capability & CLIENT_SESSION_TRACK > 0 => auth_info,
+            _ => return,
+        };
+
+        let changes = untrack_ok_packet(data);
+        for change in changes.iter() {
+            match change {
+                SessionState::SystemVariables(vars) => {
+                    for (name, value) in vars.iter() {
+                        let name = String::from_utf8_lossy(name).to_lowercase();
+                        if is_tracked_var(&name) {
+                            let value = quote_var_value(&String::from_utf8_lossy(value));
+                            auth_info.vars.insert(name, value);
+                        }
+                    }
+                }
+                SessionState::Schema(db) => auth_info.db = String::from_utf8_lossy(db).to_string(),
+                _ => {}
+            }
+        }
+
+        self.state_changes.extend(changes);
+    }
+
     fn decode_column_info(&mut self, length: usize, data: &mut BytesMut) -> (BytesMut, bool) {
         let payload = data.split_to(4 + length);
 
+        // A cursor is opened by COM_STMT_EXECUTE, rows are returned by COM_STMT_FETCH.
+        if is_eof(&payload) && get_eof_status(&payload) & SERVER_STATUS_CURSOR_EXISTS > 0 {
+            self.next_state = DecodeResultsetState::Complete;
+            return (payload, true);
+        }
+
         if is_eof(&payload) || is_ok(&payload) {
             if data.is_empty() {
                 self.next_state = DecodeResultsetState::Row;
@@ -147,7 +214,7 @@ impl ResultsetCodec {
         let payload = data.split_to(4 + length);
 
         if is_eof(&payload) {
-            self.next_state = DecodeResultsetState::Complete;
+            self.decode_complete(get_eof_status(&payload));
             return (payload, true);
         } else {
             self.next_state = DecodeResultsetState::Row;
@@ -197,6 +264,8 @@ impl Decoder for ResultsetCodec {
 pub enum ResultSendCommand<'a> {
     Plain((u8, &'a str)),
     Binary((u8, &'a [u8])),
+    // The file content of LOCAL INFILE request, an empty packet terminates it.
+    LocalInfile(&'a [u8]),
 }
 
 impl<'a> Encoder<ResultSendCommand<'a>> for ResultsetCodec {
@@ -208,24 +277,69 @@ impl<'a> Encoder<ResultSendCommand<'a>> for ResultsetCodec {
         dst: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.next_state = DecodeResultsetState::ColumnCount;
+        self.more_results = false;
         dst.reserve(1024);
 
         match item {
             ResultSendCommand::Plain(item) => write_command(item, dst),
             ResultSendCommand::Binary(item) => write_command_binary(item, dst),
+            ResultSendCommand::LocalInfile(data) => {
+                // The sequence id continues from the last packet.
+                self.seq = write_packets(data, self.seq.wrapping_add(1), dst);
+            }
         };
         Ok(())
     }
 }
 
+// The command is split into multiple packets when it is not less than MAX_PAYLOAD_LEN,
+// e.g. large blob of COM_STMT_SEND_LONG_DATA.
 pub fn write_command_binary(item: (u8, &[u8]), dst: &mut BytesMut) {
     let length = item.1.len() + 1;
+    dst.reserve(length + 4 * (length / MAX_PAYLOAD_LEN + 1));
+
+    let mut seq: u8 = 0;
+    let mut packet_len = length.min(MAX_PAYLOAD_LEN);
+    put_packet_header(packet_len, seq, dst);
+    dst.put_u8(item.0);
+    dst.extend_from_slice(&item.1[..packet_len - 1]);
+
+    let mut data = &item.1[packet_len - 1..];
+    // An empty packet is sent when the length is multiple of MAX_PAYLOAD_LEN.
+    while packet_len == MAX_PAYLOAD_LEN {
+        seq = seq.wrapping_add(1);
+        packet_len = data.len().min(MAX_PAYLOAD_LEN);
+        put_packet_header(packet_len, seq, dst);
+        dst.extend_from_slice(&data[..packet_len]);
+        data = &data[packet_len..];
+    }
+}
+
+// Write the payload from sequence id `seq`, returns the sequence id of the last packet.
+fn write_packets(payload: &[u8], seq: u8, dst: &mut BytesMut) -> u8 {
+    dst.reserve(payload.len() + 4 * (payload.len() / MAX_PAYLOAD_LEN + 1));
+
+    let mut seq = seq;
+    let mut data = payload;
+    loop {
+        let packet_len = data.len().min(MAX_PAYLOAD_LEN);
+        put_packet_header(packet_len, seq, dst);
+        dst.extend_from_slice(&data[..packet_len]);
+        data = &data[packet_len..];
+
+        if packet_len < MAX_PAYLOAD_LEN {
+            return seq;
+        }
+        seq = seq.wrapping_add(1);
+    }
+}
+
+#[inline]
+fn put_packet_header(length: usize, seq: u8, dst: &mut BytesMut) {
     dst.put_u8(length as u8);
     dst.put_u8((length >> 8) as u8);
     dst.put_u8((length >> 16) as u8);
-    dst.put_u8(0);
-    dst.put_u8(item.0);
-    dst.extend_from_slice(item.1);
+    dst.put_u8(seq);
 }
 
 #[cfg(test)]
@@ -241,6 +355,115 @@ mod test {
     use tokio_util::codec::Framed;
 
     use super::*;
+    use crate::mysql_const::{CLIENT_PROTOCOL_41, COM_QUERY};
+
+    #[test]
+    fn test_decode_more_results() {
+        let mut codec = ResultsetCodec::new();
+
+        // OK packet with SERVER_MORE_RESULTS_EXISTS
+        let mut data = BytesMut::from(&[7, 0, 0, 1, 0, 0, 0, 0x0a, 0, 0, 0][..]);
+        // resultset with one column and one row
+        data.extend_from_slice(&[1, 0, 0, 1, 1]);
+        data.extend_from_slice(&[5, 0, 0, 2, 1, 1, 1, 1, 1]);
+        data.extend_from_slice(&[5, 0, 0, 3, 0xfe, 0, 0, 0x02, 0]);
+        data.extend_from_slice(&[2, 0, 0, 4, 1, 0x31]);
+        data.extend_from_slice(&[5, 0, 0, 5, 0xfe, 0, 0, 0x0a, 0]);
+        // the last OK packet
+        data.extend_from_slice(&[7, 0, 0, 6, 0, 0, 0, 0x02, 0, 0, 0]);
+
+        let (_, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert!(is_complete);
+        assert!(codec.more_results);
+        assert_eq!(codec.next_state, DecodeResultsetState::ColumnCount);
+
+        for _ in 0..3 {
+            assert!(codec.decode(&mut data).unwrap().is_some());
+        }
+        let (row, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert_eq!(&row[4..], &[1, 0x31]);
+        assert!(!is_complete);
+
+        let (eof, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert!(is_eof(&eof));
+        assert!(is_complete);
+        assert!(codec.more_results);
+        assert!(codec.status & SERVER_MORE_RESULTS_EXISTS > 0);
+
+        let (_, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert!(is_complete);
+        assert!(!codec.more_results);
+        assert!(codec.next_state.is_complete());
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_local_infile() {
+        let mut codec = ResultsetCodec::new();
+        let mut dst = BytesMut::new();
+        codec.encode(ResultSendCommand::Binary((COM_QUERY, b"load data")), &mut dst).unwrap();
+
+        let mut data = BytesMut::from(&[5, 0, 0, 1, LOCAL_IN_FILE_HEADER, b'a', b'.', b'c', b'v'][..]);
+        let (req, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert_eq!(&req[5..], b"a.cv");
+        assert!(is_complete);
+        assert!(codec.next_state.is_complete());
+
+        let mut dst = BytesMut::new();
+        codec.encode(ResultSendCommand::LocalInfile(b"1,2\n"), &mut dst).unwrap();
+        codec.encode(ResultSendCommand::LocalInfile(&[]), &mut dst).unwrap();
+        assert_eq!(&dst[..], &[4, 0, 0, 2, b'1', b',', b'2', b'\n', 0, 0, 0, 3]);
+
+        // The response of the file is decoded
+        let mut data = BytesMut::from(&[7, 0, 0, 4, 0, 1, 0, 2, 0, 0, 0][..]);
+        let (ok, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert!(is_ok(&ok));
+        assert!(is_complete);
+    }
+
+    #[test]
+    fn test_untrack_session_state() {
+        let mut auth_info = ClientAuth::new();
+        auth_info.capability = CLIENT_PROTOCOL_41 | CLIENT_SESSION_TRACK;
+        let mut codec = ResultsetCodec::with_auth_info(Some(auth_info));
+
+        // OK packet of `SET time_zone = '+08:00'`, `time_zone` and `autocommit` are tracked
+        let mut data = BytesMut::from(&[0x2c, 0, 0, 1, 0, 0, 0, 0x02, 0x40, 0, 0, 0, 0x23][..]);
+        data.extend_from_slice(&[0, 0x11, 0x09]);
+        data.extend_from_slice(b"time_zone");
+        data.extend_from_slice(&[0x06]);
+        data.extend_from_slice(b"+08:00");
+        data.extend_from_slice(&[0, 0x0e, 0x0a]);
+        data.extend_from_slice(b"autocommit");
+        data.extend_from_slice(&[0x02]);
+        data.extend_from_slice(b"ON");
+
+        let (ok, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert!(is_complete);
+        assert_eq!(&ok[..], &[0x07, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+        assert_eq!(codec.state_changes.len(), 2);
+
+        let vars = &codec.auth_info.as_ref().unwrap().vars;
+        assert_eq!(vars.get("time_zone").unwrap(), "'+08:00'");
+        assert!(vars.get("autocommit").is_none());
+    }
+
+    #[test]
+    fn test_decode_cursor() {
+        let mut codec = ResultsetCodec::new();
+        codec.with_binary(true);
+
+        let mut data = BytesMut::from(&[1, 0, 0, 1, 1][..]);
+        data.extend_from_slice(&[5, 0, 0, 2, 1, 1, 1, 1, 1]);
+        data.extend_from_slice(&[5, 0, 0, 3, 0xfe, 0, 0, 0x42, 0]);
+
+        assert!(codec.decode(&mut data).unwrap().is_some());
+        assert!(codec.decode(&mut data).unwrap().is_some());
+        let (eof, is_complete) = codec.decode(&mut data).unwrap().unwrap();
+        assert!(is_eof(&eof));
+        assert!(is_complete);
+        assert!(codec.next_state.is_complete());
+    }
 
     #[tokio::test]
     async fn test_decode() {
@@ -19,10 +19,15 @@ use tokio_util::codec::{Decoder, Encoder};
 use super::{auth::ClientAuth, resultset::write_command_binary};
 use crate::{
     err::ProtocolError,
-    mysql_const::{EOF_HEADER, ERR_HEADER},
+    mysql_const::{
+        CURSOR_TYPE_FOR_UPDATE, CURSOR_TYPE_NO_CURSOR, CURSOR_TYPE_READ_ONLY, CURSOR_TYPE_SCROLLABLE,
+        EOF_HEADER, ERR_HEADER,
+    },
     util::get_length,
 };
 
+const CURSOR_TYPE_MASK: u8 = CURSOR_TYPE_READ_ONLY | CURSOR_TYPE_FOR_UPDATE | CURSOR_TYPE_SCROLLABLE;
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq)]
 enum DecodeStmtState {
@@ -162,6 +167,37 @@ impl Stmt {
     }
 }
 
+/// Get the cursor type from flags of `COM_STMT_EXECUTE` payload,
+/// the server opens a cursor when it is not `CURSOR_TYPE_NO_CURSOR`.
+pub fn get_execute_cursor_type(payload: &[u8]) -> u8 {
+    payload.get(4).map_or(CURSOR_TYPE_NO_CURSOR, |flags| flags & CURSOR_TYPE_MASK)
+}
+
+/// Clear the cursor type of `COM_STMT_EXECUTE` payload, the whole resultset is returned.
+pub fn clear_execute_cursor_type(payload: &mut [u8]) {
+    if let Some(flags) = payload.get_mut(4) {
+        *flags &= !CURSOR_TYPE_MASK
+    }
+}
+
+/// Parse `COM_STMT_FETCH` payload, returns stmt id and number of rows.
+pub fn decode_fetch(payload: &[u8]) -> Option<(u32, u32)> {
+    if payload.len() < 8 {
+        return None;
+    }
+
+    Some((LittleEndian::read_u32(&payload[..4]), LittleEndian::read_u32(&payload[4..8])))
+}
+
+/// Parse `COM_STMT_SEND_LONG_DATA` payload, returns stmt id, param id and data.
+pub fn decode_send_long_data(payload: &[u8]) -> Option<(u32, u16, &[u8])> {
+    if payload.len() < 6 {
+        return None;
+    }
+
+    Some((LittleEndian::read_u32(&payload[..4]), LittleEndian::read_u16(&payload[4..6]), &payload[6..]))
+}
+
 /// Implements `Decoder` trait
 impl Decoder for Stmt {
     type Item = Option<BytesMut>;
@@ -223,7 +259,67 @@ mod test {
     };
     use tracing::trace;
 
-    use crate::client::stmt::Stmt;
+    use crate::{client::stmt::*, mysql_const::{COM_STMT_SEND_LONG_DATA, MAX_PAYLOAD_LEN}};
+
+    #[test]
+    fn test_execute_cursor_type() {
+        let mut payload = [1, 0, 0, 0, CURSOR_TYPE_READ_ONLY, 1, 0, 0, 0];
+        assert_eq!(get_execute_cursor_type(&payload), CURSOR_TYPE_READ_ONLY);
+
+        clear_execute_cursor_type(&mut payload);
+        assert_eq!(get_execute_cursor_type(&payload), CURSOR_TYPE_NO_CURSOR);
+        assert_eq!(get_execute_cursor_type(&payload[..4]), CURSOR_TYPE_NO_CURSOR);
+
+        assert_eq!(decode_fetch(&[1, 0, 0, 0, 10, 0, 0, 0]), Some((1, 10)));
+        assert_eq!(decode_fetch(&[1, 0, 0, 0]), None);
+    }
+
+    fn make_send_long_data(stmt_id: u32, param_id: u16, data: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(data.len() + 6);
+        payload.extend_from_slice(&stmt_id.to_le_bytes());
+        payload.extend_from_slice(&param_id.to_le_bytes());
+        payload.extend_from_slice(data);
+        payload
+    }
+
+    #[test]
+    fn test_send_long_data() {
+        let payload = make_send_long_data(1, 2, b"blob");
+        assert_eq!(decode_send_long_data(&payload), Some((1, 2, &b"blob"[..])));
+        assert_eq!(decode_send_long_data(&payload[..5]), None);
+
+        let mut stmt = Stmt::new();
+        let mut dst = BytesMut::new();
+        stmt.encode((COM_STMT_SEND_LONG_DATA, &payload), &mut dst).unwrap();
+        assert_eq!(&dst[..5], &[11, 0, 0, 0, COM_STMT_SEND_LONG_DATA]);
+        assert_eq!(&dst[5..], &payload[..]);
+    }
+
+    #[test]
+    fn test_send_long_data_split_packets() {
+        // The command byte and payload fill the first packet, the rest is sent in the second one.
+        let data = vec![0xab; MAX_PAYLOAD_LEN - 6];
+        let payload = make_send_long_data(1, 0, &data);
+
+        let mut stmt = Stmt::new();
+        let mut dst = BytesMut::new();
+        stmt.encode((COM_STMT_SEND_LONG_DATA, &payload), &mut dst).unwrap();
+        assert_eq!(dst.len(), MAX_PAYLOAD_LEN + 4 + 4 + 1);
+        assert_eq!(get_length(&dst), MAX_PAYLOAD_LEN);
+        assert_eq!(dst[3], 0);
+        assert_eq!(dst[4], COM_STMT_SEND_LONG_DATA);
+
+        let second = &dst[MAX_PAYLOAD_LEN + 4..];
+        assert_eq!(&second[..4], &[1, 0, 0, 1]);
+        assert_eq!(second[4], 0xab);
+
+        // An empty packet terminates the command when the length is exactly MAX_PAYLOAD_LEN.
+        let payload = make_send_long_data(1, 0, &data[1..]);
+        let mut dst = BytesMut::new();
+        stmt.encode((COM_STMT_SEND_LONG_DATA, &payload), &mut dst).unwrap();
+        assert_eq!(dst.len(), MAX_PAYLOAD_LEN + 4 + 4);
+        assert_eq!(&dst[MAX_PAYLOAD_LEN + 4..], &[0, 0, 0, 1]);
+    }
 
     #[tokio::test]
     async fn test_decode_prepare() {
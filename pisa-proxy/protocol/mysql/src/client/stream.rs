@@ -24,13 +24,17 @@ use tokio::{
 };
 use tokio_native_tls::{native_tls::TlsConnector, TlsStream};
 
-use crate::err::ProtocolError;
+use crate::{
+    compress::{CompressedStream, Compression},
+    err::ProtocolError,
+};
 
 #[pin_project(project=LSProj)]
 #[derive(Debug)]
 pub enum LocalStream {
     Plain(Option<TcpStream>),
     Secure(TlsStream<TcpStream>),
+    Compressed(Box<CompressedStream<LocalStream>>),
 }
 
 impl LocalStream {
@@ -43,6 +47,11 @@ impl LocalStream {
             Self::Plain(ref mut stream) => {
                 stream.take().unwrap();
             }
+            Self::Compressed(ref mut stream) => {
+                if let Self::Plain(ref mut stream) = stream.get_mut() {
+                    stream.take().unwrap();
+                }
+            }
             _ => unreachable!(),
         }
     }
@@ -72,6 +81,19 @@ impl LocalStream {
 
         Ok(())
     }
+
+    // Wrap the stream in compressed packet layer, it is called after the handshake is complete.
+    pub fn make_compressed(&mut self, compression: Compression) {
+        let stream = std::mem::replace(self, LocalStream::Plain(None));
+        *self = LocalStream::Compressed(Box::new(CompressedStream::new(stream, compression)));
+    }
+
+    // Reset the sequence id of compressed packet when a new command starts.
+    pub fn reset_seq(&mut self) {
+        if let Self::Compressed(stream) = self {
+            stream.reset_seq();
+        }
+    }
 }
 
 impl AsyncRead for LocalStream {
@@ -88,6 +110,8 @@ impl AsyncRead for LocalStream {
             }
 
             LSProj::Secure(ref mut stream) => Pin::new(stream).as_mut().poll_read(cx, buf),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_read(cx, buf),
         }
     }
 }
@@ -106,6 +130,8 @@ impl AsyncWrite for LocalStream {
             }
 
             LSProj::Secure(ref mut stream) => Pin::new(stream).as_mut().poll_write(cx, buf),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_write(cx, buf),
         }
     }
 
@@ -118,6 +144,8 @@ impl AsyncWrite for LocalStream {
             }
 
             LSProj::Secure(ref mut stream) => Pin::new(stream).as_mut().poll_flush(cx),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_flush(cx),
         }
     }
 
@@ -130,6 +158,8 @@ impl AsyncWrite for LocalStream {
             }
 
             LSProj::Secure(ref mut stream) => Pin::new(stream).as_mut().poll_shutdown(cx),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_shutdown(cx),
         }
     }
 }
@@ -156,6 +186,10 @@ impl LocalStream {
             Self::Secure(stream) => {
                 stream.get_ref().get_ref().get_ref()
             }
+
+            Self::Compressed(stream) => {
+                stream.get_ref().get_inner()
+            }
         }
     }
 }
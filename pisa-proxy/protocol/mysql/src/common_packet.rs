@@ -16,7 +16,7 @@ use bytes::{BytesMut, BufMut, Buf};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
-use crate::{util::{BufExt, length_encoded_string, BufMutExt}, mysql_const::{CLIENT_PROTOCOL_41, CLIENT_TRANSACTIONS, CLIENT_SESSION_TRACK, SERVER_SESSION_STATE_CHANGED}};
+use crate::{util::{BufExt, BufMutExt}, mysql_const::{CLIENT_PROTOCOL_41, CLIENT_TRANSACTIONS, CLIENT_SESSION_TRACK, SERVER_SESSION_STATE_CHANGED}};
 
 #[derive(Debug, FromPrimitive)]
 #[repr(u8)]
@@ -41,51 +41,59 @@ pub enum SessionState {
 }
 
 impl SessionState {
+    // Decode one state change from `data`, the remaining changes are left in it.
     pub fn decode(data: &mut BytesMut) -> SessionState {
-        let mut payload = data.split_off(1);
-        let (num, _, _) = payload.get_lenc_int();
+        let typ = data.get_u8();
+        let (num, _, _) = data.get_lenc_int();
 
-        let mut payload = payload.split_to(num as usize);
+        let mut payload = data.split_to((num as usize).min(data.len()));
 
-        match FromPrimitive::from_u8(data[0]) {
+        match FromPrimitive::from_u8(typ) {
             Some(SessionStateType::SystemVariables) => {
                 let mut pairs = Vec::new();
                 while !payload.is_empty() {
-                    let (name, _) = length_encoded_string(&mut payload);
-                    let (value, _) = length_encoded_string(&mut payload);
+                    let name = lenc_bytes(&mut payload);
+                    let value = lenc_bytes(&mut payload);
                     pairs.push((name, value))
                 }
 
                 SessionState::SystemVariables(pairs)
             }
             Some(SessionStateType::Schema) => {
-                let (schema, _) = length_encoded_string(&mut payload);
+                let schema = lenc_bytes(&mut payload);
                 SessionState::Schema(schema)
             }
             Some(SessionStateType::StateChange) => {
-                let (is_tracked, _) = length_encoded_string(&mut payload);
-                SessionState::StateChange(is_tracked == b"1")
+                // The flag is not length encoded.
+                SessionState::StateChange(&payload[..] == b"1")
             }
             Some(SessionStateType::Gtids) => {
-                let (gtids, _) = length_encoded_string(&mut payload);
+                let gtids = lenc_bytes(&mut payload);
                 SessionState::Gtids(gtids)
             }
             Some(SessionStateType::TransactionCharacteristics) => {
-                let (char, _) = length_encoded_string(&mut payload);
+                let char = lenc_bytes(&mut payload);
                 SessionState::TransactionCharacteristics(char)
             }
             Some(SessionStateType::TransactionState) => {
-                let (state, _) = length_encoded_string(&mut payload);
+                let state = lenc_bytes(&mut payload);
                 SessionState::TransactionState(state)
             }
-            None => {
-                data.unsplit(payload);
-                SessionState::Unknown(data.to_vec())
-            }
+            None => SessionState::Unknown(payload.to_vec()),
         }
     }
 }
 
+// Read the length encoded string, the empty string is returned as is.
+fn lenc_bytes(data: &mut BytesMut) -> Vec<u8> {
+    if data.is_empty() {
+        return vec![];
+    }
+
+    let (num, _, _) = data.get_lenc_int();
+    data.split_to((num as usize).min(data.len())).to_vec()
+}
+
 #[derive(Debug)]
 pub struct ResultOkInfo {
     affected_rows: u64,
@@ -93,7 +101,7 @@ pub struct ResultOkInfo {
     status: Option<u16>,
     warnings: Option<u16>,
     info: Option<Vec<u8>>,
-    state_changes: Option<SessionState>,
+    state_changes: Vec<SessionState>,
 }
 
 impl ResultOkInfo {
@@ -104,10 +112,18 @@ impl ResultOkInfo {
             status: None,
             warnings: None,
             info: None,
-            state_changes: None,
+            state_changes: vec![],
         }
     }
 
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    pub fn state_changes(&self) -> &[SessionState] {
+        &self.state_changes
+    }
+
     pub fn decode(capability: u32, data: &mut BytesMut) -> ResultOkInfo {
         let mut ok_info = ResultOkInfo::new();
 
@@ -129,13 +145,18 @@ impl ResultOkInfo {
         }
 
         if capability & CLIENT_SESSION_TRACK > 0 {
-            let (info, _) = length_encoded_string(data);
-            ok_info.info = Some(info);
+            let info = lenc_bytes(data);
+            if !info.is_empty() {
+                ok_info.info = Some(info);
+            }
 
             if let Some(status) = ok_info.status {
-                if status & SERVER_SESSION_STATE_CHANGED > 0 {
-                    let _ = data.get_lenc_int();
-                    ok_info.state_changes = Some(SessionState::decode(data))
+                if status & SERVER_SESSION_STATE_CHANGED > 0 && !data.is_empty() {
+                    let (num, _, _) = data.get_lenc_int();
+                    let mut changes = data.split_to((num as usize).min(data.len()));
+                    while changes.len() > 1 {
+                        ok_info.state_changes.push(SessionState::decode(&mut changes))
+                    }
                 }
             }
         } else {
@@ -146,6 +167,7 @@ impl ResultOkInfo {
         ok_info
     }
 
+    // The state changes are not encoded, so that SERVER_SESSION_STATE_CHANGED is cleared.
     pub fn encode(&self, capability: u32) -> Box<[u8]> {
         let mut data = BytesMut::with_capacity(64);
         data.put_u8(0x00);
@@ -155,7 +177,7 @@ impl ResultOkInfo {
 
         if capability & CLIENT_PROTOCOL_41 > 0 {
             if let Some(status) = &self.status {
-                data.put_u16_le(*status);
+                data.put_u16_le(*status & !SERVER_SESSION_STATE_CHANGED);
             }
 
             if let Some(warnings) = &self.warnings {
@@ -164,25 +186,45 @@ impl ResultOkInfo {
 
         } else if capability & CLIENT_TRANSACTIONS > 0 {
             if let Some(status) = &self.status {
-                data.put_u16_le(*status);
+                data.put_u16_le(*status & !SERVER_SESSION_STATE_CHANGED);
             }
         }
 
         if let Some(info) = &self.info {
-            data.put_lenc_int(info.len() as u64, false);
+            // The info is a string<EOF> when CLIENT_SESSION_TRACK is not enabled.
+            if capability & CLIENT_SESSION_TRACK > 0 {
+                data.put_lenc_int(info.len() as u64, false);
+            }
             data.put_slice(&info);
         }
         data[..].into()
     }
 }
 
+// Decode the state changes of the OK packet which is returned by the conn enabled CLIENT_SESSION_TRACK,
+// the packet is rewritten for the client which doesn't enable it, the sequence id is kept.
+pub fn untrack_ok_packet(packet: &mut BytesMut) -> Vec<SessionState> {
+    let mut payload = packet.split_off(5);
+    let mut ok_info = ResultOkInfo::decode(CLIENT_PROTOCOL_41 | CLIENT_SESSION_TRACK, &mut payload);
+
+    let data = ok_info.encode(CLIENT_PROTOCOL_41);
+    let seq = packet[3];
+    packet.clear();
+    packet.put_uint_le(data.len() as u64, 3);
+    packet.put_u8(seq);
+    packet.put_slice(&data);
+
+    std::mem::take(&mut ok_info.state_changes)
+}
+
 #[cfg(test)]
 mod test {
     use bytes::BytesMut;
 
     use crate::client::conn::ClientConn;
 
-    use super::{ResultOkInfo, SessionState};
+    use super::{untrack_ok_packet, ResultOkInfo, SessionState};
+    use crate::mysql_const::{CLIENT_PROTOCOL_41, CLIENT_SESSION_TRACK};
 
     #[tokio::test]
     async fn test_decode_ok_packet_schema() {
@@ -206,8 +248,8 @@ mod test {
         let auth_info = driver.framed.as_ref().unwrap();
         let info = ResultOkInfo::decode(auth_info.capability, &mut packet);
 
-        if let Some(SessionState::Schema(schema)) = info.state_changes {
-            assert_eq!(b"test"[..], schema)
+        if let Some(SessionState::Schema(schema)) = info.state_changes.first() {
+            assert_eq!(b"test"[..], schema[..])
         }
     }
 
@@ -234,12 +276,31 @@ mod test {
         let auth_info = driver.framed.as_ref().unwrap();
         let info = ResultOkInfo::decode(auth_info.capability, &mut packet);
 
-        if let Some(SessionState::SystemVariables(vars)) = info.state_changes {
+        if let Some(SessionState::SystemVariables(vars)) = info.state_changes.first() {
             assert_eq!(b"autocommit"[..], vars[0].0);
             assert_eq!(b"OFF"[..], vars[0].1);
         }
     }
 
+    #[test]
+    fn test_untrack_ok_packet() {
+        let mut packet = BytesMut::from(
+            &[
+                0x13, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x40, 0x00, 0x00, 0x00, 0x0a, 0x01,
+                0x05, 0x04, 0x74, 0x65, 0x73, 0x74, 0x02, 0x01, 0x31,
+            ][..],
+        );
+
+        let mut payload = BytesMut::from(&packet[5..]);
+        let info = ResultOkInfo::decode(CLIENT_PROTOCOL_41 | CLIENT_SESSION_TRACK, &mut payload);
+        assert_eq!(info.state_changes().len(), 2);
+
+        let changes = untrack_ok_packet(&mut packet);
+        assert_eq!(&packet[..], &[0x07, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00][..]);
+        assert!(matches!(&changes[0], SessionState::Schema(schema) if schema == b"test"));
+        assert!(matches!(changes[1], SessionState::StateChange(true)));
+    }
+
     #[tokio::test]
     async fn test_decode_ok_packet_common() {
         let mut packet =
@@ -256,7 +317,7 @@ mod test {
 
         let auth_info = driver.framed.as_ref().unwrap();
         let info = ResultOkInfo::decode(auth_info.capability, &mut packet);
-        assert_eq!(info.state_changes.is_none(), true);
+        assert_eq!(info.state_changes.is_empty(), true);
     } 
 
      #[tokio::test]
@@ -275,7 +336,7 @@ mod test {
              status: Some(0x0022),
              warnings: Some(0),
              info: Some(b"Records: 256  Duplicates: 0  Warnings: 0".to_vec()),
-             state_changes: None,
+             state_changes: vec![],
 
          };
 
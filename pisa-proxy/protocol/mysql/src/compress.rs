@@ -0,0 +1,389 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io::{self, Read, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::io::poll_read_buf;
+
+use crate::{err::ProtocolError, mysql_const::MAX_PAYLOAD_LEN, util::get_length};
+
+// compressed length 3 bytes, sequence id 1 byte, uncompressed length 3 bytes
+const COMPRESSED_HEADER_LEN: usize = 7;
+
+// Payload shorter than it is sent uncompressed, the same as mysqld.
+const MIN_COMPRESS_LEN: usize = 50;
+
+pub const DEFAULT_ZLIB_LEVEL: i32 = 6;
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Compression algorithm of the compressed protocol with its level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    // CLIENT_COMPRESS
+    Zlib(u32),
+    // CLIENT_ZSTD_COMPRESSION_ALGORITHM
+    Zstd(i32),
+}
+
+impl Compression {
+    pub fn new(algorithm: &str, level: Option<i32>) -> Result<Self, ProtocolError> {
+        match algorithm {
+            "zlib" => {
+                let level = level.unwrap_or(DEFAULT_ZLIB_LEVEL);
+                if !(1..=9).contains(&level) {
+                    return Err(ProtocolError::CompressionConfig(format!(
+                        "invalid zlib compression level {}, expect 1 ~ 9",
+                        level
+                    )));
+                }
+                Ok(Self::Zlib(level as u32))
+            }
+
+            "zstd" => {
+                let level = level.unwrap_or(DEFAULT_ZSTD_LEVEL);
+                if !(1..=22).contains(&level) {
+                    return Err(ProtocolError::CompressionConfig(format!(
+                        "invalid zstd compression level {}, expect 1 ~ 22",
+                        level
+                    )));
+                }
+                Ok(Self::Zstd(level))
+            }
+
+            x => Err(ProtocolError::CompressionConfig(format!(
+                "unsupported compression algorithm {:?}, expect zlib or zstd",
+                x
+            ))),
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Zlib(level) => {
+                let mut encoder = ZlibEncoder::new(
+                    Vec::with_capacity(data.len()),
+                    flate2::Compression::new(*level),
+                );
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+
+            Self::Zstd(level) => zstd::bulk::compress(data, *level),
+        }
+    }
+
+    fn decompress(&self, data: &[u8], length: usize) -> io::Result<Vec<u8>> {
+        let out = match self {
+            Self::Zlib(_) => {
+                let mut out = Vec::with_capacity(length);
+                ZlibDecoder::new(data).take(length as u64 + 1).read_to_end(&mut out)?;
+                out
+            }
+
+            Self::Zstd(_) => zstd::bulk::decompress(data, length)?,
+        };
+
+        if out.len() != length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("decompressed length {} mismatch, expect {}", out.len(), length),
+            ));
+        }
+
+        Ok(out)
+    }
+}
+
+/// Wraps a stream in the compressed packet layer, the codecs above it still read and write plain packets.
+/// One flush of the codec is sent as one compressed packet, unless it is longer than `MAX_PAYLOAD_LEN`.
+#[derive(Debug)]
+pub struct CompressedStream<S> {
+    inner: S,
+    compression: Compression,
+    seq: u8,
+    // Compressed packets read from inner stream
+    read_buf: BytesMut,
+    // Payload waiting to be read by codec
+    decompressed: BytesMut,
+    // Payload waiting to be compressed
+    write_buf: BytesMut,
+    // Compressed packets waiting to be written to inner stream
+    out_buf: BytesMut,
+}
+
+impl<S> CompressedStream<S> {
+    pub fn new(inner: S, compression: Compression) -> Self {
+        Self {
+            inner,
+            compression,
+            seq: 0,
+            read_buf: BytesMut::with_capacity(16384),
+            decompressed: BytesMut::with_capacity(16384),
+            write_buf: BytesMut::with_capacity(16384),
+            out_buf: BytesMut::with_capacity(16384),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    // The sequence id of compressed packet is reset when a new command starts, it is independent of
+    // the sequence id of the packets inside, which wraps in large resultsets.
+    pub fn reset_seq(&mut self) {
+        self.seq = 0;
+    }
+
+    // Decode one compressed packet from `read_buf`, return false when the packet is incomplete.
+    fn decode_packet(&mut self) -> io::Result<bool> {
+        if self.read_buf.len() < COMPRESSED_HEADER_LEN {
+            return Ok(false);
+        }
+
+        let length = get_length(&self.read_buf);
+        if self.read_buf.len() < COMPRESSED_HEADER_LEN + length {
+            return Ok(false);
+        }
+
+        let seq = self.read_buf[3];
+        let uncompressed_length = get_length(&self.read_buf[4..]);
+        self.read_buf.advance(COMPRESSED_HEADER_LEN);
+        let payload = self.read_buf.split_to(length);
+
+        // The reply continues the sequence id of request, the server side is reset by the command of client.
+        self.seq = seq.wrapping_add(1);
+
+        // Uncompressed length 0 means that payload is not compressed.
+        if uncompressed_length == 0 {
+            self.decompressed.extend_from_slice(&payload);
+        } else {
+            let data = self.compression.decompress(&payload, uncompressed_length)?;
+            self.decompressed.extend_from_slice(&data);
+        }
+
+        Ok(true)
+    }
+
+    // Move payload from `write_buf` to `out_buf`, the remaining payload shorter than
+    // `MAX_PAYLOAD_LEN` is kept until `is_flush` is true.
+    fn encode_packets(&mut self, is_flush: bool) -> io::Result<()> {
+        while !self.write_buf.is_empty() && (is_flush || self.write_buf.len() >= MAX_PAYLOAD_LEN) {
+            let length = self.write_buf.len().min(MAX_PAYLOAD_LEN);
+            let payload = self.write_buf.split_to(length);
+
+            let compressed = if length < MIN_COMPRESS_LEN {
+                None
+            } else {
+                Some(self.compression.compress(&payload)?)
+            };
+
+            match compressed {
+                Some(data) if data.len() < length => {
+                    self.put_header(data.len(), length);
+                    self.out_buf.extend_from_slice(&data);
+                }
+                _ => {
+                    self.put_header(length, 0);
+                    self.out_buf.extend_from_slice(&payload);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn put_header(&mut self, length: usize, uncompressed_length: usize) {
+        self.out_buf.put_uint_le(length as u64, 3);
+        self.out_buf.put_u8(self.seq);
+        self.out_buf.put_uint_le(uncompressed_length as u64, 3);
+        self.seq = self.seq.wrapping_add(1);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CompressedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        loop {
+            if !this.decompressed.is_empty() {
+                let length = buf.remaining().min(this.decompressed.len());
+                buf.put_slice(&this.decompressed.split_to(length));
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.decode_packet()? {
+                continue;
+            }
+
+            this.read_buf.reserve(COMPRESSED_HEADER_LEN);
+            if ready!(poll_read_buf(Pin::new(&mut this.inner), cx, &mut this.read_buf))? == 0 {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CompressedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.write_buf.extend_from_slice(buf);
+        self.encode_packets(false)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        this.encode_packets(true)?;
+
+        while !this.out_buf.is_empty() {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &this.out_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.out_buf.advance(n);
+        }
+
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn make_packet(seq: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(payload.len() + 4);
+        data.put_uint_le(payload.len() as u64, 3);
+        data.put_u8(seq);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    async fn round_trip(compression: Compression) {
+        let (client, server) = duplex(1 << 20);
+        let mut client = CompressedStream::new(client, compression);
+        let mut server = CompressedStream::new(server, compression);
+
+        // Short payload is not compressed
+        let query = make_packet(0, b"\x03select 1");
+        client.write_all(&query).await.unwrap();
+        client.flush().await.unwrap();
+
+        let mut data = vec![0; query.len()];
+        server.read_exact(&mut data).await.unwrap();
+        assert_eq!(data, query);
+        assert_eq!(server.seq, 1);
+
+        let mut rows = make_packet(1, &[b'a'; 1000]);
+        rows.extend_from_slice(&make_packet(2, &[b'b'; 1000]));
+        server.write_all(&rows).await.unwrap();
+        server.flush().await.unwrap();
+        assert_eq!(server.seq, 2);
+
+        let mut data = vec![0; rows.len()];
+        client.read_exact(&mut data).await.unwrap();
+        assert_eq!(data, rows);
+        assert_eq!(client.seq, 2);
+
+        // The inner sequence id wraps, it doesn't reset the compressed one
+        server.write_all(&make_packet(0, &[b'd'; 10])).await.unwrap();
+        server.flush().await.unwrap();
+        assert_eq!(server.seq, 3);
+        let mut data = vec![0; 14];
+        client.read_exact(&mut data).await.unwrap();
+        assert_eq!(client.seq, 3);
+
+        // New command resets the sequence id
+        client.reset_seq();
+        let query = make_packet(0, &[b'c'; 100]);
+        client.write_all(&query).await.unwrap();
+        client.flush().await.unwrap();
+
+        let mut data = vec![0; query.len()];
+        server.read_exact(&mut data).await.unwrap();
+        assert_eq!(data, query);
+        assert_eq!(server.seq, 1);
+    }
+
+    #[tokio::test]
+    async fn test_zlib_round_trip() {
+        round_trip(Compression::Zlib(6)).await;
+    }
+
+    #[tokio::test]
+    async fn test_zstd_round_trip() {
+        round_trip(Compression::Zstd(3)).await;
+    }
+
+    #[tokio::test]
+    async fn test_compressed_packet() {
+        let (client, mut server) = duplex(1 << 20);
+        let mut client = CompressedStream::new(client, Compression::Zlib(6));
+        client.seq = 5;
+        client.reset_seq();
+
+        let query = make_packet(0, &[b'a'; 1000]);
+        client.write_all(&query).await.unwrap();
+        client.flush().await.unwrap();
+
+        let mut header = [0; COMPRESSED_HEADER_LEN];
+        server.read_exact(&mut header).await.unwrap();
+        let length = get_length(&header);
+        assert!(length < query.len());
+        assert_eq!(header[3], 0);
+        assert_eq!(get_length(&header[4..]), query.len());
+
+        let mut payload = vec![0; length];
+        server.read_exact(&mut payload).await.unwrap();
+        assert_eq!(Compression::Zlib(6).decompress(&payload, query.len()).unwrap(), query);
+    }
+
+    #[test]
+    fn test_compression_config() {
+        assert_eq!(Compression::new("zlib", None).unwrap(), Compression::Zlib(6));
+        assert_eq!(Compression::new("zstd", Some(10)).unwrap(), Compression::Zstd(10));
+        assert!(Compression::new("zstd", Some(23)).is_err());
+        assert!(Compression::new("lz4", None).is_err());
+    }
+}
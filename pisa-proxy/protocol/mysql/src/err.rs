@@ -35,6 +35,18 @@ pub enum ProtocolError {
     #[error("make tls error {0:?}")]
     MakeTls(#[from] native_tls::Error),
 
+    #[error("ssl error {0:?}")]
+    Ssl(#[from] openssl::ssl::Error),
+
+    #[error("ssl error stack {0:?}")]
+    SslErrorStack(#[from] openssl::error::ErrorStack),
+
+    #[error("tls config error {0}")]
+    TlsConfig(String),
+
+    #[error("compression config error {0}")]
+    CompressionConfig(String),
+
     #[error("method: {:?} invalid packet {:?}", .method, .data)]
     InvalidPacket { method: String, data: Vec<u8> },
 
@@ -15,6 +15,7 @@
 pub mod charset;
 pub mod client;
 pub mod column;
+pub mod compress;
 pub mod err;
 mod macros;
 pub mod mysql_const;
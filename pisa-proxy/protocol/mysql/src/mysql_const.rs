@@ -39,6 +39,12 @@ pub const SERVER_QUERY_WAS_SLOW: u16 = 0x0800;
 pub const SERVER_PS_OUT_PARAMS: u16 = 0x1000;
 pub const SERVER_SESSION_STATE_CHANGED: u16 = 0x4000;
 
+// Flags of COM_STMT_EXECUTE
+pub const CURSOR_TYPE_NO_CURSOR: u8 = 0x00;
+pub const CURSOR_TYPE_READ_ONLY: u8 = 0x01;
+pub const CURSOR_TYPE_FOR_UPDATE: u8 = 0x02;
+pub const CURSOR_TYPE_SCROLLABLE: u8 = 0x04;
+
 //TODO: change to enum
 pub const AUTH_MYSQL_OLD_PASSWORD: &str = "mysql_old_password";
 pub const AUTH_CACHING_SHA2_PASSWORD: &str = "caching_sha2_password";
@@ -190,6 +196,9 @@ iota! {
          ,CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA
          ,CLIENT_CAN_HANDLE_EXPIRED_PASSWORDS
          ,CLIENT_SESSION_TRACK
+         ,CLIENT_DEPRECATE_EOF
+         ,CLIENT_OPTIONAL_RESULTSET_METADATA
+         ,CLIENT_ZSTD_COMPRESSION_ALGORITHM
 }
 
 #[allow(non_camel_case_types)]
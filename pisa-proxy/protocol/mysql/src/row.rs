@@ -18,7 +18,7 @@ use crate::{
     column::ColumnInfo,
     err::DecodeRowError,
     mysql_const::*,
-    util::{length_encode_int, BufExt},
+    util::{length_encode_int, BufExt, BufMutExt},
     value::{self, Value},
 };
 
@@ -254,13 +254,26 @@ impl<T: AsRef<[u8]>> RowData<T> for RowDataBinary<T> {
     }
 }
 
+/// Encode a row of the text protocol, `None` is encoded as NULL.
+pub fn encode_text_row<B: BufMutExt, V: AsRef<[u8]>>(buf: &mut B, values: &[Option<V>]) {
+    for value in values {
+        match value {
+            Some(value) => {
+                buf.put_lenc_int(value.as_ref().len() as u64, true);
+                buf.put_slice(value.as_ref());
+            }
+            None => buf.put_u8(0xfb),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
 
     use chrono::{naive::NaiveDateTime, Duration, NaiveDate, NaiveTime};
 
-    use super::RowDataBinary;
+    use super::{encode_text_row, RowDataBinary};
     use crate::{
         column::{Column, ColumnInfo},
         row::{RowData, RowDataText},
@@ -374,6 +387,22 @@ mod test {
         assert_eq!(res.unwrap(), None)
     }
 
+    #[test]
+    fn test_encode_text_row() {
+        let mut column_buf = &get_test_column_data()[5..];
+        let columns = column_buf.decode_columns();
+
+        let mut row_buf = vec![];
+        let values = [Some("1"), Some("root"), Some("127.0.0.1"), None];
+        encode_text_row(&mut row_buf, &values);
+
+        let mut row = RowDataText::new(columns.into_boxed_slice().into(), &row_buf[..]);
+        assert_eq!(row.decode_with_name::<String>("Id").unwrap().unwrap(), "1");
+        assert_eq!(row.decode_with_name::<String>("User").unwrap().unwrap(), "root");
+        assert_eq!(row.decode_with_name::<String>("Host").unwrap().unwrap(), "127.0.0.1");
+        assert_eq!(row.decode_with_name::<String>("db").unwrap(), None);
+    }
+
     #[test]
     fn test_decode_binary_row() {
         let mut column_buf = &get_test_column_data()[5..];
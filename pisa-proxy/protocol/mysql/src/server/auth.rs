@@ -13,22 +13,36 @@
 // limitations under the License.
 
 use std::{
+    net::IpAddr,
     str,
-    sync::atomic::{AtomicU32, Ordering},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
 
 use bytes::{Buf, BufMut, BytesMut};
 use futures::{SinkExt, StreamExt};
+use openssl::{
+    pkey::Private,
+    rsa::{Padding, Rsa},
+};
 use tokio_util::codec::{Decoder, Encoder, Framed};
 use tracing::debug;
 
-use super::{err::MySQLError, stream::LocalStream};
+use super::{
+    err::MySQLError,
+    stream::LocalStream,
+    tls::ServerTlsAcceptor,
+    users::{AuthSecret, AuthUser, AuthUsers},
+};
 use crate::{
     charset::{COLLATION_NAME_ID_MYSQL5, DEFAULT_CHARSET_NAME},
+    compress::{Compression, DEFAULT_ZLIB_LEVEL, DEFAULT_ZSTD_LEVEL},
     err::ProtocolError,
     mysql_const::*,
     server::codec::{make_err_packet, ok_packet},
-    session::{Session, SessionMut},
+    session::{Session, SessionMut, SessionVars},
     util::*,
 };
 
@@ -41,6 +55,7 @@ const DEFAULT_CAPABILITY: u32 = CLIENT_LONG_PASSWORD
     | CLIENT_SSL
     | CLIENT_FOUND_ROWS
     | CLIENT_MULTI_STATEMENTS
+    | CLIENT_MULTI_RESULTS
     | CLIENT_PS_MULTI_RESULTS
     | CLIENT_LOCAL_FILES
     | CLIENT_CONNECT_ATTRS
@@ -50,6 +65,9 @@ const DEFAULT_CAPABILITY: u32 = CLIENT_LONG_PASSWORD
 
 lazy_static! {
     static ref CONNECTION_ID: AtomicU32 = AtomicU32::new(0);
+    // RSA key pair of `caching_sha2_password` full authentication over insecure connections.
+    static ref RSA_PRIVATE_KEY: Rsa<Private> = Rsa::generate(2048).unwrap();
+    static ref RSA_PUBLIC_KEY_PEM: Vec<u8> = RSA_PRIVATE_KEY.public_key_to_pem().unwrap();
 }
 
 #[derive(Debug, PartialEq)]
@@ -60,6 +78,10 @@ pub enum ServerHandshakeStatus {
     SwitchToTLS,
     WriteAutoSwitch,
     ReadAutoSwitchResponse,
+    WriteFullAuth,
+    ReadFullAuthResponse,
+    WritePublicKey,
+    ReadEncryptedPassword,
     CompareAuthData,
     Complete,
 }
@@ -74,15 +96,49 @@ pub struct ServerHandshakeCodec {
     status: u16,
     user: String,
     db: String,
-    password: String,
+    users: Arc<AuthUsers>,
+    client_addr: Option<IpAddr>,
+    auth_user: Option<AuthUser>,
     auth_data: BytesMut,
     auth_plugin_name: String,
     autocommit: Option<String>,
+    vars: SessionVars,
     next_handshake_status: ServerHandshakeStatus,
+    tls_acceptor: ServerTlsAcceptor,
+    require_secure_transport: bool,
+    is_secure: bool,
+    default_auth_plugin_name: &'static str,
+    is_full_auth: bool,
+    allow_compression: bool,
+    compression: Option<Compression>,
+    allow_local_infile: bool,
+    // The client must use the configured db when there is no user table.
+    is_fixed_db: bool,
+    // The pending COM_CHANGE_USER which is waiting for the auth switch response.
+    change_user: Option<ChangeUser>,
+}
+
+struct ChangeUser {
+    user: String,
+    db: String,
+    auth_user: AuthUser,
+    auth_plugin_name: String,
 }
 
 impl ServerHandshakeCodec {
     pub fn new(user: String, password: String, db: String, server_version: String) -> Self {
+        let auth_user = AuthUser::new(user, AuthSecret::Password(password));
+        Self::with_users(Arc::new(AuthUsers::new(vec![auth_user])), db, server_version, None)
+            .with_fixed_db(true)
+    }
+
+    // Build codec with a user table, `client_addr` is checked against the allowed hosts of each user.
+    pub fn with_users(
+        users: Arc<AuthUsers>,
+        db: String,
+        server_version: String,
+        client_addr: Option<IpAddr>,
+    ) -> Self {
         CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
 
         Self {
@@ -93,14 +149,75 @@ impl ServerHandshakeCodec {
             charset: DEFAULT_CHARSET_NAME.to_string(),
             salt: random_buf(20),
             status: SERVER_STATUS_AUTOCOMMIT,
-            user,
+            user: "".to_string(),
             db,
-            password,
+            users,
+            client_addr,
+            auth_user: None,
             auth_data: BytesMut::with_capacity(20),
             auth_plugin_name: "".to_string(),
             autocommit: None,
+            vars: SessionVars::new(),
             next_handshake_status: ServerHandshakeStatus::ReadResponseFirst,
+            tls_acceptor: ServerTlsAcceptor::default(),
+            require_secure_transport: false,
+            is_secure: false,
+            default_auth_plugin_name: AUTH_NATIVE_PASSWORD,
+            is_full_auth: false,
+            allow_compression: false,
+            compression: None,
+            allow_local_infile: true,
+            is_fixed_db: false,
+            change_user: None,
+        }
+    }
+
+    // Use `tls_acceptor` for SSL request, plaintext handshake is rejected when `require_secure_transport` is true.
+    pub fn with_tls(mut self, tls_acceptor: ServerTlsAcceptor, require_secure_transport: bool) -> Self {
+        self.tls_acceptor = tls_acceptor;
+        self.require_secure_transport = require_secure_transport;
+        self
+    }
+
+    // Auth plugin advertised in the initial handshake, either `mysql_native_password` or `caching_sha2_password`.
+    pub fn with_auth_plugin(mut self, auth_plugin_name: &'static str) -> Self {
+        self.default_auth_plugin_name = auth_plugin_name;
+        self
+    }
+
+    // Advertise CLIENT_COMPRESS and CLIENT_ZSTD_COMPRESSION_ALGORITHM, the client decides whether to use them.
+    pub fn with_compression(mut self, allow_compression: bool) -> Self {
+        self.allow_compression = allow_compression;
+        self
+    }
+
+    // Advertise CLIENT_LOCAL_FILES, LOAD DATA LOCAL INFILE is rejected by the proxy when it is false.
+    pub fn with_local_infile(mut self, allow_local_infile: bool) -> Self {
+        self.allow_local_infile = allow_local_infile;
+        self
+    }
+
+    pub fn with_fixed_db(mut self, is_fixed_db: bool) -> Self {
+        self.is_fixed_db = is_fixed_db;
+        self
+    }
+
+    fn server_capability(&self) -> u32 {
+        let mut capability = if self.allow_compression {
+            DEFAULT_CAPABILITY | CLIENT_COMPRESS | CLIENT_ZSTD_COMPRESSION_ALGORITHM
+        } else {
+            DEFAULT_CAPABILITY
+        };
+
+        if !self.allow_local_infile {
+            capability &= !CLIENT_LOCAL_FILES
         }
+        capability
+    }
+
+    // The compression negotiated with client, `None` means uncompressed.
+    pub fn get_compression(&self) -> Option<Compression> {
+        self.compression
     }
 
     fn encode_initial_handshake(&self) -> BytesMut {
@@ -127,8 +244,9 @@ impl ServerHandshakeCodec {
         data.put_u8(0);
 
         // capability flag lower 2 bytes, using default capability here
-        data.put_u8(DEFAULT_CAPABILITY as u8);
-        data.put_u8((DEFAULT_CAPABILITY >> 8) as u8);
+        let capability = self.server_capability();
+        data.put_u8(capability as u8);
+        data.put_u8((capability >> 8) as u8);
 
         //charset, utf-8 default
         data.put_u8(COLLATION_NAME_ID_MYSQL5[&*self.charset]);
@@ -139,8 +257,8 @@ impl ServerHandshakeCodec {
 
         //below 13 byte may not be used
         //capability flag upper 2 bytes, using default capability here
-        data.put_u8((DEFAULT_CAPABILITY >> 16) as u8);
-        data.put_u8((DEFAULT_CAPABILITY >> 24) as u8);
+        data.put_u8((capability >> 16) as u8);
+        data.put_u8((capability >> 24) as u8);
 
         // fiter [0x15], for wireshark dump, value is 0x15
         // data.push(0x15);
@@ -157,7 +275,7 @@ impl ServerHandshakeCodec {
         //filter [00]
         data.put_u8(0);
 
-        data.extend_from_slice(AUTH_NATIVE_PASSWORD.as_bytes());
+        data.extend_from_slice(self.default_auth_plugin_name.as_bytes());
         data.put_u8(0);
 
         data
@@ -168,12 +286,13 @@ impl ServerHandshakeCodec {
     fn decode_handshake_response(&mut self, data: &mut BytesMut) -> Result<(), ProtocolError> {
         let idx = data.iter().position(|&x| x == 0).unwrap();
         let user = str::from_utf8(&data.split_to(idx)).unwrap().to_string();
-        debug!("user: {}, self.user: {}", user, self.user);
+        debug!("user: {}, client_addr: {:?}", user, self.client_addr);
 
-        if user != self.user {
-            self.user = user;
-            return Err(ProtocolError::AuthFailed(self.make_auth_err_info()));
-        }
+        self.user = user;
+        let auth_user = match self.users.find(&self.user, self.client_addr) {
+            Some(auth_user) => auth_user.clone(),
+            None => return Err(ProtocolError::AuthFailed(self.make_auth_err_info())),
+        };
 
         let _ = data.get_u8();
 
@@ -196,10 +315,15 @@ impl ServerHandshakeCodec {
         if self.capability & CLIENT_CONNECT_WITH_DB > 0 && !data.is_empty() {
             let idx = data.iter().position(|&x| x == 0).unwrap();
             let db = str::from_utf8(&data.split_to(idx)).unwrap().to_string();
-            if self.db != db {
+            if self.is_fixed_db && self.db != db {
                 self.db = db;
                 return Err(ProtocolError::AuthFailed(self.make_schema_err_info()));
             }
+            if !auth_user.is_db_allowed(&db) {
+                self.db = db;
+                return Err(ProtocolError::AuthFailed(self.make_db_access_err_info()));
+            }
+            self.db = db;
 
             // Eat db \0
             let _ = data.get_u8();
@@ -214,11 +338,28 @@ impl ServerHandshakeCodec {
         }
 
         // Currently, we don't use CLIENT_CONNECT_ATTRS
+        if self.capability & CLIENT_CONNECT_ATTRS > 0 && !data.is_empty() {
+            data.try_skip_lenc_length()?;
+        }
+
+        let capability = self.capability & self.server_capability();
+        if capability & CLIENT_ZSTD_COMPRESSION_ALGORITHM > 0 {
+            let level = if data.is_empty() { DEFAULT_ZSTD_LEVEL } else { data.get_u8() as i32 };
+            self.compression = Some(Compression::Zstd(level));
+        } else if capability & CLIENT_COMPRESS > 0 {
+            self.compression = Some(Compression::Zlib(DEFAULT_ZLIB_LEVEL as u32));
+        }
+
         data.clear();
 
-        if &self.auth_plugin_name != AUTH_NATIVE_PASSWORD {
+        // Switch to the auth plugin which is able to verify the secret of user.
+        let is_plugin_supported = auth_user.is_plugin_supported(&self.auth_plugin_name);
+        let auth_plugin_name = auth_user.auth_plugin_name();
+        self.auth_user = Some(auth_user);
+
+        if !is_plugin_supported {
             debug!("auth_plugin_name: {}", self.auth_plugin_name);
-            self.auth_plugin_name = AUTH_NATIVE_PASSWORD.to_string();
+            self.auth_plugin_name = auth_plugin_name.to_string();
             self.next_handshake_status = ServerHandshakeStatus::WriteAutoSwitch;
             return Ok(());
         }
@@ -257,32 +398,289 @@ impl ServerHandshakeCodec {
     }
 
     fn compare_auth_data(&mut self) -> Result<(), ProtocolError> {
+        let auth_data = self.auth_data.split();
+        self.verify_auth_data(&auth_data)
+    }
+
+    fn handle_auth_switch_response(&mut self, data: &mut BytesMut) -> Result<(), ProtocolError> {
+        debug!("auth switch response data: {:?}", data);
+        self.verify_auth_data(&data[..])
+    }
+
+    fn verify_auth_data(&mut self, auth_data: &[u8]) -> Result<(), ProtocolError> {
         self.next_handshake_status = ServerHandshakeStatus::Complete;
 
-        match self.auth_plugin_name.as_str() {
-            AUTH_NATIVE_PASSWORD => {
-                if !compare(&self.auth_data, &calc_password(&self.salt, self.password.as_bytes())) {
-                    return Err(ProtocolError::AuthFailed(self.make_auth_err_info()));
-                }
-                Ok(())
+        let is_match = match (&self.auth_user, self.auth_plugin_name.as_str()) {
+            (Some(auth_user), AUTH_NATIVE_PASSWORD) => {
+                auth_user.verify_native_password(&self.salt, auth_data)
             }
-            _ => Err(ProtocolError::AuthFailed(self.make_auth_plugin_err_info())),
+            (Some(auth_user), AUTH_CACHING_SHA2_PASSWORD) => {
+                auth_user.verify_caching_sha2_password(&self.salt, auth_data)
+            }
+            (None, _) => false,
+            _ => return Err(ProtocolError::AuthFailed(self.make_auth_plugin_err_info())),
+        };
+
+        // Either the scramble is wrong or the password is not cached, move to full authentication
+        // like mysqld does, the password is verified again there.
+        if !is_match && self.auth_plugin_name == AUTH_CACHING_SHA2_PASSWORD && !self.is_full_auth {
+            self.is_full_auth = true;
+            self.next_handshake_status = ServerHandshakeStatus::WriteFullAuth;
+            return Ok(());
         }
+
+        if !is_match {
+            return Err(ProtocolError::AuthFailed(self.make_auth_err_info()));
+        }
+
+        Ok(())
     }
 
-    fn handle_auth_switch_response(&mut self, data: &mut BytesMut) -> Result<(), ProtocolError> {
+    // The client sends the clear text password over tls, otherwise it requests the public key at first,
+    // or sends the encrypted password directly when it has the public key already.
+    fn handle_full_auth_response(&mut self, data: &mut BytesMut) -> Result<(), ProtocolError> {
+        if self.is_secure {
+            let password = data.split();
+            return self.verify_plain_password(&password);
+        }
+
+        if data.len() == 1 && data[0] as i64 == CACHING_SHA2_PASSWORD_REQUEST_PUBLIC_KEY {
+            data.clear();
+            self.next_handshake_status = ServerHandshakeStatus::WritePublicKey;
+            return Ok(());
+        }
+
+        if data.len() == RSA_PRIVATE_KEY.size() as usize {
+            return self.handle_encrypted_password(data);
+        }
+
         self.next_handshake_status = ServerHandshakeStatus::Complete;
+        Err(ProtocolError::AuthFailed(self.make_auth_secure_err_info()))
+    }
 
-        match self.auth_plugin_name.as_str() {
-            AUTH_NATIVE_PASSWORD => {
-                let passwd_salt = calc_password(&self.salt, self.password.as_bytes());
-                debug!("passwd_salt: {:?}, data: {:?}", passwd_salt, data);
-                if !compare(&passwd_salt, &data[..]) {
+    // The password is xor-ed with salt, then encrypted with the public key by RSA_PKCS1_OAEP_PADDING.
+    fn handle_encrypted_password(&mut self, data: &mut BytesMut) -> Result<(), ProtocolError> {
+        let encrypted = data.split();
+        let mut password = vec![0; RSA_PRIVATE_KEY.size() as usize];
+        let length =
+            match RSA_PRIVATE_KEY.private_decrypt(&encrypted, &mut password, Padding::PKCS1_OAEP) {
+                Ok(length) => length,
+                Err(e) => {
+                    debug!("decrypt password error: {:?}", e);
+                    self.next_handshake_status = ServerHandshakeStatus::Complete;
                     return Err(ProtocolError::AuthFailed(self.make_auth_err_info()));
                 }
-                Ok(())
+            };
+
+        password.truncate(length);
+        for (i, x) in password.iter_mut().enumerate() {
+            *x ^= self.salt[i % self.salt.len()];
+        }
+
+        self.verify_plain_password(&password)
+    }
+
+    fn verify_plain_password(&mut self, password: &[u8]) -> Result<(), ProtocolError> {
+        self.next_handshake_status = ServerHandshakeStatus::Complete;
+
+        // Eat the terminated 0x00
+        let password = password.strip_suffix(&[0]).unwrap_or(password);
+        let is_match = match &self.auth_user {
+            Some(auth_user) if auth_user.verify_plain_password(password) => {
+                auth_user.cache_sha2_password(password);
+                true
+            }
+            _ => false,
+        };
+
+        if !is_match {
+            return Err(ProtocolError::AuthFailed(self.make_auth_err_info()));
+        }
+
+        Ok(())
+    }
+
+    fn make_full_auth_request(&self) -> BytesMut {
+        let mut dst = BytesMut::with_capacity(6);
+        dst.extend_from_slice(&[0; 4]);
+        dst.put_u8(MORE_DATA_HEADER);
+        dst.put_u8(CACHE_SHA2_FULL_AUTH);
+
+        dst
+    }
+
+    fn make_public_key_response(&self) -> BytesMut {
+        let mut dst = BytesMut::with_capacity(5 + RSA_PUBLIC_KEY_PEM.len());
+        dst.extend_from_slice(&[0; 4]);
+        dst.put_u8(MORE_DATA_HEADER);
+        dst.extend_from_slice(&RSA_PUBLIC_KEY_PEM);
+
+        dst
+    }
+
+    fn make_fast_auth_success(&self) -> BytesMut {
+        let mut dst = BytesMut::with_capacity(6);
+        dst.extend_from_slice(&[0; 4]);
+        dst.put_u8(MORE_DATA_HEADER);
+        dst.put_u8(CACHE_SHA2_FAST_AUTH);
+
+        dst
+    }
+
+    pub fn get_user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn get_connection_id(&self) -> u32 {
+        self.connection_id
+    }
+
+    // Handle COM_SET_OPTION, toggle CLIENT_MULTI_STATEMENTS of the session.
+    pub fn set_multi_statements(&mut self, on: bool) {
+        if on {
+            self.capability |= CLIENT_MULTI_STATEMENTS
+        } else {
+            self.capability &= !CLIENT_MULTI_STATEMENTS
+        }
+    }
+
+    pub fn is_multi_statements(&self) -> bool {
+        self.capability & CLIENT_MULTI_STATEMENTS > 0
+    }
+
+    // Whether the client accepts the LOCAL INFILE request, CLIENT_LOCAL_FILES is negotiated in handshake.
+    pub fn is_local_files(&self) -> bool {
+        self.allow_local_infile && self.capability & CLIENT_LOCAL_FILES > 0
+    }
+
+    // Handle COM_RESET_CONNECTION, session variables go back to the values of the handshake.
+    pub fn reset_session(&mut self) {
+        self.charset = DEFAULT_CHARSET_NAME.to_string();
+        self.autocommit = None;
+        self.vars.clear();
+    }
+
+    // Handle COM_CHANGE_USER, returns the auth switch request with a fresh scramble, the response of
+    // the client is verified by `change_user_auth`. The session is left untouched until it succeeds.
+    pub fn change_user(&mut self, payload: &[u8]) -> Result<BytesMut, ProtocolError> {
+        let invalid_packet =
+            || ProtocolError::InvalidPacket { method: "change_user".to_string(), data: payload.to_vec() };
+        let mut data = BytesMut::from(payload);
+
+        let idx = data.iter().position(|&x| x == 0).ok_or_else(invalid_packet)?;
+        let user = str::from_utf8(&data.split_to(idx)).map_err(|_| invalid_packet())?.to_string();
+        let _ = data.get_u8();
+
+        // The auth response is scrambled with the salt of the initial handshake, it is not verified.
+        if self.capability & CLIENT_SECURE_CONNECTION > 0 {
+            let n = if data.is_empty() { 0 } else { data.get_u8() as usize };
+            if data.len() < n {
+                return Err(invalid_packet());
+            }
+            data.advance(n);
+        } else {
+            let idx = data.iter().position(|&x| x == 0).ok_or_else(invalid_packet)?;
+            data.advance(idx + 1);
+        }
+
+        let mut db = String::new();
+        if !data.is_empty() {
+            let idx = data.iter().position(|&x| x == 0).ok_or_else(invalid_packet)?;
+            db = str::from_utf8(&data.split_to(idx)).map_err(|_| invalid_packet())?.to_string();
+            let _ = data.get_u8();
+        }
+
+        // Skip charset, use `set names` to change it.
+        if data.len() >= 2 {
+            let _ = data.get_u16_le();
+        }
+
+        let mut auth_plugin_name = AUTH_NATIVE_PASSWORD.to_string();
+        if self.capability & CLIENT_PLUGIN_AUTH > 0 && !data.is_empty() {
+            let idx = data.iter().position(|&x| x == 0).unwrap_or(data.len());
+            auth_plugin_name = str::from_utf8(&data.split_to(idx)).map_err(|_| invalid_packet())?.to_string();
+        }
+
+        let auth_user = match self.users.find(&user, self.client_addr) {
+            Some(auth_user) => auth_user.clone(),
+            None => return Err(ProtocolError::AuthFailed(make_access_denied_err(&user))),
+        };
+
+        // The plugin of the client is kept, it is not switched to the default plugin of the user.
+        if !auth_user.is_plugin_supported(&auth_plugin_name)
+            || !matches!(auth_plugin_name.as_str(), AUTH_NATIVE_PASSWORD | AUTH_CACHING_SHA2_PASSWORD)
+        {
+            return Err(ProtocolError::AuthFailed(make_access_denied_err(&user)));
+        }
+
+        self.salt = random_buf(20);
+        let mut dst = BytesMut::with_capacity(128);
+        dst.extend_from_slice(&[0; 4]);
+        dst.put_u8(EOF_HEADER);
+        dst.extend_from_slice(auth_plugin_name.as_bytes());
+        dst.put_u8(0);
+        dst.extend_from_slice(&self.salt);
+        dst.put_u8(0);
+
+        self.change_user = Some(ChangeUser { user, db, auth_user, auth_plugin_name });
+        Ok(dst)
+    }
+
+    // Verify the auth switch response of COM_CHANGE_USER, returns the packet which must be sent
+    // before OK packet if any.
+    pub fn change_user_auth(&mut self, auth_data: &[u8]) -> Result<Option<BytesMut>, ProtocolError> {
+        let ChangeUser { user, db, auth_user, auth_plugin_name } = match self.change_user.take() {
+            Some(x) => x,
+            None => {
+                return Err(ProtocolError::InvalidPacket {
+                    method: "change_user_auth".to_string(),
+                    data: auth_data.to_vec(),
+                })
             }
-            _ => Err(ProtocolError::AuthFailed(self.make_auth_plugin_err_info())),
+        };
+
+        let is_match = match auth_plugin_name.as_str() {
+            AUTH_NATIVE_PASSWORD => auth_user.verify_native_password(&self.salt, auth_data),
+            AUTH_CACHING_SHA2_PASSWORD => auth_user.verify_caching_sha2_password(&self.salt, auth_data),
+            _ => false,
+        };
+        if !is_match {
+            return Err(ProtocolError::AuthFailed(make_access_denied_err(&user)));
+        }
+
+        if !db.is_empty() && !auth_user.is_db_allowed(&db) {
+            return Err(ProtocolError::AuthFailed(make_err_packet(MySQLError::new(
+                1044,
+                "42000".as_bytes().to_vec(),
+                format!("Access denied for user {:?} to database {:?}", user, db),
+            ))));
+        }
+
+        self.user = user;
+        self.db = db;
+        self.auth_user = Some(auth_user);
+        self.reset_session();
+
+        self.auth_plugin_name = auth_plugin_name;
+        if self.auth_plugin_name == AUTH_CACHING_SHA2_PASSWORD {
+            return Ok(Some(self.make_fast_auth_success()));
+        }
+        Ok(None)
+    }
+
+    // Whether the databases of the authenticated user are limited by `allowed_dbs`.
+    pub fn is_db_restricted(&self) -> bool {
+        match &self.auth_user {
+            Some(auth_user) => !auth_user.is_db_allowed("*"),
+            None => true,
+        }
+    }
+
+    // Check whether the authenticated user is allowed to access `db`.
+    pub fn is_db_allowed(&self, db: &str) -> bool {
+        match &self.auth_user {
+            Some(auth_user) => auth_user.is_db_allowed(db),
+            None => false,
         }
     }
 
@@ -302,6 +700,14 @@ impl ServerHandshakeCodec {
         ))
     }
 
+    fn make_db_access_err_info(&mut self) -> Vec<u8> {
+        make_err_packet(MySQLError::new(
+            1044,
+            "42000".as_bytes().to_vec(),
+            format!("Access denied for user {:?} to database {:?}", self.user, self.db),
+        ))
+    }
+
     fn make_auth_plugin_err_info(&mut self) -> Vec<u8> {
         make_err_packet(MySQLError::new(
             1045,
@@ -310,6 +716,26 @@ impl ServerHandshakeCodec {
         ))
     }
 
+    fn make_insecure_transport_err_info(&mut self) -> Vec<u8> {
+        make_err_packet(MySQLError::new(
+            3159,
+            "HY000".as_bytes().to_vec(),
+            "Connections using insecure transport are prohibited while --require_secure_transport=ON."
+                .to_string(),
+        ))
+    }
+
+    fn make_auth_secure_err_info(&mut self) -> Vec<u8> {
+        make_err_packet(MySQLError::new(
+            2061,
+            "HY000".as_bytes().to_vec(),
+            format!(
+                "Authentication plugin {:?} reported error: Authentication requires secure connection.",
+                self.auth_plugin_name
+            ),
+        ))
+    }
+
     fn make_auth_lenc_err_info(&mut self) -> Vec<u8> {
         make_err_packet(MySQLError::new(
             1045,
@@ -319,6 +745,14 @@ impl ServerHandshakeCodec {
     }
 }
 
+fn make_access_denied_err(user: &str) -> Vec<u8> {
+    make_err_packet(MySQLError::new(
+        1045,
+        "28000".as_bytes().to_vec(),
+        format!("Access denied for user {:?}, (using password: Yes)", user),
+    ))
+}
+
 impl Decoder for ServerHandshakeCodec {
     type Item = ();
     type Error = ProtocolError;
@@ -343,6 +777,8 @@ impl Decoder for ServerHandshakeCodec {
 
                 if is_empty {
                     self.next_handshake_status = ServerHandshakeStatus::SwitchToTLS;
+                } else if self.require_secure_transport {
+                    return Err(ProtocolError::AuthFailed(self.make_insecure_transport_err_info()));
                 } else {
                     self.decode_handshake_response(src)?;
                     if self.next_handshake_status == ServerHandshakeStatus::CompareAuthData {
@@ -368,6 +804,16 @@ impl Decoder for ServerHandshakeCodec {
                 Ok(Some(()))
             }
 
+            ServerHandshakeStatus::ReadFullAuthResponse => {
+                self.handle_full_auth_response(src)?;
+                Ok(Some(()))
+            }
+
+            ServerHandshakeStatus::ReadEncryptedPassword => {
+                self.handle_encrypted_password(src)?;
+                Ok(Some(()))
+            }
+
             _ => Ok(Some(())),
         }
     }
@@ -377,8 +823,17 @@ impl Encoder<BytesMut> for ServerHandshakeCodec {
     type Error = ProtocolError;
 
     fn encode(&mut self, item: BytesMut, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        if self.next_handshake_status == ServerHandshakeStatus::WriteAutoSwitch {
-            self.next_handshake_status = ServerHandshakeStatus::ReadAutoSwitchResponse;
+        match self.next_handshake_status {
+            ServerHandshakeStatus::WriteAutoSwitch => {
+                self.next_handshake_status = ServerHandshakeStatus::ReadAutoSwitchResponse
+            }
+            ServerHandshakeStatus::WriteFullAuth => {
+                self.next_handshake_status = ServerHandshakeStatus::ReadFullAuthResponse
+            }
+            ServerHandshakeStatus::WritePublicKey => {
+                self.next_handshake_status = ServerHandshakeStatus::ReadEncryptedPassword
+            }
+            _ => {}
         }
 
         dst.extend_from_slice(&item[..]);
@@ -414,6 +869,10 @@ impl Session for ServerHandshakeCodec {
     fn get_autocommit(&self) -> Option<String> {
         self.autocommit.clone()
     }
+
+    fn get_vars(&self) -> SessionVars {
+        self.vars.clone()
+    }
 }
 
 impl SessionMut for ServerHandshakeCodec {
@@ -428,6 +887,13 @@ impl SessionMut for ServerHandshakeCodec {
     fn set_autocommit(&mut self, autocommit: String) {
         self.autocommit = Some(autocommit)
     }
+
+    fn set_var(&mut self, name: &str, value: Option<String>) {
+        match value {
+            Some(value) => self.vars.insert(name.to_lowercase(), value),
+            None => self.vars.remove(&name.to_lowercase()),
+        };
+    }
 }
 
 pub async fn handshake(
@@ -447,7 +913,9 @@ pub async fn handshake(
         match next_state {
             ServerHandshakeStatus::SwitchToTLS => {
                 let mut parts = framed.into_parts();
-                parts.io.make_tls().await?;
+                let acceptor = parts.codec.tls_acceptor.get();
+                parts.io.make_tls(&acceptor).await?;
+                parts.codec.is_secure = true;
 
                 framed = Framed::from_parts(parts);
                 framed.codec_mut().next_handshake_status = ServerHandshakeStatus::ReadResponse;
@@ -457,6 +925,14 @@ pub async fn handshake(
                 framed.send(framed.codec().generate_auth_switch_request()).await?;
             }
 
+            ServerHandshakeStatus::WriteFullAuth => {
+                framed.send(framed.codec().make_full_auth_request()).await?;
+            }
+
+            ServerHandshakeStatus::WritePublicKey => {
+                framed.send(framed.codec().make_public_key_response()).await?;
+            }
+
             ServerHandshakeStatus::Complete => {
                 break;
             }
@@ -464,8 +940,17 @@ pub async fn handshake(
         }
     }
 
+    if framed.codec().auth_plugin_name == AUTH_CACHING_SHA2_PASSWORD && !framed.codec().is_full_auth {
+        framed.send(framed.codec().make_fast_auth_success()).await?;
+    }
+
     framed.send(BytesMut::from(&ok_packet()[..])).await?;
 
+    // Packets after the OK packet are compressed.
+    if let Some(compression) = framed.codec().compression {
+        framed.get_mut().make_compressed(compression);
+    }
+
     Ok((framed, true))
 }
 
@@ -473,15 +958,346 @@ pub async fn handshake(
 mod test {
     use bytes::BytesMut;
     use futures::{SinkExt, StreamExt};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
     use tokio_util::codec::Framed;
 
+    use super::*;
     use crate::{
+        client::{
+            auth::{handshake as client_handshake, ClientAuth},
+            codec::ClientCodec,
+            stream::LocalStream as ClientLocalStream,
+        },
         err::ProtocolError,
         server::{
             auth::{ServerHandshakeCodec, ServerHandshakeStatus},
         },
     };
 
+    fn make_handshake_response(user: &str, auth_data: &[u8], db: &str, plugin: &str) -> BytesMut {
+        let mut data = BytesMut::new();
+        data.put_bytes(0, 4);
+        data.put_u32_le(
+            CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_CONNECT_WITH_DB | CLIENT_PLUGIN_AUTH,
+        );
+        data.put_u32_le(1 << 24);
+        data.put_u8(0x21);
+        data.put_bytes(0, 23);
+        data.extend_from_slice(user.as_bytes());
+        data.put_u8(0);
+        data.put_u8(auth_data.len() as u8);
+        data.extend_from_slice(auth_data);
+        data.extend_from_slice(db.as_bytes());
+        data.put_u8(0);
+        data.extend_from_slice(plugin.as_bytes());
+        data.put_u8(0);
+
+        let length = (data.len() - 4) as u32;
+        data[..3].copy_from_slice(&length.to_le_bytes()[..3]);
+        data[3] = 1;
+        data
+    }
+
+    fn make_users() -> Arc<AuthUsers> {
+        Arc::new(AuthUsers::new(vec![
+            AuthUser::new("app".to_string(), AuthSecret::Password("app_pwd".to_string()))
+                .with_allowed_dbs(vec!["app_db".to_string()])
+                .with_allowed_hosts(&["10.0.0.0/8".to_string()])
+                .unwrap(),
+            AuthUser::new(
+                "ops".to_string(),
+                // SELECT PASSWORD('123456')
+                AuthSecret::from_native_password_hash("*6BB4837EB74329105EE4568DDA7DC67ED2CA2AD9")
+                    .unwrap(),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn test_multi_users_auth() {
+        let addr = Some("10.0.0.1".parse().unwrap());
+
+        let mut hs = ServerHandshakeCodec::with_users(make_users(), "".to_string(), "5.7.36".to_string(), addr);
+        let auth_data = calc_password(&hs.salt, b"app_pwd");
+        let mut data = make_handshake_response("app", &auth_data, "app_db", AUTH_NATIVE_PASSWORD);
+        assert!(hs.decode(&mut data).unwrap().is_some());
+        assert_eq!(hs.next_handshake_status, ServerHandshakeStatus::Complete);
+        assert_eq!(hs.get_db(), Some("app_db".to_string()));
+        assert!(!hs.is_db_allowed("ops_db"));
+        assert!(hs.is_db_restricted());
+
+        // The single user must use the configured db
+        let mut hs = ServerHandshakeCodec::new("app".to_string(), "app_pwd".to_string(), "app_db".to_string(), "5.7.36".to_string());
+        let auth_data = calc_password(&hs.salt, b"app_pwd");
+        let mut data = make_handshake_response("app", &auth_data, "ops_db", AUTH_NATIVE_PASSWORD);
+        assert!(matches!(hs.decode(&mut data), Err(ProtocolError::AuthFailed(_))));
+
+        // Database is not allowed
+        let mut hs = ServerHandshakeCodec::with_users(make_users(), "".to_string(), "5.7.36".to_string(), addr);
+        let auth_data = calc_password(&hs.salt, b"app_pwd");
+        let mut data = make_handshake_response("app", &auth_data, "ops_db", AUTH_NATIVE_PASSWORD);
+        assert!(matches!(hs.decode(&mut data), Err(ProtocolError::AuthFailed(_))));
+
+        // Source address is not allowed
+        let mut hs = ServerHandshakeCodec::with_users(
+            make_users(),
+            "".to_string(),
+            "5.7.36".to_string(),
+            Some("127.0.0.1".parse().unwrap()),
+        );
+        let auth_data = calc_password(&hs.salt, b"app_pwd");
+        let mut data = make_handshake_response("app", &auth_data, "app_db", AUTH_NATIVE_PASSWORD);
+        assert!(matches!(hs.decode(&mut data), Err(ProtocolError::AuthFailed(_))));
+
+        // Pre-hashed secret
+        let mut hs = ServerHandshakeCodec::with_users(make_users(), "".to_string(), "5.7.36".to_string(), addr);
+        let auth_data = calc_password(&hs.salt, b"123456");
+        let mut data = make_handshake_response("ops", &auth_data, "ops_db", AUTH_NATIVE_PASSWORD);
+        assert!(hs.decode(&mut data).unwrap().is_some());
+        assert_eq!(hs.next_handshake_status, ServerHandshakeStatus::Complete);
+
+        let mut hs = ServerHandshakeCodec::with_users(make_users(), "".to_string(), "5.7.36".to_string(), addr);
+        let auth_data = calc_password(&hs.salt, b"1234567");
+        let mut data = make_handshake_response("ops", &auth_data, "ops_db", AUTH_NATIVE_PASSWORD);
+        assert!(matches!(hs.decode(&mut data), Err(ProtocolError::AuthFailed(_))));
+    }
+
+    #[test]
+    fn test_require_secure_transport() {
+        let mut hs = ServerHandshakeCodec::with_users(make_users(), "".to_string(), "5.7.36".to_string(), None)
+            .with_tls(ServerTlsAcceptor::default(), true);
+        let auth_data = calc_password(&hs.salt, b"123456");
+        let mut data = make_handshake_response("ops", &auth_data, "", AUTH_NATIVE_PASSWORD);
+        assert!(matches!(hs.decode(&mut data), Err(ProtocolError::AuthFailed(_))));
+
+        // SSL request only contains capability, max packet size, charset and reserved bytes
+        let mut data = BytesMut::from(&[32, 0, 0, 1][..]);
+        data.put_u32_le(CLIENT_PROTOCOL_41 | CLIENT_SSL);
+        data.put_bytes(0, 28);
+        assert!(hs.decode(&mut data).unwrap().is_some());
+        assert_eq!(hs.next_handshake_status, ServerHandshakeStatus::SwitchToTLS);
+    }
+
+    fn make_change_user_payload(user: &str, auth_data: &[u8], db: &str, plugin: &str) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(user.as_bytes());
+        data.put_u8(0);
+        data.put_u8(auth_data.len() as u8);
+        data.extend_from_slice(auth_data);
+        data.extend_from_slice(db.as_bytes());
+        data.put_u8(0);
+        data.put_u16_le(0x21);
+        data.extend_from_slice(plugin.as_bytes());
+        data.put_u8(0);
+        data
+    }
+
+    #[test]
+    fn test_change_user() {
+        let addr = Some("10.0.0.1".parse().unwrap());
+        let mut hs = ServerHandshakeCodec::with_users(make_users(), "".to_string(), "5.7.36".to_string(), addr);
+        let auth_data = calc_password(&hs.salt, b"123456");
+        let mut data = make_handshake_response("ops", &auth_data, "ops_db", AUTH_NATIVE_PASSWORD);
+        assert!(hs.decode(&mut data).unwrap().is_some());
+
+        // The auth response of the payload is ignored, it is verified with a fresh scramble
+        let salt = hs.salt.clone();
+        let auth_data = calc_password(&salt, b"app_pwd");
+        let payload = make_change_user_payload("app", &auth_data, "app_db", AUTH_NATIVE_PASSWORD);
+        let packet = hs.change_user(&payload).unwrap();
+        assert_ne!(hs.salt, salt);
+        assert_eq!(packet[4], EOF_HEADER);
+        assert!(packet.ends_with(&[&hs.salt[..], &[0]].concat()));
+        assert_eq!(hs.get_user(), "ops");
+        assert!(matches!(hs.change_user_auth(&auth_data), Err(ProtocolError::AuthFailed(_))));
+        assert_eq!(hs.get_user(), "ops");
+
+        let _ = hs.change_user(&payload).unwrap();
+        let auth_data = calc_password(&hs.salt, b"app_pwd");
+        assert!(hs.change_user_auth(&auth_data).unwrap().is_none());
+        assert_eq!(hs.get_user(), "app");
+        assert_eq!(hs.get_db(), Some("app_db".to_string()));
+        assert!(!hs.is_db_allowed("ops_db"));
+
+        // Wrong password keeps the session
+        let payload = make_change_user_payload("ops", &[], "", AUTH_NATIVE_PASSWORD);
+        let _ = hs.change_user(&payload).unwrap();
+        let auth_data = calc_password(&hs.salt, b"1234567");
+        assert!(matches!(hs.change_user_auth(&auth_data), Err(ProtocolError::AuthFailed(_))));
+        assert_eq!(hs.get_user(), "app");
+
+        // Database is not allowed
+        let payload = make_change_user_payload("app", &[], "ops_db", AUTH_NATIVE_PASSWORD);
+        let _ = hs.change_user(&payload).unwrap();
+        let auth_data = calc_password(&hs.salt, b"app_pwd");
+        assert!(matches!(hs.change_user_auth(&auth_data), Err(ProtocolError::AuthFailed(_))));
+        assert_eq!(hs.get_db(), Some("app_db".to_string()));
+
+        // Fast auth success is sent before OK packet
+        let payload = make_change_user_payload("app", &[], "", AUTH_CACHING_SHA2_PASSWORD);
+        let _ = hs.change_user(&payload).unwrap();
+        let auth_data = calc_caching_sha2password(&hs.salt, b"app_pwd");
+        let packet = hs.change_user_auth(&auth_data).unwrap().unwrap();
+        assert_eq!(&packet[4..], &[MORE_DATA_HEADER, CACHE_SHA2_FAST_AUTH]);
+        assert_eq!(hs.get_db(), None);
+
+        assert!(matches!(hs.change_user(b"app"), Err(ProtocolError::InvalidPacket { .. })));
+        assert!(matches!(hs.change_user_auth(&auth_data), Err(ProtocolError::InvalidPacket { .. })));
+    }
+
+    async fn test_client_handshake(users: Arc<AuthUsers>, user: &str, password: &str) -> (bool, bool) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let hs = ServerHandshakeCodec::with_users(users, "".to_string(), "8.0.26".to_string(), None)
+                .with_auth_plugin(AUTH_CACHING_SHA2_PASSWORD);
+            let (framed, is_auth) = handshake(Framed::new(LocalStream::from(socket), hs)).await.unwrap();
+            (is_auth, framed.codec().is_full_auth)
+        });
+
+        let mut auth_codec = ClientAuth::new();
+        auth_codec.user = user.to_string();
+        auth_codec.password = password.to_string();
+        let sock = TcpStream::connect(addr).await.unwrap();
+        let framed = Framed::new(ClientLocalStream::from(sock), auth_codec);
+        let res = client_handshake(ClientCodec::ClientAuth(framed)).await;
+
+        let (is_auth, is_full_auth) = server.await.unwrap();
+        assert_eq!(res.is_ok(), is_auth);
+        (is_auth, is_full_auth)
+    }
+
+    async fn test_compression_handshake(
+        allow_compression: bool,
+        compression: Option<Compression>,
+    ) -> (Option<Compression>, Option<Compression>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let hs = ServerHandshakeCodec::new(
+                "root".to_string(),
+                "123456".to_string(),
+                "".to_string(),
+                "8.0.26".to_string(),
+            )
+            .with_compression(allow_compression);
+            let (mut framed, is_auth) =
+                handshake(Framed::new(LocalStream::from(socket), hs)).await.unwrap();
+            assert!(is_auth);
+
+            // Echo the first packet
+            let mut data = vec![0; 1004];
+            framed.get_mut().read_exact(&mut data).await.unwrap();
+            data[3] = 1;
+            framed.get_mut().write_all(&data).await.unwrap();
+            framed.get_mut().flush().await.unwrap();
+            framed.codec().get_compression()
+        });
+
+        let mut auth_codec = ClientAuth::new();
+        auth_codec.user = "root".to_string();
+        auth_codec.password = "123456".to_string();
+        auth_codec.compression = compression;
+        let sock = TcpStream::connect(addr).await.unwrap();
+        let framed = Framed::new(ClientLocalStream::from(sock), auth_codec);
+        let (mut framed, ..) = client_handshake(ClientCodec::ClientAuth(framed)).await.unwrap();
+
+        let mut query = vec![0xe8, 0x03, 0x00, 0x00];
+        query.extend_from_slice(&[b'a'; 1000]);
+        framed.get_mut().write_all(&query).await.unwrap();
+        framed.get_mut().flush().await.unwrap();
+
+        let mut data = vec![0; 1004];
+        framed.get_mut().read_exact(&mut data).await.unwrap();
+        assert_eq!(&data[4..], &query[4..]);
+
+        let is_compressed = matches!(framed.get_ref(), ClientLocalStream::Compressed(_));
+        assert_eq!(is_compressed, framed.codec().compression.is_some());
+        (server.await.unwrap(), framed.codec().compression)
+    }
+
+    #[tokio::test]
+    async fn test_compression_negotiation() {
+        let zstd = Some(Compression::Zstd(5));
+        assert_eq!(test_compression_handshake(true, zstd).await, (zstd, zstd));
+
+        let zlib = Some(Compression::Zlib(6));
+        assert_eq!(test_compression_handshake(true, zlib).await, (zlib, zlib));
+
+        assert_eq!(test_compression_handshake(false, zstd).await, (None, None));
+        assert_eq!(test_compression_handshake(true, None).await, (None, None));
+    }
+
+    #[tokio::test]
+    async fn test_caching_sha2_fast_auth() {
+        let users = Arc::new(AuthUsers::new(vec![AuthUser::new(
+            "root".to_string(),
+            AuthSecret::Password("123456".to_string()),
+        )]));
+
+        assert_eq!(test_client_handshake(users.clone(), "root", "123456").await, (true, false));
+        // Wrong scramble moves to full authentication
+        assert_eq!(test_client_handshake(users, "root", "1234567").await, (false, true));
+    }
+
+    #[tokio::test]
+    async fn test_caching_sha2_full_auth() {
+        let users = make_users();
+
+        // Wrong password doesn't fill the cache
+        assert_eq!(test_client_handshake(users.clone(), "ops", "654321").await, (false, true));
+        // The native password hash is not able to verify the scramble until the password is cached
+        assert_eq!(test_client_handshake(users.clone(), "ops", "123456").await, (true, true));
+        assert_eq!(test_client_handshake(users.clone(), "ops", "123456").await, (true, false));
+        assert_eq!(test_client_handshake(users, "ops", "654321").await, (false, true));
+    }
+
+    #[test]
+    fn test_caching_sha2_full_auth_clear_text() {
+        let mut hs = ServerHandshakeCodec::with_users(make_users(), "".to_string(), "8.0.26".to_string(), None);
+        let auth_data = calc_caching_sha2password(&hs.salt, b"123456");
+        let mut data = make_handshake_response("ops", &auth_data, "", AUTH_CACHING_SHA2_PASSWORD);
+        assert!(hs.decode(&mut data).unwrap().is_some());
+        assert_eq!(hs.next_handshake_status, ServerHandshakeStatus::WriteFullAuth);
+
+        let mut dst = BytesMut::new();
+        hs.encode(hs.make_full_auth_request(), &mut dst).unwrap();
+        assert_eq!(&dst[4..], &[MORE_DATA_HEADER, CACHE_SHA2_FULL_AUTH]);
+        assert_eq!(hs.next_handshake_status, ServerHandshakeStatus::ReadFullAuthResponse);
+
+        // Clear text password is rejected over insecure connection
+        let mut data = BytesMut::from(&[7, 0, 0, 3][..]);
+        data.extend_from_slice(b"123456\0");
+        assert!(matches!(hs.decode(&mut data), Err(ProtocolError::AuthFailed(_))));
+
+        // The password is encrypted by the public key which the client has already
+        hs.next_handshake_status = ServerHandshakeStatus::ReadFullAuthResponse;
+        let mut password = b"123456\0".to_vec();
+        for (i, x) in password.iter_mut().enumerate() {
+            *x ^= hs.salt[i % hs.salt.len()];
+        }
+        let mut encrypted = vec![0; RSA_PRIVATE_KEY.size() as usize];
+        RSA_PRIVATE_KEY.public_encrypt(&password, &mut encrypted, Padding::PKCS1_OAEP).unwrap();
+        let mut data = BytesMut::from(&[0, 1, 0, 3][..]);
+        data.extend_from_slice(&encrypted);
+        assert!(hs.decode(&mut data).unwrap().is_some());
+        assert_eq!(hs.next_handshake_status, ServerHandshakeStatus::Complete);
+
+        hs.next_handshake_status = ServerHandshakeStatus::ReadFullAuthResponse;
+        hs.is_secure = true;
+        let mut data = BytesMut::from(&[7, 0, 0, 3][..]);
+        data.extend_from_slice(b"123456\0");
+        assert!(hs.decode(&mut data).unwrap().is_some());
+        assert_eq!(hs.next_handshake_status, ServerHandshakeStatus::Complete);
+    }
+
     #[tokio::test]
     async fn test_handshake() {
         //let packet_codec = PacketCodec::new(8192);
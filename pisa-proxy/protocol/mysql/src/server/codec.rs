@@ -161,6 +161,12 @@ pub fn make_eof_packet() -> [u8; 9] {
     [5, 0, 0, 0, 0xfe, 0, 0, 2, 0]
 }
 
+#[inline]
+pub fn make_eof_packet_with_status(status: u16) -> [u8; 9] {
+    let status = status.to_le_bytes();
+    [5, 0, 0, 0, 0xfe, 0, 0, status[0], status[1]]
+}
+
 #[inline]
 pub fn ok_packet() -> [u8; 11] {
     [7, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0]
@@ -14,6 +14,7 @@
 
 pub mod err;
 pub mod stream;
-mod tls;
+pub mod tls;
 pub mod codec;
 pub mod auth;
+pub mod users;
@@ -17,44 +17,47 @@ use std::{
     task::{Context, Poll},
 };
 
+use openssl::ssl::{Ssl, SslAcceptor};
 use pin_project::pin_project;
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
     net::TcpStream,
 };
-use tokio_native_tls::{
-    native_tls::{Identity, TlsAcceptor},
-    TlsStream,
-};
-
-use crate::{server::tls::make_pkcs12, err::ProtocolError};
+use tokio_openssl::SslStream;
 
-lazy_static! {
-    static ref TLS_ACCEPTOR: tokio_native_tls::TlsAcceptor = {
-        let chain = make_pkcs12();
-        let identity = Identity::from_pkcs12(&chain.2, "pisa-proxy").unwrap();
-        tokio_native_tls::TlsAcceptor::from(TlsAcceptor::new(identity).unwrap())
-    };
-}
+use crate::{
+    compress::{CompressedStream, Compression},
+    err::ProtocolError,
+};
 
 #[pin_project(project=LSProj)]
 #[derive(Debug)]
 pub enum LocalStream {
     Plain(Option<TcpStream>),
-    Secure(#[pin] TlsStream<TcpStream>),
+    Secure(#[pin] SslStream<TcpStream>),
+    Compressed(Box<CompressedStream<LocalStream>>),
 }
 
 impl LocalStream {
-    pub async fn make_tls(&mut self) -> Result<(), ProtocolError>{
+    pub async fn make_tls(&mut self, acceptor: &SslAcceptor) -> Result<(), ProtocolError>{
         *self = match self {
             LocalStream::Plain(ref mut plain) => {
-                LocalStream::Secure(TLS_ACCEPTOR.accept(plain.take().unwrap()).await?)
+                let ssl = Ssl::new(acceptor.context())?;
+                let mut stream = SslStream::new(ssl, plain.take().unwrap())?;
+                Pin::new(&mut stream).accept().await?;
+                LocalStream::Secure(stream)
             }
             _ => unreachable!(),
         };
 
         Ok(())
     }
+
+    // Wrap the stream in compressed packet layer, it is called after the handshake is complete.
+    pub fn make_compressed(&mut self, compression: Compression) {
+        let stream = std::mem::replace(self, LocalStream::Plain(None));
+        *self = LocalStream::Compressed(Box::new(CompressedStream::new(stream, compression)));
+    }
 }
 
 impl AsyncRead for LocalStream {
@@ -69,6 +72,8 @@ impl AsyncRead for LocalStream {
             LSProj::Plain(ref mut stream) => Pin::new(stream.as_mut().unwrap()).poll_read(cx, buf),
 
             LSProj::Secure(ref mut stream) => stream.as_mut().poll_read(cx, buf),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_read(cx, buf),
         }
     }
 }
@@ -85,6 +90,8 @@ impl AsyncWrite for LocalStream {
             LSProj::Plain(ref mut stream) => Pin::new(stream.as_mut().unwrap()).poll_write(cx, buf),
 
             LSProj::Secure(ref mut stream) => stream.as_mut().poll_write(cx, buf),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_write(cx, buf),
         }
     }
 
@@ -95,6 +102,8 @@ impl AsyncWrite for LocalStream {
             LSProj::Plain(ref mut stream) => Pin::new(stream.as_mut().unwrap()).poll_flush(cx),
 
             LSProj::Secure(ref mut stream) => stream.as_mut().poll_flush(cx),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_flush(cx),
         }
     }
 
@@ -105,6 +114,8 @@ impl AsyncWrite for LocalStream {
             LSProj::Plain(ref mut stream) => Pin::new(stream.as_mut().unwrap()).poll_shutdown(cx),
 
             LSProj::Secure(ref mut stream) => stream.as_mut().poll_shutdown(cx),
+
+            LSProj::Compressed(ref mut stream) => Pin::new(&mut **stream).poll_shutdown(cx),
         }
     }
 }
@@ -115,8 +126,8 @@ impl From<TcpStream> for LocalStream {
     }
 }
 
-impl From<TlsStream<TcpStream>> for LocalStream {
-    fn from(stream: tokio_native_tls::TlsStream<TcpStream>) -> Self {
+impl From<SslStream<TcpStream>> for LocalStream {
+    fn from(stream: SslStream<TcpStream>) -> Self {
         LocalStream::Secure(stream)
     }
 }
@@ -129,7 +140,11 @@ impl LocalStream {
             },
 
             Self::Secure(stream) => {
-                stream.get_ref().get_ref().get_ref()
+                stream.get_ref()
+            }
+
+            Self::Compressed(stream) => {
+                stream.get_ref().get_inner()
             }
         }
     }
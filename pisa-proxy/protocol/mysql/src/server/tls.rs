@@ -12,21 +12,120 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::{
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
 use openssl::{
     asn1::Asn1Time,
     hash::MessageDigest,
     nid::Nid,
-    pkcs12::Pkcs12,
     pkey::{PKey, Private},
     rsa::Rsa,
+    ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode},
     x509::{extension::KeyUsage, X509Name, X509},
 };
 
-pub fn make_pkcs12() -> (Rsa<Private>, PKey<Private>, Vec<u8>) {
+use crate::err::ProtocolError;
+
+lazy_static! {
+    static ref SELF_SIGNED: (PKey<Private>, X509) = make_self_signed();
+    static ref DEFAULT_TLS_ACCEPTOR: ServerTlsAcceptor =
+        ServerTlsAcceptor::new(ServerTlsConfig::default()).unwrap();
+}
+
+/// PEM files of the client-facing listener, a self-signed certificate is generated
+/// when `cert` and `key` are not set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerTlsConfig {
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    // Client certificates are required and verified when it is set.
+    pub client_ca: Option<String>,
+}
+
+/// A reloadable `SslAcceptor`, the established connections are not affected by reload.
+#[derive(Clone)]
+pub struct ServerTlsAcceptor {
+    config: ServerTlsConfig,
+    inner: Arc<RwLock<Arc<SslAcceptor>>>,
+}
+
+impl ServerTlsAcceptor {
+    pub fn new(config: ServerTlsConfig) -> Result<Self, ProtocolError> {
+        let acceptor = build_acceptor(&config)?;
+        Ok(Self { config, inner: Arc::new(RwLock::new(Arc::new(acceptor))) })
+    }
+
+    // Re-read the certificate files.
+    pub fn reload(&self) -> Result<(), ProtocolError> {
+        let acceptor = build_acceptor(&self.config)?;
+        *self.inner.write().unwrap() = Arc::new(acceptor);
+        Ok(())
+    }
+
+    pub fn get(&self) -> Arc<SslAcceptor> {
+        self.inner.read().unwrap().clone()
+    }
+
+    pub fn config(&self) -> &ServerTlsConfig {
+        &self.config
+    }
+
+    // The latest modified time of the certificate files, used to detect changes.
+    pub fn modified(&self) -> Option<SystemTime> {
+        [&self.config.cert, &self.config.key, &self.config.client_ca]
+            .iter()
+            .filter_map(|x| x.as_ref())
+            .filter_map(|x| std::fs::metadata(x).and_then(|m| m.modified()).ok())
+            .max()
+    }
+}
+
+impl Default for ServerTlsAcceptor {
+    fn default() -> Self {
+        DEFAULT_TLS_ACCEPTOR.clone()
+    }
+}
+
+impl std::fmt::Debug for ServerTlsAcceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerTlsAcceptor").field("config", &self.config).finish()
+    }
+}
+
+fn build_acceptor(config: &ServerTlsConfig) -> Result<SslAcceptor, ProtocolError> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls_server())?;
+
+    match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => {
+            builder.set_certificate_chain_file(cert)?;
+            builder.set_private_key_file(key, SslFiletype::PEM)?;
+        }
+        (None, None) => {
+            let (pkey, cert) = &*SELF_SIGNED;
+            builder.set_private_key(pkey)?;
+            builder.set_certificate(cert)?;
+        }
+        _ => return Err(ProtocolError::TlsConfig("both cert and key are required".to_string())),
+    }
+
+    builder.check_private_key()?;
+
+    if let Some(ca) = &config.client_ca {
+        builder.set_ca_file(ca)?;
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    Ok(builder.build())
+}
+
+fn make_self_signed() -> (PKey<Private>, X509) {
     let subject_name = "ns.pisa-proxy.io";
 
     let rsa_key = Rsa::generate(2048).unwrap();
-    let pub_key = PKey::from_rsa(rsa_key.clone()).unwrap();
+    let pub_key = PKey::from_rsa(rsa_key).unwrap();
 
     let mut name = X509Name::builder().unwrap();
     name.append_entry_by_nid(Nid::COMMONNAME, subject_name).unwrap();
@@ -46,9 +145,52 @@ pub fn make_pkcs12() -> (Rsa<Private>, PKey<Private>, Vec<u8>) {
     builder.sign(&pub_key, MessageDigest::sha256()).unwrap();
     let cert = builder.build();
 
-    let pkcs12_builder = Pkcs12::builder();
-    let pkcs12 = pkcs12_builder.build("pisa-proxy", subject_name, &pub_key, &cert).unwrap();
-    let der = pkcs12.to_der().unwrap();
+    (pub_key, cert)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_build_acceptor() {
+        let (pkey, cert) = make_self_signed();
+        let dir = std::env::temp_dir().join(format!("pisa-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::File::create(&cert_path).unwrap().write_all(&cert.to_pem().unwrap()).unwrap();
+        std::fs::File::create(&key_path)
+            .unwrap()
+            .write_all(&pkey.private_key_to_pem_pkcs8().unwrap())
+            .unwrap();
+
+        let config = ServerTlsConfig {
+            cert: Some(cert_path.to_string_lossy().to_string()),
+            key: Some(key_path.to_string_lossy().to_string()),
+            client_ca: Some(cert_path.to_string_lossy().to_string()),
+        };
+        let acceptor = ServerTlsAcceptor::new(config).unwrap();
+        assert!(acceptor.modified().is_some());
+        let before = acceptor.get();
+        acceptor.reload().unwrap();
+        assert!(!Arc::ptr_eq(&before, &acceptor.get()));
+
+        let config = ServerTlsConfig {
+            cert: Some(cert_path.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(ServerTlsAcceptor::new(config), Err(ProtocolError::TlsConfig(_))));
+
+        let config = ServerTlsConfig {
+            cert: Some(dir.join("not_found.pem").to_string_lossy().to_string()),
+            key: Some(key_path.to_string_lossy().to_string()),
+            client_ca: None,
+        };
+        assert!(ServerTlsAcceptor::new(config).is_err());
 
-    (rsa_key, pub_key, der)
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
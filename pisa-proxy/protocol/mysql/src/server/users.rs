@@ -0,0 +1,370 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    net::IpAddr,
+    sync::{Arc, RwLock},
+};
+
+use crypto::digest::Digest;
+use ipnet::IpNet;
+
+use crate::{
+    mysql_const::{AUTH_CACHING_SHA2_PASSWORD, AUTH_NATIVE_PASSWORD},
+    util::{calc_caching_sha2password, calc_password, compare},
+};
+
+/// The credential of a frontend user.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthSecret {
+    /// Plain text password.
+    Password(String),
+    /// SHA1(SHA1(password)), the `authentication_string` of `mysql_native_password` without the leading `*`.
+    NativePassword(Vec<u8>),
+    /// SHA256(SHA256(password)), can only be verified by `caching_sha2_password`.
+    CachingSha2Password(Vec<u8>),
+}
+
+impl AuthSecret {
+    pub fn from_native_password_hash(hash: &str) -> Result<Self, String> {
+        let hash = hash.trim_start_matches('*');
+        match hex::decode(hash) {
+            Ok(data) if data.len() == 20 => Ok(Self::NativePassword(data)),
+            _ => Err(format!("invalid mysql_native_password hash {:?}", hash)),
+        }
+    }
+
+    pub fn from_caching_sha2_password_hash(hash: &str) -> Result<Self, String> {
+        match hex::decode(hash) {
+            Ok(data) if data.len() == 32 => Ok(Self::CachingSha2Password(data)),
+            _ => Err(format!("invalid caching_sha2_password hash {:?}", hash)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user: String,
+    pub secret: AuthSecret,
+    /// Empty means all databases are allowed.
+    pub allowed_dbs: Vec<String>,
+    /// Empty means all source addresses are allowed.
+    pub allowed_hosts: Vec<IpNet>,
+    /// SHA256(SHA256(password)) cached after a successful full authentication of `caching_sha2_password`,
+    /// shared by all clones so that following connections are able to use fast authentication.
+    sha2_cache: Arc<RwLock<Option<Vec<u8>>>>,
+}
+
+impl AuthUser {
+    pub fn new(user: String, secret: AuthSecret) -> Self {
+        Self {
+            user,
+            secret,
+            allowed_dbs: vec![],
+            allowed_hosts: vec![],
+            sha2_cache: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn with_allowed_dbs(mut self, dbs: Vec<String>) -> Self {
+        self.allowed_dbs = dbs;
+        self
+    }
+
+    pub fn with_allowed_hosts(mut self, hosts: &[String]) -> Result<Self, String> {
+        for host in hosts {
+            self.allowed_hosts.push(parse_host(host)?);
+        }
+        Ok(self)
+    }
+
+    pub fn is_db_allowed(&self, db: &str) -> bool {
+        self.allowed_dbs.is_empty() || self.allowed_dbs.iter().any(|x| x == "*" || x == db)
+    }
+
+    pub fn is_host_allowed(&self, addr: Option<IpAddr>) -> bool {
+        if self.allowed_hosts.is_empty() {
+            return true;
+        }
+
+        match addr {
+            Some(addr) => self.allowed_hosts.iter().any(|x| x.contains(&addr)),
+            None => false,
+        }
+    }
+
+    // The auth plugin which is able to verify this user's secret.
+    pub fn auth_plugin_name(&self) -> &'static str {
+        match self.secret {
+            AuthSecret::CachingSha2Password(_) => AUTH_CACHING_SHA2_PASSWORD,
+            _ => AUTH_NATIVE_PASSWORD,
+        }
+    }
+
+    // Every secret can be verified by `caching_sha2_password`, a `NativePassword` secret requires
+    // full authentication until the password is cached.
+    pub fn is_plugin_supported(&self, plugin: &str) -> bool {
+        match plugin {
+            AUTH_CACHING_SHA2_PASSWORD => true,
+            AUTH_NATIVE_PASSWORD => !matches!(self.secret, AuthSecret::CachingSha2Password(_)),
+            _ => false,
+        }
+    }
+
+    pub fn verify_native_password(&self, salt: &[u8], auth_data: &[u8]) -> bool {
+        match &self.secret {
+            AuthSecret::Password(password) => {
+                compare(auth_data, &calc_password(salt, password.as_bytes()))
+            }
+            AuthSecret::NativePassword(stage2) => check_native_scramble(salt, auth_data, stage2),
+            AuthSecret::CachingSha2Password(_) => false,
+        }
+    }
+
+    pub fn verify_caching_sha2_password(&self, salt: &[u8], auth_data: &[u8]) -> bool {
+        match &self.secret {
+            AuthSecret::Password(password) => {
+                compare(auth_data, &calc_caching_sha2password(salt, password.as_bytes()))
+            }
+            AuthSecret::CachingSha2Password(stage2) => {
+                check_caching_sha2_scramble(salt, auth_data, stage2)
+            }
+            AuthSecret::NativePassword(_) => match &*self.sha2_cache.read().unwrap() {
+                Some(stage2) => check_caching_sha2_scramble(salt, auth_data, stage2),
+                None => false,
+            },
+        }
+    }
+
+    // Verify the clear text password received by full authentication.
+    pub fn verify_plain_password(&self, password: &[u8]) -> bool {
+        match &self.secret {
+            AuthSecret::Password(x) => compare(x.as_bytes(), password),
+            AuthSecret::NativePassword(stage2) => compare(&sha1_sha1(password), stage2),
+            AuthSecret::CachingSha2Password(stage2) => compare(&sha256_sha256(password), stage2),
+        }
+    }
+
+    // Cache the verified password for fast authentication of `caching_sha2_password`.
+    pub fn cache_sha2_password(&self, password: &[u8]) {
+        if let AuthSecret::NativePassword(_) = self.secret {
+            *self.sha2_cache.write().unwrap() = Some(sha256_sha256(password));
+        }
+    }
+}
+
+/// The users which are allowed to connect to a proxy.
+#[derive(Debug, Clone, Default)]
+pub struct AuthUsers {
+    users: Vec<AuthUser>,
+}
+
+impl AuthUsers {
+    pub fn new(users: Vec<AuthUser>) -> Self {
+        Self { users }
+    }
+
+    pub fn add(&mut self, user: AuthUser) {
+        self.users.push(user)
+    }
+
+    // Find the first user entry matching both user name and source address.
+    pub fn find(&self, user: &str, addr: Option<IpAddr>) -> Option<&AuthUser> {
+        self.users.iter().find(|x| x.user == user && x.is_host_allowed(addr))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+fn parse_host(host: &str) -> Result<IpNet, String> {
+    if let Ok(net) = host.parse::<IpNet>() {
+        return Ok(net);
+    }
+
+    host.parse::<IpAddr>()
+        .map(IpNet::from)
+        .map_err(|_| format!("invalid allowed host {:?}, expect ip address or cidr", host))
+}
+
+fn sha1_sha1(password: &[u8]) -> Vec<u8> {
+    let mut crypt = crypto::sha1::Sha1::new();
+    crypt.input(password);
+    let mut stage1 = vec![0; 20];
+    crypt.result(&mut stage1);
+    crypt.reset();
+    crypt.input(&stage1);
+    let mut stage2 = vec![0; 20];
+    crypt.result(&mut stage2);
+    stage2
+}
+
+fn sha256_sha256(password: &[u8]) -> Vec<u8> {
+    let mut crypt = crypto::sha2::Sha256::new();
+    crypt.input(password);
+    let mut stage1 = vec![0; 32];
+    crypt.result(&mut stage1);
+    crypt.reset();
+    crypt.input(&stage1);
+    let mut stage2 = vec![0; 32];
+    crypt.result(&mut stage2);
+    stage2
+}
+
+// Recover SHA1(password) from the scramble, then check SHA1(SHA1(password)) against `stage2`.
+fn check_native_scramble(salt: &[u8], scramble: &[u8], stage2: &[u8]) -> bool {
+    if scramble.len() != 20 || stage2.len() != 20 {
+        return false;
+    }
+
+    let mut crypt = crypto::sha1::Sha1::new();
+    crypt.input(salt);
+    crypt.input(stage2);
+    let mut stage1 = vec![0; 20];
+    crypt.result(&mut stage1);
+
+    for i in 0..20 {
+        stage1[i] ^= scramble[i];
+    }
+
+    crypt.reset();
+    crypt.input(&stage1);
+    let mut hash = vec![0; 20];
+    crypt.result(&mut hash);
+
+    compare(&hash, stage2)
+}
+
+// Recover SHA256(password) from the scramble, then check SHA256(SHA256(password)) against `stage2`.
+fn check_caching_sha2_scramble(salt: &[u8], scramble: &[u8], stage2: &[u8]) -> bool {
+    if scramble.len() != 32 || stage2.len() != 32 {
+        return false;
+    }
+
+    let mut crypt = crypto::sha2::Sha256::new();
+    crypt.input(stage2);
+    crypt.input(salt);
+    let mut stage1 = vec![0; 32];
+    crypt.result(&mut stage1);
+
+    for i in 0..32 {
+        stage1[i] ^= scramble[i];
+    }
+
+    crypt.reset();
+    crypt.input(&stage1);
+    let mut hash = vec![0; 32];
+    crypt.result(&mut hash);
+
+    compare(&hash, stage2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::random_buf;
+
+    #[test]
+    fn test_native_password_hash() {
+        // SELECT PASSWORD('123456')
+        let secret =
+            AuthSecret::from_native_password_hash("*6BB4837EB74329105EE4568DDA7DC67ED2CA2AD9")
+                .unwrap();
+        assert_eq!(secret, AuthSecret::NativePassword(sha1_sha1(b"123456")));
+
+        let user = AuthUser::new("root".to_string(), secret);
+        let salt = random_buf(20);
+        assert!(user.verify_native_password(&salt, &calc_password(&salt, b"123456")));
+        assert!(!user.verify_native_password(&salt, &calc_password(&salt, b"1234567")));
+        assert!(!user.verify_caching_sha2_password(&salt, &calc_password(&salt, b"123456")));
+    }
+
+    #[test]
+    fn test_sha2_cache() {
+        let secret =
+            AuthSecret::from_native_password_hash("*6BB4837EB74329105EE4568DDA7DC67ED2CA2AD9")
+                .unwrap();
+        let user = AuthUser::new("root".to_string(), secret);
+        let salt = random_buf(20);
+        let scramble = calc_caching_sha2password(&salt, b"123456");
+        assert!(user.is_plugin_supported(AUTH_CACHING_SHA2_PASSWORD));
+        assert!(!user.verify_caching_sha2_password(&salt, &scramble));
+
+        assert!(!user.verify_plain_password(b"1234567"));
+        assert!(user.verify_plain_password(b"123456"));
+        user.cache_sha2_password(b"123456");
+
+        // The cache is shared by clones
+        let user = user.clone();
+        assert!(user.verify_caching_sha2_password(&salt, &scramble));
+        assert!(
+            !user.verify_caching_sha2_password(&salt, &calc_caching_sha2password(&salt, b"654321"))
+        );
+    }
+
+    #[test]
+    fn test_caching_sha2_password_hash() {
+        let mut crypt = crypto::sha2::Sha256::new();
+        crypt.input(b"123456");
+        let mut stage1 = vec![0; 32];
+        crypt.result(&mut stage1);
+        crypt.reset();
+        crypt.input(&stage1);
+        let stage2 = crypt.result_str();
+
+        let secret = AuthSecret::from_caching_sha2_password_hash(&stage2).unwrap();
+        let user = AuthUser::new("root".to_string(), secret);
+        let salt = random_buf(20);
+        assert_eq!(user.auth_plugin_name(), AUTH_CACHING_SHA2_PASSWORD);
+        assert!(
+            user.verify_caching_sha2_password(&salt, &calc_caching_sha2password(&salt, b"123456"))
+        );
+        assert!(
+            !user.verify_caching_sha2_password(&salt, &calc_caching_sha2password(&salt, b"654321"))
+        );
+
+        assert!(AuthSecret::from_caching_sha2_password_hash("abc").is_err());
+    }
+
+    #[test]
+    fn test_find_user() {
+        let users = AuthUsers::new(vec![
+            AuthUser::new("app".to_string(), AuthSecret::Password("a".to_string()))
+                .with_allowed_hosts(&["10.0.0.0/8".to_string()])
+                .unwrap()
+                .with_allowed_dbs(vec!["app_db".to_string()]),
+            AuthUser::new("app".to_string(), AuthSecret::Password("b".to_string()))
+                .with_allowed_hosts(&["127.0.0.1".to_string()])
+                .unwrap(),
+        ]);
+
+        let user = users.find("app", Some("10.1.2.3".parse().unwrap())).unwrap();
+        assert_eq!(user.secret, AuthSecret::Password("a".to_string()));
+        assert!(user.is_db_allowed("app_db"));
+        assert!(!user.is_db_allowed("other_db"));
+
+        let user = users.find("app", Some("127.0.0.1".parse().unwrap())).unwrap();
+        assert_eq!(user.secret, AuthSecret::Password("b".to_string()));
+        assert!(user.is_db_allowed("other_db"));
+
+        assert!(users.find("app", Some("192.168.0.1".parse().unwrap())).is_none());
+        assert!(users.find("app", None).is_none());
+        assert!(users.find("other", Some("127.0.0.1".parse().unwrap())).is_none());
+
+        assert!(AuthUser::new("app".to_string(), AuthSecret::Password("".to_string()))
+            .with_allowed_hosts(&["localhost".to_string()])
+            .is_err());
+    }
+}
@@ -1,25 +1,133 @@
 // Copyright 2022 SphereEx Authors
-// 
+//
 // Licensed under the Apache License, Version 2.0 (the "License");
 // you may not use this file except in compliance with the License.
 // You may obtain a copy of the License at
-// 
+//
 //     http://www.apache.org/licenses/LICENSE-2.0
-// 
+//
 // Unless required by applicable law or agreed to in writing, software
 // distributed under the License is distributed on an "AS IS" BASIS,
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
+// Session variables keyed by the lowercase name, user variables are prefixed with `@`.
+// The value is the sql text which is used to set it again, e.g. `'UTC'`.
+pub type SessionVars = BTreeMap<String, String>;
+
+// The variables are set by dedicated attrs of the conn, e.g. `SET NAMES`, or by the proxy itself.
+const UNTRACKED_VARS: [&str; 6] = [
+    "autocommit",
+    "character_set_client",
+    "character_set_connection",
+    "character_set_results",
+    "collation_connection",
+    "session_track_system_variables",
+];
+
 pub trait Session {
-    fn get_db(&self) -> Option<String>; 
+    fn get_db(&self) -> Option<String>;
     fn get_charset(&self) -> Option<String>;
     fn get_autocommit(&self) -> Option<String>;
+    fn get_vars(&self) -> SessionVars;
 }
 
 pub trait SessionMut {
     fn set_db(&mut self, db: String);
     fn set_charset(&mut self, charset: String);
     fn set_autocommit(&mut self, autocommit: String);
-}
\ No newline at end of file
+    // `None` means the variable is reset to default.
+    fn set_var(&mut self, name: &str, value: Option<String>);
+}
+
+// Whether the variable is replayed by `build_set_vars`.
+pub fn is_tracked_var(name: &str) -> bool {
+    !UNTRACKED_VARS.contains(&name.to_lowercase().as_str())
+}
+
+// Quote the value reported by SESSION_TRACK, numbers are kept as is since integer variables don't accept strings.
+pub fn quote_var_value(value: &str) -> String {
+    let is_num = value.bytes().all(|b| b.is_ascii_digit() || b == b'.' || b == b'-');
+    if is_num && value.parse::<f64>().is_ok() {
+        return value.to_string();
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for ch in value.chars() {
+        if ch == '\'' || ch == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+// Build the `SET` statement which turns the variables `current` of the backend conn into `target`,
+// the variable which is not in `target` is reset to `DEFAULT`, or `NULL` for user variable.
+pub fn build_set_vars(current: &SessionVars, target: &SessionVars) -> Option<String> {
+    let var_name = |name: &str| match name.strip_prefix('@') {
+        Some(name) => format!("@`{}`", name.replace('`', "``")),
+        None => format!("@@SESSION.{}", name),
+    };
+
+    let mut items = vec![];
+    for (name, value) in target.iter() {
+        if current.get(name) != Some(value) {
+            items.push(format!("{} = {}", var_name(name), value));
+        }
+    }
+
+    for name in current.keys() {
+        if !target.contains_key(name) {
+            let value = if name.starts_with('@') { "NULL" } else { "DEFAULT" };
+            items.push(format!("{} = {}", var_name(name), value));
+        }
+    }
+
+    if items.is_empty() {
+        return None;
+    }
+
+    Some(format!("SET {}", items.join(", ")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_set_vars() {
+        let mut current = SessionVars::new();
+        current.insert("sql_mode".to_string(), "'ANSI'".to_string());
+        current.insert("time_zone".to_string(), "'UTC'".to_string());
+        current.insert("@a".to_string(), "1".to_string());
+
+        assert_eq!(build_set_vars(&current, &current), None);
+
+        let mut target = SessionVars::new();
+        target.insert("time_zone".to_string(), "'+08:00'".to_string());
+        target.insert("@b".to_string(), "'x'".to_string());
+
+        assert_eq!(
+            build_set_vars(&current, &target).unwrap(),
+            "SET @`b` = 'x', @@SESSION.time_zone = '+08:00', @`a` = NULL, @@SESSION.sql_mode = DEFAULT"
+        );
+    }
+
+    #[test]
+    fn test_quote_var_value() {
+        assert_eq!(quote_var_value("28800"), "28800");
+        assert_eq!(quote_var_value("-1.5"), "-1.5");
+        assert_eq!(quote_var_value(""), "''");
+        assert_eq!(quote_var_value("inf"), "'inf'");
+        assert_eq!(quote_var_value("STRICT_TRANS_TABLES"), "'STRICT_TRANS_TABLES'");
+        assert_eq!(quote_var_value("it's"), "'it\\'s'");
+        assert!(!is_tracked_var("AUTOCOMMIT"));
+        assert!(is_tracked_var("sql_mode"));
+    }
+}
@@ -20,7 +20,10 @@ use chrono::prelude::*;
 use crypto::{self, digest::Digest};
 use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::mysql_const::{EOF_HEADER, OK_HEADER};
+use crate::{
+    err::ProtocolError,
+    mysql_const::{EOF_HEADER, OK_HEADER},
+};
 
 // random_buf: generate random byte vector
 #[inline]
@@ -150,6 +153,34 @@ pub trait BufExt: Buf {
         self.advance(length as usize)
     }
 
+    // The same as `skip_lenc_length`, but the data from client may be malformed, returns error
+    // instead of panic when it is shorter than the length.
+    fn try_skip_lenc_length(&mut self) -> Result<(), ProtocolError> {
+        let header_len = match self.chunk().first() {
+            Some(0xfc) => 3,
+            Some(0xfd) => 4,
+            Some(0xfe) => 9,
+            Some(_) => 1,
+            None => 0,
+        };
+        if header_len == 0 || header_len > self.remaining() {
+            return Err(ProtocolError::InvalidPacket {
+                method: "skip_lenc_length".to_string(),
+                data: self.chunk().to_vec(),
+            });
+        }
+
+        let (length, ..) = self.get_lenc_int();
+        if length > self.remaining() as u64 {
+            return Err(ProtocolError::InvalidPacket {
+                method: "skip_lenc_length".to_string(),
+                data: self.chunk().to_vec(),
+            });
+        }
+        self.advance(length as usize);
+        Ok(())
+    }
+
 }
 
 //impl<T: AsRef<[u8]> + Buf> BufExt for T {}
@@ -207,6 +238,40 @@ pub fn is_eof(data: &[u8]) -> bool {
     data.len() <= 9 && *unsafe { data.get_unchecked(4) } == EOF_HEADER
 }
 
+// Status flags of EOF packet, `data` contains the packet header.
+#[inline]
+pub fn get_eof_status(data: &[u8]) -> u16 {
+    if data.len() < 9 {
+        return 0;
+    }
+    LittleEndian::read_u16(&data[7..9])
+}
+
+// Status flags of OK packet, `data` contains the packet header.
+pub fn get_ok_status(data: &[u8]) -> u16 {
+    if data.len() < 6 {
+        return 0;
+    }
+
+    // Skip affected rows and last insert id.
+    let mut pos = 5;
+    for _ in 0..2 {
+        let n = match data.get(pos) {
+            Some(0xfc) => 3,
+            Some(0xfd) => 4,
+            Some(0xfe) => 9,
+            Some(_) => 1,
+            None => return 0,
+        };
+        pos += n;
+    }
+
+    if data.len() < pos + 2 {
+        return 0;
+    }
+    LittleEndian::read_u16(&data[pos..pos + 2])
+}
+
 // https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_basic_ok_packet.html
 #[inline]
 pub fn is_ok(data: &[u8]) -> bool {
@@ -237,8 +302,8 @@ mod test {
 
     use super::{length_encoded_string, BufExt};
     use crate::util::{
-        calc_caching_sha2password, calc_password, compare, get_length, is_eof, is_ok,
-        random_buf,
+        calc_caching_sha2password, calc_password, compare, get_eof_status, get_length, get_ok_status,
+        is_eof, is_ok, random_buf,
     };
 
     #[test]
@@ -320,6 +385,20 @@ mod test {
         assert_eq!(c, 1);
     }
 
+    #[test]
+    fn test_try_skip_lenc_length() {
+        let mut data = &[0x02, 0x31, 0x32, 0x33][..];
+        assert!(data.try_skip_lenc_length().is_ok());
+        assert_eq!(data, &[0x33]);
+
+        let mut data = &[0x05, 0x31, 0x32][..];
+        assert!(data.try_skip_lenc_length().is_err());
+        let mut data = &[0xfc, 0x31][..];
+        assert!(data.try_skip_lenc_length().is_err());
+        let mut data = &[][..];
+        assert!(data.try_skip_lenc_length().is_err());
+    }
+
     #[test]
     fn test_length_enc_string() {
         let data = [0x04, 0x55, 0x73, 0x65, 0x72];
@@ -361,6 +440,25 @@ mod test {
         assert_eq!(result, false);
     }
 
+    #[test]
+    fn test_get_eof_status() {
+        let data = [0x05, 0x00, 0x00, 0x05, 0xfe, 0x00, 0x00, 0x42, 0x00];
+        assert_eq!(get_eof_status(&data[..]), 0x42);
+        assert_eq!(get_eof_status(&data[..5]), 0);
+    }
+
+    #[test]
+    fn test_get_ok_status() {
+        let data = [7, 0, 0, 1, 0, 1, 0, 0x0a, 0, 0, 0];
+        assert_eq!(get_ok_status(&data), 0x0a);
+
+        // affected rows is encoded by 3 bytes
+        let data = [9, 0, 0, 1, 0, 0xfc, 0, 1, 0, 0x08, 0, 0, 0];
+        assert_eq!(get_ok_status(&data), 0x08);
+
+        assert_eq!(get_ok_status(&data[..8]), 0);
+    }
+
     #[test]
     fn test_is_ok_success() {
         let data = [0x07, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00];
@@ -22,6 +22,9 @@ pub struct Endpoint {
     pub user: String,
     pub password: String,
     pub addr: String,
+    // compression algorithm of backend connections, `None` means uncompressed
+    pub compression: Option<String>,
+    pub compression_level: Option<i32>,
 }
 
 impl fmt::Debug for Endpoint {
@@ -113,6 +113,8 @@ mod test {
             user: String::from("root"),
             password: String::from("root"),
             addr: String::from("127.0.0.1:3306"),
+            compression: None,
+            compression_level: None,
         };
         let ep2 = Endpoint {
             weight: 1,
@@ -121,10 +123,39 @@ mod test {
             user: String::from("root"),
             password: String::from("root"),
             addr: String::from("127.0.0.1:3307"),
+            compression: None,
+            compression_level: None,
         };
         balance.add(ep1);
         balance.add(ep2);
         assert_eq!(balance.next().unwrap().name, String::from("dasheng001"));
         assert_eq!(balance.next().unwrap().name, String::from("dasheng002"));
     }
+
+    #[test]
+    fn remove_item() {
+        let ep = |name: &str, weight: i64| Endpoint {
+            weight,
+            name: String::from(name),
+            addr: format!("{}:3306", name),
+            ..Default::default()
+        };
+
+        for algorithm_name in [AlgorithmName::Random, AlgorithmName::RoundRobin] {
+            let mut balance = Balance.build_balance(algorithm_name);
+            balance.add(ep("dasheng001", 1));
+            balance.add(ep("dasheng002", 3));
+
+            balance.remove_item(ep("dasheng002", 3));
+            for _ in 0..4 {
+                assert_eq!(balance.next().unwrap().name, String::from("dasheng001"));
+            }
+
+            balance.remove_item(ep("dasheng001", 1));
+            assert!(balance.next().is_none());
+
+            balance.add(ep("dasheng002", 2));
+            assert_eq!(balance.next().unwrap().name, String::from("dasheng002"));
+        }
+    }
 }
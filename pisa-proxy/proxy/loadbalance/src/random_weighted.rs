@@ -82,13 +82,18 @@ impl LoadBalance for RandomWeighted {
 
     // remove_item: remove item
     fn remove_item(&mut self, endpoint: Endpoint) {
-        let index = self.items.iter().position(|x| *x.name == *endpoint.name).unwrap();
-        self.items.remove(index);
+        if let Some(index) = self.items.iter().position(|x| *x.name == *endpoint.name) {
+            let item = self.items.remove(index);
+            self.sum_of_weights -= item.weight;
+            self.n -= 1;
+        }
     }
 
     // remove_all: remove all item
     fn remove_all(&mut self) {
         self.items = vec![];
+        self.n = 0;
+        self.sum_of_weights = 0;
         self.r = StdRng::seed_from_u64(Utc::now().timestamp_subsec_nanos().into());
     }
 }
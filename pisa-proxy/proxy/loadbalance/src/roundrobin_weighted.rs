@@ -89,8 +89,10 @@ impl LoadBalance for RoundRobinWeighted {
     }
 
     fn remove_item(&mut self, endpoint: Endpoint) {
-        let index = self.items.iter().position(|x| *x.name == *endpoint.name).unwrap();
-        self.items.remove(index);
+        // The gcd and max weight are computed again from the remaining items.
+        let items = std::mem::take(&mut self.items);
+        self.remove_all();
+        items.into_iter().filter(|x| x.name != endpoint.name).for_each(|x| self.add(x));
     }
     fn remove_all(&mut self) {
         self.items = vec![];
@@ -44,6 +44,16 @@ pub trait ConnAttr {
     fn get_charset(&self) -> Option<String>;
     // Get current autocommit status
     fn get_autocommit(&self) -> Option<String>;
+    // Get the key of pool partition which current conn belongs to
+    fn get_pool_key(&self) -> String {
+        pool_key(&self.get_endpoint(), &self.get_user())
+    }
+}
+
+/// Connections are partitioned by endpoint and user, so that a conn dialed with one
+/// backend user is never handed out to a session mapped to another backend user.
+pub fn pool_key(endpoint: &str, user: &str) -> String {
+    format!("{}@{}", user, endpoint)
 }
 
 #[async_trait]
@@ -80,6 +90,9 @@ where
 {
     pub pool: Arc<DashMap<String, PoolInner<T>>>,
     pub conn: Option<T>,
+    // Endpoints removed from the pool, their conns are closed instead of being put back.
+    removed: Arc<DashMap<String, ()>>,
+    size: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -90,6 +103,7 @@ where
     factory: Option<T>,
     size: usize,
     pool: Arc<DashMap<String, PoolInner<T>>>,
+    removed: Arc<DashMap<String, ()>>,
 }
 
 impl<T> Pool<T>
@@ -99,7 +113,12 @@ where
     pub fn new(size: usize) -> Pool<T> {
         //let pool_inner = PoolInner::new(size);
 
-        Pool { factory: None, size, pool: Arc::new(DashMap::<String, PoolInner<T>>::new()) }
+        Pool {
+            factory: None,
+            size,
+            pool: Arc::new(DashMap::<String, PoolInner<T>>::new()),
+            removed: Arc::new(DashMap::new()),
+        }
     }
 
     pub fn set_factory(&mut self, factory: T) {
@@ -108,38 +127,36 @@ where
 
     pub async fn rebuild_conn(&self) -> Result<PoolConn<T>, T::Error> {
         let conn = self.factory.as_ref().unwrap().build_conn().await?;
-        Ok(PoolConn { pool: Arc::clone(&self.pool), conn: Some(conn) })
+        Ok(self.pool_conn(conn))
     }
     
     pub async fn rebuild_conn_with_session(&self, attrs: &[<T as ConnAttrMut>::Item]) -> Result<PoolConn<T>, T::Error> {
         let mut conn = self.factory.as_ref().unwrap().build_conn().await?;
         self.reinit_session(&mut conn, attrs).await;
-        Ok(PoolConn { pool: Arc::clone(&self.pool), conn: Some(conn) })
+        Ok(self.pool_conn(conn))
     }
 
     pub async fn get_conn_with_endpoint_session(&self, endpoint: &str, attrs: &[<T as ConnAttrMut>::Item]) -> Result<PoolConn<T>, T::Error> {
         let mut conn = self.get_conn_with_endpoint(endpoint).await?;
         self.reinit_session(&mut conn, attrs).await;
 
-        Ok(PoolConn { pool: Arc::clone(&self.pool), conn: Some(conn) })
+        Ok(self.pool_conn(conn))
     }
 
-    // Get connection by endpoint attribute
+    // Get connection by endpoint attribute, the user of factory decides which partition is used.
+    // The partition is created when the conn is put back, keyed by the conn's own user and endpoint.
     pub async fn get_conn_with_endpoint(&self, endpoint: &str) -> Result<T, T::Error> {
-        let conn = self.pool.get(endpoint);
-        let conn = match conn {
-            Some(val) => val.get_conn(),
+        // The endpoint is added back, e.g. by reloading config.
+        self.removed.remove(endpoint);
+
+        let conn = match self.factory_pool_key(endpoint) {
+            Some(key) => self.pool.get(&key).and_then(|x| x.get_conn()),
             None => None,
         };
 
         let conn = match conn {
             Some(conn) => conn,
-            None => {
-                if !self.pool.contains_key(endpoint) {
-                    self.pool.insert(endpoint.to_string(), PoolInner::new(self.size));
-                }
-                self.factory.as_ref().unwrap().build_conn().await?
-            }
+            None => self.factory.as_ref().unwrap().build_conn().await?,
         };
 
         Ok(conn)
@@ -150,14 +167,89 @@ where
         conn.init(attrs).await
     }
 
+    fn factory_pool_key(&self, endpoint: &str) -> Option<String> {
+        self.factory.as_ref().map(|x| pool_key(endpoint, &x.get_user()))
+    }
+
+    fn pool_conn(&self, conn: T) -> PoolConn<T> {
+        PoolConn {
+            pool: Arc::clone(&self.pool),
+            conn: Some(conn),
+            removed: Arc::clone(&self.removed),
+            size: self.size,
+        }
+    }
+
+    // Idle conns of endpoint, summed over all users.
     pub fn len(&self, endpoint: &str) -> usize {
-        match self.pool.get(endpoint) {
+        self.pool
+            .iter()
+            .filter(|x| matches!(x.key().rsplit_once('@'), Some((_, ep)) if ep == endpoint))
+            .map(|x| x.inner.len())
+            .sum()
+    }
+
+    // Take all idle conns out of the pool, e.g. they are closed when the proxy shuts down.
+    pub fn drain(&self) -> Vec<T> {
+        let mut conns = vec![];
+        for inner in self.pool.iter() {
+            while let Some(conn) = inner.get_conn() {
+                conns.push(conn);
+            }
+        }
+        conns
+    }
+
+    // Remove the partitions of endpoint and take their idle conns, e.g. the node is removed
+    // by reloading config. The conns in use are closed when they are put back, until the endpoint
+    // is used again.
+    pub fn remove_endpoint(&self, endpoint: &str) -> Vec<T> {
+        self.removed.insert(endpoint.to_string(), ());
+        let mut conns = vec![];
+        self.pool.retain(|key, inner| match key.rsplit_once('@') {
+            Some((_, ep)) if ep == endpoint => {
+                while let Some(conn) = inner.get_conn() {
+                    conns.push(conn);
+                }
+                false
+            }
+            _ => true,
+        });
+        conns
+    }
+
+    // Idle conns of each partition, the key is `user@endpoint`.
+    pub fn idle_stats(&self) -> Vec<(String, usize)> {
+        let mut stats =
+            self.pool.iter().map(|x| (x.key().clone(), x.inner.len())).collect::<Vec<_>>();
+        stats.sort();
+        stats
+    }
+
+    // Capacity of each partition.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Idle conns of endpoint which are dialed with `user`.
+    pub fn len_with_user(&self, endpoint: &str, user: &str) -> usize {
+        match self.pool.get(&pool_key(endpoint, user)) {
             Some(inner) => inner.inner.len(),
             None => 0,
         }
     }
 }
 
+impl<T> PoolConn<T>
+where
+    T: ConnLike + ConnAttr + ConnAttrMut,
+{
+    // Close the conn instead of putting it back to the pool, e.g. its session state can not be reset.
+    pub fn discard(mut self) {
+        let _ = self.conn.take();
+    }
+}
+
 impl<T> Deref for PoolConn<T>
 where
     T: ConnLike + ConnAttr + ConnAttrMut,
@@ -186,8 +278,150 @@ where
         if self.conn.is_some() {
             debug!("put conn {:?}", &self.conn);
             let conn = self.conn.take().unwrap();
-            let endpoint = conn.get_endpoint();
-            self.pool.get_mut(&endpoint).unwrap().put_conn(conn);
+            if self.removed.contains_key(&conn.get_endpoint()) {
+                debug!("close conn of removed endpoint {}", conn.get_endpoint());
+                return;
+            }
+
+            let key = conn.get_pool_key();
+            self.pool.entry(key).or_insert_with(|| PoolInner::new(self.size)).put_conn(conn);
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct TestConn {
+        user: String,
+        endpoint: String,
+    }
+
+    #[async_trait]
+    impl ConnLike for TestConn {
+        type Error = ();
+
+        async fn build_conn(&self) -> Result<Self, Self::Error> {
+            Ok(TestConn { user: self.user.clone(), endpoint: self.endpoint.clone() })
+        }
+    }
+
+    impl ConnAttr for TestConn {
+        fn get_host(&self) -> String {
+            "".to_string()
+        }
+
+        fn get_port(&self) -> u16 {
+            0
+        }
+
+        fn get_user(&self) -> String {
+            self.user.clone()
+        }
+
+        fn get_endpoint(&self) -> String {
+            self.endpoint.clone()
+        }
+
+        fn get_db(&self) -> Option<String> {
+            None
+        }
+
+        fn get_charset(&self) -> Option<String> {
+            None
+        }
+
+        fn get_autocommit(&self) -> Option<String> {
+            None
+        }
+    }
+
+    impl ConnAttrMut for TestConn {
+        type Item = ();
+    }
+
+    #[test]
+    fn test_pool_partition_by_user() {
+        let endpoint = "127.0.0.1:3306";
+        let mut pool = Pool::<TestConn>::new(2);
+
+        futures::executor::block_on(async {
+            pool.set_factory(TestConn { user: "app".to_string(), endpoint: endpoint.to_string() });
+            let conn = pool.get_conn_with_endpoint_session(endpoint, &[]).await.unwrap();
+            assert_eq!(conn.get_user(), "app");
+            drop(conn);
+            assert_eq!(pool.len_with_user(endpoint, "app"), 1);
+
+            pool.set_factory(TestConn { user: "ops".to_string(), endpoint: endpoint.to_string() });
+            let conn = pool.get_conn_with_endpoint_session(endpoint, &[]).await.unwrap();
+            assert_eq!(conn.get_user(), "ops");
+            assert_eq!(pool.len_with_user(endpoint, "app"), 1);
+            drop(conn);
+
+            assert_eq!(pool.len_with_user(endpoint, "ops"), 1);
+            assert_eq!(pool.len(endpoint), 2);
+            assert_eq!(pool.len("127.0.0.1:3307"), 0);
+
+            let conn = pool.get_conn_with_endpoint_session(endpoint, &[]).await.unwrap();
+            conn.discard();
+            assert_eq!(pool.len_with_user(endpoint, "ops"), 0);
+        });
+    }
+
+    #[test]
+    fn test_pool_drain() {
+        let mut pool = Pool::<TestConn>::new(2);
+
+        futures::executor::block_on(async {
+            for endpoint in ["127.0.0.1:3306", "127.0.0.1:3307"] {
+                pool.set_factory(TestConn { user: "app".to_string(), endpoint: endpoint.to_string() });
+                let conn = pool.get_conn_with_endpoint_session(endpoint, &[]).await.unwrap();
+                drop(conn);
+            }
+
+            assert_eq!(
+                pool.idle_stats(),
+                vec![("app@127.0.0.1:3306".to_string(), 1), ("app@127.0.0.1:3307".to_string(), 1)]
+            );
+            assert_eq!(pool.drain().len(), 2);
+            assert_eq!(pool.len("127.0.0.1:3306"), 0);
+            assert!(pool.drain().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_pool_remove_endpoint() {
+        let mut pool = Pool::<TestConn>::new(2);
+
+        futures::executor::block_on(async {
+            pool.set_factory(TestConn { user: "app".to_string(), endpoint: "127.0.0.1:3306".to_string() });
+            let conn = pool.get_conn_with_endpoint_session("127.0.0.1:3306", &[]).await.unwrap();
+            let in_use = pool.get_conn_with_endpoint_session("127.0.0.1:3306", &[]).await.unwrap();
+            drop(conn);
+
+            assert_eq!(pool.remove_endpoint("127.0.0.1:3307").len(), 0);
+            assert_eq!(pool.remove_endpoint("127.0.0.1:3306").len(), 1);
+
+            drop(in_use);
+            assert_eq!(pool.len("127.0.0.1:3306"), 0);
+
+            let conn = pool.get_conn_with_endpoint_session("127.0.0.1:3306", &[]).await.unwrap();
+            drop(conn);
+            assert_eq!(pool.len("127.0.0.1:3306"), 1);
+        });
+    }
+
+    #[test]
+    fn test_pool_put_rebuilt_conn() {
+        let mut pool = Pool::<TestConn>::new(2);
+
+        futures::executor::block_on(async {
+            pool.set_factory(TestConn { user: "app".to_string(), endpoint: "127.0.0.1:3306".to_string() });
+            let conn = pool.rebuild_conn().await.unwrap();
+            drop(conn);
+            assert_eq!(pool.idle_stats(), vec![("app@127.0.0.1:3306".to_string(), 1)]);
+        });
+    }
+}
@@ -14,6 +14,8 @@
 
 use pisa_error::error::Error;
 
+use crate::shutdown::Shutdown;
+
 pub enum ProxyKind {
     MySQL,
     ShardingSphereProxy,
@@ -21,7 +23,8 @@ pub enum ProxyKind {
 
 #[async_trait::async_trait]
 pub trait Proxy {
-    async fn start(&mut self) -> Result<(), Error>;
+    // Serve until the shutdown is triggered, the open transactions are drained before returning.
+    async fn start(&mut self, shutdown: Shutdown) -> Result<(), Error>;
 }
 
 pub trait ProxyFactory {
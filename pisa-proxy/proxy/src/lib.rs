@@ -15,3 +15,5 @@
 pub mod factory;
 pub mod listener;
 pub mod proxy;
+pub mod reload;
+pub mod shutdown;
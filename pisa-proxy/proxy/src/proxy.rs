@@ -55,6 +55,110 @@ pub struct ProxyConfig {
     pub plugin: Option<plugin::config::Plugin>,
     // read write splitting config structure
     pub read_write_splitting: Option<ReadWriteSplitting>,
+    // frontend user table, `user` and `password` above are used when it is empty
+    pub users: Option<Vec<ProxyUser>>,
+    // tls config of the client-facing listener
+    pub tls: Option<ProxyTls>,
+    // auth plugin advertised to clients, `mysql_native_password` or `caching_sha2_password`
+    pub default_auth_plugin: Option<String>,
+    // allow clients to negotiate the compressed protocol
+    #[serde(default)]
+    pub compression: bool,
+    // relay `LOAD DATA LOCAL INFILE` requests of backends to clients
+    #[serde(default = "default_local_infile")]
+    pub local_infile: bool,
+    // split the rows of `LOAD DATA LOCAL INFILE` to shards by sharding column
+    #[serde(default)]
+    pub local_infile_sharding: bool,
+    // forward the binlog stream of the read-write endpoint to replication clients
+    #[serde(default)]
+    pub replication_passthrough: bool,
+    // frontend users allowed to subscribe the binlog stream, the users with `allowed_dbs` are refused
+    #[serde(default)]
+    pub replication_users: Vec<String>,
+    // commit the transactions across shards by XA two-phase commit
+    pub xa: Option<ProxyXa>,
+    // seconds to wait for the open transactions to finish when the proxy shuts down, the sessions still open are aborted then
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    // frontend users allowed to run the `SHOW PISA` and `PISA SET` admin statements
+    #[serde(default)]
+    pub admin_users: Vec<String>,
+    // log the statements which take longer than the threshold
+    pub slow_log: Option<ProxySlowLog>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxySlowLog {
+    // milliseconds, the statements taking longer than it are logged
+    #[serde(default = "default_slow_log_threshold")]
+    pub threshold: u64,
+    // `stdout` or the path of log file
+    #[serde(default = "default_slow_log_output")]
+    pub output: String,
+    // megabytes, the log file is rotated when it exceeds the size
+    #[serde(default = "default_slow_log_max_size")]
+    pub max_size: u64,
+    // the rotated files kept as `<output>.1`, `<output>.2` and so on, `<output>.1` is the latest
+    #[serde(default = "default_slow_log_max_files")]
+    pub max_files: u32,
+    // replace the literals of the logged sql with `?`
+    #[serde(default)]
+    pub redact: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxyXa {
+    // recovery log of the XA transactions decided to commit, it must be unique per proxy,
+    // the id of the proxy instance is persisted to `<log_path>.id`
+    #[serde(default = "default_xa_log_path")]
+    pub log_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxyTls {
+    // PEM certificate chain, a self-signed certificate is used when `cert` and `key` are not set
+    pub cert: Option<String>,
+    // PEM private key
+    pub key: Option<String>,
+    // PEM client CA, client certificates are required when it is set
+    pub client_ca: Option<String>,
+    // reject clients which don't upgrade to tls
+    #[serde(default)]
+    pub require_secure_transport: bool,
+    // interval in seconds to check whether the certificate files are changed, 0 disables reload
+    #[serde(default = "default_tls_reload_interval")]
+    pub reload_interval: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxyUser {
+    pub user: String,
+    pub password: Option<String>,
+    // hex encoded SHA1(SHA1(password)), the same as `authentication_string` in `mysql.user`
+    pub native_password_hash: Option<String>,
+    // hex encoded SHA256(SHA256(password))
+    pub caching_sha2_password_hash: Option<String>,
+    // allowed databases, empty means all
+    #[serde(default)]
+    pub allowed_dbs: Vec<String>,
+    // allowed source ip or cidr, empty means all
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    // connect to backend with the same user and password, `password` is required
+    #[serde(default)]
+    pub backend_passthrough: bool,
+    // backend user per node, falls back to the user of node config
+    #[serde(default)]
+    pub backend_users: Vec<ProxyBackendUser>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxyBackendUser {
+    // node name, `None` means all nodes
+    pub node: Option<String>,
+    pub user: String,
+    pub password: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -81,7 +185,11 @@ pub struct MySQLNode {
     pub name: String,
     pub db: String,
     pub user: String,
-    pub password: String,
+    pub password: Option<String>,
+    // read the password from the file, e.g. a mounted secret
+    pub password_file: Option<String>,
+    // read the password from the environment variable
+    pub password_env: Option<String>,
     #[serde(default = "default_mysql_node_host")]
     pub host: String,
     #[serde(default = "default_mysql_node_port")]
@@ -89,6 +197,10 @@ pub struct MySQLNode {
     #[serde(default = "default_mysql_node_weight")]
     pub weight: i64,
     pub role: TargetRole,
+    // compression algorithm of backend connections: [zlib/zstd], not compressed when it is not set
+    pub compression: Option<String>,
+    // zlib: 1 ~ 9, default 6; zstd: 1 ~ 22, default 3
+    pub compression_level: Option<i32>,
 }
 
 fn default_auto_proxy_name() -> String {
@@ -127,6 +239,14 @@ fn default_auto_balance_type() -> AlgorithmName {
     AlgorithmName::Random
 }
 
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_xa_log_path() -> String {
+    "pisa_xa.log".into()
+}
+
 fn default_mysql_node_host() -> String {
     "127.0.0.1".into()
 }
@@ -143,6 +263,30 @@ fn default_mysql_node_weight() -> i64 {
     1
 }
 
+fn default_tls_reload_interval() -> u64 {
+    60
+}
+
+fn default_slow_log_threshold() -> u64 {
+    1000
+}
+
+fn default_slow_log_output() -> String {
+    "stdout".to_string()
+}
+
+fn default_slow_log_max_size() -> u64 {
+    100
+}
+
+fn default_slow_log_max_files() -> u32 {
+    5
+}
+
+fn default_local_infile() -> bool {
+    true
+}
+
 impl From<MySQLNode> for Endpoint {
     fn from(node: MySQLNode) -> Self {
         Self {
@@ -150,8 +294,10 @@ impl From<MySQLNode> for Endpoint {
             name: node.name,
             db: node.db,
             user: node.user,
-            password: node.password,
+            password: node.password.unwrap_or_default(),
             addr: format!("{}:{}", node.host, node.port),
+            compression: node.compression,
+            compression_level: node.compression_level,
         }
     }
 }
@@ -191,8 +337,10 @@ impl Proxy {
                         addr: format!("{}:{}", node.host, node.port),
                         db: node.db,
                         user: node.user,
-                        password: node.password,
+                        password: node.password.unwrap_or_default(),
                         weight: node.weight,
+                        compression: node.compression,
+                        compression_level: node.compression_level,
                     };
                     balancer.add(endpoint);
                 }
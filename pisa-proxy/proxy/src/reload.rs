@@ -0,0 +1,65 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use strategy::config::NodeGroup;
+use tokio::sync::watch;
+
+use crate::proxy::{MySQLNode, ProxyConfig};
+
+/// The config which is applied to the running proxies without restarting.
+#[derive(Debug, Clone, Default)]
+pub struct ReloadConfig {
+    pub proxies: Vec<ProxyConfig>,
+    pub mysql_nodes: Vec<MySQLNode>,
+    pub node_group: Option<NodeGroup>,
+}
+
+impl ReloadConfig {
+    pub fn get_proxy(&self, name: &str) -> Option<&ProxyConfig> {
+        self.proxies.iter().find(|x| x.name == name)
+    }
+}
+
+pub type ReloadSender = watch::Sender<Arc<ReloadConfig>>;
+pub type ReloadReceiver = watch::Receiver<Arc<ReloadConfig>>;
+
+/// Create the channel, the receiver is cloned to the proxies and the sender is held by the config watcher.
+pub fn reload_channel(config: ReloadConfig) -> (ReloadSender, ReloadReceiver) {
+    watch::channel(Arc::new(config))
+}
+
+// The config is compared by the debug output, the config types are plain data.
+pub fn is_changed<T: std::fmt::Debug>(old: &T, new: &T) -> bool {
+    format!("{:?}", old) != format!("{:?}", new)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reload_config() {
+        let mut config = ReloadConfig::default();
+        config.proxies.push(ProxyConfig { name: "p1".to_string(), ..Default::default() });
+        assert!(config.get_proxy("p1").is_some());
+        assert!(config.get_proxy("p2").is_none());
+
+        let mut changed = config.proxies[0].clone();
+        assert!(!is_changed(&config.proxies[0], &changed));
+        changed.pool_size = 10;
+        assert!(is_changed(&config.proxies[0], &changed));
+    }
+}
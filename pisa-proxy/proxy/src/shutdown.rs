@@ -0,0 +1,68 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tokio::sync::watch;
+
+/// Create a shutdown trigger and the handle which is cloned to the proxies and their sessions.
+pub fn shutdown_channel() -> (ShutdownTrigger, Shutdown) {
+    let (tx, rx) = watch::channel(false);
+    (ShutdownTrigger { tx }, Shutdown { rx })
+}
+
+/// Notify the proxies to stop accepting and drain the sessions, e.g. on SIGTERM.
+pub struct ShutdownTrigger {
+    tx: watch::Sender<bool>,
+}
+
+impl ShutdownTrigger {
+    pub fn shutdown(&self) {
+        let _ = self.tx.send(true);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn is_shutdown(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    // Wait until the shutdown is triggered, it never completes when the trigger is dropped.
+    pub async fn recv(&mut self) {
+        while !*self.rx.borrow() {
+            if self.rx.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (trigger, mut shutdown) = shutdown_channel();
+        let cloned = shutdown.clone();
+        assert!(!cloned.is_shutdown());
+
+        trigger.shutdown();
+        shutdown.recv().await;
+        assert!(cloned.is_shutdown());
+    }
+}
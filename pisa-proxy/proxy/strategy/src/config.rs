@@ -43,6 +43,22 @@ pub struct ReadWriteSplitting {
     pub dynamic: Option<ReadWriteSplittingDynamic>,
 }
 
+impl ReadWriteSplitting {
+    // Check the regex of rules, so that the invalid config is rejected before building routes.
+    pub fn check(&self) -> Result<(), regex::Error> {
+        let statics = self.statics.iter().flat_map(|x| x.rules.iter());
+        let dynamic = self.dynamic.iter().flat_map(|x| x.rules.iter());
+        for rule in statics.chain(dynamic) {
+            if let ReadWriteSplittingRule::Regex(rule) = rule {
+                for regex in &rule.regex {
+                    regex::Regex::new(regex)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ReadWriteSplittingStatic {
     pub default_target: TargetRole,
@@ -74,7 +90,11 @@ pub enum Discovery {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub struct MasterHighAvailability {
     pub user: String,
-    pub password: String,
+    pub password: Option<String>,
+    // read the password from the file, e.g. a mounted secret
+    pub password_file: Option<String>,
+    // read the password from the environment variable
+    pub password_env: Option<String>,
     #[serde(default = "default_monitor_period")]
     pub monitor_period: u64,
     #[serde(default = "default_connect_period")]
@@ -176,6 +196,9 @@ pub enum ShardingAlgorithmName {
 pub struct DatabaseStrategyConfig {
     pub database_sharding_algorithm_name: ShardingAlgorithmName,
     pub database_sharding_column: String,
+    // The databases are sharded by the number of `actual_datanodes`, it must be equal to them when set
+    #[serde(default)]
+    pub sharding_count: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use tokio_util::sync::CancellationToken;
+
 use crate::{
     config::MasterHighAvailability,
     monitors::{
@@ -56,7 +58,7 @@ impl Discovery for DiscoveryMasterHighAvailability {
         let mut monitors = vec![];
         monitors.push(MonitorKind::Connect(MonitorConnect::new(
             self.config.user.clone(),
-            self.config.password.clone(),
+            self.config.password.clone().unwrap_or_default(),
             self.config.connect_period,
             self.config.connect_timeout,
             self.config.connect_failure_threshold,
@@ -65,7 +67,7 @@ impl Discovery for DiscoveryMasterHighAvailability {
         )));
         monitors.push(MonitorKind::Ping(MonitorPing::new(
             self.config.user.clone(),
-            self.config.password.clone(),
+            self.config.password.clone().unwrap_or_default(),
             self.config.ping_period,
             self.config.ping_timeout,
             self.config.ping_failure_threshold,
@@ -75,7 +77,7 @@ impl Discovery for DiscoveryMasterHighAvailability {
         if self.config.read_only_enabled {
             monitors.push(MonitorKind::ReadOnly(MonitorReadOnly::new(
                 self.config.user.clone(),
-                self.config.password.clone(),
+                self.config.password.clone().unwrap_or_default(),
                 self.config.read_only_period,
                 self.config.read_only_timeout,
                 self.config.read_only_failure_threshold,
@@ -86,7 +88,7 @@ impl Discovery for DiscoveryMasterHighAvailability {
         if self.config.replication_lag_enabled {
             monitors.push(MonitorKind::ReplicationLag(MonitorReplicationLag::new(
                 self.config.user.clone(),
-                self.config.password.clone(),
+                self.config.password.clone().unwrap_or_default(),
                 self.config.replication_lag_period,
                 self.config.replication_lag_timeout,
                 self.config.replication_lag_failure_threshold,
@@ -110,13 +112,13 @@ pub enum MonitorKind {
 
 #[async_trait::async_trait]
 impl Monitor for MonitorKind {
-    async fn run_check(&self) {
+    async fn run_check(&self, cancel: CancellationToken) {
         match self {
-            MonitorKind::Connect(inner_connect_monitor) => inner_connect_monitor.run_check().await,
-            MonitorKind::Ping(inner_ping_monitor) => inner_ping_monitor.run_check().await,
-            MonitorKind::ReplicationLag(inner_lag_monitor) => inner_lag_monitor.run_check().await,
+            MonitorKind::Connect(inner_connect_monitor) => inner_connect_monitor.run_check(cancel).await,
+            MonitorKind::Ping(inner_ping_monitor) => inner_ping_monitor.run_check(cancel).await,
+            MonitorKind::ReplicationLag(inner_lag_monitor) => inner_lag_monitor.run_check(cancel).await,
             MonitorKind::ReadOnly(inner_read_only_monitor) => {
-                inner_read_only_monitor.run_check().await
+                inner_read_only_monitor.run_check(cancel).await
             }
         }
     }
@@ -124,5 +126,6 @@ impl Monitor for MonitorKind {
 
 #[async_trait::async_trait]
 pub trait Monitor {
-    async fn run_check(&self);
+    // The check runs until the token is cancelled.
+    async fn run_check(&self, cancel: CancellationToken);
 }
@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crossbeam_channel::unbounded;
+use crossbeam_channel::{unbounded, RecvTimeoutError};
+use tokio_util::sync::CancellationToken;
 use tracing::error;
 
 use crate::{
@@ -42,6 +43,7 @@ impl MonitorReconcile {
         master_high_availability: MasterHighAvailability,
         monitor_response_channel: crate::readwritesplitting::MonitorResponseChannel,
         monitors_len: usize,
+        cancel: CancellationToken,
     ) -> crossbeam_channel::Receiver<ReadWriteEndpoint> {
         let (send, recv) = unbounded();
         let tx = send.clone();
@@ -57,6 +59,7 @@ impl MonitorReconcile {
                 rw_endpoint,
                 monitor_response_channel,
                 monitors_len,
+                cancel,
             )
             .await;
         });
@@ -70,6 +73,7 @@ impl MonitorReconcile {
         rw_endpoint: ReadWriteEndpoint,
         monitor_response_channel: crate::readwritesplitting::MonitorResponseChannel,
         monitors_len: usize,
+        cancel: CancellationToken,
     ) {
         let mut connect_monitor_response: Option<ConnectMonitorResponse> = None;
         let mut ping_monitor_response: Option<PingMonitorResponse> = None;
@@ -82,7 +86,21 @@ impl MonitorReconcile {
                 let monitor_response_channel = monitor_response_channel.clone();
                 let mut curr_rw_endpoint = rw_endpoint.clone();
                 for _ in 0..monitors_len {
-                    match monitor_response_channel.monitor_response_rx.recv().unwrap() {
+                    // The responses are polled so that the reconcile stops once the monitors are cancelled.
+                    let response = loop {
+                        if cancel.is_cancelled() {
+                            return;
+                        }
+
+                        let timeout = std::time::Duration::from_millis(100);
+                        match monitor_response_channel.monitor_response_rx.recv_timeout(timeout) {
+                            Ok(response) => break response,
+                            Err(RecvTimeoutError::Timeout) => continue,
+                            Err(RecvTimeoutError::Disconnected) => return,
+                        }
+                    };
+
+                    match response {
                         MonitorResponse::ConnectMonitorResponse(connect_response) => {
                             connect_monitor_response = Some(connect_response);
                         }
@@ -15,6 +15,7 @@
 use std::collections::HashMap;
 
 use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error};
 
 use crate::{
@@ -95,7 +96,7 @@ impl MonitorConnect {
 
 #[async_trait::async_trait]
 impl Monitor for MonitorConnect {
-    async fn run_check(&self) {
+    async fn run_check(&self, cancel: CancellationToken) {
         let connect_period = self.connect_period;
         let connect_failure_threshold = self.connect_failure_threshold;
         let connect_timeout = self.connect_timeout;
@@ -105,7 +106,7 @@ impl Monitor for MonitorConnect {
         // build connect monitor message channel
         let mut response = ConnectMonitorResponse::new(rw_endpoint.clone());
 
-        tokio::spawn(async move {
+        let check = async move {
             let mut retries = 1;
             loop {
                 // probe read endpoint
@@ -203,6 +204,13 @@ impl Monitor for MonitorConnect {
                 // connect monitor probe interval
                 std::thread::sleep(std::time::Duration::from_millis(connect_period));
             }
+        };
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = check => {}
+                _ = cancel.cancelled() => {}
+            }
         });
     }
 }
@@ -17,6 +17,7 @@ use std::collections::HashMap;
 use mysql_protocol::client::conn::ClientConn;
 use pisa_error::error::Error;
 use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error};
 
 use crate::{
@@ -104,7 +105,7 @@ impl MonitorPing {
 
 #[async_trait::async_trait]
 impl Monitor for MonitorPing {
-    async fn run_check(&self) {
+    async fn run_check(&self, cancel: CancellationToken) {
         let user = self.user.clone();
         let password = self.password.clone();
         let ping_period = self.ping_period;
@@ -115,7 +116,7 @@ impl Monitor for MonitorPing {
 
         let mut response = PingMonitorResponse::new(rw_endpoint.clone());
 
-        tokio::spawn(async move {
+        let check = async move {
             let mut retries = 1;
             loop {
                 for read in &rw_endpoint.read {
@@ -308,6 +309,13 @@ impl Monitor for MonitorPing {
                 }
                 std::thread::sleep(std::time::Duration::from_millis(ping_period));
             }
+        };
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = check => {}
+                _ = cancel.cancelled() => {}
+            }
         });
     }
 }
@@ -18,6 +18,7 @@ use futures::StreamExt;
 use mysql_protocol::{client::conn::ClientConn, row::RowData};
 use pisa_error::error::{Error, ErrorKind};
 use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error};
 
 use crate::{
@@ -114,7 +115,7 @@ impl MonitorReadOnly {
 
 #[async_trait::async_trait]
 impl Monitor for MonitorReadOnly {
-    async fn run_check(&self) {
+    async fn run_check(&self, cancel: CancellationToken) {
         let user = self.user.clone();
         let password = self.password.clone();
         let rw_endpoint = self.rw_endpoint.clone();
@@ -125,7 +126,7 @@ impl Monitor for MonitorReadOnly {
 
         let mut response = ReadOnlyMonitorResponse::new(rw_endpoint.clone());
 
-        tokio::spawn(async move {
+        let check = async move {
             let mut retries = 1;
             loop {
                 // probe read endpoint
@@ -218,6 +219,13 @@ impl Monitor for MonitorReadOnly {
                 }
                 std::thread::sleep(time::Duration::from_millis(read_only_period));
             }
+        };
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = check => {}
+                _ = cancel.cancelled() => {}
+            }
         });
     }
 }
@@ -18,6 +18,7 @@ use futures::StreamExt;
 use mysql_protocol::{client::conn::ClientConn, row::RowData};
 use pisa_error::error::{Error, ErrorKind};
 use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error};
 
 use crate::{
@@ -161,7 +162,7 @@ impl ReplicationLagMonitorResponse {
 
 #[async_trait::async_trait]
 impl Monitor for MonitorReplicationLag {
-    async fn run_check(&self) {
+    async fn run_check(&self, cancel: CancellationToken) {
         let user = self.user.clone();
         let password = self.password.clone();
         let replication_lag_timeout = self.replication_lag_timeout;
@@ -171,7 +172,7 @@ impl Monitor for MonitorReplicationLag {
         let max_replication_lag = self.max_replication_lag;
         let rw_endpoint = self.rw_endpoint.clone();
 
-        tokio::spawn(async move {
+        let check = async move {
             let mut retries = 1;
             loop {
                 let curr_rw_endpoint = MonitorReplicationLag::build_read_only_endpoint(
@@ -365,6 +366,13 @@ impl Monitor for MonitorReplicationLag {
 
                 std::thread::sleep(time::Duration::from_millis(reaplication_lag_period));
             }
+        };
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = check => {}
+                _ = cancel.cancelled() => {}
+            }
         });
     }
 }
@@ -15,6 +15,8 @@
 use endpoint::endpoint::Endpoint;
 use indexmap::IndexMap;
 use loadbalance::balance::LoadBalance;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 
 use super::{
     rule_match::{RulesMatch, RulesMatchBuilder},
@@ -32,7 +34,7 @@ use crate::{
         read_only_monitor::ReadOnlyMonitorResponse,
         replication_lag_monitor::ReplicationLagMonitorResponse,
     },
-    route::{BoxError, RouteBalance, RouteStrategy},
+    route::{BoxError, NodeOverrides, StragegyError},
     Route, RouteInput,
 };
 
@@ -76,6 +78,7 @@ impl ReadWriteSplittingDynamicBuilder {
         );
 
         let monitor_response_channel = MonitorResponseChannel::build();
+        let cancel = CancellationToken::new();
 
         // Match discovery type
         let reciver = match config.clone().discovery {
@@ -86,8 +89,9 @@ impl ReadWriteSplittingDynamicBuilder {
                         .build_monitors(monitor_response_channel.clone());
                 let monitors_len = monitors.len();
                 for monitor in monitors {
+                    let cancel = cancel.clone();
                     tokio::spawn(async move {
-                        monitor.run_check().await;
+                        monitor.run_check(cancel).await;
                     });
                 }
 
@@ -98,11 +102,22 @@ impl ReadWriteSplittingDynamicBuilder {
                     cc.clone(),
                     monitor_response_channel.clone(),
                     monitors_len,
+                    cancel.clone(),
                 )
             }
         };
 
-        ReadWriteSplittingDynamic { rx: reciver, rules: config.clone().rules, node_group_config, rules_match }
+        ReadWriteSplittingDynamic {
+            rx: reciver,
+            rules: config.clone().rules,
+            node_group_config,
+            rules_match,
+            rw_endpoint,
+            overrides: NodeOverrides::default(),
+            role_changes: vec![],
+            overrides_cleared: false,
+            cancel,
+        }
     }
 }
 
@@ -111,6 +126,52 @@ pub struct ReadWriteSplittingDynamic {
     rules: Vec<ReadWriteSplittingRule>,
     node_group_config: Option<config::NodeGroup>,
     rules_match: RulesMatch,
+    // The discovered endpoints
+    rw_endpoint: ReadWriteEndpoint,
+    overrides: NodeOverrides,
+    // The role changes reported by the discovery, not taken yet
+    role_changes: Vec<RoleChange>,
+    // The overrides are cleared since they conflict with the discovery, not taken yet
+    overrides_cleared: bool,
+    // Stops the monitors and the reconcile of the discovery
+    cancel: CancellationToken,
+}
+
+// A node whose role is changed by the discovery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoleChange {
+    pub node: String,
+    // One of `readwrite`, `read` and `offline`
+    pub role: &'static str,
+}
+
+fn node_roles(rw_endpoint: &ReadWriteEndpoint) -> IndexMap<&str, &'static str> {
+    let mut roles = IndexMap::new();
+    for e in &rw_endpoint.read {
+        roles.insert(e.name.as_str(), "read");
+    }
+    for e in &rw_endpoint.readwrite {
+        roles.insert(e.name.as_str(), "readwrite");
+    }
+    roles
+}
+
+fn diff_roles(old: &ReadWriteEndpoint, new: &ReadWriteEndpoint) -> Vec<RoleChange> {
+    let old_roles = node_roles(old);
+    let new_roles = node_roles(new);
+
+    let mut changes = vec![];
+    for (node, role) in &new_roles {
+        if old_roles.get(node) != Some(role) {
+            changes.push(RoleChange { node: node.to_string(), role: *role });
+        }
+    }
+    for node in old_roles.keys() {
+        if !new_roles.contains_key(node) {
+            changes.push(RoleChange { node: node.to_string(), role: "offline" });
+        }
+    }
+    changes
 }
 
 impl Route for ReadWriteSplittingDynamic {
@@ -120,35 +181,122 @@ impl Route for ReadWriteSplittingDynamic {
         &mut self,
         input: &RouteInput,
     ) -> Result<(Option<Endpoint>, TargetRole), Self::Error> {
+        let (endpoint, role, _) = self.dispatch_with_rule(input);
+        Ok((endpoint, role))
+    }
+}
+
+impl ReadWriteSplittingDynamic {
+    // Stop the monitors and the reconcile, the endpoints are not refreshed then.
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    // Rebuild the balances when the discovery reports new endpoints.
+    fn refresh(&mut self) {
         let v: Vec<_> = self.rx.try_iter().collect();
-        match v.last() {
-            Some(rw_endpoint) => {
-                self.rules_match.default_balance = RulesMatchBuilder::build_default_balance(
-                    &self.rules_match.default_target,
-                    rw_endpoint.clone(),
-                );
-                self.rules_match.default_trans_balance = RulesMatchBuilder::build_default_balance(
-                    &TargetRole::ReadWrite,
-                    rw_endpoint.clone(),
-                );
-
-                // `unwrap` is safely`,  because has initilized success when to here.
-                let endpoint_group = RouteStrategy::get_endpoint_group(&self.node_group_config, rw_endpoint).unwrap();
-                self.rules_match.inner = RulesMatchBuilder::build_rules(
-                    self.rules.clone(),
-                    endpoint_group,
-                    rw_endpoint.clone(),
-                    self.rules_match.default_target.clone(),
-                );
-
-                let b = self.rules_match.get(input);
-                Ok((b.0.next(), b.1))
-            }
+        if let Some(rw_endpoint) = v.last() {
+            self.role_changes.extend(diff_roles(&self.rw_endpoint, rw_endpoint));
+            self.rw_endpoint = rw_endpoint.clone();
+            self.rebuild();
+        }
+    }
 
-            None => {
-                let b = self.rules_match.get(input);
-                Ok((b.0.next(), b.1))
-            }
+    // The nodes disabled by the admin api are kept disabled after the discovery changes. The
+    // overrides are cleared when they conflict with the discovered endpoints, e.g. the disabled
+    // node becomes the readwrite node, the owner takes the clearing by `take_overrides_cleared`.
+    fn rebuild(&mut self) {
+        let rw_endpoint = self.overrides.apply_rw(&self.rw_endpoint);
+        let rules = self.rules.clone();
+        if let Err(e) = self.rules_match.rebuild(rules, &self.node_group_config, rw_endpoint) {
+            warn!("node changes {:?} conflict with the discovery, they are cleared: {}", self.overrides, e);
+            self.overrides = NodeOverrides::default();
+            self.overrides_cleared = true;
+            // `unwrap` is safely`,  because has initilized success when to here.
+            self.rules_match
+                .rebuild(self.rules.clone(), &self.node_group_config, self.rw_endpoint.clone())
+                .unwrap();
         }
     }
+
+    pub fn set_overrides(&mut self, overrides: NodeOverrides) -> Result<(), StragegyError> {
+        self.refresh();
+        let rw_endpoint = overrides.apply_rw(&self.rw_endpoint);
+        self.rules_match.rebuild(self.rules.clone(), &self.node_group_config, rw_endpoint)?;
+        self.overrides = overrides;
+        self.overrides_cleared = false;
+        Ok(())
+    }
+
+    // Whether the overrides are cleared by the discovery since the last call.
+    pub fn take_overrides_cleared(&mut self) -> bool {
+        self.refresh();
+        std::mem::take(&mut self.overrides_cleared)
+    }
+
+    // The endpoints after discovery, the disabled nodes are excluded.
+    pub fn get_endpoints(&mut self) -> ReadWriteEndpoint {
+        self.refresh();
+        self.overrides.apply_rw(&self.rw_endpoint)
+    }
+
+    pub fn has_node_group(&self, name: &str) -> bool {
+        self.node_group_config.as_ref().map_or(false, |x| x.members.iter().any(|m| m.name == name))
+    }
+
+    // Dispatch the input, the name of matched rule is returned as well.
+    pub fn dispatch_with_rule(
+        &mut self,
+        input: &RouteInput,
+    ) -> (Option<Endpoint>, TargetRole, String) {
+        self.refresh();
+        let (balance, role, rule) = self.rules_match.get_with_rule(input);
+        (balance.next(), role, rule)
+    }
+
+    pub fn take_role_changes(&mut self) -> Vec<RoleChange> {
+        self.refresh();
+        std::mem::take(&mut self.role_changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn endpoint(name: &str) -> Endpoint {
+        Endpoint { name: name.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_diff_roles() {
+        let old = ReadWriteEndpoint {
+            read: vec![endpoint("r1"), endpoint("r2")],
+            readwrite: vec![endpoint("rw")],
+        };
+        assert!(diff_roles(&old, &old.clone()).is_empty());
+
+        let new = ReadWriteEndpoint {
+            read: vec![endpoint("r1"), endpoint("rw")],
+            readwrite: vec![endpoint("r2")],
+        };
+        let changes = diff_roles(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                RoleChange { node: "rw".to_string(), role: "read" },
+                RoleChange { node: "r2".to_string(), role: "readwrite" },
+            ]
+        );
+
+        let new = ReadWriteEndpoint { read: vec![], readwrite: vec![endpoint("rw")] };
+        let changes = diff_roles(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                RoleChange { node: "r1".to_string(), role: "offline" },
+                RoleChange { node: "r2".to_string(), role: "offline" },
+            ]
+        );
+    }
 }
@@ -21,7 +21,7 @@ pub use dynamic_rw::*;
 use endpoint::endpoint::Endpoint;
 pub use static_rw::*;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct ReadWriteEndpoint {
     pub read: Vec<Endpoint>,
     pub readwrite: Vec<Endpoint>,
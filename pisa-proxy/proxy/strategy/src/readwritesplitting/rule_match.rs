@@ -22,7 +22,7 @@ use regex::Regex;
 use super::ReadWriteEndpoint;
 use crate::{
     config::{GenericRule, ReadWriteSplittingRule, RegexRule, TargetRole, NodeGroup},
-    route::{RouteBalance, RouteRuleMatch, StragegyError},
+    route::{RouteBalance, RouteRuleMatch, RouteStrategy, StragegyError},
     RouteInput,
 };
 
@@ -48,11 +48,15 @@ impl RulesMatchBuilder {
             RulesMatchBuilder::build_default_balance(&default_target, rw_endpoint.clone());
 
         let default_trans_balance =
-            RulesMatchBuilder::build_default_balance(&TargetRole::ReadWrite, rw_endpoint);
+            RulesMatchBuilder::build_default_balance(&TargetRole::ReadWrite, rw_endpoint.clone());
+
+        let default_read_only_trans_balance =
+            RulesMatchBuilder::build_read_only_trans_balance(rw_endpoint);
 
         let rules_match = RulesMatch {
             default_target: default_target.clone(),
             default_trans_balance,
+            default_read_only_trans_balance,
             inner,
             default_balance,
         };
@@ -103,6 +107,15 @@ impl RulesMatchBuilder {
         }
         default_balance
     }
+
+    // Read only transaction is routed to the read endpoints, fallback to readwrite endpoints when there is no read endpoint.
+    pub fn build_read_only_trans_balance(rw_endpoint: ReadWriteEndpoint) -> BalanceType {
+        if rw_endpoint.read.is_empty() {
+            return RulesMatchBuilder::build_default_balance(&TargetRole::ReadWrite, rw_endpoint);
+        }
+
+        RulesMatchBuilder::build_default_balance(&TargetRole::Read, rw_endpoint)
+    }
 }
 
 pub struct RulesMatch {
@@ -110,39 +123,93 @@ pub struct RulesMatch {
     pub default_balance: BalanceType,
     // Default transaction balance
     pub default_trans_balance: BalanceType,
+    // Default read only transaction balance
+    pub default_read_only_trans_balance: BalanceType,
     pub inner: Vec<RulesMatchInner>,
 }
 
+impl RulesMatch {
+    // Rebuild the balances with the new endpoints, the rules are kept.
+    pub fn rebuild(
+        &mut self,
+        rules: Vec<ReadWriteSplittingRule>,
+        node_group_config: &Option<NodeGroup>,
+        rw_endpoint: ReadWriteEndpoint,
+    ) -> Result<(), StragegyError> {
+        let endpoint_group = RouteStrategy::get_endpoint_group(node_group_config, &rw_endpoint)?;
+
+        self.default_balance =
+            RulesMatchBuilder::build_default_balance(&self.default_target, rw_endpoint.clone());
+        self.default_trans_balance =
+            RulesMatchBuilder::build_default_balance(&TargetRole::ReadWrite, rw_endpoint.clone());
+        self.default_read_only_trans_balance =
+            RulesMatchBuilder::build_read_only_trans_balance(rw_endpoint.clone());
+        self.inner = RulesMatchBuilder::build_rules(
+            rules,
+            endpoint_group,
+            rw_endpoint,
+            self.default_target.clone(),
+        );
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RulesMatchInner {
     Regex(RegexRuleMatchInner),
     Generic(GenericRuleMatchInner),
 }
 
-// Retrun balance when match success, otherwise return default_balance
-impl RouteBalance for RulesMatch {
-    fn get(&mut self, input: &RouteInput) -> (&mut BalanceType, TargetRole) {
+impl RulesMatch {
+    // The same as `get`, the name of matched rule is returned as well. It is `transaction` or
+    // `read_only_transaction` for transactions and `default` when no rule is matched.
+    pub fn get_with_rule(&mut self, input: &RouteInput) -> (&mut BalanceType, TargetRole, String) {
         // Currently, if RouteInput variant type is Transaction, return readwrite balnace directly.
         if let RouteInput::Transaction(_) = input {
-            return (&mut self.default_trans_balance, TargetRole::ReadWrite);
+            return (
+                &mut self.default_trans_balance,
+                TargetRole::ReadWrite,
+                "transaction".to_string(),
+            );
+        }
+
+        if let RouteInput::ReadOnlyTransaction(_) = input {
+            return (
+                &mut self.default_read_only_trans_balance,
+                TargetRole::Read,
+                "read_only_transaction".to_string(),
+            );
         }
 
         for rule in self.inner.iter_mut() {
             match rule {
                 RulesMatchInner::Regex(inner) => {
                     if inner.is_match(input) {
-                        return inner.get(input);
+                        let name = inner.rule.name.clone();
+                        let (balance, role) = inner.get(input);
+                        return (balance, role, name);
                     }
                 }
                 RulesMatchInner::Generic(inner) => {
                     if inner.is_match(input) {
-                        return inner.get(input);
+                        let name = inner.name.clone();
+                        let (balance, role) = inner.get(input);
+                        return (balance, role, name);
                     }
                 }
             }
         }
 
-        (&mut self.default_balance, self.default_target.clone())
+        (&mut self.default_balance, self.default_target.clone(), "default".to_string())
+    }
+}
+
+// Retrun balance when match success, otherwise return default_balance
+impl RouteBalance for RulesMatch {
+    fn get(&mut self, input: &RouteInput) -> (&mut BalanceType, TargetRole) {
+        let (balance, role, _) = self.get_with_rule(input);
+        (balance, role)
     }
 }
 
@@ -233,12 +300,9 @@ impl RouteRuleMatch for RegexRuleMatchInner {
                 self.regexs.iter().any(|r| r.is_match(val))
             }
 
-            RouteInput::ShardingStatement(val, _) => {
-                self.regexs.iter().any(|r| r.is_match(val))
-            }
-
-            RouteInput::ShardingTransaction(val, _) => {
-                self.regexs.iter().any(|r| r.is_match(val))
+            // The rule applies to the node groups it lists only.
+            RouteInput::ShardingStatement(val, node_group) | RouteInput::ShardingTransaction(val, node_group) => {
+                self.balance.contains_key(node_group) && self.regexs.iter().any(|r| r.is_match(val))
             }
 
             RouteInput::None => false,
@@ -273,6 +337,7 @@ pub struct GenericRuleMatchInner {
     rw_balance: BalanceType,
     default_balance: BalanceType,
     default_target_role: TargetRole,
+    name: String,
 }
 
 impl GenericRuleMatchInner {
@@ -296,7 +361,13 @@ impl GenericRuleMatchInner {
             rule.algorithm_name,
             rw_endpoint,
         );
-        GenericRuleMatchInner { r_balance, rw_balance, default_balance, default_target_role }
+        GenericRuleMatchInner {
+            r_balance,
+            rw_balance,
+            default_balance,
+            default_target_role,
+            name: rule.name,
+        }
     }
 
     fn build_balance(
@@ -408,6 +479,8 @@ mod test {
                 user: String::from("user"),
                 password: String::from("password"),
                 addr: String::from("127.0.0.1"),
+                compression: None,
+                compression_level: None,
             }],
             readwrite: vec![Endpoint {
                 weight: 1,
@@ -416,6 +489,8 @@ mod test {
                 user: String::from("user"),
                 password: String::from("password"),
                 addr: String::from("127.0.0.2"),
+                compression: None,
+                compression_level: None,
             }],
         };
 
@@ -429,5 +504,13 @@ mod test {
         let endpoint = b.next();
         assert_eq!(target, TargetRole::ReadWrite);
         assert_eq!(endpoint.unwrap().name, "test2");
+
+        assert_eq!(m.get_with_rule(&RouteInput::Statement("insert")).2, "t2");
+        assert_eq!(m.get_with_rule(&RouteInput::Statement("create")).2, "default");
+        assert_eq!(m.get_with_rule(&RouteInput::Transaction("select")).2, "transaction");
+
+        // The rule does not apply to the node group it does not list.
+        let input = RouteInput::ShardingStatement("select", "ds_group_0".to_string());
+        assert_eq!(m.get_with_rule(&input).2, "default");
     }
 }
\ No newline at end of file
@@ -21,9 +21,9 @@ use super::{
     ReadWriteEndpoint,
 };
 use crate::{
-    config::{self, NodeGroup},
+    config::{self, NodeGroup, ReadWriteSplittingRule},
     config::TargetRole,
-    route::{BoxError, RouteBalance},
+    route::{BoxError, NodeOverrides, StragegyError},
     Route, RouteInput,
 };
 
@@ -36,15 +36,58 @@ impl ReadWriteSplittingStaticBuilder {
         endpoint_group: IndexMap<String, ReadWriteEndpoint>,
         rw_endpoint: ReadWriteEndpoint,
     ) -> ReadWriteSplittingStatic {
-        let rules_match =
-            RulesMatchBuilder::build(config.rules, config.default_target, node_group_config, endpoint_group, rw_endpoint);
-
-        ReadWriteSplittingStatic { rules_match }
+        let rules_match = RulesMatchBuilder::build(
+            config.rules.clone(),
+            config.default_target,
+            node_group_config.clone(),
+            endpoint_group,
+            rw_endpoint.clone(),
+        );
+
+        ReadWriteSplittingStatic {
+            rules: config.rules,
+            node_group_config,
+            rules_match,
+            rw_endpoint,
+            overrides: NodeOverrides::default(),
+        }
     }
 }
 
 pub struct ReadWriteSplittingStatic {
+    rules: Vec<ReadWriteSplittingRule>,
+    node_group_config: Option<NodeGroup>,
     rules_match: RulesMatch,
+    // The configured endpoints
+    rw_endpoint: ReadWriteEndpoint,
+    overrides: NodeOverrides,
+}
+
+impl ReadWriteSplittingStatic {
+    // The disabled nodes are excluded.
+    pub fn get_endpoints(&self) -> ReadWriteEndpoint {
+        self.overrides.apply_rw(&self.rw_endpoint)
+    }
+
+    pub fn has_node_group(&self, name: &str) -> bool {
+        self.node_group_config.as_ref().map_or(false, |x| x.members.iter().any(|m| m.name == name))
+    }
+
+    pub fn set_overrides(&mut self, overrides: NodeOverrides) -> Result<(), StragegyError> {
+        let rw_endpoint = overrides.apply_rw(&self.rw_endpoint);
+        self.rules_match.rebuild(self.rules.clone(), &self.node_group_config, rw_endpoint)?;
+        self.overrides = overrides;
+        Ok(())
+    }
+
+    // Dispatch the input, the name of matched rule is returned as well.
+    pub fn dispatch_with_rule(
+        &mut self,
+        input: &RouteInput,
+    ) -> (Option<Endpoint>, TargetRole, String) {
+        let (balance, role, rule) = self.rules_match.get_with_rule(input);
+        (balance.next(), role, rule)
+    }
 }
 
 impl Route for ReadWriteSplittingStatic {
@@ -53,8 +96,8 @@ impl Route for ReadWriteSplittingStatic {
         &mut self,
         input: &RouteInput,
     ) -> Result<(Option<Endpoint>, TargetRole), Self::Error> {
-        let b = self.rules_match.get(input);
-        Ok((b.0.next(), b.1))
+        let (endpoint, role, _) = self.dispatch_with_rule(input);
+        Ok((endpoint, role))
     }
 }
 
@@ -67,7 +110,7 @@ mod test {
     use crate::{
         config::{ReadWriteSplittingRule, RegexRule, TargetRole},
         readwritesplitting::{static_rw::ReadWriteSplittingStaticBuilder, ReadWriteEndpoint},
-        route::{Route, RouteInput},
+        route::{NodeOverrides, Route, RouteInput},
     };
 
     #[test]
@@ -101,6 +144,8 @@ mod test {
                 user: String::from("user"),
                 password: String::from("password"),
                 addr: String::from("127.0.0.1"),
+                compression: None,
+                compression_level: None,
             }],
             readwrite: vec![Endpoint {
                 weight: 1,
@@ -109,6 +154,8 @@ mod test {
                 user: String::from("user"),
                 password: String::from("password"),
                 addr: String::from("127.0.0.2"),
+                compression: None,
+                compression_level: None,
             }],
         };
 
@@ -118,7 +165,9 @@ mod test {
         };
 
         let endpoint_group: IndexMap<String, ReadWriteEndpoint> = IndexMap::new();
-        let mut rws = ReadWriteSplittingStaticBuilder::build(config.statics.unwrap(), None, endpoint_group, rw_endpoint);
+        let mut rws = ReadWriteSplittingStaticBuilder::build(config.statics.unwrap(), None, endpoint_group, rw_endpoint.clone());
+        assert_eq!(rws.get_endpoints(), rw_endpoint);
+
         let input = RouteInput::Statement("insert");
         let res = rws.dispatch(&input).unwrap();
         assert_eq!(res.0.unwrap().addr, "127.0.0.2");
@@ -138,5 +187,26 @@ mod test {
         let input = RouteInput::Transaction("begin");
         let res = rws.dispatch(&input).unwrap();
         assert_eq!(res.0.unwrap().addr, "127.0.0.2");
+
+        let input = RouteInput::ReadOnlyTransaction("start transaction read only");
+        let res = rws.dispatch(&input).unwrap();
+        assert_eq!(res.0.unwrap().addr, "127.0.0.1");
+        assert_eq!(res.1, TargetRole::Read);
+
+        // The reads fall back to the readwrite endpoint when the read endpoint is disabled.
+        let mut overrides = NodeOverrides::default();
+        overrides.disabled.insert(String::from("test1"));
+        overrides.weights.insert(String::from("test2"), 5);
+        rws.set_overrides(overrides).unwrap();
+        assert!(rws.get_endpoints().read.is_empty());
+        assert_eq!(rws.get_endpoints().readwrite[0].weight, 5);
+
+        let input = RouteInput::Statement("select 1");
+        let res = rws.dispatch(&input).unwrap();
+        assert_eq!(res.0.unwrap().addr, "127.0.0.2");
+
+        rws.set_overrides(NodeOverrides::default()).unwrap();
+        let res = rws.dispatch(&input).unwrap();
+        assert_eq!(res.0.unwrap().addr, "127.0.0.1");
     }
 }
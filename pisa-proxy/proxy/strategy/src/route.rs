@@ -21,7 +21,7 @@ use crate::{
     config::{self, TargetRole},
     readwritesplitting::{
         ReadWriteEndpoint, ReadWriteSplittingDynamic, ReadWriteSplittingDynamicBuilder,
-        ReadWriteSplittingStatic, ReadWriteSplittingStaticBuilder,
+        ReadWriteSplittingStatic, ReadWriteSplittingStaticBuilder, RoleChange,
     },
 };
 
@@ -35,6 +35,46 @@ pub enum StragegyError {
 
     #[error("build node group name not found {0:?}")]
     NodeGroupNotFound(String),
+
+    #[error("node {0:?} is a sharding node, it can not be disabled")]
+    ShardingNode(String),
+}
+
+/// The node states changed at runtime, they are applied on the configured or discovered endpoints.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeOverrides {
+    pub disabled: IndexSet<String>,
+    pub weights: IndexMap<String, i64>,
+}
+
+impl NodeOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.disabled.is_empty() && self.weights.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.disabled.contains(name) || self.weights.contains_key(name)
+    }
+
+    // Return `None` when the endpoint is disabled.
+    pub fn apply(&self, endpoint: &Endpoint) -> Option<Endpoint> {
+        if self.disabled.contains(&endpoint.name) {
+            return None;
+        }
+
+        let mut endpoint = endpoint.clone();
+        if let Some(weight) = self.weights.get(&endpoint.name) {
+            endpoint.weight = *weight;
+        }
+        Some(endpoint)
+    }
+
+    pub fn apply_rw(&self, rw_endpoint: &ReadWriteEndpoint) -> ReadWriteEndpoint {
+        ReadWriteEndpoint {
+            read: rw_endpoint.read.iter().filter_map(|x| self.apply(x)).collect(),
+            readwrite: rw_endpoint.readwrite.iter().filter_map(|x| self.apply(x)).collect(),
+        }
+    }
 }
 
 
@@ -43,6 +83,7 @@ pub enum StragegyError {
 pub enum RouteInputTyp {
     Statement,
     Transaction,
+    ReadOnlyTransaction,
     None,
 }
 /// RouteInput may have more fields or variants added in the future,
@@ -52,6 +93,8 @@ pub enum RouteInputTyp {
 pub enum RouteInput<'a> {
     Statement(&'a str),
     Transaction(&'a str),
+    // `START TRANSACTION READ ONLY` and so on, can be routed to the read endpoints.
+    ReadOnlyTransaction(&'a str),
     Sharding(Endpoint),
     ShardingStatement(&'a str, String),
     ShardingTransaction(&'a str, String),
@@ -96,7 +139,8 @@ pub enum RouteStrategy {
     ReadWriteSplitting(ReadWriteSplittingRouteStrategy),
     ShardingReadWriteSplitting(ReadWriteSplittingRouteStrategy),
     Sharding(BalanceType),
-    Simple(BalanceType),
+    // The balance and the configured endpoints, the disabled endpoints are removed from the balance.
+    Simple(BalanceType, Vec<Endpoint>),
     None,
 }
 
@@ -144,8 +188,9 @@ impl RouteStrategy {
         }
     }
 
-    pub fn new_with_simple_route(balance: BalanceType) -> Self {
-        Self::Simple(balance)
+    pub fn new_with_simple_route(mut balance: BalanceType) -> Self {
+        let endpoints = balance.get_all().clone();
+        Self::Simple(balance, endpoints)
     }
 
     pub fn new_with_sharding_only(balance: BalanceType) -> Self {
@@ -186,42 +231,157 @@ impl RouteStrategy {
         }
     }
 
-    fn readwritesplitting_dispatch(strategy: &mut ReadWriteSplittingRouteStrategy, input: &RouteInput) -> Result<(Option<Endpoint>, TargetRole), BoxError> {
+    fn readwritesplitting_dispatch(
+        strategy: &mut ReadWriteSplittingRouteStrategy,
+        input: &RouteInput,
+    ) -> (Option<Endpoint>, TargetRole, String) {
         match strategy {
-            ReadWriteSplittingRouteStrategy::Static(ins) => ins.dispatch(input),
-            ReadWriteSplittingRouteStrategy::Dynamic(ins) => ins.dispatch(input),
-            _ => unreachable!()
+            ReadWriteSplittingRouteStrategy::Static(ins) => ins.dispatch_with_rule(input),
+            ReadWriteSplittingRouteStrategy::Dynamic(ins) => ins.dispatch_with_rule(input),
+            _ => unreachable!(),
         }
     }
-}
-
-impl Route for RouteStrategy {
-    type Error = BoxError;
 
-    fn dispatch(
+    // Dispatch the input, the name of matched readwritesplitting rule is returned as well, it is
+    // `default` for the other strategies.
+    pub fn dispatch_with_rule(
         &mut self,
         input: &RouteInput,
-    ) -> Result<(Option<Endpoint>, TargetRole), Self::Error> {
+    ) -> Result<(Option<Endpoint>, TargetRole, String), BoxError> {
         match self {
-            Self::ReadWriteSplitting(strategy) => {
-                Self::readwritesplitting_dispatch(strategy, input)
-            }
-
-            Self::ShardingReadWriteSplitting(strateyy) => {
-                Self::readwritesplitting_dispatch(strateyy, input)
+            Self::ReadWriteSplitting(strategy) | Self::ShardingReadWriteSplitting(strategy) => {
+                Ok(Self::readwritesplitting_dispatch(strategy, input))
             }
 
             Self::Sharding(ins) => {
-                if let RouteInput::Sharding(input) = input {
-                    Ok((Some(input.clone()), TargetRole::ReadWrite))
+                let endpoint = if let RouteInput::Sharding(input) = input {
+                    Some(input.clone())
                 } else {
-                    Ok((ins.next(), TargetRole::ReadWrite))
-                }
+                    ins.next()
+                };
+                Ok((endpoint, TargetRole::ReadWrite, "default".to_string()))
             }
-            
-            Self::Simple(ins) => Ok((ins.next(), TargetRole::ReadWrite)),
+
+            Self::Simple(ins, _) => Ok((ins.next(), TargetRole::ReadWrite, "default".to_string())),
 
             _ => unreachable!(),
         }
     }
+
+    // The role changes reported by the dynamic discovery since the last call.
+    pub fn take_role_changes(&mut self) -> Vec<RoleChange> {
+        match self {
+            Self::ReadWriteSplitting(ReadWriteSplittingRouteStrategy::Dynamic(x))
+            | Self::ShardingReadWriteSplitting(ReadWriteSplittingRouteStrategy::Dynamic(x)) => {
+                x.take_role_changes()
+            }
+            _ => vec![],
+        }
+    }
+
+    // Whether the node overrides are cleared by the dynamic discovery since the last call.
+    pub fn take_overrides_cleared(&mut self) -> bool {
+        match self {
+            Self::ReadWriteSplitting(ReadWriteSplittingRouteStrategy::Dynamic(x))
+            | Self::ShardingReadWriteSplitting(ReadWriteSplittingRouteStrategy::Dynamic(x)) => {
+                x.take_overrides_cleared()
+            }
+            _ => false,
+        }
+    }
+
+    // The live endpoints, the nodes of simple and sharding route are all regarded as readwrite.
+    pub fn get_endpoints(&mut self) -> ReadWriteEndpoint {
+        match self {
+            Self::ReadWriteSplitting(strategy) | Self::ShardingReadWriteSplitting(strategy) => {
+                match strategy {
+                    ReadWriteSplittingRouteStrategy::Static(x) => x.get_endpoints(),
+                    ReadWriteSplittingRouteStrategy::Dynamic(x) => x.get_endpoints(),
+                    ReadWriteSplittingRouteStrategy::None => ReadWriteEndpoint::default(),
+                }
+            }
+            Self::Sharding(balance) | Self::Simple(balance, _) => {
+                ReadWriteEndpoint { read: vec![], readwrite: balance.get_all().clone() }
+            }
+            Self::None => ReadWriteEndpoint::default(),
+        }
+    }
+
+    // Whether the node group is configured, the group routed by the sharding rewriter of an open
+    // transaction may be removed by reloading.
+    pub fn has_node_group(&self, name: &str) -> bool {
+        match self {
+            Self::ReadWriteSplitting(strategy) | Self::ShardingReadWriteSplitting(strategy) => {
+                match strategy {
+                    ReadWriteSplittingRouteStrategy::Static(x) => x.has_node_group(name),
+                    ReadWriteSplittingRouteStrategy::Dynamic(x) => x.has_node_group(name),
+                    ReadWriteSplittingRouteStrategy::None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    // Stop the discovery of dynamic read write splitting, it is called before the strategy is replaced.
+    pub fn stop(&self) {
+        match self {
+            Self::ReadWriteSplitting(ReadWriteSplittingRouteStrategy::Dynamic(x))
+            | Self::ShardingReadWriteSplitting(ReadWriteSplittingRouteStrategy::Dynamic(x)) => x.stop(),
+            _ => {}
+        }
+    }
+
+    // Apply the node states on the live balances, the sharding nodes can not be changed because
+    // the statements are routed to them by the sharding key.
+    pub fn set_overrides(&mut self, overrides: &NodeOverrides) -> Result<(), StragegyError> {
+        match self {
+            Self::ReadWriteSplitting(strategy) | Self::ShardingReadWriteSplitting(strategy) => {
+                match strategy {
+                    ReadWriteSplittingRouteStrategy::Static(x) => x.set_overrides(overrides.clone())?,
+                    ReadWriteSplittingRouteStrategy::Dynamic(x) => x.set_overrides(overrides.clone())?,
+                    ReadWriteSplittingRouteStrategy::None => {}
+                }
+            }
+
+            Self::Sharding(balance) => {
+                if let Some(x) = balance.get_all().iter().find(|x| overrides.contains(&x.name)) {
+                    return Err(StragegyError::ShardingNode(x.name.clone()));
+                }
+            }
+
+            Self::Simple(balance, endpoints) => {
+                for endpoint in endpoints.iter() {
+                    let current =
+                        balance.get_all().iter().find(|x| x.name == endpoint.name).cloned();
+                    let next = overrides.apply(endpoint);
+                    if current == next {
+                        continue;
+                    }
+
+                    if let Some(current) = current {
+                        balance.remove_item(current);
+                    }
+                    if let Some(next) = next {
+                        balance.add(next);
+                    }
+                }
+            }
+
+            Self::None => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Route for RouteStrategy {
+    type Error = BoxError;
+
+    fn dispatch(
+        &mut self,
+        input: &RouteInput,
+    ) -> Result<(Option<Endpoint>, TargetRole), Self::Error> {
+        let (endpoint, role, _) = self.dispatch_with_rule(input)?;
+        Ok((endpoint, role))
+    }
 }
@@ -232,6 +232,78 @@ impl ShardingRewrite {
         self.default_db = db;
     }
 
+    fn find_rule_by_name(&self, table: &str) -> Option<&Sharding> {
+        let name = table.replace("`", "");
+        self.rules.iter().find(|x| x.table_name == name)
+    }
+
+    pub fn is_sharding_table(&self, table: &str) -> bool {
+        self.find_rule_by_name(table).is_some()
+    }
+
+    /// Returns the sharding column of the table, `None` when the table is not sharded
+    /// by a single column, e.g. `database_table_strategy`.
+    pub fn get_table_sharding_column(&self, table: &str) -> Option<&str> {
+        let rule = self.find_rule_by_name(table)?;
+        match rule.get_sharding_column() {
+            (Some(column), None) | (None, Some(column)) => Some(column),
+            _ => None,
+        }
+    }
+
+    /// Route a row of the table by the value of sharding column, returns the data source and
+    /// the target table. It is used to split the rows of `LOAD DATA LOCAL INFILE` to shards.
+    pub fn route_row(
+        &self,
+        schema: Option<&str>,
+        table: &str,
+        value: &str,
+    ) -> Result<Option<(DataSource, String)>, ShardingRewriteError> {
+        let rule = match self.find_rule_by_name(table) {
+            Some(rule) => rule,
+            None => return Ok(None),
+        };
+        let name = table.replace("`", "");
+        let value = value.trim().parse::<u64>()?;
+
+        if let Some(StrategyType::TableStrategyConfig(config)) = &rule.table_strategy {
+            let schema = schema
+                .map(|x| x.replace("`", ""))
+                .or_else(|| self.default_db.clone())
+                .ok_or_else(|| ShardingRewriteError::DatabaseNotFound)?;
+            let idx = value
+                .calc(&config.table_sharding_algorithm_name, config.sharding_count as u64)
+                .ok_or_else(|| ShardingRewriteError::CalcModError)?;
+
+            let data_source = if self.has_rw {
+                DataSource::NodeGroup(rule.actual_datanodes[0].clone())
+            } else {
+                let ep = self.endpoints.iter().find(|e| e.name == rule.actual_datanodes[0]).ok_or_else(|| ShardingRewriteError::EndpointNotFound)?;
+                DataSource::Endpoint(ep.clone())
+            };
+
+            return Ok(Some((data_source, format!("`{}`.`{}_{:05}`", schema, name, idx))));
+        }
+
+        if let Some(StrategyType::DatabaseStrategyConfig(config)) = &rule.database_strategy {
+            let idx = value
+                .calc(&config.database_sharding_algorithm_name, rule.actual_datanodes.len() as u64)
+                .ok_or_else(|| ShardingRewriteError::CalcModError)?;
+            let node = &rule.actual_datanodes[idx as usize];
+            let ep = self.endpoints.iter().find(|e| &e.name == node).ok_or_else(|| ShardingRewriteError::EndpointNotFound)?;
+
+            let data_source = if self.has_rw {
+                DataSource::NodeGroup(node.clone())
+            } else {
+                DataSource::Endpoint(ep.clone())
+            };
+
+            return Ok(Some((data_source, format!("`{}`.`{}`", ep.db, name))));
+        }
+
+        Ok(None)
+    }
+
     fn database_strategy(
         &self,
         meta: RewriteMetaData,
@@ -1215,6 +1287,7 @@ mod test {
                     DatabaseStrategyConfig {
                         database_sharding_algorithm_name: ShardingAlgorithmName::Mod,
                         database_sharding_column: "idx".to_string(),
+                        sharding_count: None,
                     },
                 )),
                 table_strategy: None,
@@ -1228,6 +1301,8 @@ mod test {
                     user: String::from("user"),
                     password: String::from("password"),
                     addr: String::from("127.0.0.1"),
+                    compression: None,
+                    compression_level: None,
                 },
                 Endpoint {
                     weight: 1,
@@ -1236,6 +1311,8 @@ mod test {
                     user: String::from("user"),
                     password: String::from("password"),
                     addr: String::from("127.0.0.2"),
+                    compression: None,
+                    compression_level: None,
                 },
             ],
         )
@@ -1265,6 +1342,8 @@ mod test {
                 user: String::from("user"),
                 password: String::from("password"),
                 addr: String::from("127.0.0.1:3306"),
+                compression: None,
+                compression_level: None,
             }],
         )
     }
@@ -1375,6 +1454,30 @@ mod test {
 
     }
 
+    #[test]
+    fn test_route_row() {
+        let config = get_table_sharding_config();
+        let mut sr = ShardingRewrite::new(config.0, config.1.clone(), None, false);
+        sr.set_default_db(Some("test".to_string()));
+
+        assert!(sr.is_sharding_table("`tshard`"));
+        assert!(!sr.is_sharding_table("t"));
+        assert_eq!(sr.get_table_sharding_column("`tshard`"), Some("idx"));
+        assert_eq!(sr.get_table_sharding_column("t"), None);
+        assert!(sr.route_row(None, "t", "1").unwrap().is_none());
+
+        let (data_source, table) = sr.route_row(None, "tshard", "6").unwrap().unwrap();
+        assert_eq!(data_source, DataSource::Endpoint(config.1[0].clone()));
+        assert_eq!(table, "`test`.`tshard_00002`");
+        assert!(sr.route_row(None, "tshard", "a").is_err());
+
+        let config = get_database_sharding_config();
+        let sr = ShardingRewrite::new(config.0, config.1.clone(), None, false);
+        let (data_source, table) = sr.route_row(None, "tshard", "3").unwrap().unwrap();
+        assert_eq!(data_source, DataSource::Endpoint(config.1[1].clone()));
+        assert_eq!(table, "`db1`.`tshard`");
+    }
+
     #[test]
     fn test_table_sharding_strategy_insert() {
         let config = get_table_sharding_config();
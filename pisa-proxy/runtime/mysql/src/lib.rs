@@ -13,5 +13,7 @@
 // limitations under the License.
 
 pub mod mysql;
+pub mod registry;
 pub mod server;
+pub mod telemetry;
 pub mod transaction_fsm;
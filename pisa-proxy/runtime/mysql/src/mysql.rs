@@ -14,27 +14,31 @@
 
 use std::{
     marker::PhantomData,
-    sync::{atomic::AtomicU32, Arc},
+    sync::{atomic::AtomicU32, Arc, Weak},
     time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
+use byteorder::{ByteOrder, LittleEndian};
 use bytes::{Buf, BytesMut};
 use common::ast_cache::ParserAstCache;
 use conn_pool::Pool;
 use endpoint::endpoint::Endpoint;
-use futures::{SinkExt, StreamExt};
+use futures::{FutureExt, SinkExt, StreamExt};
 use loadbalance::balance::{Balance, LoadBalance};
-use mysql_parser::parser::Parser;
+use mysql_parser::parser::{ParseError, Parser};
 use mysql_protocol::{
     client::conn::ClientConn,
+    compress::Compression,
     err::ProtocolError,
-    mysql_const::ComType,
+    mysql_const::{ComType, AUTH_CACHING_SHA2_PASSWORD, AUTH_NATIVE_PASSWORD, COM_QUIT},
     server::{
         auth::{handshake, ServerHandshakeCodec},
         codec::{make_err_packet, ok_packet, CommonPacket, PacketCodec, PacketSend},
         err::MySQLError,
         stream::LocalStream,
+        tls::{ServerTlsAcceptor, ServerTlsConfig},
+        users::{AuthSecret, AuthUser, AuthUsers},
     },
     session::Session,
 };
@@ -44,6 +48,8 @@ use plugin::{build_phase::PluginPhase, err::BoxError, layer::Service};
 use proxy::{
     listener::Listener,
     proxy::{MySQLNode, Proxy, ProxyConfig},
+    reload::{is_changed, ReloadConfig, ReloadReceiver},
+    shutdown::Shutdown,
 };
 use strategy::{
     config::{NodeGroup, TargetRole},
@@ -51,12 +57,28 @@ use strategy::{
     route::RouteStrategy,
     sharding_rewrite::{ShardingRewrite, ShardingRewriteOutput},
 };
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_util::codec::{Decoder, Encoder, Framed};
-use tracing::error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+};
+use tokio_util::{
+    codec::{Decoder, Encoder, Framed},
+    sync::CancellationToken,
+};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    server::{metrics::*, stmt_cache::StmtCache, PisaMySQLService},
+    registry::{self, EffectiveConfig, ProxyRuntime, SessionHandle},
+    server::{
+        backend_user::{BackendUser, BackendUsers},
+        binlog::BinlogSession,
+        metrics::*,
+        slow_log::{SlowLog, SlowQuery},
+        stmt_cache::StmtCache,
+        xa::{XaCoordinator, XA_RETRY_INTERVAL},
+        PisaMySQLService,
+    },
+    telemetry,
     transaction_fsm::*,
 };
 
@@ -66,10 +88,25 @@ pub struct MySQLProxy {
     pub node_group: Option<NodeGroup>,
     pub mysql_nodes: Vec<MySQLNode>,
     pub pisa_version: String,
+    // The reloaded config is applied when it is set
+    pub reload: Option<ReloadReceiver>,
+}
+
+/// The parts of runtime which are replaced by reloading config, the sessions pick them up
+/// before the next command out of transaction.
+#[derive(Clone)]
+pub struct ReloadState {
+    pub rewriter: Option<ShardingRewrite>,
+    pub plugin: Option<PluginPhase>,
+    pub has_readwritesplitting: bool,
 }
 
 impl MySQLProxy {
     fn build_route(&self) -> Result<RouteStrategy, Error> {
+        if let Some(config) = &self.proxy_config.read_write_splitting {
+            config.check().map_err(|e| Error::new(ErrorKind::Runtime(e.into())))?;
+        }
+
         let length = self.mysql_nodes.len();
         let (mut rw, mut ro) = (Vec::with_capacity(length), Vec::with_capacity(length));
         for node in &self.mysql_nodes {
@@ -102,8 +139,13 @@ impl MySQLProxy {
             .map_err(|e| Error::new(ErrorKind::Runtime(e.into())))?
         } else {
             //let rw_endpoint = ReadWriteEndpoint { read: ro, readwrite: rw };
-            let balance_type =
-                self.proxy_config.simple_loadbalance.as_ref().unwrap().balance_type.clone();
+            let balance_type = self
+                .proxy_config
+                .simple_loadbalance
+                .as_ref()
+                .ok_or_else(|| Error::new(ErrorKind::Runtime("simple_loadbalance is not set".into())))?
+                .balance_type
+                .clone();
             let mut balance = Balance.build_balance(balance_type);
             rw.append(&mut ro);
             for ep in rw.into_iter() {
@@ -155,11 +197,285 @@ impl MySQLProxy {
 
         Some(ShardingRewrite::new(config.unwrap(), endpoints, self.node_group.clone(), has_rw))
     }
+
+    fn build_plugin(&self) -> Result<Option<PluginPhase>, Error> {
+        match &self.proxy_config.plugin {
+            Some(config) => {
+                config.check().map_err(|e| Error::new(ErrorKind::Runtime(e.into())))?;
+                Ok(Some(PluginPhase::new(config.clone())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // The pending XA transactions of the last run are finished before serving.
+    async fn build_xa_coordinator(&self) -> Result<Option<Arc<XaCoordinator>>, Error> {
+        let config = match &self.proxy_config.xa {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let xa = XaCoordinator::new(&self.proxy_config.name, &config.log_path)
+            .map_err(|e| Error::new(ErrorKind::Runtime(e.into())))?;
+
+        if let Err(e) = xa.recover(&Self::xa_endpoints(&self.mysql_nodes)).await {
+            error!("xa recover error {:?}", e);
+        }
+
+        Ok(Some(Arc::new(xa)))
+    }
+
+    // The branches of XA transactions are on the read-write nodes.
+    fn xa_endpoints(nodes: &[MySQLNode]) -> Vec<Endpoint> {
+        nodes.iter().filter(|x| x.role == TargetRole::ReadWrite).map(|x| Endpoint::from(x.clone())).collect()
+    }
+
+    // Commit the XA transactions failed in phase 2 in the background, their prepared branches hold row locks
+    // until they are committed. The task exits when the proxy is stopped.
+    fn spawn_xa_retry(xa: Arc<XaCoordinator>, runtime: Weak<ProxyRuntime>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(XA_RETRY_INTERVAL));
+            loop {
+                ticker.tick().await;
+                let nodes = match runtime.upgrade() {
+                    Some(runtime) => runtime.get_config().mysql_nodes,
+                    None => return,
+                };
+
+                if xa.has_failed_commits() {
+                    xa.retry_commits(&Self::xa_endpoints(&nodes)).await;
+                }
+            }
+        });
+    }
+
+    // Close the idle backend conns by COM_QUIT.
+    pub(crate) async fn close_conns(conns: Vec<ClientConn>) {
+        for mut conn in conns {
+            if let Err(e) = conn.send_no_response_command(COM_QUIT, &[]).await {
+                debug!("close conn {:?} error {:?}", conn.get_endpoint(), e);
+            }
+        }
+    }
+
+    // Wait for the reloaded config, it never completes when reloading is disabled.
+    async fn reload_changed(reload: &mut Option<ReloadReceiver>) -> Arc<ReloadConfig> {
+        if let Some(rx) = reload {
+            if rx.changed().await.is_ok() {
+                return rx.borrow().clone();
+            }
+        }
+        std::future::pending().await
+    }
+
+    // Diff the reloaded config with the running one and apply the changed parts in place,
+    // the running config is kept when the reloaded one is invalid.
+    async fn apply_reload(
+        &mut self,
+        config: &ReloadConfig,
+        runtime: &ProxyRuntime,
+        state_tx: &watch::Sender<Arc<ReloadState>>,
+    ) {
+        let name = self.proxy_config.name.clone();
+        let proxy_config = match config.get_proxy(&name) {
+            Some(proxy_config) => proxy_config.clone(),
+            None => {
+                warn!("proxy {:?} is not found in the reloaded config, it is kept running", name);
+                return;
+            }
+        };
+
+        let next = MySQLProxy {
+            proxy_config,
+            node_group: config.node_group.clone(),
+            mysql_nodes: config.mysql_nodes.clone(),
+            pisa_version: self.pisa_version.clone(),
+            reload: None,
+        };
+
+        let (old, new) = (&self.proxy_config, &next.proxy_config);
+        let nodes_changed = is_changed(&self.mysql_nodes, &next.mysql_nodes)
+            || is_changed(&self.node_group, &next.node_group);
+        let route_changed = nodes_changed
+            || is_changed(&old.read_write_splitting, &new.read_write_splitting)
+            || is_changed(&old.simple_loadbalance, &new.simple_loadbalance)
+            || is_changed(&old.sharding, &new.sharding);
+        let plugin_changed = is_changed(&old.plugin, &new.plugin);
+
+        // The fields other than the nodes and rules are applied after restart.
+        let mut cold = new.clone();
+        cold.read_write_splitting = old.read_write_splitting.clone();
+        cold.simple_loadbalance = old.simple_loadbalance.clone();
+        cold.sharding = old.sharding.clone();
+        cold.plugin = old.plugin.clone();
+        if is_changed(old, &cold) {
+            warn!("proxy {:?}: only the nodes and rules are reloaded, other changes are applied after restart", name);
+        }
+
+        // The route is built last, the monitors of dynamic read write splitting are started by it.
+        let built = next.check_compression().and_then(|_| {
+            let plugin = if plugin_changed { Some(next.build_plugin()?) } else { None };
+            let route = if route_changed { Some(next.build_route()?) } else { None };
+            Ok((route, plugin))
+        });
+        let (route, plugin) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                error!("proxy {:?} reload config error {:?}, the running config is kept", name, e);
+                return;
+            }
+        };
+
+        if route_changed || plugin_changed {
+            let current = state_tx.borrow().clone();
+            let state = ReloadState {
+                rewriter: if route_changed { next.build_sharding_rewriter() } else { current.rewriter.clone() },
+                plugin: plugin.unwrap_or_else(|| current.plugin.clone()),
+                has_readwritesplitting: new.read_write_splitting.is_some(),
+            };
+
+            // The route strategy is shared by sessions, it is replaced in place. The bound conns
+            // of open transactions are not affected.
+            if let Some(mut route) = route {
+                runtime.restore_nodes(&mut route);
+                let mut current = runtime.route_strategy.lock();
+                current.stop();
+                *current = route;
+            }
+            let _ = state_tx.send(Arc::new(state));
+        }
+
+        if nodes_changed {
+            for node in &self.mysql_nodes {
+                let addr = Endpoint::from(node.clone()).addr;
+                if !next.mysql_nodes.iter().any(|x| Endpoint::from(x.clone()).addr == addr) {
+                    Self::close_conns(runtime.pool.remove_endpoint(&addr)).await;
+                }
+            }
+        }
+
+        info!("proxy {:?} config is reloaded", name);
+        // Only the hot fields are applied, others keep the running values until restart.
+        self.mysql_nodes = next.mysql_nodes;
+        self.node_group = next.node_group;
+        self.proxy_config.read_write_splitting = next.proxy_config.read_write_splitting;
+        self.proxy_config.simple_loadbalance = next.proxy_config.simple_loadbalance;
+        self.proxy_config.sharding = next.proxy_config.sharding;
+        self.proxy_config.plugin = next.proxy_config.plugin;
+        runtime.set_config(self.effective_config());
+    }
+
+    fn effective_config(&self) -> EffectiveConfig {
+        EffectiveConfig {
+            proxy_config: self.proxy_config.clone(),
+            mysql_nodes: self.mysql_nodes.clone(),
+            node_group: self.node_group.clone(),
+        }
+    }
+
+    fn build_auth_users(&self) -> Result<AuthUsers, Error> {
+        let users = match &self.proxy_config.users {
+            Some(users) if !users.is_empty() => users,
+            _ => {
+                let user = AuthUser::new(
+                    self.proxy_config.user.clone(),
+                    AuthSecret::Password(self.proxy_config.password.clone()),
+                );
+                return Ok(AuthUsers::new(vec![user]));
+            }
+        };
+
+        let mut auth_users = AuthUsers::default();
+        for user in users {
+            let secret = if let Some(hash) = &user.native_password_hash {
+                AuthSecret::from_native_password_hash(hash)
+            } else if let Some(hash) = &user.caching_sha2_password_hash {
+                AuthSecret::from_caching_sha2_password_hash(hash)
+            } else if let Some(password) = &user.password {
+                Ok(AuthSecret::Password(password.clone()))
+            } else {
+                Err(format!("user {:?} has no password or password hash", user.user))
+            }
+            .map_err(|e| Error::new(ErrorKind::Runtime(e.into())))?;
+
+            let auth_user = AuthUser::new(user.user.clone(), secret)
+                .with_allowed_dbs(user.allowed_dbs.clone())
+                .with_allowed_hosts(&user.allowed_hosts)
+                .map_err(|e| Error::new(ErrorKind::Runtime(e.into())))?;
+            auth_users.add(auth_user);
+        }
+
+        Ok(auth_users)
+    }
+
+    fn build_auth_plugin(&self) -> Result<&'static str, Error> {
+        match self.proxy_config.default_auth_plugin.as_deref() {
+            None | Some(AUTH_NATIVE_PASSWORD) => Ok(AUTH_NATIVE_PASSWORD),
+            Some(AUTH_CACHING_SHA2_PASSWORD) => Ok(AUTH_CACHING_SHA2_PASSWORD),
+            Some(x) => Err(Error::new(ErrorKind::Runtime(
+                format!("unsupported default_auth_plugin {:?}", x).into(),
+            ))),
+        }
+    }
+
+    // Check the compression config of backend nodes.
+    fn check_compression(&self) -> Result<(), Error> {
+        for node in &self.mysql_nodes {
+            if let Some(algorithm) = &node.compression {
+                Compression::new(algorithm, node.compression_level).map_err(|e| {
+                    Error::new(ErrorKind::Runtime(format!("node {}: {}", node.name, e).into()))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build_tls_acceptor(&self) -> Result<ServerTlsAcceptor, Error> {
+        match &self.proxy_config.tls {
+            Some(tls) => {
+                let config = ServerTlsConfig {
+                    cert: tls.cert.clone(),
+                    key: tls.key.clone(),
+                    client_ca: tls.client_ca.clone(),
+                };
+                ServerTlsAcceptor::new(config).map_err(|e| Error::new(ErrorKind::Protocol(e)))
+            }
+            None => Ok(ServerTlsAcceptor::default()),
+        }
+    }
+
+    // Reload the certificates when the files are changed, new handshakes use the reloaded certificates.
+    fn spawn_tls_reloader(acceptor: ServerTlsAcceptor, interval: u64) {
+        if interval == 0 || acceptor.config().cert.is_none() {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut last_modified = acceptor.modified();
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+            loop {
+                ticker.tick().await;
+                let modified = acceptor.modified();
+                if modified == last_modified {
+                    continue;
+                }
+
+                match acceptor.reload() {
+                    Ok(_) => {
+                        info!("reload tls certificates {:?}", acceptor.config());
+                        last_modified = modified;
+                    }
+                    Err(e) => error!("reload tls certificates error {:?}", e),
+                }
+            }
+        });
+    }
 }
 
 #[async_trait::async_trait]
 impl proxy::factory::Proxy for MySQLProxy {
-    async fn start(&mut self) -> Result<(), Error> {
+    async fn start(&mut self, mut shutdown: Shutdown) -> Result<(), Error> {
         let listener = Listener {
             name: self.proxy_config.name.clone(),
             backend_type: "mysql".to_string(),
@@ -186,50 +502,135 @@ impl proxy::factory::Proxy for MySQLProxy {
         // Build sharding rewriter
         let rewriter = self.build_sharding_rewriter();
 
-        let mut plugin: Option<PluginPhase> = None;
-        if let Some(config) = &self.proxy_config.plugin {
-            plugin = Some(PluginPhase::new(config.clone()))
-        };
+        let plugin = self.build_plugin()?;
 
         let parser = Arc::new(Parser::new());
         //let metrics_collector = MySQLServerMetricsCollector::new();
 
         let has_rw = self.proxy_config.read_write_splitting.is_some();
 
+        let auth_users = Arc::new(self.build_auth_users()?);
+        // Without the user table, the client must use the configured db.
+        let is_fixed_db = !matches!(&self.proxy_config.users, Some(users) if !users.is_empty());
+        let auth_plugin = self.build_auth_plugin()?;
+        self.check_compression()?;
+        let tls_acceptor = self.build_tls_acceptor()?;
+        let require_secure_transport =
+            self.proxy_config.tls.as_ref().map_or(false, |x| x.require_secure_transport);
+        if let Some(tls) = &self.proxy_config.tls {
+            Self::spawn_tls_reloader(tls_acceptor.clone(), tls.reload_interval);
+        }
+
+        let backend_users = Arc::new(
+            BackendUsers::new(self.proxy_config.users.as_deref().unwrap_or_default())
+                .map_err(|e| Error::new(ErrorKind::Runtime(e.into())))?,
+        );
+
+        let xa = if rewriter.is_some() { self.build_xa_coordinator().await? } else { None };
+        let slow_log = self.proxy_config.slow_log.as_ref().map(SlowLog::new).transpose();
+        let slow_log = slow_log.map_err(ErrorKind::Io)?;
+
+        let (state_tx, state_rx) = watch::channel(Arc::new(ReloadState {
+            rewriter,
+            plugin,
+            has_readwritesplitting: has_rw,
+        }));
+        let mut reload = self.reload.take();
+
+        let runtime = Arc::new(ProxyRuntime::new(
+            self.effective_config(),
+            route_strategy.clone(),
+            pool.clone(),
+            state_rx.clone(),
+        ));
+        registry::register(runtime.clone());
+        if let Some(xa) = xa.clone() {
+            Self::spawn_xa_retry(xa, Arc::downgrade(&runtime));
+        }
+
+        // Each session holds a sender, the receiver returns `None` when all sessions are finished.
+        let (drain_tx, mut drain_rx) = tokio::sync::mpsc::channel::<()>(1);
+        // The sessions which are still open when shutdown times out are aborted.
+        let abort = CancellationToken::new();
+
         loop {
             // TODO: need refactor
-            let socket = proxy.accept(&listener).await.map_err(ErrorKind::Io)?;
+            let socket = tokio::select! {
+                socket = proxy.accept(&listener) => socket.map_err(ErrorKind::Io)?,
+                _ = shutdown.recv() => break,
+                config = Self::reload_changed(&mut reload) => {
+                    self.apply_reload(&config, &runtime, &state_tx).await;
+                    continue;
+                }
+            };
 
+            let state = state_rx.borrow().clone();
             let route_strategy = route_strategy.clone();
-            let plugin = plugin.clone();
+            let plugin = state.plugin.clone();
             let _pcfg = self.proxy_config.clone();
             let parser = parser.clone();
             let ast_cache = ast_cache.clone();
             let pool = pool.clone();
             let proxy_name = self.proxy_config.name.clone();
-            let rewriter = rewriter.clone();
+            let rewriter = state.rewriter.clone();
+            let has_rw = state.has_readwritesplitting;
+            let reload = state_rx.clone();
+            let backend_users = backend_users.clone();
+            let local_infile = self.proxy_config.local_infile;
+            let local_infile_sharding = self.proxy_config.local_infile_sharding;
+            let replication_passthrough = self.proxy_config.replication_passthrough;
+            let xa = xa.clone();
+            let shutdown = shutdown.clone();
+            let drain_tx = drain_tx.clone();
+            let runtime = runtime.clone();
+            let slow_log = slow_log.clone();
 
-            let handshake_codec = ServerHandshakeCodec::new(
-                self.proxy_config.user.clone(),
-                self.proxy_config.password.clone(),
+            let client_addr = socket.peer_addr().ok().map(|addr| addr.ip());
+            let handshake_codec = ServerHandshakeCodec::with_users(
+                auth_users.clone(),
                 self.proxy_config.db.clone(),
                 self.proxy_config.server_version.clone(),
-            );
+                client_addr,
+            )
+            .with_tls(tls_acceptor.clone(), require_secure_transport)
+            .with_auth_plugin(auth_plugin)
+            .with_compression(self.proxy_config.compression)
+            .with_local_infile(local_infile)
+            .with_fixed_db(is_fixed_db);
 
             let handshake_framed =
                 Framed::with_capacity(LocalStream::from(socket), handshake_codec, 8196);
 
             let mut ins = MySQLInstance::new(PisaMySQLService::new());
 
-            tokio::spawn(async move {
+            let metrics_collector = MySQLServerMetricsCollector::new();
+            metrics_collector.set_client_connections_total(&[proxy_name.as_str()]);
+
+            let serve = async move {
                 let res = handshake(handshake_framed).await;
                 if let Err(e) = res {
                     error!("handshake error {:?}", e);
+                    let reason = handshake_failure_reason(&e);
+                    metrics_collector
+                        .set_client_handshake_failures_total(&[proxy_name.as_str(), reason]);
+                    return;
+                }
+
+                let (handshake_framed, is_auth) = res.unwrap();
+                if !is_auth {
+                    metrics_collector
+                        .set_client_handshake_failures_total(&[proxy_name.as_str(), "auth"]);
                     return;
                 }
 
-                let handshake_framed = res.unwrap().0;
                 let parts = handshake_framed.into_parts();
+                let backend_user = backend_users.get(parts.codec.get_user());
+                let host = client_addr.map(|x| x.to_string()).unwrap_or_default();
+                let session = runtime.sessions.register(
+                    parts.codec.get_connection_id(),
+                    parts.codec.get_user(),
+                    &host,
+                );
 
                 let packet_codec = PacketCodec::new(parts.codec, 8196);
                 let io = parts.io;
@@ -240,8 +641,9 @@ impl proxy::factory::Proxy for MySQLProxy {
                     route_strategy,
                     pool,
                     ast_cache,
+                    last_parse_err: None,
                     plugin,
-                    metrics_collector: MySQLServerMetricsCollector,
+                    metrics_collector,
                     concurrency_control_rule_idx: None,
                     framed,
                     name: proxy_name,
@@ -251,13 +653,57 @@ impl proxy::factory::Proxy for MySQLProxy {
                     has_readwritesplitting: has_rw,
                     stmt_cache: StmtCache::new(),
                     stmt_id: AtomicU32::new(0),
+                    backend_user,
+                    backend_users,
+                    local_infile,
+                    local_infile_sharding,
+                    local_infile_file: None,
+                    binlog: BinlogSession::new(replication_passthrough),
+                    pending_vars: vec![],
+                    read_back_vars: vec![],
+                    xa,
+                    shutdown,
+                    reload,
+                    session,
+                    runtime,
+                    slow_log,
+                    rows: 0,
                 };
 
-                if let Err(e) = ins.run(context).await {
+                let session = context.session.info();
+                let domain = context.name.clone();
+                metrics_collector.set_client_connections_active_inc(&[domain.as_str()]);
+                if let Err(e) = registry::scope(session, ins.run(context)).await {
                     error!("instance run error {:?}", e);
                 }
+                metrics_collector.set_client_connections_active_dec(&[domain.as_str()]);
+            };
+
+            let abort = abort.clone();
+            tokio::spawn(async move {
+                let _drain_tx = drain_tx;
+                tokio::select! {
+                    _ = serve => {}
+                    _ = abort.cancelled() => {}
+                }
             });
         }
+
+        drop(listener);
+        drop(drain_tx);
+        info!("proxy {:?} stops accepting, waiting for the open transactions", self.proxy_config.name);
+
+        let timeout = Duration::from_secs(self.proxy_config.shutdown_timeout);
+        if tokio::time::timeout(timeout, drain_rx.recv()).await.is_err() {
+            warn!("proxy {:?} shutdown timeout, the open sessions are aborted", self.proxy_config.name);
+            // The backend conns of the aborted sessions are dropped before the pool is drained.
+            abort.cancel();
+            let _ = drain_rx.recv().await;
+        }
+
+        Self::close_conns(pool.drain()).await;
+        registry::unregister(&self.proxy_config.name);
+        Ok(())
     }
 }
 
@@ -269,6 +715,8 @@ pub struct ReqContext<T, C> {
     pub pool: Pool<ClientConn>,
     pub mysql_parser: Arc<Parser>,
     pub ast_cache: Arc<Mutex<ParserAstCache>>,
+    // The sql failed to parse last time, the cache keeps only parsed stmts
+    pub last_parse_err: Option<(String, ParseError)>,
     pub plugin: Option<PluginPhase>,
     pub metrics_collector: MySQLServerMetricsCollector,
     // `concurrency_control_rule_idx` is index of concurrency_control rules
@@ -282,6 +730,50 @@ pub struct ReqContext<T, C> {
     pub has_readwritesplitting: bool,
     pub stmt_cache: StmtCache,
     pub stmt_id: AtomicU32,
+    // The backend credential mapped from the frontend user
+    pub backend_user: BackendUser,
+    // Used to map the backend credential again after COM_CHANGE_USER
+    pub backend_users: Arc<BackendUsers>,
+    // Whether `LOAD DATA LOCAL INFILE` is relayed to the client
+    pub local_infile: bool,
+    // Whether the rows of `LOAD DATA LOCAL INFILE` are split to shards
+    pub local_infile_sharding: bool,
+    // The file of the current `LOAD DATA LOCAL INFILE`, the backend is only allowed to request it
+    pub local_infile_file: Option<Vec<u8>>,
+    // The replication state, the binlog stream is forwarded when it is enabled
+    pub binlog: BinlogSession,
+    // The session variables changed by the SET stmt, they are recorded when the stmt succeeds
+    pub pending_vars: Vec<(String, Option<String>)>,
+    // The user variables set by expressions, their values are read back from the conn after the stmt
+    pub read_back_vars: Vec<String>,
+    // The transaction across shards is committed by XA when it is set
+    pub xa: Option<Arc<XaCoordinator>>,
+    // The session is closed when the proxy shuts down and there is no open transaction
+    pub shutdown: Shutdown,
+    // The rewriter and plugin replaced by reloading config
+    pub reload: watch::Receiver<Arc<ReloadState>>,
+    // The session status shown by the admin api
+    pub session: SessionHandle,
+    // The runtime state of the proxy, it is used by the admin statements
+    pub runtime: Arc<ProxyRuntime>,
+    // The statements taking longer than the threshold are logged when it is set
+    pub slow_log: Option<SlowLog>,
+    // The rows returned by the current command
+    pub rows: u64,
+}
+
+impl<T, C> ReqContext<T, C> {
+    // Pick up the reloaded parts, the open transaction keeps the parts it started with.
+    pub fn apply_reload(&mut self) {
+        if self.fsm.in_transaction() || !matches!(self.reload.changed().now_or_never(), Some(Ok(_))) {
+            return;
+        }
+
+        let state = self.reload.borrow().clone();
+        self.rewriter = state.rewriter.clone();
+        self.plugin = state.plugin.clone();
+        self.has_readwritesplitting = state.has_readwritesplitting;
+    }
 }
 
 /// Handle the return value of the command
@@ -304,6 +796,17 @@ pub trait MySQLService<T, C> {
     async fn stmt_close(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
     async fn quit(cx: &mut ReqContext<T, C>) -> Result<RespContext, Error>;
     async fn field_list(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    async fn stmt_reset(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    // COM_STMT_SEND_LONG_DATA has no response
+    async fn stmt_send_long_data(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    async fn stmt_fetch(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    async fn change_user(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    async fn reset_connection(cx: &mut ReqContext<T, C>) -> Result<RespContext, Error>;
+    async fn statistics(cx: &mut ReqContext<T, C>) -> Result<RespContext, Error>;
+    async fn set_option(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    async fn process_kill(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    async fn register_slave(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error>;
+    async fn binlog_dump(cx: &mut ReqContext<T, C>, com: u8, payload: &[u8]) -> Result<RespContext, Error>;
 }
 
 /// Start an instance of the `MySQLService`, its used to execute method
@@ -337,9 +840,29 @@ where
         let db = cx.framed.codec_mut().get_session().get_db();
         cx.fsm.set_db(db);
 
-        while let Some(data) = cx.framed.next().await {
+        loop {
+            let data = tokio::select! {
+                data = cx.framed.next() => data,
+                _ = cx.session.killed() => return Ok(()),
+                _ = cx.shutdown.recv(), if !cx.fsm.in_transaction() => {
+                    let err_info = make_err_packet(MySQLError::new(
+                        1053,
+                        "08S01".as_bytes().to_vec(),
+                        String::from("Server shutdown in progress"),
+                    ));
+                    cx.framed.send(PacketSend::Encode(err_info[4..].into())).await.map_err(ErrorKind::from)?;
+                    return Ok(());
+                }
+            };
+
+            let data = match data {
+                Some(data) => data,
+                None => break,
+            };
+
             match data {
                 Ok(data) => {
+                    cx.apply_reload();
                     if let Err(err) = self.handle_command(&mut cx, data).await {
                         let err_info = make_err_packet(MySQLError::new(
                             2002,
@@ -354,6 +877,12 @@ where
                     };
 
                     cx.framed.codec_mut().reset_seq();
+                    cx.session.end_command(
+                        format!("{:?}", cx.fsm.current_state),
+                        cx.fsm.in_transaction(),
+                        cx.fsm.db.clone(),
+                        cx.fsm.endpoint.as_ref().map(|x| x.addr.clone()),
+                    );
 
                     if let Some(idx) = &cx.concurrency_control_rule_idx {
                         cx.plugin.as_mut().unwrap().concurrency_control.add_permits(*idx);
@@ -381,6 +910,14 @@ where
         let com = data.get_u8();
         let payload = data.split();
 
+        let com_type = ComType::from(com);
+        let info = match com_type {
+            ComType::QUERY | ComType::STMT_PREPARE => Some(&payload[..]),
+            _ => None,
+        };
+        cx.session.begin_command(com_type.as_ref(), info);
+        cx.rows = 0;
+
         if let Err(err) = self.plugin_run(cx, &payload) {
             let err_info = make_err_packet(MySQLError::new(
                 1047,
@@ -394,7 +931,7 @@ where
             return Ok(RespContext { ep: None, duration: now.elapsed() });
         }
 
-        match ComType::from(com) {
+        let res = match ComType::from(com) {
             ComType::QUIT => {
                 self.is_quit = true;
                 S::quit(cx).await
@@ -412,13 +949,21 @@ where
             ComType::STMT_PREPARE => S::prepare(cx, &payload).await,
             ComType::STMT_EXECUTE => S::execute(cx, &payload).await,
             ComType::STMT_CLOSE => S::stmt_close(cx, &payload).await,
-            ComType::STMT_RESET => {
-                cx.framed
-                    .send(PacketSend::Encode(ok_packet()[4..].into()))
-                    .await
-                    .map_err(ErrorKind::from)?;
-                return Ok(RespContext { ep: None, duration: now.elapsed() });
+            ComType::STMT_RESET => S::stmt_reset(cx, &payload).await,
+            ComType::STMT_SEND_LONG_DATA => S::stmt_send_long_data(cx, &payload).await,
+            ComType::STMT_FETCH => S::stmt_fetch(cx, &payload).await,
+            ComType::CHANGE_USER => S::change_user(cx, &payload).await,
+            ComType::RESET_CONNECTION => S::reset_connection(cx).await,
+            ComType::STATISTICS => S::statistics(cx).await,
+            ComType::SET_OPTION => S::set_option(cx, &payload).await,
+            ComType::PROCESS_KILL => {
+                // Killing the connection itself closes it like mysqld does
+                let sess = cx.framed.codec_mut().get_session();
+                self.is_quit = payload.len() >= 4 && LittleEndian::read_u32(&payload) == sess.get_connection_id();
+                S::process_kill(cx, &payload).await
             }
+            ComType::REGISTER_SLAVE => S::register_slave(cx, &payload).await,
+            ComType::BINLOG_DUMP | ComType::BINLOG_DUMP_GTID => S::binlog_dump(cx, com, &payload).await,
             x => {
                 let err_info = make_err_packet(MySQLError::new(
                     1047,
@@ -431,7 +976,45 @@ where
                     .map_err(ErrorKind::from)?;
                 return Ok(RespContext { ep: None, duration: now.elapsed() });
             }
+        };
+
+        if let Ok(resp) = &res {
+            match ComType::from(com) {
+                ComType::QUERY => {
+                    Self::log_slow_query(cx, resp, std::str::from_utf8(&payload).unwrap_or_default())
+                }
+                ComType::STMT_EXECUTE if payload.len() >= 4 => {
+                    let stmt_id = LittleEndian::read_u32(&payload);
+                    if let Some(sql) = cx.stmt_cache.get_sql(stmt_id) {
+                        Self::log_slow_query(cx, resp, sql);
+                    }
+                }
+                _ => {}
+            }
         }
+        res
+    }
+
+    fn log_slow_query(cx: &ReqContext<T, C>, resp: &RespContext, sql: &str) {
+        let slow_log = match &cx.slow_log {
+            Some(slow_log) if slow_log.is_slow(resp.duration) => slow_log,
+            _ => return,
+        };
+
+        let endpoints = match &resp.ep {
+            Some(ep) => ep.clone(),
+            None if cx.rewriter.is_some() => telemetry::shard_endpoints(&cx.rewrite_outputs),
+            None => String::new(),
+        };
+        slow_log.log(SlowQuery {
+            proxy: &cx.name,
+            user: &cx.session.user,
+            db: cx.fsm.db.as_deref(),
+            endpoints: &endpoints,
+            sql,
+            rows: cx.rows,
+            elapsed: resp.duration,
+        });
     }
 
     fn plugin_run(&mut self, cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<(), BoxError> {
@@ -0,0 +1,586 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use conn_pool::Pool;
+use endpoint::endpoint::Endpoint;
+use indexmap::IndexMap;
+use mysql_protocol::client::conn::ClientConn;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use plugin::stats::PluginStats;
+use proxy::proxy::{MySQLNode, ProxyConfig};
+use serde::Serialize;
+use strategy::{
+    config::NodeGroup,
+    route::{NodeOverrides, RouteStrategy, StragegyError},
+};
+use thiserror::Error;
+use tokio::sync::{watch, Notify};
+use tracing::{info, warn};
+
+use crate::mysql::{MySQLProxy, ReloadState};
+
+#[derive(Debug, Error)]
+pub enum AdminError {
+    #[error("node {0:?} is not found")]
+    NodeNotFound(String),
+
+    #[error("weight {0} must be greater than 0")]
+    InvalidWeight(i64),
+
+    #[error(transparent)]
+    Strategy(#[from] StragegyError),
+}
+
+tokio::task_local! {
+    // The session running on the current task, the backend conns used by the task are bound to it.
+    static CURRENT_SESSION: Arc<SessionInfo>;
+}
+
+// The running proxies, they are inspected by the admin api.
+static PROXIES: Lazy<RwLock<IndexMap<String, Arc<ProxyRuntime>>>> = Lazy::new(Default::default);
+
+pub fn register(runtime: Arc<ProxyRuntime>) {
+    PROXIES.write().insert(runtime.name.clone(), runtime);
+}
+
+pub fn unregister(name: &str) {
+    PROXIES.write().shift_remove(name);
+}
+
+pub fn get_proxies() -> Vec<Arc<ProxyRuntime>> {
+    PROXIES.read().values().cloned().collect()
+}
+
+pub fn get_proxy(name: &str) -> Option<Arc<ProxyRuntime>> {
+    PROXIES.read().get(name).cloned()
+}
+
+/// The config which is running, it is changed by reloading.
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectiveConfig {
+    pub proxy_config: ProxyConfig,
+    pub mysql_nodes: Vec<MySQLNode>,
+    pub node_group: Option<NodeGroup>,
+}
+
+/// The runtime state shared by the proxy and its sessions.
+pub struct ProxyRuntime {
+    pub name: String,
+    pub route_strategy: Arc<Mutex<RouteStrategy>>,
+    pub pool: Pool<ClientConn>,
+    pub state: watch::Receiver<Arc<ReloadState>>,
+    pub sessions: Arc<SessionRegistry>,
+    config: Mutex<EffectiveConfig>,
+    // The nodes disabled or reweighted by the admin api
+    nodes: Mutex<NodeOverrides>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointView {
+    pub name: String,
+    pub addr: String,
+    pub weight: i64,
+}
+
+impl From<&Endpoint> for EndpointView {
+    fn from(ep: &Endpoint) -> Self {
+        EndpointView { name: ep.name.clone(), addr: ep.addr.clone(), weight: ep.weight }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct BackendsView {
+    pub read: Vec<EndpointView>,
+    pub readwrite: Vec<EndpointView>,
+    pub disabled: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeView {
+    pub name: String,
+    pub addr: String,
+    pub weight: i64,
+    pub disabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DrainView {
+    pub node: NodeView,
+    // The open transactions on the node when the drain returns
+    pub pinned_transactions: usize,
+    pub drained: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolView {
+    pub user: String,
+    pub endpoint: String,
+    pub idle: usize,
+    pub size: usize,
+}
+
+impl ProxyRuntime {
+    pub fn new(
+        config: EffectiveConfig,
+        route_strategy: Arc<Mutex<RouteStrategy>>,
+        pool: Pool<ClientConn>,
+        state: watch::Receiver<Arc<ReloadState>>,
+    ) -> Self {
+        ProxyRuntime {
+            name: config.proxy_config.name.clone(),
+            route_strategy,
+            pool,
+            state,
+            sessions: Arc::new(SessionRegistry::default()),
+            config: Mutex::new(config),
+            nodes: Mutex::new(NodeOverrides::default()),
+        }
+    }
+
+    pub fn get_config(&self) -> EffectiveConfig {
+        self.config.lock().clone()
+    }
+
+    pub fn set_config(&self, config: EffectiveConfig) {
+        *self.config.lock() = config;
+    }
+
+    // The node endpoint of the backend addr.
+    pub fn find_endpoint(&self, addr: &str) -> Option<Endpoint> {
+        self.config
+            .lock()
+            .mysql_nodes
+            .iter()
+            .map(|x| Endpoint::from(x.clone()))
+            .find(|x| x.addr == addr)
+    }
+
+    // Whether the frontend user is allowed to run the `PISA` admin statements.
+    pub fn is_admin(&self, user: &str) -> bool {
+        self.config.lock().proxy_config.admin_users.iter().any(|x| x == user)
+    }
+
+    // Whether the frontend user is allowed to subscribe the binlog stream.
+    pub fn is_replication_user(&self, user: &str) -> bool {
+        self.config.lock().proxy_config.replication_users.iter().any(|x| x == user)
+    }
+
+    // The node changes conflicting with the discovery are cleared by the route strategy, e.g. the
+    // disabled node becomes the readwrite node, they are cleared here as well.
+    fn sync_nodes(&self) {
+        let is_cleared = self.route_strategy.lock().take_overrides_cleared();
+        if is_cleared {
+            warn!("proxy {:?} node changes are cleared by the discovery", self.name);
+            *self.nodes.lock() = NodeOverrides::default();
+        }
+    }
+
+    // The live endpoints after discovery.
+    pub fn get_backends(&self) -> BackendsView {
+        self.sync_nodes();
+        let rw_endpoint = self.route_strategy.lock().get_endpoints();
+        BackendsView {
+            read: rw_endpoint.read.iter().map(EndpointView::from).collect(),
+            readwrite: rw_endpoint.readwrite.iter().map(EndpointView::from).collect(),
+            disabled: self.nodes.lock().disabled.iter().cloned().collect(),
+        }
+    }
+
+    pub fn get_node(&self, name: &str) -> Result<NodeView, AdminError> {
+        self.sync_nodes();
+        let node = self
+            .config
+            .lock()
+            .mysql_nodes
+            .iter()
+            .find(|x| x.name == name)
+            .cloned()
+            .ok_or_else(|| AdminError::NodeNotFound(name.to_string()))?;
+
+        let nodes = self.nodes.lock();
+        Ok(NodeView {
+            weight: nodes.weights.get(name).copied().unwrap_or(node.weight),
+            disabled: nodes.disabled.contains(name),
+            addr: Endpoint::from(node).addr,
+            name: name.to_string(),
+        })
+    }
+
+    // The nodes are changed on the live route strategy, the sessions pick up the change on
+    // their next routing.
+    fn update_nodes<F>(&self, name: &str, f: F) -> Result<NodeView, AdminError>
+    where
+        F: FnOnce(&mut NodeOverrides),
+    {
+        self.get_node(name)?;
+
+        let mut nodes = self.nodes.lock();
+        let mut next = nodes.clone();
+        f(&mut next);
+        self.route_strategy.lock().set_overrides(&next)?;
+        *nodes = next;
+        drop(nodes);
+
+        info!("proxy {:?} node {:?} is changed", self.name, name);
+        self.get_node(name)
+    }
+
+    pub fn disable_node(&self, name: &str) -> Result<NodeView, AdminError> {
+        self.update_nodes(name, |x| {
+            x.disabled.insert(name.to_string());
+        })
+    }
+
+    pub fn enable_node(&self, name: &str) -> Result<NodeView, AdminError> {
+        self.update_nodes(name, |x| {
+            x.disabled.shift_remove(name);
+        })
+    }
+
+    pub fn set_node_weight(&self, name: &str, weight: i64) -> Result<NodeView, AdminError> {
+        if weight <= 0 {
+            return Err(AdminError::InvalidWeight(weight));
+        }
+
+        self.update_nodes(name, |x| {
+            x.weights.insert(name.to_string(), weight);
+        })
+    }
+
+    // Stop routing to the node, then wait for the transactions pinned on it. The idle conns of
+    // the node are closed when there is no pinned transaction.
+    pub async fn drain_node(&self, name: &str, timeout: Duration) -> Result<DrainView, AdminError> {
+        let node = self.disable_node(name)?;
+        let now = Instant::now();
+
+        let pinned_transactions = loop {
+            let pinned = self
+                .sessions
+                .get_all()
+                .iter()
+                .map(|x| x.view())
+                .filter(|x| x.in_transaction && x.is_bound_to(&node.addr))
+                .count();
+            if pinned == 0 || now.elapsed() >= timeout {
+                break pinned;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+
+        let drained = pinned_transactions == 0;
+        if drained {
+            MySQLProxy::close_conns(self.pool.remove_endpoint(&node.addr)).await;
+        } else {
+            warn!(
+                "proxy {:?} node {:?} has {} pinned transactions",
+                self.name, name, pinned_transactions
+            );
+        }
+
+        Ok(DrainView { node, pinned_transactions, drained })
+    }
+
+    // The reloaded route strategy keeps the nodes changed by the admin api.
+    pub fn restore_nodes(&self, route_strategy: &mut RouteStrategy) {
+        self.sync_nodes();
+        let mut nodes = self.nodes.lock();
+        if nodes.is_empty() {
+            return;
+        }
+
+        if let Err(e) = route_strategy.set_overrides(&nodes) {
+            warn!("proxy {:?} node changes are dropped by reloading: {}", self.name, e);
+            *nodes = NodeOverrides::default();
+        }
+    }
+
+    // Idle conns of each backend user and endpoint.
+    pub fn get_pools(&self) -> Vec<PoolView> {
+        let size = self.pool.size();
+        self.pool
+            .idle_stats()
+            .into_iter()
+            .map(|(key, idle)| {
+                let (user, endpoint) = key.rsplit_once('@').unwrap_or(("", &key));
+                PoolView { user: user.to_string(), endpoint: endpoint.to_string(), idle, size }
+            })
+            .collect()
+    }
+
+    pub fn get_sessions(&self) -> Vec<SessionView> {
+        self.sessions.get_all().iter().map(|x| x.view()).collect()
+    }
+
+    pub fn get_rules(&self) -> PluginStats {
+        self.state.borrow().plugin.as_ref().map(|x| x.stats()).unwrap_or_default()
+    }
+}
+
+/// The client sessions of a proxy.
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: Mutex<IndexMap<u32, Arc<SessionInfo>>>,
+}
+
+impl SessionRegistry {
+    // The session is removed when the handle is dropped.
+    pub fn register(self: &Arc<Self>, id: u32, user: &str, host: &str) -> SessionHandle {
+        let info = Arc::new(SessionInfo {
+            id,
+            user: user.to_string(),
+            host: host.to_string(),
+            status: Mutex::new(SessionStatus::default()),
+            killed: Notify::new(),
+        });
+        self.sessions.lock().insert(id, info.clone());
+        SessionHandle { info, registry: self.clone() }
+    }
+
+    pub fn get(&self, id: u32) -> Option<Arc<SessionInfo>> {
+        self.sessions.lock().get(&id).cloned()
+    }
+
+    pub fn get_all(&self) -> Vec<Arc<SessionInfo>> {
+        self.sessions.lock().values().cloned().collect()
+    }
+}
+
+pub struct SessionHandle {
+    info: Arc<SessionInfo>,
+    registry: Arc<SessionRegistry>,
+}
+
+impl SessionHandle {
+    pub fn info(&self) -> Arc<SessionInfo> {
+        self.info.clone()
+    }
+}
+
+impl std::ops::Deref for SessionHandle {
+    type Target = SessionInfo;
+
+    fn deref(&self) -> &Self::Target {
+        &self.info
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.registry.sessions.lock().shift_remove(&self.info.id);
+    }
+}
+
+pub struct SessionInfo {
+    pub id: u32,
+    pub user: String,
+    pub host: String,
+    status: Mutex<SessionStatus>,
+    killed: Notify,
+}
+
+/// The backend conn used by a session, it is killed by `KILL` of the session.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BackendThread {
+    pub addr: String,
+    pub thread_id: u32,
+}
+
+// Run the future as the session, the backend conns used by it are bound to the session.
+pub async fn scope<F: Future>(session: Arc<SessionInfo>, f: F) -> F::Output {
+    CURRENT_SESSION.scope(session, f).await
+}
+
+// Bind the backend conn to the session of the current task, it does nothing out of a session.
+pub fn bind_backend(conn: &ClientConn) {
+    let thread = match (conn.get_endpoint(), conn.get_connection_id()) {
+        (Some(addr), Some(thread_id)) => BackendThread { addr, thread_id },
+        _ => return,
+    };
+
+    let _ = CURRENT_SESSION.try_with(|x| {
+        let mut status = x.status.lock();
+        if !status.backends.contains(&thread) {
+            status.backends.push(thread);
+        }
+    });
+}
+
+#[derive(Debug, Clone)]
+struct SessionStatus {
+    db: Option<String>,
+    command: String,
+    info: Option<String>,
+    state: String,
+    endpoint: Option<String>,
+    in_transaction: bool,
+    backends: Vec<BackendThread>,
+    since: Instant,
+}
+
+impl Default for SessionStatus {
+    fn default() -> Self {
+        SessionStatus {
+            db: None,
+            command: "sleep".to_string(),
+            info: None,
+            state: String::new(),
+            endpoint: None,
+            in_transaction: false,
+            backends: vec![],
+            since: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionView {
+    pub id: u32,
+    pub user: String,
+    pub host: String,
+    pub db: Option<String>,
+    pub command: String,
+    // Seconds in the current command
+    pub time: u64,
+    // The state of transaction fsm
+    pub state: String,
+    pub info: Option<String>,
+    // The backend endpoint which the session is bound to
+    pub endpoint: Option<String>,
+    pub in_transaction: bool,
+    // The backend conns used by the running command or the open transaction
+    pub backends: Vec<BackendThread>,
+}
+
+impl SessionView {
+    // The sharded transaction holds a conn of each shard, it is not bound to a single endpoint.
+    pub fn is_bound_to(&self, addr: &str) -> bool {
+        self.endpoint.as_deref() == Some(addr) || self.backends.iter().any(|b| b.addr == addr)
+    }
+}
+
+// The statement is truncated in the session info.
+const MAX_INFO_LEN: usize = 1024;
+
+impl SessionInfo {
+    pub fn begin_command(&self, command: &str, info: Option<&[u8]>) {
+        let mut status = self.status.lock();
+        status.command = command.to_string();
+        status.info =
+            info.map(|x| String::from_utf8_lossy(&x[..x.len().min(MAX_INFO_LEN)]).to_string());
+        status.since = Instant::now();
+    }
+
+    pub fn end_command(
+        &self,
+        state: String,
+        in_transaction: bool,
+        db: Option<String>,
+        endpoint: Option<String>,
+    ) {
+        let mut status = self.status.lock();
+        status.command = "sleep".to_string();
+        status.info = None;
+        status.state = state;
+        status.in_transaction = in_transaction;
+        status.db = db;
+        status.endpoint = endpoint;
+        if !in_transaction {
+            status.backends.clear();
+        }
+        status.since = Instant::now();
+    }
+
+    pub fn get_backends(&self) -> Vec<BackendThread> {
+        self.status.lock().backends.clone()
+    }
+
+    // The session is closed after the running command.
+    pub fn kill(&self) {
+        self.killed.notify_one();
+    }
+
+    pub async fn killed(&self) {
+        self.killed.notified().await
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.status.lock().since.elapsed()
+    }
+
+    pub fn view(&self) -> SessionView {
+        let status = self.status.lock();
+        SessionView {
+            id: self.id,
+            user: self.user.clone(),
+            host: self.host.clone(),
+            db: status.db.clone(),
+            command: status.command.clone(),
+            time: status.since.elapsed().as_secs(),
+            state: status.state.clone(),
+            info: status.info.clone(),
+            endpoint: status.endpoint.clone(),
+            in_transaction: status.in_transaction,
+            backends: status.backends.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_registry() {
+        let registry = Arc::new(SessionRegistry::default());
+        let session = registry.register(1, "root", "127.0.0.1");
+        assert_eq!(registry.get_all().len(), 1);
+
+        session.begin_command("query", Some(b"select 1"));
+        let view = registry.get(1).unwrap().view();
+        assert_eq!((view.command.as_str(), view.info.as_deref()), ("query", Some("select 1")));
+
+        session.end_command("TransStartState".to_string(), true, Some("test".to_string()), None);
+        let view = session.view();
+        assert_eq!(view.command, "sleep");
+        assert!(view.info.is_none() && view.in_transaction);
+        assert_eq!(view.db.as_deref(), Some("test"));
+
+        // The sharded transaction is bound to the endpoints of its conns.
+        let mut view = session.view();
+        view.backends.push(BackendThread { addr: "127.0.0.1:3307".to_string(), thread_id: 9 });
+        assert!(view.is_bound_to("127.0.0.1:3307"));
+        assert!(!view.is_bound_to("127.0.0.1:3306"));
+
+        drop(session);
+        assert!(registry.get(1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_kill() {
+        let registry = Arc::new(SessionRegistry::default());
+        let session = registry.register(1, "root", "127.0.0.1");
+
+        // The kill is kept until the session checks it.
+        registry.get(1).unwrap().kill();
+        tokio::time::timeout(Duration::from_secs(1), session.killed()).await.unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::BytesMut;
+use mysql_protocol::{
+    column::ColumnInfo,
+    mysql_const::{ColumnFlag, ColumnType},
+    row::encode_text_row,
+    server::codec::{make_eof_packet, PacketSend},
+    util::BufMutExt,
+};
+use tokio_util::codec::Encoder;
+
+use crate::registry::{AdminError, ProxyRuntime};
+
+/// The statements of the `PISA` namespace, they are answered by the proxy.
+#[derive(Debug, PartialEq)]
+pub enum AdminStmt {
+    ShowBackends,
+    ShowPools,
+    ShowSessions,
+    ShowRules,
+    SetNode(String, NodeAction),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NodeAction {
+    Online,
+    Offline,
+    Weight(i64),
+}
+
+// Return `None` when the sql is not in the `PISA` namespace, it is routed to backends.
+pub fn parse_admin_stmt(sql: &str) -> Option<Result<AdminStmt, String>> {
+    let sql = sql.trim_matches(char::from(0)).trim().trim_end_matches(';');
+    let tokens: Vec<&str> = sql.split_whitespace().collect();
+    let keywords: Vec<String> = tokens.iter().map(|x| x.to_uppercase()).collect();
+    let keywords: Vec<&str> = keywords.iter().map(|x| x.as_str()).collect();
+
+    let stmt = match keywords.as_slice() {
+        ["SHOW", "PISA", rest @ ..] => match rest {
+            ["BACKENDS"] => Ok(AdminStmt::ShowBackends),
+            ["POOLS"] => Ok(AdminStmt::ShowPools),
+            ["SESSIONS"] => Ok(AdminStmt::ShowSessions),
+            ["RULES"] => Ok(AdminStmt::ShowRules),
+            _ => Err(sql),
+        },
+
+        ["PISA", "SET", "NODE", _, rest @ ..] => {
+            let node = tokens[3].trim_matches(|c| c == '`' || c == '\'' || c == '"').to_string();
+            match rest {
+                ["ONLINE"] => Ok(AdminStmt::SetNode(node, NodeAction::Online)),
+                ["OFFLINE"] => Ok(AdminStmt::SetNode(node, NodeAction::Offline)),
+                ["WEIGHT", weight] => weight
+                    .parse()
+                    .map(|x| AdminStmt::SetNode(node, NodeAction::Weight(x)))
+                    .map_err(|_| sql),
+                _ => Err(sql),
+            }
+        }
+
+        ["PISA", ..] => Err(sql),
+
+        _ => return None,
+    };
+
+    Some(stmt.map_err(|sql| {
+        format!("You have an error in your SQL syntax; check the PISA statement near '{}'", sql)
+    }))
+}
+
+/// The text resultset answered by the proxy.
+#[derive(Debug, Default)]
+pub struct AdminResultset {
+    pub columns: Vec<(&'static str, ColumnType)>,
+    pub rows: Vec<Vec<Option<String>>>,
+}
+
+impl AdminResultset {
+    pub(crate) fn new(columns: Vec<(&'static str, ColumnType)>) -> Self {
+        AdminResultset { columns, rows: vec![] }
+    }
+
+    pub(crate) fn add_row(&mut self, row: Vec<Option<String>>) {
+        self.rows.push(row);
+    }
+
+    // Encode the packets of column count, columns, eof, rows and eof.
+    pub fn encode<C>(&self, codec: &mut C, buf: &mut BytesMut) -> Result<(), C::Error>
+    where
+        C: Encoder<PacketSend<Box<[u8]>>>,
+    {
+        let mut data = Vec::with_capacity(128);
+        data.put_lenc_int(self.columns.len() as u64, true);
+        codec.encode(PacketSend::EncodeOffset(data[..].into(), buf.len()), buf)?;
+
+        for (name, column_type) in &self.columns {
+            let is_num = *column_type == ColumnType::MYSQL_TYPE_LONGLONG;
+            let column = ColumnInfo {
+                schema: None,
+                table_name: None,
+                column_name: name.to_string(),
+                // binary for numbers, utf8mb4_general_ci for strings
+                charset: if is_num { 0x3f } else { 0x2d },
+                column_length: if is_num { 20 } else { 1024 },
+                column_type: *column_type,
+                column_flag: if is_num {
+                    ColumnFlag::BINARY_FLAG as u16 | ColumnFlag::NUM_FLAG as u16
+                } else {
+                    0
+                },
+                decimals: 0,
+            };
+
+            data.clear();
+            column.encode(&mut data);
+            codec.encode(PacketSend::EncodeOffset(data[..].into(), buf.len()), buf)?;
+        }
+
+        codec.encode(PacketSend::EncodeOffset(make_eof_packet()[4..].into(), buf.len()), buf)?;
+
+        for row in &self.rows {
+            data.clear();
+            encode_text_row(&mut data, row);
+            codec.encode(PacketSend::EncodeOffset(data[..].into(), buf.len()), buf)?;
+        }
+
+        codec.encode(PacketSend::EncodeOffset(make_eof_packet()[4..].into(), buf.len()), buf)
+    }
+}
+
+pub fn execute(runtime: &ProxyRuntime, stmt: AdminStmt) -> Result<AdminResultset, AdminError> {
+    use ColumnType::{MYSQL_TYPE_LONGLONG as LONGLONG, MYSQL_TYPE_VAR_STRING as VARCHAR};
+
+    match stmt {
+        AdminStmt::ShowBackends => {
+            let mut rs = AdminResultset::new(vec![
+                ("name", VARCHAR),
+                ("addr", VARCHAR),
+                ("role", VARCHAR),
+                ("weight", LONGLONG),
+                ("state", VARCHAR),
+            ]);
+
+            let backends = runtime.get_backends();
+            let roles = [("read", &backends.read), ("readwrite", &backends.readwrite)];
+            for (role, endpoints) in roles {
+                for ep in endpoints.iter() {
+                    rs.add_row(vec![
+                        Some(ep.name.clone()),
+                        Some(ep.addr.clone()),
+                        Some(role.to_string()),
+                        Some(ep.weight.to_string()),
+                        Some("online".to_string()),
+                    ]);
+                }
+            }
+
+            for name in backends.disabled.iter() {
+                let node = runtime.get_node(name)?;
+                rs.add_row(vec![
+                    Some(node.name),
+                    Some(node.addr),
+                    None,
+                    Some(node.weight.to_string()),
+                    Some("offline".to_string()),
+                ]);
+            }
+
+            Ok(rs)
+        }
+
+        AdminStmt::ShowPools => {
+            let mut rs = AdminResultset::new(vec![
+                ("user", VARCHAR),
+                ("endpoint", VARCHAR),
+                ("idle", LONGLONG),
+                ("size", LONGLONG),
+            ]);
+
+            for pool in runtime.get_pools() {
+                rs.add_row(vec![
+                    Some(pool.user),
+                    Some(pool.endpoint),
+                    Some(pool.idle.to_string()),
+                    Some(pool.size.to_string()),
+                ]);
+            }
+
+            Ok(rs)
+        }
+
+        AdminStmt::ShowSessions => {
+            let mut rs = AdminResultset::new(vec![
+                ("id", LONGLONG),
+                ("user", VARCHAR),
+                ("host", VARCHAR),
+                ("db", VARCHAR),
+                ("command", VARCHAR),
+                ("time", LONGLONG),
+                ("state", VARCHAR),
+                ("in_transaction", LONGLONG),
+                ("endpoint", VARCHAR),
+                ("backends", VARCHAR),
+                ("info", VARCHAR),
+            ]);
+
+            for session in runtime.get_sessions() {
+                // addr#thread_id of the bound backend conns
+                let backends = session
+                    .backends
+                    .iter()
+                    .map(|x| format!("{}#{}", x.addr, x.thread_id))
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                rs.add_row(vec![
+                    Some(session.id.to_string()),
+                    Some(session.user),
+                    Some(session.host),
+                    session.db,
+                    Some(session.command),
+                    Some(session.time.to_string()),
+                    Some(session.state),
+                    Some((session.in_transaction as u8).to_string()),
+                    session.endpoint,
+                    Some(backends),
+                    session.info,
+                ]);
+            }
+
+            Ok(rs)
+        }
+
+        AdminStmt::ShowRules => {
+            let mut rs = AdminResultset::new(vec![
+                ("plugin", VARCHAR),
+                ("regex", VARCHAR),
+                ("hits", LONGLONG),
+                ("rejects", LONGLONG),
+            ]);
+
+            let stats = runtime.get_rules();
+            let plugins = [
+                ("concurrency_control", stats.concurrency_control),
+                ("circuit_break", stats.circuit_break),
+            ];
+            for (plugin, rules) in plugins {
+                for rule in rules {
+                    rs.add_row(vec![
+                        Some(plugin.to_string()),
+                        Some(rule.regex.join(",")),
+                        Some(rule.hits.to_string()),
+                        Some(rule.rejects.to_string()),
+                    ]);
+                }
+            }
+
+            Ok(rs)
+        }
+
+        AdminStmt::SetNode(name, action) => {
+            let node = match action {
+                NodeAction::Online => runtime.enable_node(&name)?,
+                NodeAction::Offline => runtime.disable_node(&name)?,
+                NodeAction::Weight(weight) => runtime.set_node_weight(&name, weight)?,
+            };
+
+            let mut rs = AdminResultset::new(vec![
+                ("name", VARCHAR),
+                ("addr", VARCHAR),
+                ("weight", LONGLONG),
+                ("state", VARCHAR),
+            ]);
+            rs.add_row(vec![
+                Some(node.name),
+                Some(node.addr),
+                Some(node.weight.to_string()),
+                Some(if node.disabled { "offline" } else { "online" }.to_string()),
+            ]);
+
+            Ok(rs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_admin_stmt() {
+        assert_eq!(parse_admin_stmt("select 1"), None);
+        assert_eq!(parse_admin_stmt("show databases"), None);
+        assert_eq!(parse_admin_stmt("show pisa backends;"), Some(Ok(AdminStmt::ShowBackends)));
+        assert_eq!(parse_admin_stmt("  SHOW PISA Sessions "), Some(Ok(AdminStmt::ShowSessions)));
+        assert_eq!(
+            parse_admin_stmt("pisa set node `ds001` offline"),
+            Some(Ok(AdminStmt::SetNode("ds001".to_string(), NodeAction::Offline)))
+        );
+        assert_eq!(
+            parse_admin_stmt("PISA SET NODE ds001 WEIGHT 3"),
+            Some(Ok(AdminStmt::SetNode("ds001".to_string(), NodeAction::Weight(3))))
+        );
+        assert!(matches!(parse_admin_stmt("pisa set node ds001 weight x"), Some(Err(_))));
+        assert!(matches!(parse_admin_stmt("show pisa tables"), Some(Err(_))));
+        assert!(matches!(parse_admin_stmt("pisa"), Some(Err(_))));
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use endpoint::endpoint::Endpoint;
+use proxy::proxy::ProxyUser;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Credential {
+    user: String,
+    password: String,
+}
+
+/// The backend credential of a frontend user, the node config credential is used when nothing is mapped.
+#[derive(Debug, Clone, Default)]
+pub struct BackendUser {
+    passthrough: Option<Credential>,
+    nodes: HashMap<String, Credential>,
+    default: Option<Credential>,
+}
+
+impl BackendUser {
+    // Replace user and password of endpoint with the mapped backend credential.
+    pub fn map_endpoint(&self, mut endpoint: Endpoint) -> Endpoint {
+        let credential = self
+            .passthrough
+            .as_ref()
+            .or_else(|| self.nodes.get(&endpoint.name))
+            .or_else(|| self.default.as_ref());
+
+        if let Some(credential) = credential {
+            endpoint.user = credential.user.clone();
+            endpoint.password = credential.password.clone();
+        }
+
+        endpoint
+    }
+}
+
+/// Backend users keyed by frontend user name.
+#[derive(Debug, Clone, Default)]
+pub struct BackendUsers {
+    users: HashMap<String, BackendUser>,
+}
+
+impl BackendUsers {
+    pub fn new(users: &[ProxyUser]) -> Result<Self, String> {
+        let mut backend_users = HashMap::with_capacity(users.len());
+
+        for user in users {
+            let mut backend_user = BackendUser::default();
+
+            if user.backend_passthrough {
+                let password = user.password.clone().ok_or_else(|| {
+                    format!(
+                        "backend_passthrough of user {:?} requires a plain text password",
+                        user.user
+                    )
+                })?;
+                backend_user.passthrough = Some(Credential { user: user.user.clone(), password });
+            }
+
+            for item in &user.backend_users {
+                let credential =
+                    Credential { user: item.user.clone(), password: item.password.clone() };
+                match &item.node {
+                    Some(node) => {
+                        backend_user.nodes.insert(node.clone(), credential);
+                    }
+                    None => backend_user.default = Some(credential),
+                }
+            }
+
+            backend_users.insert(user.user.clone(), backend_user);
+        }
+
+        Ok(Self { users: backend_users })
+    }
+
+    pub fn get(&self, user: &str) -> BackendUser {
+        self.users.get(user).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proxy::proxy::ProxyBackendUser;
+
+    use super::*;
+
+    fn make_endpoint(name: &str) -> Endpoint {
+        Endpoint {
+            weight: 1,
+            name: name.to_string(),
+            db: "db".to_string(),
+            user: "root".to_string(),
+            password: "root".to_string(),
+            addr: "127.0.0.1:3306".to_string(),
+            compression: None,
+            compression_level: None,
+        }
+    }
+
+    #[test]
+    fn test_map_endpoint() {
+        let users = vec![
+            ProxyUser {
+                user: "app".to_string(),
+                password: Some("app_pwd".to_string()),
+                backend_passthrough: true,
+                ..Default::default()
+            },
+            ProxyUser {
+                user: "ops".to_string(),
+                native_password_hash: Some("*6BB4837EB74329105EE4568DDA7DC67ED2CA2AD9".to_string()),
+                backend_users: vec![
+                    ProxyBackendUser {
+                        node: Some("ds001".to_string()),
+                        user: "ops_ds001".to_string(),
+                        password: "p1".to_string(),
+                    },
+                    ProxyBackendUser {
+                        node: None,
+                        user: "ops_all".to_string(),
+                        password: "p2".to_string(),
+                    },
+                ],
+                ..Default::default()
+            },
+        ];
+
+        let backend_users = BackendUsers::new(&users).unwrap();
+
+        let ep = backend_users.get("app").map_endpoint(make_endpoint("ds001"));
+        assert_eq!((ep.user.as_str(), ep.password.as_str()), ("app", "app_pwd"));
+
+        let ep = backend_users.get("ops").map_endpoint(make_endpoint("ds001"));
+        assert_eq!((ep.user.as_str(), ep.password.as_str()), ("ops_ds001", "p1"));
+
+        let ep = backend_users.get("ops").map_endpoint(make_endpoint("ds002"));
+        assert_eq!((ep.user.as_str(), ep.password.as_str()), ("ops_all", "p2"));
+
+        let ep = backend_users.get("unknown").map_endpoint(make_endpoint("ds002"));
+        assert_eq!((ep.user.as_str(), ep.password.as_str()), ("root", "root"));
+
+        let users = vec![ProxyUser {
+            user: "app".to_string(),
+            native_password_hash: Some("*6BB4837EB74329105EE4568DDA7DC67ED2CA2AD9".to_string()),
+            backend_passthrough: true,
+            ..Default::default()
+        }];
+        assert!(BackendUsers::new(&users).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replication passthrough, the binlog stream of the read-write endpoint is forwarded to
+//! CDC clients such as Debezium and Canal.
+
+use std::collections::BTreeMap;
+
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::BytesMut;
+use endpoint::endpoint::Endpoint;
+use mysql_protocol::{client::conn::ClientConn, err::ProtocolError};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+// The flag of COM_BINLOG_DUMP_GTID, the gtid set is sent in the command.
+const BINLOG_THROUGH_GTID: u16 = 0x04;
+
+const QUERY_EVENT: u8 = 2;
+const XID_EVENT: u8 = 16;
+const GTID_LOG_EVENT: u8 = 33;
+const XA_PREPARE_LOG_EVENT: u8 = 38;
+
+// timestamp, type, server_id, event_size, log_pos, flags
+const EVENT_HEADER_LEN: usize = 19;
+
+// The session variables of replication, they are replayed on the dedicated conn.
+const REPLICATION_VARS: [&str; 7] = [
+    "@master_binlog_checksum",
+    "@source_binlog_checksum",
+    "@master_heartbeat_period",
+    "@source_heartbeat_period",
+    "@slave_uuid",
+    "@replica_uuid",
+    "@rpl_semi_sync",
+];
+
+/// The executed gtid set, intervals are `[start, end)` like the encoding of MySQL.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GtidSet {
+    sids: BTreeMap<[u8; 16], Vec<(u64, u64)>>,
+}
+
+impl GtidSet {
+    pub fn decode(data: &[u8]) -> Option<GtidSet> {
+        let mut set = GtidSet::default();
+        let n_sids = LittleEndian::read_u64(data.get(..8)?);
+        let mut pos = 8;
+
+        for _ in 0..n_sids {
+            let mut sid = [0u8; 16];
+            sid.copy_from_slice(data.get(pos..pos + 16)?);
+            let n_intervals = LittleEndian::read_u64(data.get(pos + 16..pos + 24)?);
+            pos += 24;
+
+            let intervals = set.sids.entry(sid).or_default();
+            for _ in 0..n_intervals {
+                let interval = data.get(pos..pos + 16)?;
+                intervals.push((
+                    LittleEndian::read_u64(&interval[..8]),
+                    LittleEndian::read_u64(&interval[8..]),
+                ));
+                pos += 16;
+            }
+        }
+
+        Some(set)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0; 8];
+        LittleEndian::write_u64(&mut data, self.sids.len() as u64);
+
+        let mut buf = [0; 8];
+        for (sid, intervals) in self.sids.iter() {
+            data.extend_from_slice(sid);
+            LittleEndian::write_u64(&mut buf, intervals.len() as u64);
+            data.extend_from_slice(&buf);
+            for (start, end) in intervals.iter() {
+                LittleEndian::write_u64(&mut buf, *start);
+                data.extend_from_slice(&buf);
+                LittleEndian::write_u64(&mut buf, *end);
+                data.extend_from_slice(&buf);
+            }
+        }
+
+        data
+    }
+
+    pub fn add(&mut self, sid: [u8; 16], gno: u64) {
+        let intervals = self.sids.entry(sid).or_default();
+        intervals.push((gno, gno + 1));
+        intervals.sort_unstable();
+
+        // Merge the adjacent and overlapped intervals.
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(intervals.len());
+        for (start, end) in intervals.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+}
+
+/// COM_BINLOG_DUMP_GTID, the gtid set is updated by the forwarded events so that
+/// the dump can be sent to the new primary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinlogDumpGtid {
+    flags: u16,
+    server_id: u32,
+    gtid_set: GtidSet,
+    // The gtid of the transaction which is not committed yet.
+    pending: Option<([u8; 16], u64)>,
+}
+
+impl BinlogDumpGtid {
+    pub fn decode(payload: &[u8]) -> Option<BinlogDumpGtid> {
+        let flags = LittleEndian::read_u16(payload.get(..2)?);
+        let server_id = LittleEndian::read_u32(payload.get(2..6)?);
+        let file_len = LittleEndian::read_u32(payload.get(6..10)?) as usize;
+        // The file and position are skipped.
+        payload.get(10 + file_len..18 + file_len)?;
+
+        let gtid_set = if flags & BINLOG_THROUGH_GTID > 0 {
+            let data_len =
+                LittleEndian::read_u32(payload.get(18 + file_len..22 + file_len)?) as usize;
+            GtidSet::decode(payload.get(22 + file_len..22 + file_len + data_len)?)?
+        } else {
+            GtidSet::default()
+        };
+
+        Some(BinlogDumpGtid { flags, server_id, gtid_set, pending: None })
+    }
+
+    // The dump is sent to the new primary from the gtid set, the file and position are cleared
+    // because they are not the same on the new primary.
+    pub fn encode(&self) -> Vec<u8> {
+        let gtid_set = self.gtid_set.encode();
+        let mut data = vec![0; 22];
+
+        LittleEndian::write_u16(&mut data[..2], self.flags | BINLOG_THROUGH_GTID);
+        LittleEndian::write_u32(&mut data[2..6], self.server_id);
+        LittleEndian::write_u32(&mut data[6..10], 0);
+        LittleEndian::write_u64(&mut data[10..18], 4);
+        LittleEndian::write_u32(&mut data[18..22], gtid_set.len() as u32);
+        data.extend_from_slice(&gtid_set);
+
+        data
+    }
+
+    // Track the gtid of the event packet, the gtid is added to the set when the transaction is committed.
+    pub fn track(&mut self, packet: &[u8]) {
+        // The packet starts with the OK byte.
+        if packet.len() < 1 + EVENT_HEADER_LEN || packet[0] != 0 {
+            return;
+        }
+
+        let body = &packet[1 + EVENT_HEADER_LEN..];
+        match packet[5] {
+            GTID_LOG_EVENT if body.len() >= 25 => {
+                let mut sid = [0u8; 16];
+                sid.copy_from_slice(&body[1..17]);
+                self.pending = Some((sid, LittleEndian::read_u64(&body[17..25])));
+            }
+
+            XID_EVENT | XA_PREPARE_LOG_EVENT => self.commit(),
+
+            // DDL is committed by the QUERY_EVENT, `BEGIN` starts the transaction.
+            QUERY_EVENT if !is_begin_query(body) => self.commit(),
+
+            _ => {}
+        }
+    }
+
+    // Whether the events of a transaction are being forwarded, its gtid is not in the set yet.
+    pub fn in_transaction(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn commit(&mut self) {
+        if let Some((sid, gno)) = self.pending.take() {
+            self.gtid_set.add(sid, gno);
+        }
+    }
+}
+
+fn is_begin_query(body: &[u8]) -> bool {
+    // thread_id, exec_time, schema_len, error_code, status_vars_len, status_vars, schema, 0x00, query
+    if body.len() < 13 {
+        return false;
+    }
+    let schema_len = body[8] as usize;
+    let status_len = LittleEndian::read_u16(&body[11..13]) as usize;
+    let start = 13 + status_len + schema_len + 1;
+
+    body.get(start..start + 5).map(|x| x.eq_ignore_ascii_case(b"BEGIN")).unwrap_or(false)
+}
+
+/// The replication state of the client session.
+#[derive(Debug, Default)]
+pub struct BinlogSession {
+    pub enabled: bool,
+    // The replication session variables set by the client.
+    vars: Vec<String>,
+    // COM_REGISTER_SLAVE is sent again when the dump is re-targeted.
+    pub register: Option<Vec<u8>>,
+    // The dedicated conn registered by COM_REGISTER_SLAVE, it's used by the following dump.
+    pub conn: Option<(ClientConn, Endpoint)>,
+}
+
+impl BinlogSession {
+    pub fn new(enabled: bool) -> Self {
+        BinlogSession { enabled, ..Default::default() }
+    }
+
+    // Record the replication session variable set by the query, it is still forwarded.
+    pub fn record_query(&mut self, sql: &str) {
+        if !self.enabled || !is_replication_var(sql) {
+            return;
+        }
+        if !self.vars.iter().any(|x| x == sql) {
+            self.vars.push(sql.to_string())
+        }
+    }
+
+    pub fn get_vars(&self) -> &[String] {
+        &self.vars
+    }
+}
+
+fn is_replication_var(sql: &str) -> bool {
+    let sql = sql.trim_start().to_lowercase();
+    sql.starts_with("set") && REPLICATION_VARS.iter().any(|x| sql.contains(x))
+}
+
+/// Reads the binlog stream in a task, so that the stream is not cancelled by `select!`.
+/// The task is aborted when it is dropped.
+pub struct BinlogReader {
+    rx: mpsc::Receiver<Result<BytesMut, ProtocolError>>,
+    handle: JoinHandle<()>,
+}
+
+impl BinlogReader {
+    pub fn new(mut conn: ClientConn) -> Self {
+        let (tx, rx) = mpsc::channel(64);
+        let handle = tokio::spawn(async move {
+            while let Some(res) = conn.read_binlog_packet().await {
+                let is_err = res.is_err();
+                if tx.send(res).await.is_err() || is_err {
+                    break;
+                }
+            }
+        });
+
+        BinlogReader { rx, handle }
+    }
+
+    // Returns `None` when the conn is closed.
+    pub async fn next(&mut self) -> Option<Result<BytesMut, ProtocolError>> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for BinlogReader {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_event(typ: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; 1 + EVENT_HEADER_LEN];
+        packet[5] = typ;
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn make_query_event(query: &str) -> Vec<u8> {
+        let mut body = vec![0u8; 13];
+        body[8] = 4;
+        LittleEndian::write_u16(&mut body[11..13], 2);
+        body.extend_from_slice(&[0, 0]);
+        body.extend_from_slice(b"test\0");
+        body.extend_from_slice(query.as_bytes());
+        make_event(QUERY_EVENT, &body)
+    }
+
+    fn make_gtid_event(sid: [u8; 16], gno: u64) -> Vec<u8> {
+        let mut body = vec![0u8];
+        body.extend_from_slice(&sid);
+        let mut buf = [0u8; 8];
+        LittleEndian::write_u64(&mut buf, gno);
+        body.extend_from_slice(&buf);
+        make_event(GTID_LOG_EVENT, &body)
+    }
+
+    #[test]
+    fn test_gtid_set() {
+        let mut set = GtidSet::default();
+        set.add([1; 16], 1);
+        set.add([1; 16], 3);
+        set.add([1; 16], 2);
+        set.add([2; 16], 10);
+        assert_eq!(set.sids[&[1; 16]], vec![(1, 4)]);
+
+        let data = set.encode();
+        assert_eq!(data.len(), 8 + (16 + 8 + 16) * 2);
+        assert_eq!(GtidSet::decode(&data), Some(set));
+        assert_eq!(GtidSet::decode(&data[..20]), None);
+    }
+
+    #[test]
+    fn test_binlog_dump_gtid() {
+        let mut set = GtidSet::default();
+        set.add([1; 16], 1);
+        let gtid_data = set.encode();
+
+        let mut payload = vec![0u8; 22];
+        LittleEndian::write_u16(&mut payload[..2], BINLOG_THROUGH_GTID);
+        LittleEndian::write_u32(&mut payload[2..6], 100);
+        LittleEndian::write_u32(&mut payload[6..10], 0);
+        LittleEndian::write_u64(&mut payload[10..18], 4);
+        LittleEndian::write_u32(&mut payload[18..22], gtid_data.len() as u32);
+        payload.extend_from_slice(&gtid_data);
+
+        let mut dump = BinlogDumpGtid::decode(&payload).unwrap();
+        assert_eq!(dump.server_id, 100);
+        assert_eq!(dump.encode(), payload);
+        assert!(BinlogDumpGtid::decode(&payload[..30]).is_none());
+
+        dump.track(&make_gtid_event([1; 16], 2));
+        dump.track(&make_query_event("BEGIN"));
+        assert!(dump.in_transaction());
+        assert_eq!(dump.gtid_set.sids[&[1; 16]], vec![(1, 2)]);
+        dump.track(&make_event(XID_EVENT, &[0; 8]));
+        assert!(!dump.in_transaction());
+        assert_eq!(dump.gtid_set.sids[&[1; 16]], vec![(1, 3)]);
+
+        dump.track(&make_gtid_event([2; 16], 5));
+        dump.track(&make_query_event("CREATE TABLE t (id int)"));
+        assert_eq!(dump.gtid_set.sids[&[2; 16]], vec![(5, 6)]);
+        assert_eq!(BinlogDumpGtid::decode(&dump.encode()).unwrap().gtid_set, dump.gtid_set);
+    }
+
+    #[test]
+    fn test_record_query() {
+        let mut sess = BinlogSession::new(true);
+        sess.record_query("SET @master_binlog_checksum= @@global.binlog_checksum");
+        sess.record_query("set @master_binlog_checksum= @@global.binlog_checksum");
+        sess.record_query("SET @master_binlog_checksum= @@global.binlog_checksum");
+        sess.record_query("SET autocommit = 1");
+        sess.record_query("SELECT @slave_uuid");
+        assert_eq!(sess.get_vars().len(), 2);
+
+        let mut sess = BinlogSession::new(false);
+        sess.record_query("SET @slave_uuid = 'a'");
+        assert!(sess.get_vars().is_empty());
+    }
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::{
+    collections::VecDeque,
     marker::PhantomData,
     sync::{atomic::Ordering, Arc},
     vec, ops::Div,
@@ -25,24 +26,32 @@ use mysql_protocol::{
     client::{
         codec::{MergeResultsetState, MergeStream, ResultsetStream},
         conn::{ClientConn, SessionAttr},
-        stmt::Stmt,
+        stmt::{clear_execute_cursor_type, get_execute_cursor_type, Stmt},
     },
     column::{Column, ColumnInfo, decode_column},
     err::ProtocolError,
     mysql_const::*,
     row::{RowData, RowDataBinary, RowDataText, RowDataTyp},
-    server::codec::{make_eof_packet, CommonPacket, PacketSend},
-    util::{is_eof, length_encode_int, BufMutExt},
+    server::codec::{make_eof_packet, make_eof_packet_with_status, CommonPacket, PacketSend},
+    util::{get_eof_status, get_length, is_eof, length_encode_int, BufMutExt},
 };
 use pisa_error::error::{Error, ErrorKind};
 use rayon::prelude::*;
 use strategy::sharding_rewrite::{DataSource, ShardingRewriteOutput, RewriteChange, meta::FieldWrapFunc, rewrite_const::{AVG_COUNT, AVG_SUM, AVG_FIELD}};
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+    task::JoinHandle,
+};
 use tokio_util::codec::{Decoder, Encoder};
+use tracing::{debug, field, info_span, Instrument, Span};
 
+use super::{backend_user::BackendUser, stmt_cache::Cursor};
 use crate::{
     mysql::ReqContext,
-    transaction_fsm::check_get_conn,
+    registry::bind_backend,
+    telemetry,
+    transaction_fsm::{build_conn_factory, check_get_conn},
 };
 
 use byteorder::{ByteOrder, LittleEndian};
@@ -51,6 +60,9 @@ use byteorder::{ByteOrder, LittleEndian};
 pub enum ExecuteError {
     #[error("execute sql: {0:?} error")]
     DataSourceNotFound(String),
+
+    #[error("no conn of the transaction is available for sql: {0:?}")]
+    ShardNotInTransaction(String),
 }
 
 pub struct Executor<T, C> {
@@ -73,11 +85,25 @@ where
         let mut curr_server_stmt_id: Option<u32> = None;
         let mut curr_cached_stmt_id = vec![];
 
+        let mut rest_conns = vec![];
+        let shard_count = req.rewrite_outputs.len();
+        collect_shard_fanout!(req, shard_count);
+        let endpoints = telemetry::shard_endpoints(&req.rewrite_outputs);
         let conns = if is_get_conn {
-            Self::get_shard_conns(&req.rewrite_outputs, req.pool.clone(), attrs).await?
+            Self::get_shard_conns(&req.name, &req.rewrite_outputs, req.pool.clone(), attrs, &req.backend_user)
+                .instrument(info_span!(
+                    "pisa.pool_acquire",
+                    endpoint = endpoints.as_str(),
+                    shard_count
+                ))
+                .await?
         } else {
             let mut cached_conn = req.fsm.get_shard_conn();
-            if cached_conn.is_empty() {
+            if !cached_conn.is_empty() {
+                let (picked, rest) = Self::pick_shard_conns(cached_conn, &req.rewrite_outputs);
+                cached_conn = picked;
+                rest_conns = rest;
+            } else {
                 let server_stmt_id = req.stmt_id.load(Ordering::Relaxed);
                 let cached_stmt_conn = req.stmt_cache.get_all(server_stmt_id);
                 curr_cached_stmt_id = cached_stmt_conn.iter().map(|x| x.0).collect();
@@ -88,7 +114,9 @@ where
             cached_conn
         };
 
-        let mut conns = Self::shard_send_query(conns, &req.rewrite_outputs).await?;
+        let mut conns = Self::shard_send_query(conns, &req.rewrite_outputs)
+            .instrument(telemetry::execute_span(&endpoints, shard_count))
+            .await?;
         let shards_length = conns.len();
         let mut shard_streams = Vec::with_capacity(shards_length);
 
@@ -99,30 +127,61 @@ where
 
         let mut merge_stream = MergeStream::new(shard_streams, shards_length);
 
+        // The rows are counted when they are merged.
         let sharding_column = req.rewrite_outputs[0].sharding_column.clone();
-        Self::handle_shard_resultset(req, &mut merge_stream, sharding_column, false).await?;
+        Self::handle_shard_resultset(req, &mut merge_stream, sharding_column, false, false)
+            .instrument(info_span!("pisa.merge", shard_count, rows = field::Empty))
+            .await?;
 
         if let Some(id) = curr_server_stmt_id {
             let stmt_conns = curr_cached_stmt_id.into_iter().zip(conns.into_iter()).collect();
             req.stmt_cache.put_all(id, stmt_conns)
         } else {
             // Put shard conn to fsm
+            conns.extend(rest_conns);
             req.fsm.put_shard_conn(conns);
         }
         Ok(())
     }
 
+    // Pick the cached conn of the shard which each output is routed to, the conns of other shards are kept
+    // for the transaction. Falls back to the conns in order when the shard is not found.
+    fn pick_shard_conns(
+        mut conns: Vec<PoolConn<ClientConn>>,
+        rewrite_outputs: &[ShardingRewriteOutput],
+    ) -> (Vec<PoolConn<ClientConn>>, Vec<PoolConn<ClientConn>>) {
+        let mut picked = Vec::with_capacity(rewrite_outputs.len());
+        for ro in rewrite_outputs.iter() {
+            if conns.is_empty() {
+                break;
+            }
+
+            let idx = match &ro.data_source {
+                DataSource::Endpoint(ep) => {
+                    conns.iter().position(|x| x.get_endpoint().as_ref() == Some(&ep.addr)).unwrap_or(0)
+                }
+                _ => 0,
+            };
+            picked.push(conns.remove(idx));
+        }
+
+        (picked, conns)
+    }
+
+    // The cursor is returned when `is_cursor` is true, rows are fetched by COM_STMT_FETCH then.
+    // It is opened on the shards unless they return the whole resultset to be merged.
     async fn handle_shard_resultset<'a>(
         req: &mut ReqContext<T, C>,
         merge_stream: &mut MergeStream<ResultsetStream<'a>>,
         sharding_column: Option<String>,
         is_binary: bool,
-    ) -> Result<(), Error> {
+        is_cursor: bool,
+    ) -> Result<Option<Cursor>, Error> {
         let header = merge_stream.next().await;
         let mut header = if let Some(header) = Self::get_shard_one_data(header)? {
             header.1
         } else {
-            return Ok(());
+            return Ok(None);
         };
 
         let ok_or_err = header[4];
@@ -132,7 +191,7 @@ where
                 .send(PacketSend::Encode(header[4..].into()))
                 .await
                 .map_err(ErrorKind::Protocol)?;
-            return Ok(());
+            return Ok(None);
         }
 
         let (cols, ..) = length_encode_int(&header[4..]);
@@ -159,17 +218,57 @@ where
 
         let col_info = Self::get_columns(req, merge_stream, cols, &mut buf).await?;
 
-        // read eof
-        let _ = merge_stream.next().await;
+        // read eof, the status of the first shard is returned to client.
+        let eof = Self::get_shard_one_data(merge_stream.next().await)?;
+        let status = eof.map(|x| get_eof_status(&x.1)).unwrap_or(SERVER_STATUS_AUTOCOMMIT);
 
+        if is_cursor && status & SERVER_STATUS_CURSOR_EXISTS > 0 {
+            let _ = req
+                .framed
+                .codec_mut()
+                .encode(PacketSend::EncodeOffset(make_eof_packet_with_status(status)[4..].into(), buf.len()), &mut buf);
+            req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::Protocol)?;
+            return Ok(Some(Cursor::Shard(0)));
+        }
+
+        let eof_packet = if is_cursor {
+            make_eof_packet_with_status(status | SERVER_STATUS_CURSOR_EXISTS)
+        } else {
+            make_eof_packet()
+        };
         let _ = req
             .framed
             .codec_mut()
-            .encode(PacketSend::EncodeOffset(make_eof_packet()[4..].into(), buf.len()), &mut buf);
+            .encode(PacketSend::EncodeOffset(eof_packet[4..].into(), buf.len()), &mut buf);
 
         merge_stream.set_state(MergeResultsetState::Row);
+
+        if is_cursor {
+            req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::Protocol)?;
+
+            let mut rows_buf = BytesMut::with_capacity(1 << 16);
+            Self::get_rows(req, merge_stream, &mut rows_buf, sharding_column, col_info, is_binary).await?;
+            let count = count_packets(&rows_buf);
+            Span::current().record("rows", &count);
+            req.rows += count;
+
+            // Strip packet header, it is encoded again when rows are fetched.
+            let mut rows = VecDeque::new();
+            while rows_buf.len() >= 4 {
+                let length = get_length(&rows_buf);
+                let mut row = rows_buf.split_to(4 + length);
+                row.advance(4);
+                rows.push_back(row);
+            }
+            return Ok(Some(Cursor::Buffered(rows, status | SERVER_STATUS_CURSOR_EXISTS)));
+        }
+
         // get rows
+        let offset = buf.len();
         Self::get_rows(req, merge_stream, &mut buf, sharding_column, col_info, is_binary).await?;
+        let rows = count_packets(&buf[offset..]);
+        Span::current().record("rows", &rows);
+        req.rows += rows;
 
         let _ = req
             .framed
@@ -177,7 +276,7 @@ where
             .encode(PacketSend::EncodeOffset(make_eof_packet()[4..].into(), buf.len()), &mut buf);
 
         req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::Protocol)?;
-        Ok(())
+        Ok(None)
     }
 
     async fn get_rows<'a>(
@@ -471,6 +570,14 @@ where
         Ok(arc_col_info)
     }
 
+    // Whether the rows of every shard are merged, e.g. `AVG`, `MIN`, `MAX`, `ORDER BY` and `GROUP BY`.
+    fn is_merged_resultset(ro: &ShardingRewriteOutput) -> bool {
+        !ro.min_max_fields.is_empty()
+            || ro.changes.iter().any(|x| {
+                matches!(x, RewriteChange::AvgChange(_) | RewriteChange::OrderChange(_) | RewriteChange::GroupChange(_))
+            })
+    }
+
     fn get_shard_one_data(
         data: Option<Vec<Option<Result<BytesMut, ProtocolError>>>>,
     ) -> Result<Option<(usize, BytesMut)>, Error> {
@@ -519,9 +626,11 @@ where
     }
 
     pub async fn get_shard_conns(
+        domain: &str,
         rewrite_outputs: &[ShardingRewriteOutput],
         pool: Pool<ClientConn>,
         attrs: Vec<SessionAttr>,
+        backend_user: &BackendUser,
     ) -> Result<Vec<PoolConn<ClientConn>>, Error> {
         let endpoints = rewrite_outputs
             .iter()
@@ -534,13 +643,14 @@ where
 
         let mut conn_futs = FuturesOrdered::new();
         for e in endpoints.iter() {
-            let ep = e.clone();
+            let ep = backend_user.map_endpoint(e.clone());
             let mut pool = pool.clone();
             let attrs = attrs.clone();
+            let domain = domain.to_string();
             let f = tokio::spawn(async move {
-                let factory = ClientConn::with_opts(ep.user, ep.password, ep.addr.clone());
+                let factory = build_conn_factory(&ep).map_err(ErrorKind::Protocol)?;
                 pool.set_factory(factory);
-                check_get_conn(pool, &ep.addr, &attrs).await
+                check_get_conn(&domain, pool, &ep.addr, &attrs).await
             });
             conn_futs.push(f);
         }
@@ -548,6 +658,8 @@ where
         let mut conns = Vec::with_capacity(conn_futs.len());
         while let Some(conn) = conn_futs.next().await {
             let conn = conn.map_err(|e| ErrorKind::Runtime(e.into()))??;
+            // The conns are got on the spawned tasks, they are bound to the session here.
+            bind_backend(&conn);
             conns.push(conn);
         }
 
@@ -559,7 +671,7 @@ where
         attrs: Vec<SessionAttr>,
         _is_get_conn: bool,
     ) -> Result<(Vec<Stmt>, Vec<PoolConn<ClientConn>>), Error> {
-        let conns = Self::get_shard_conns(&req.rewrite_outputs, req.pool.clone(), attrs).await?;
+        let conns = Self::get_shard_conns(&req.name, &req.rewrite_outputs, req.pool.clone(), attrs, &req.backend_user).await?;
         let mut send_futs = FuturesOrdered::new();
         let mut sended_conns = Vec::with_capacity(conns.len());
 
@@ -588,22 +700,175 @@ where
         payload: &[u8],
     ) -> Result<(), Error> {
         let stmt_id = LittleEndian::read_u32(payload);
-        let mut conns = Self::shard_send_execute(req, stmt_id, payload).await?;
+        req.stmt_cache.close_cursor(stmt_id);
+
+        // The cursor is emulated by proxy when the rows of every shard have to be merged, the merged rows
+        // are ordered by the sharding column as well. Otherwise it is opened on the shards and the rows are
+        // fetched from them in order.
+        let is_cursor = get_execute_cursor_type(payload) != CURSOR_TYPE_NO_CURSOR;
+        let sharding_column = req.stmt_cache.get_sharding_column(stmt_id);
+        let mut payload = payload.to_vec();
+        if is_cursor && (sharding_column.is_some() || Self::is_merged_resultset(&req.rewrite_outputs[0])) {
+            clear_execute_cursor_type(&mut payload);
+        }
+
+        let mut conns = Self::shard_send_execute(req, stmt_id, &payload).await?;
         let shard_length = conns.len();
         let mut shard_streams = Vec::with_capacity(shard_length);
         for conn in conns.iter_mut() {
             shard_streams.push(ResultsetStream::new(conn.1.framed.as_mut()).fuse());
         }
 
-        let sharding_column = req.stmt_cache.get_sharding_column(stmt_id);
         let mut merge_stream = MergeStream::new(shard_streams, shard_length);
-        Self::handle_shard_resultset(req, &mut merge_stream, sharding_column, true).await?;
+        let cursor = Self::handle_shard_resultset(req, &mut merge_stream, sharding_column, true, is_cursor).await?;
 
         req.stmt_cache.put_all(stmt_id, conns);
+        if let Some(cursor) = cursor {
+            req.stmt_cache.put_cursor(stmt_id, cursor);
+        }
+
+        Ok(())
+    }
+
+    // COM_STMT_SEND_LONG_DATA is sent to every shard conn, there is no response.
+    pub async fn shard_send_long_data_executor(
+        req: &mut ReqContext<T, C>,
+        stmt_id: u32,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let mut send_futs = FuturesOrdered::new();
+        let stmt_cache = req.stmt_cache.get_all(stmt_id);
+        let mut sended_conns = Vec::with_capacity(stmt_cache.len());
+
+        for (id, mut conn) in stmt_cache.into_iter() {
+            let mut payload = payload.to_vec();
+            LittleEndian::write_u32(&mut payload[..4], id);
+            let f = tokio::spawn(async move {
+                let res = conn.send_no_response_command(COM_STMT_SEND_LONG_DATA, &payload).await;
+                (conn, id, res)
+            });
+            send_futs.push(f);
+        }
+
+        let mut send_err = None;
+        while let Some(res) = send_futs.next().await {
+            let (conn, id, send_res) = res.map_err(|e| ErrorKind::Runtime(e.into()))?;
+            if let Err(e) = send_res {
+                send_err = Some(e);
+            }
+            sended_conns.push((id, conn));
+        }
+
+        req.stmt_cache.put_all(stmt_id, sended_conns);
+        if let Some(e) = send_err {
+            return Err(Error::new(ErrorKind::Protocol(e)));
+        }
+
+        Ok(())
+    }
+
+    // Send `LOAD DATA LOCAL INFILE` of `ro` to the shard, the rows routed to it are sent by the
+    // returned `ShardLoad` while the file is read from client.
+    pub async fn shard_load_data_open(
+        req: &mut ReqContext<T, C>,
+        ro: &ShardingRewriteOutput,
+        attrs: Vec<SessionAttr>,
+    ) -> Result<ShardLoad, Error> {
+        // The rows are loaded in the open transaction on the conn of the shard which it started on.
+        let mut conn = if req.fsm.in_transaction() {
+            let mut cached_conns = req.fsm.get_shard_conn();
+            let idx = match &ro.data_source {
+                DataSource::Endpoint(ep) => cached_conns.iter().position(|x| x.get_endpoint().as_ref() == Some(&ep.addr)),
+                _ => None,
+            };
+            let conn = idx.map(|idx| cached_conns.remove(idx));
+            req.fsm.put_shard_conn(cached_conns);
+            conn.ok_or_else(|| ErrorKind::Runtime(ExecuteError::ShardNotInTransaction(ro.target_sql.clone()).into()))?
+        } else {
+            let mut conns =
+                Self::get_shard_conns(&req.name, std::slice::from_ref(ro), req.pool.clone(), attrs, &req.backend_user).await?;
+            conns.pop().unwrap()
+        };
+        let sql = ro.target_sql.clone();
+        let (tx, rx) = mpsc::channel(LOAD_DATA_CHANNEL_SIZE);
+        let handle = tokio::spawn(async move {
+            let res = shard_load_data(&mut conn, &sql, rx).await;
+            (conn, res)
+        });
+
+        Ok(ShardLoad { tx, handle })
+    }
+
+    // End the file of every shard, the affected rows are summed, the first ERR is forwarded.
+    pub async fn shard_load_data_finish(req: &mut ReqContext<T, C>, loads: Vec<ShardLoad>) -> Result<(), Error> {
+        let mut sended_conns = Vec::with_capacity(loads.len());
+        let mut affected_rows = 0;
+        let mut err_packet = None;
+        let mut send_err = None;
+
+        for load in loads.into_iter() {
+            // The shard may have returned ERR before the file is read, the end is not received then.
+            let _ = load.send(vec![]).await;
+            let (conn, send_res) = load.handle.await.map_err(|e| ErrorKind::Runtime(e.into()))?;
+
+            match send_res {
+                Ok(packet) if packet[4] == OK_HEADER => {
+                    let (rows, ..) = length_encode_int(&packet[5..]);
+                    affected_rows += rows;
+                    sended_conns.push(conn);
+                }
+                Ok(packet) => {
+                    if err_packet.is_none() {
+                        err_packet = Some(packet)
+                    }
+                    sended_conns.push(conn);
+                }
+                Err(e) => {
+                    conn.discard();
+                    send_err = Some(e);
+                }
+            }
+        }
+
+        // The conns of the open transaction are kept with the conns of other shards.
+        let in_transaction = req.fsm.in_transaction();
+        if in_transaction {
+            req.fsm.merge_shard_conn(sended_conns);
+        } else {
+            req.fsm.put_shard_conn(sended_conns);
+        }
+
+        if let Some(e) = send_err {
+            return Err(Error::new(ErrorKind::Protocol(e)));
+        }
+
+        if let Some(packet) = err_packet {
+            req.framed.send(PacketSend::Encode(packet[4..].into())).await.map_err(ErrorKind::from)?;
+            return Ok(());
+        }
+
+        let mut ok = BytesMut::with_capacity(16);
+        ok.put_u8(OK_HEADER);
+        ok.put_lenc_int(affected_rows, true);
+        ok.put_lenc_int(0, true);
+        ok.put_u16_le(if in_transaction { SERVER_STATUS_IN_TRANS } else { SERVER_STATUS_AUTOCOMMIT });
+        ok.put_u16_le(0);
+        req.framed.send(PacketSend::Encode(ok[..].into())).await.map_err(ErrorKind::from)?;
 
         Ok(())
     }
 
+    // Abort the load of every shard, the conns are closed so that the loaded rows are not committed.
+    pub async fn shard_load_data_abort(loads: Vec<ShardLoad>) {
+        for load in loads.into_iter() {
+            drop(load.tx);
+            if let Ok((conn, res)) = load.handle.await {
+                debug!("shard load data is aborted {:?}", res);
+                conn.discard();
+            }
+        }
+    }
+
     async fn shard_send_execute(
         req: &mut ReqContext<T, C>,
         stmt_id: u32,
@@ -638,10 +903,90 @@ where
     }
 }
 
+// The number of packets in buf, each row is a packet.
+fn count_packets(mut buf: &[u8]) -> u64 {
+    let mut count = 0;
+    while buf.len() >= 4 {
+        let length = get_length(buf);
+        buf = &buf[(4 + length).min(buf.len())..];
+        count += 1;
+    }
+    count
+}
+
+// The file is sent in chunks less than MAX_PAYLOAD_LEN, so that no empty packet is sent before the end.
+const LOAD_DATA_CHUNK_SIZE: usize = 1 << 16;
+
+// The rows of client packets buffered for a shard before they are sent.
+const LOAD_DATA_CHANNEL_SIZE: usize = 16;
+
+// The shard which the rows of `LOAD DATA LOCAL INFILE` are sent to, an empty chunk ends the file.
+pub struct ShardLoad {
+    tx: mpsc::Sender<Vec<u8>>,
+    handle: JoinHandle<(PoolConn<ClientConn>, Result<BytesMut, ProtocolError>)>,
+}
+
+impl ShardLoad {
+    // Returns false when the shard doesn't read the file any more, e.g. ERR is returned.
+    pub async fn send(&self, rows: Vec<u8>) -> bool {
+        self.tx.send(rows).await.is_ok()
+    }
+}
+
+// Send `LOAD DATA LOCAL INFILE` to the shard and stream the rows received from `rx` as the file,
+// returns the OK or ERR packet of the shard. The load is aborted when `rx` is closed before the end.
+async fn shard_load_data(
+    conn: &mut PoolConn<ClientConn>,
+    sql: &str,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+) -> Result<BytesMut, ProtocolError> {
+    let mut stream = conn.send_query(sql.as_bytes()).await?;
+    let header = match stream.next().await {
+        Some(header) => header?,
+        None => return Err(ProtocolError::Default),
+    };
+
+    if header[4] != LOCAL_IN_FILE_HEADER {
+        return Ok(header);
+    }
+
+    loop {
+        match rx.recv().await {
+            Some(data) if data.is_empty() => break,
+            Some(data) => {
+                for chunk in data.chunks(LOAD_DATA_CHUNK_SIZE) {
+                    stream.send_local_infile(chunk).await?;
+                }
+            }
+            None => {
+                return Err(ProtocolError::Io(std::io::Error::new(
+                    std::io::ErrorKind::Interrupted,
+                    "load data is aborted",
+                )))
+            }
+        }
+    }
+    stream.send_local_infile(&[]).await?;
+
+    match stream.next().await {
+        Some(packet) => packet,
+        None => Err(ProtocolError::Default),
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn test() {
         assert_eq!(1, 1);
     }
+
+    #[test]
+    fn test_count_packets() {
+        let buf = [1, 0, 0, 0, 0x31, 2, 0, 0, 1, 0x31, 0x32];
+        assert_eq!(count_packets(&buf), 2);
+        assert_eq!(count_packets(&[]), 0);
+    }
 }
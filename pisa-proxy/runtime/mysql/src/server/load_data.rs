@@ -0,0 +1,750 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `LOAD DATA LOCAL INFILE` is not supported by the sql parser, the statement is parsed here
+//! to split the rows of the file to shards by sharding column.
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum LoadDataError {
+    #[error("syntax error near {0:?}")]
+    Syntax(String),
+
+    #[error("unexpected end of statement")]
+    UnexpectedEnd,
+
+    #[error("column list is required")]
+    ColumnsRequired,
+
+    #[error("sharding column {0:?} is not in column list")]
+    ShardingColumnNotFound(String),
+
+    #[error("empty LINES TERMINATED BY is not supported")]
+    FixedRowFormat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Ident(String),
+    Str(Vec<u8>),
+    Symbol(char),
+}
+
+#[derive(Debug)]
+struct Spanned {
+    token: Token,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug)]
+pub struct LoadData {
+    pub file: Vec<u8>,
+    pub schema: Option<String>,
+    pub table: String,
+    // Span of the table ident in the statement, it is replaced by the actual table.
+    pub table_span: (usize, usize),
+    pub ignore_lines: u64,
+    // Span of `IGNORE n LINES`, lines are ignored by the proxy before rows are split.
+    pub ignore_span: Option<(usize, usize)>,
+    pub columns: Vec<String>,
+    pub fields_terminated: Vec<u8>,
+    pub fields_enclosed: Option<u8>,
+    pub fields_escaped: Option<u8>,
+    pub lines_starting: Vec<u8>,
+    pub lines_terminated: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct LoadRow<'a> {
+    // The raw row, includes the prefix and the line terminator.
+    pub data: &'a [u8],
+    // Whether the row ends with the line terminator, the last row may not.
+    terminated: bool,
+    // Spans of the fields in `data`.
+    fields: Vec<(usize, usize)>,
+}
+
+// Returns true when the sql is `LOAD DATA ... LOCAL INFILE`.
+pub fn is_load_data_local(sql: &str) -> bool {
+    let tokens = match tokenize(sql) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+
+    let mut iter = tokens.iter().map(|x| &x.token);
+    if !matches!(iter.next(), Some(Token::Word(x)) if x == "LOAD") {
+        return false;
+    }
+    if !matches!(iter.next(), Some(Token::Word(x)) if x == "DATA") {
+        return false;
+    }
+
+    for token in iter {
+        match token {
+            Token::Word(x) if x == "LOCAL" => return true,
+            Token::Word(x) if x == "LOW_PRIORITY" || x == "CONCURRENT" => {}
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+// The file of `LOAD DATA LOCAL INFILE`, only this file is allowed to be requested by the backend.
+pub fn load_data_local_file(sql: &str) -> Option<Vec<u8>> {
+    let mut p = Parser { sql, tokens: tokenize(sql).ok()?, pos: 0 };
+
+    p.expect_word("LOAD").ok()?;
+    p.expect_word("DATA").ok()?;
+    let _ = p.eat_word("LOW_PRIORITY") || p.eat_word("CONCURRENT");
+    p.expect_word("LOCAL").ok()?;
+    p.expect_word("INFILE").ok()?;
+    p.expect_str().ok()
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Spanned>, LoadDataError> {
+    let bytes = sql.as_bytes();
+    let mut tokens = vec![];
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+
+        match c {
+            b' ' | b'\t' | b'\r' | b'\n' | b'\0' => pos += 1,
+            b'#' => pos = find_from(bytes, pos, b"\n").map(|x| x + 1).unwrap_or(bytes.len()),
+            b'-' if bytes[pos..].starts_with(b"-- ") => {
+                pos = find_from(bytes, pos, b"\n").map(|x| x + 1).unwrap_or(bytes.len())
+            }
+            b'/' if bytes[pos..].starts_with(b"/*") => {
+                pos = find_from(bytes, pos + 2, b"*/")
+                    .map(|x| x + 2)
+                    .ok_or(LoadDataError::UnexpectedEnd)?
+            }
+            b'\'' | b'"' => {
+                let (value, end) = read_str(bytes, pos)?;
+                pos = end;
+                tokens.push(Spanned { token: Token::Str(value), start, end });
+            }
+            b'`' => {
+                let end = find_from(bytes, pos + 1, b"`").ok_or(LoadDataError::UnexpectedEnd)?;
+                pos = end + 1;
+                let ident = String::from_utf8_lossy(&bytes[start + 1..end]).to_string();
+                tokens.push(Spanned { token: Token::Ident(ident), start, end: pos });
+            }
+            c if c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c == b'@' || c >= 0x80 => {
+                while pos < bytes.len()
+                    && (bytes[pos].is_ascii_alphanumeric()
+                        || bytes[pos] == b'_'
+                        || bytes[pos] == b'$'
+                        || bytes[pos] == b'@'
+                        || bytes[pos] >= 0x80)
+                {
+                    pos += 1;
+                }
+                let word = &sql[start..pos];
+                tokens.push(Spanned { token: Token::Word(word.to_uppercase()), start, end: pos });
+            }
+            _ => {
+                pos += 1;
+                tokens.push(Spanned { token: Token::Symbol(c as char), start, end: pos });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn find_from(data: &[u8], from: usize, pat: &[u8]) -> Option<usize> {
+    if pat.is_empty() || from > data.len() {
+        return None;
+    }
+    data[from..].windows(pat.len()).position(|x| x == pat).map(|x| x + from)
+}
+
+// Read the quoted string from `start`, returns the unescaped value and the end offset.
+fn read_str(bytes: &[u8], start: usize) -> Result<(Vec<u8>, usize), LoadDataError> {
+    let quote = bytes[start];
+    let mut value = vec![];
+    let mut pos = start + 1;
+
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        if c == b'\\' && pos + 1 < bytes.len() {
+            value.push(match bytes[pos + 1] {
+                b'0' => b'\0',
+                b'b' => 0x08,
+                b'n' => b'\n',
+                b'r' => b'\r',
+                b't' => b'\t',
+                b'Z' => 0x1a,
+                x => x,
+            });
+            pos += 2;
+        } else if c == quote {
+            if bytes.get(pos + 1) == Some(&quote) {
+                value.push(quote);
+                pos += 2;
+            } else {
+                return Ok((value, pos + 1));
+            }
+        } else {
+            value.push(c);
+            pos += 1;
+        }
+    }
+
+    Err(LoadDataError::UnexpectedEnd)
+}
+
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|x| &x.token)
+    }
+
+    fn next(&mut self) -> Result<&Spanned, LoadDataError> {
+        let token = self.tokens.get(self.pos).ok_or(LoadDataError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn syntax_error(&self) -> LoadDataError {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                LoadDataError::Syntax(self.sql[token.start..].chars().take(32).collect())
+            }
+            None => LoadDataError::UnexpectedEnd,
+        }
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(x)) if x == word)
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if self.is_word(word) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), LoadDataError> {
+        if self.eat_word(word) {
+            return Ok(());
+        }
+        Err(self.syntax_error())
+    }
+
+    fn expect_str(&mut self) -> Result<Vec<u8>, LoadDataError> {
+        match self.peek() {
+            Some(Token::Str(_)) => match &self.next()?.token {
+                Token::Str(value) => Ok(value.clone()),
+                _ => unreachable!(),
+            },
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, LoadDataError> {
+        match self.peek() {
+            Some(Token::Ident(_)) | Some(Token::Word(_)) => {
+                let sql = self.sql;
+                let token = self.next()?;
+                match &token.token {
+                    Token::Ident(x) => Ok(x.clone()),
+                    _ => Ok(sql[token.start..token.end].to_string()),
+                }
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), LoadDataError> {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            return Ok(());
+        }
+        Err(self.syntax_error())
+    }
+
+    // Skip the tokens until `symbol`, the `symbol` is consumed.
+    fn skip_to(&mut self, symbol: char) -> Result<(), LoadDataError> {
+        while self.next()?.token != Token::Symbol(symbol) {}
+        Ok(())
+    }
+
+    fn prev_end(&self) -> Result<usize, LoadDataError> {
+        let pos = self.pos.checked_sub(1).ok_or(LoadDataError::UnexpectedEnd)?;
+        self.tokens.get(pos).map(|x| x.end).ok_or(LoadDataError::UnexpectedEnd)
+    }
+
+    fn cur_start(&self) -> Result<usize, LoadDataError> {
+        self.tokens.get(self.pos).map(|x| x.start).ok_or(LoadDataError::UnexpectedEnd)
+    }
+}
+
+pub fn parse_load_data(sql: &str) -> Result<LoadData, LoadDataError> {
+    let mut p = Parser { sql, tokens: tokenize(sql)?, pos: 0 };
+
+    p.expect_word("LOAD")?;
+    p.expect_word("DATA")?;
+    let _ = p.eat_word("LOW_PRIORITY") || p.eat_word("CONCURRENT");
+    p.expect_word("LOCAL")?;
+    p.expect_word("INFILE")?;
+    let file = p.expect_str()?;
+    let _ = p.eat_word("REPLACE") || p.eat_word("IGNORE");
+    p.expect_word("INTO")?;
+    p.expect_word("TABLE")?;
+
+    let table_start = p.cur_start()?;
+    let mut schema = None;
+    let mut table = p.expect_ident()?;
+    if p.peek() == Some(&Token::Symbol('.')) {
+        p.pos += 1;
+        schema = Some(table);
+        table = p.expect_ident()?;
+    }
+    let table_span = (table_start, p.prev_end()?);
+
+    let mut load = LoadData {
+        file,
+        schema,
+        table,
+        table_span,
+        ignore_lines: 0,
+        ignore_span: None,
+        columns: vec![],
+        fields_terminated: b"\t".to_vec(),
+        fields_enclosed: None,
+        fields_escaped: Some(b'\\'),
+        lines_starting: vec![],
+        lines_terminated: b"\n".to_vec(),
+    };
+
+    while let Some(token) = p.peek() {
+        match token {
+            Token::Word(x) if x == "PARTITION" => {
+                p.pos += 1;
+                p.expect_symbol('(')?;
+                p.skip_to(')')?;
+            }
+            Token::Word(x) if x == "CHARACTER" || x == "CHARSET" => {
+                if p.eat_word("CHARACTER") {
+                    p.expect_word("SET")?;
+                } else {
+                    p.pos += 1;
+                }
+                p.expect_ident()?;
+            }
+            Token::Word(x) if x == "FIELDS" || x == "COLUMNS" => {
+                p.pos += 1;
+                loop {
+                    if p.eat_word("TERMINATED") {
+                        p.expect_word("BY")?;
+                        load.fields_terminated = p.expect_str()?;
+                    } else if p.eat_word("OPTIONALLY") || p.is_word("ENCLOSED") {
+                        p.expect_word("ENCLOSED")?;
+                        p.expect_word("BY")?;
+                        load.fields_enclosed = p.expect_str()?.first().copied();
+                    } else if p.eat_word("ESCAPED") {
+                        p.expect_word("BY")?;
+                        load.fields_escaped = p.expect_str()?.first().copied();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            Token::Word(x) if x == "LINES" => {
+                p.pos += 1;
+                loop {
+                    if p.eat_word("STARTING") {
+                        p.expect_word("BY")?;
+                        load.lines_starting = p.expect_str()?;
+                    } else if p.eat_word("TERMINATED") {
+                        p.expect_word("BY")?;
+                        load.lines_terminated = p.expect_str()?;
+                    } else {
+                        break;
+                    }
+                }
+            }
+            Token::Word(x) if x == "IGNORE" => {
+                let start = p.cur_start()?;
+                p.pos += 1;
+                let lines = match &p.next()?.token {
+                    Token::Word(x) => x.parse::<u64>().ok(),
+                    _ => None,
+                };
+                load.ignore_lines = lines.ok_or_else(|| {
+                    p.pos -= 1;
+                    p.syntax_error()
+                })?;
+                if !(p.eat_word("LINES") || p.eat_word("ROWS")) {
+                    return Err(p.syntax_error());
+                }
+                load.ignore_span = Some((start, p.prev_end()?));
+            }
+            Token::Symbol('(') => {
+                p.pos += 1;
+                loop {
+                    load.columns.push(p.expect_ident()?);
+                    match p.next()?.token {
+                        Token::Symbol(',') => {}
+                        Token::Symbol(')') => break,
+                        _ => {
+                            p.pos -= 1;
+                            return Err(p.syntax_error());
+                        }
+                    }
+                }
+            }
+            // The columns assigned by SET are not split by.
+            Token::Word(x) if x == "SET" => break,
+            Token::Symbol(';') => break,
+            _ => return Err(p.syntax_error()),
+        }
+    }
+
+    if load.lines_terminated.is_empty() {
+        return Err(LoadDataError::FixedRowFormat);
+    }
+
+    Ok(load)
+}
+
+impl LoadData {
+    // Returns the index of the sharding column in the column list.
+    pub fn column_index(&self, column: &str) -> Result<usize, LoadDataError> {
+        if self.columns.is_empty() {
+            return Err(LoadDataError::ColumnsRequired);
+        }
+
+        self.columns
+            .iter()
+            .position(|x| x.eq_ignore_ascii_case(column))
+            .ok_or_else(|| LoadDataError::ShardingColumnNotFound(column.to_string()))
+    }
+
+    // Replace the table by `target` and remove the `IGNORE n LINES` clause,
+    // the ignored lines are skipped by the proxy.
+    pub fn rewrite(&self, sql: &str, target: &str) -> String {
+        let mut target_sql = String::with_capacity(sql.len() + target.len());
+        target_sql.push_str(&sql[..self.table_span.0]);
+        target_sql.push_str(target);
+
+        match self.ignore_span {
+            Some((start, end)) => {
+                target_sql.push_str(&sql[self.table_span.1..start]);
+                target_sql.push_str(&sql[end..]);
+            }
+            None => target_sql.push_str(&sql[self.table_span.1..]),
+        }
+
+        target_sql
+    }
+
+    // Split the file to rows, the ignored lines and the lines without prefix are skipped.
+    pub fn rows<'a>(&self, data: &'a [u8]) -> Vec<LoadRow<'a>> {
+        let (rows, _) = self.split_rows(data, true);
+        rows.into_iter().skip(self.ignore_lines as usize).collect()
+    }
+
+    // Split the rows from `data` which is read from the file so far, the ignored lines are not skipped.
+    // The last row is kept until it is terminated unless `is_end` is true. Returns the rows and the
+    // length of `data` which is consumed, the rest is prepended to the next data.
+    pub fn split_rows<'a>(&self, data: &'a [u8], is_end: bool) -> (Vec<LoadRow<'a>>, usize) {
+        let mut rows = vec![];
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let start = pos;
+            let body = if self.lines_starting.is_empty() {
+                pos
+            } else {
+                match find_from(data, pos, &self.lines_starting) {
+                    Some(idx) => idx + self.lines_starting.len(),
+                    None => {
+                        // The lines without prefix are skipped, except the part of prefix at the end.
+                        pos = if is_end {
+                            data.len()
+                        } else {
+                            data.len().saturating_sub(self.lines_starting.len() - 1).max(pos)
+                        };
+                        break;
+                    }
+                }
+            };
+
+            let (length, terminated, fields) = self.scan_row(&data[body..]);
+            if !terminated && !is_end {
+                break;
+            }
+            pos = body + length;
+
+            let fields =
+                fields.into_iter().map(|(s, e)| (s + body - start, e + body - start)).collect();
+            rows.push(LoadRow { data: &data[start..pos], terminated, fields });
+        }
+
+        (rows, pos)
+    }
+
+    // Scan a row from the start of `data`, returns the length of the row including the line
+    // terminator, whether the terminator is found and the spans of the fields.
+    fn scan_row(&self, data: &[u8]) -> (usize, bool, Vec<(usize, usize)>) {
+        let mut fields = vec![];
+        let mut start = 0;
+        let mut pos = 0;
+        let mut field_start = true;
+        let mut in_enclosed = false;
+
+        while pos < data.len() {
+            let c = data[pos];
+
+            if field_start {
+                field_start = false;
+                if Some(c) == self.fields_enclosed {
+                    in_enclosed = true;
+                    pos += 1;
+                    continue;
+                }
+            }
+
+            if Some(c) == self.fields_escaped {
+                pos += 2;
+                continue;
+            }
+
+            if in_enclosed {
+                if Some(c) == self.fields_enclosed {
+                    // A doubled enclosing char is literal, the enclosure is closed by a terminator.
+                    if data.get(pos + 1) == Some(&c) {
+                        pos += 2;
+                        continue;
+                    }
+                    let rest = &data[pos + 1..];
+                    if rest.is_empty()
+                        || rest.starts_with(&self.lines_terminated)
+                        || (!self.fields_terminated.is_empty()
+                            && rest.starts_with(&self.fields_terminated))
+                    {
+                        in_enclosed = false;
+                    }
+                }
+                pos += 1;
+                continue;
+            }
+
+            if data[pos..].starts_with(&self.lines_terminated) {
+                fields.push((start, pos));
+                return (pos + self.lines_terminated.len(), true, fields);
+            }
+
+            if !self.fields_terminated.is_empty()
+                && data[pos..].starts_with(&self.fields_terminated)
+            {
+                fields.push((start, pos));
+                pos += self.fields_terminated.len();
+                start = pos;
+                field_start = true;
+                continue;
+            }
+
+            pos += 1;
+        }
+
+        fields.push((start, data.len()));
+        (data.len(), false, fields)
+    }
+
+    // Returns the unescaped value of the field, `None` when the field is NULL or not found.
+    pub fn field(&self, row: &LoadRow, idx: usize) -> Option<String> {
+        let (start, end) = *row.fields.get(idx)?;
+        let mut value = &row.data[start..end];
+
+        if let Some(escaped) = self.fields_escaped {
+            if value == [escaped, b'N'] {
+                return None;
+            }
+        }
+
+        match self.fields_enclosed {
+            Some(enclosed)
+                if value.len() >= 2
+                    && value[0] == enclosed
+                    && value[value.len() - 1] == enclosed =>
+            {
+                value = &value[1..value.len() - 1];
+            }
+            Some(_) if value == b"NULL" => return None,
+            _ => {}
+        }
+
+        let mut unescaped = Vec::with_capacity(value.len());
+        let mut pos = 0;
+        while pos < value.len() {
+            let c = value[pos];
+            if Some(c) == self.fields_escaped && pos + 1 < value.len() {
+                unescaped.push(value[pos + 1]);
+                pos += 2;
+            } else if Some(c) == self.fields_enclosed && value.get(pos + 1) == Some(&c) {
+                unescaped.push(c);
+                pos += 2;
+            } else {
+                unescaped.push(c);
+                pos += 1;
+            }
+        }
+
+        Some(String::from_utf8_lossy(&unescaped).to_string())
+    }
+
+    // Append the row to `buf`, the line terminator is added when the row doesn't end with it.
+    pub fn write_row(&self, row: &LoadRow, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(row.data);
+        if !row.terminated {
+            buf.extend_from_slice(&self.lines_terminated);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_load_data_local() {
+        assert!(is_load_data_local("LOAD DATA LOCAL INFILE 'a.csv' INTO TABLE t"));
+        assert!(is_load_data_local(
+            "/* c */ load data low_priority local infile 'a.csv' into table t"
+        ));
+        assert!(!is_load_data_local("LOAD DATA INFILE 'a.csv' INTO TABLE t"));
+        assert!(!is_load_data_local("SELECT 'LOAD DATA LOCAL'"));
+    }
+
+    #[test]
+    fn test_load_data_local_file() {
+        assert_eq!(
+            load_data_local_file("LOAD DATA LOCAL INFILE '/tmp/a.csv' INTO TABLE t"),
+            Some(b"/tmp/a.csv".to_vec())
+        );
+        assert_eq!(load_data_local_file("load data concurrent local infile 'a.csv' into table t"), Some(b"a.csv".to_vec()));
+        assert_eq!(load_data_local_file("LOAD DATA INFILE 'a.csv' INTO TABLE t"), None);
+        assert_eq!(load_data_local_file("SELECT 1"), None);
+    }
+
+    #[test]
+    fn test_parse_load_data() {
+        let sql = "LOAD DATA LOCAL INFILE '/tmp/a.csv' IGNORE INTO TABLE `db`.tshard CHARACTER SET utf8mb4 \
+                   FIELDS TERMINATED BY ',' OPTIONALLY ENCLOSED BY '\"' ESCAPED BY '\\\\' \
+                   LINES STARTING BY '>' TERMINATED BY '\\r\\n' IGNORE 1 LINES (id, `name`, @v) SET c = @v";
+        let load = parse_load_data(sql).unwrap();
+
+        assert_eq!(load.file, b"/tmp/a.csv");
+        assert_eq!(load.schema, Some("db".to_string()));
+        assert_eq!(load.table, "tshard");
+        assert_eq!(&sql[load.table_span.0..load.table_span.1], "`db`.tshard");
+        assert_eq!(load.fields_terminated, b",");
+        assert_eq!(load.fields_enclosed, Some(b'"'));
+        assert_eq!(load.fields_escaped, Some(b'\\'));
+        assert_eq!(load.lines_starting, b">");
+        assert_eq!(load.lines_terminated, b"\r\n");
+        assert_eq!(load.ignore_lines, 1);
+        assert_eq!(load.columns, vec!["id", "name", "@v"]);
+        assert_eq!(load.column_index("ID"), Ok(0));
+        assert_eq!(
+            load.column_index("c"),
+            Err(LoadDataError::ShardingColumnNotFound("c".to_string()))
+        );
+
+        let target_sql = load.rewrite(sql, "`db`.`tshard_00001`");
+        assert!(target_sql.contains("INTO TABLE `db`.`tshard_00001` CHARACTER SET"));
+        assert!(target_sql.contains("TERMINATED BY '\\r\\n'  (id"));
+
+        let load = parse_load_data("LOAD DATA LOCAL INFILE 'a.csv' INTO TABLE t").unwrap();
+        assert_eq!(load.column_index("id"), Err(LoadDataError::ColumnsRequired));
+        assert!(
+            parse_load_data("LOAD DATA LOCAL INFILE 'a.csv' INTO TABLE t IGNORE x LINES").is_err()
+        );
+        assert!(parse_load_data("LOAD DATA LOCAL INFILE 'a.csv' INTO").is_err());
+        assert_eq!(
+            parse_load_data("LOAD DATA LOCAL INFILE 'a' INTO TABLE").unwrap_err(),
+            LoadDataError::UnexpectedEnd
+        );
+    }
+
+    #[test]
+    fn test_load_data_rows() {
+        let sql = "LOAD DATA LOCAL INFILE 'a.csv' INTO TABLE t FIELDS TERMINATED BY ',' ENCLOSED BY '\"' IGNORE 1 LINES (id, name)";
+        let load = parse_load_data(sql).unwrap();
+        let data = b"id,name\n1,\"a,\"\"b\"\"\nc\"\n2,\\N\n\"3\",d\\,e";
+        let rows = load.rows(data);
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].data, b"1,\"a,\"\"b\"\"\nc\"\n");
+        assert_eq!(load.field(&rows[0], 0), Some("1".to_string()));
+        assert_eq!(load.field(&rows[0], 1), Some("a,\"b\"\nc".to_string()));
+        assert_eq!(load.field(&rows[1], 1), None);
+        assert_eq!(load.field(&rows[2], 0), Some("3".to_string()));
+        assert_eq!(load.field(&rows[2], 1), Some("d,e".to_string()));
+        assert_eq!(load.field(&rows[2], 2), None);
+
+        let mut buf = vec![];
+        load.write_row(&rows[1], &mut buf);
+        load.write_row(&rows[2], &mut buf);
+        assert_eq!(buf, b"2,\\N\n\"3\",d\\,e\n");
+
+        let sql = "LOAD DATA LOCAL INFILE 'a.csv' INTO TABLE t LINES STARTING BY 'x:' (id)";
+        let load = parse_load_data(sql).unwrap();
+        let rows = load.rows(b"x:1\nskip\nyy x:2\n");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(load.field(&rows[0], 0), Some("1".to_string()));
+        assert_eq!(rows[1].data, b"skip\nyy x:2\n");
+        assert_eq!(load.field(&rows[1], 0), Some("2".to_string()));
+
+        let (rows, consumed) = load.split_rows(b"x:1\nskip\nyy x", false);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(consumed, 12);
+        let (rows, consumed) = load.split_rows(b"x:2\n", true);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(consumed, 4);
+    }
+
+    #[test]
+    fn test_load_data_split_rows() {
+        let sql = "LOAD DATA LOCAL INFILE 'a.csv' INTO TABLE t FIELDS TERMINATED BY ',' ENCLOSED BY '\"' (id, name)";
+        let load = parse_load_data(sql).unwrap();
+        let data = b"1,a\n2,\"b\nc";
+
+        let (rows, consumed) = load.split_rows(data, false);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].data, b"1,a\n");
+        assert_eq!(consumed, 4);
+
+        let (rows, consumed) = load.split_rows(&data[consumed..], true);
+        assert_eq!(rows.len(), 1);
+        assert!(!rows[0].terminated);
+        assert_eq!(rows[0].data, b"2,\"b\nc");
+        assert_eq!(consumed, data.len() - 4);
+    }
+}
@@ -12,8 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use mysql_protocol::err::ProtocolError;
 use once_cell::sync::Lazy;
-use rocket_prometheus::prometheus::{opts, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec};
+use rocket_prometheus::prometheus::{
+    core::{Collector, Desc},
+    opts, proto, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec,
+};
+use strategy::route::RouteStrategy;
+
+use crate::registry;
 
 // LABEL_NAME_DOMAIN refers to the name of current working proxy runtime
 const LABEL_NAME_DOMAIN: &'static str = "domain";
@@ -21,6 +28,25 @@ const LABEL_NAME_DOMAIN: &'static str = "domain";
 const LABEL_NAME_TYPE: &'static str = "type";
 // LABEL_NAME_SERVER refers to the host of current backend database
 const LABEL_NAME_SERVER: &'static str = "server";
+// LABEL_NAME_REASON refers to the reason of failed handshake
+const LABEL_NAME_REASON: &'static str = "reason";
+// LABEL_NAME_RULE refers to the name of matched rule
+const LABEL_NAME_RULE: &'static str = "rule";
+// LABEL_NAME_ROLE refers to the target role of routed statement or the role of node
+const LABEL_NAME_ROLE: &'static str = "role";
+// LABEL_NAME_USER refers to the user of backend conns
+const LABEL_NAME_USER: &'static str = "user";
+// LABEL_NAME_PLUGIN refers to the plugin type, `circuit_break` or `concurrency_control`
+const LABEL_NAME_PLUGIN: &'static str = "plugin";
+// LABEL_NAME_NODE refers to the name of backend node
+const LABEL_NAME_NODE: &'static str = "node";
+
+// The buckets in seconds from 0.5ms to 10s
+const DURATION_BUCKETS: &[f64] = &[0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+// The buckets in seconds from 0.1ms to 5s, getting an idle conn takes less than 1ms
+const POOL_WAIT_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+// The buckets of the number of shards
+const SHARD_FANOUT_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0];
 
 pub static SQL_PROCESSED_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
     IntCounterVec::new(
@@ -33,7 +59,7 @@ pub static SQL_PROCESSED_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
 pub static SQL_PROCESSED_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
     let opt = HistogramOpts {
         common_opts: opts!("sql_processed_duration", "The duration of processed SQL"),
-        buckets: Vec::<f64>::new(),
+        buckets: DURATION_BUCKETS.to_vec(),
     };
     HistogramVec::new(opt, &[LABEL_NAME_DOMAIN, LABEL_NAME_TYPE, LABEL_NAME_SERVER])
         .expect("Cound not create SQL_PROCESSED_DURATION")
@@ -47,6 +73,71 @@ pub static SQL_UNDER_PROCESSING: Lazy<GaugeVec> = Lazy::new(|| {
     .expect("Cound not create SQL_UNDER_PROCESSING")
 });
 
+pub static CLIENT_CONNECTIONS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        opts!("client_connections_total", "The total of accepted client connections"),
+        &[LABEL_NAME_DOMAIN],
+    )
+    .expect("Could not create CLIENT_CONNECTIONS_TOTAL")
+});
+
+pub static CLIENT_CONNECTIONS_ACTIVE: Lazy<GaugeVec> = Lazy::new(|| {
+    GaugeVec::new(
+        opts!("client_connections_active", "The authenticated client connections"),
+        &[LABEL_NAME_DOMAIN],
+    )
+    .expect("Could not create CLIENT_CONNECTIONS_ACTIVE")
+});
+
+pub static CLIENT_HANDSHAKE_FAILURES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        opts!("client_handshake_failures_total", "The total of failed client handshakes"),
+        &[LABEL_NAME_DOMAIN, LABEL_NAME_REASON],
+    )
+    .expect("Could not create CLIENT_HANDSHAKE_FAILURES_TOTAL")
+});
+
+pub static POOL_WAIT_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    let opt = HistogramOpts {
+        common_opts: opts!("pool_wait_duration", "The duration of getting backend conns"),
+        buckets: POOL_WAIT_BUCKETS.to_vec(),
+    };
+    HistogramVec::new(opt, &[LABEL_NAME_DOMAIN, LABEL_NAME_SERVER])
+        .expect("Could not create POOL_WAIT_DURATION")
+});
+
+pub static ROUTE_DECISIONS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        opts!("route_decisions_total", "The total of route decisions"),
+        &[LABEL_NAME_DOMAIN, LABEL_NAME_RULE, LABEL_NAME_ROLE],
+    )
+    .expect("Could not create ROUTE_DECISIONS_TOTAL")
+});
+
+pub static SHARD_FANOUT: Lazy<HistogramVec> = Lazy::new(|| {
+    let opt = HistogramOpts {
+        common_opts: opts!("shard_fanout", "The number of shards of sharding statements"),
+        buckets: SHARD_FANOUT_BUCKETS.to_vec(),
+    };
+    HistogramVec::new(opt, &[LABEL_NAME_DOMAIN]).expect("Could not create SHARD_FANOUT")
+});
+
+pub static REWRITE_ERRORS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        opts!("rewrite_errors_total", "The total of failed sharding rewrites"),
+        &[LABEL_NAME_DOMAIN],
+    )
+    .expect("Could not create REWRITE_ERRORS_TOTAL")
+});
+
+pub static DISCOVERY_ROLE_CHANGES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        opts!("discovery_role_changes_total", "The total of node role changes by the discovery"),
+        &[LABEL_NAME_DOMAIN, LABEL_NAME_NODE, LABEL_NAME_ROLE],
+    )
+    .expect("Could not create DISCOVERY_ROLE_CHANGES_TOTAL")
+});
+
 #[derive(Clone, Copy)]
 pub struct MySQLServerMetricsCollector;
 
@@ -69,6 +160,139 @@ impl MySQLServerMetricsCollector {
     pub fn set_sql_under_processing_dec(&self, labels: &[&str]) {
         SQL_UNDER_PROCESSING.with_label_values(labels).dec();
     }
+
+    pub fn set_client_connections_total(&self, labels: &[&str]) {
+        CLIENT_CONNECTIONS_TOTAL.with_label_values(labels).inc();
+    }
+
+    pub fn set_client_connections_active_inc(&self, labels: &[&str]) {
+        CLIENT_CONNECTIONS_ACTIVE.with_label_values(labels).inc();
+    }
+
+    pub fn set_client_connections_active_dec(&self, labels: &[&str]) {
+        CLIENT_CONNECTIONS_ACTIVE.with_label_values(labels).dec();
+    }
+
+    pub fn set_client_handshake_failures_total(&self, labels: &[&str]) {
+        CLIENT_HANDSHAKE_FAILURES_TOTAL.with_label_values(labels).inc();
+    }
+
+    pub fn set_pool_wait_duration(&self, labels: &[&str], duration: f64) {
+        POOL_WAIT_DURATION.with_label_values(labels).observe(duration);
+    }
+
+    pub fn set_route_decisions_total(&self, labels: &[&str]) {
+        ROUTE_DECISIONS_TOTAL.with_label_values(labels).inc();
+    }
+
+    pub fn set_shard_fanout(&self, labels: &[&str], shards: usize) {
+        SHARD_FANOUT.with_label_values(labels).observe(shards as f64);
+    }
+
+    pub fn set_rewrite_errors_total(&self, labels: &[&str]) {
+        REWRITE_ERRORS_TOTAL.with_label_values(labels).inc();
+    }
+
+    // Count the role changes reported by the discovery since the last call.
+    pub fn set_discovery_role_changes(&self, domain: &str, strategy: &mut RouteStrategy) {
+        for change in strategy.take_role_changes() {
+            DISCOVERY_ROLE_CHANGES_TOTAL
+                .with_label_values(&[domain, change.node.as_str(), change.role])
+                .inc();
+        }
+    }
+}
+
+// The reason label of failed handshake.
+pub fn handshake_failure_reason(err: &ProtocolError) -> &'static str {
+    match err {
+        ProtocolError::Io(_) => "io",
+        ProtocolError::Tls
+        | ProtocolError::TlsConfig(_)
+        | ProtocolError::MakeTls(_)
+        | ProtocolError::Ssl(_)
+        | ProtocolError::SslErrorStack(_) => "tls",
+        ProtocolError::AuthFailed(_) | ProtocolError::AuthPluginUnsupport(_) => "auth",
+        _ => "protocol",
+    }
+}
+
+// The stats which are kept by the pool and plugins, they are read from the registered proxies
+// when scraping.
+pub struct ProxyStatsCollector {
+    pool_size: IntGaugeVec,
+    pool_idle_conns: IntGaugeVec,
+    plugin_rule_rejections_total: IntCounterVec,
+}
+
+impl ProxyStatsCollector {
+    pub fn new() -> Self {
+        let labels = &[LABEL_NAME_DOMAIN, LABEL_NAME_SERVER, LABEL_NAME_USER];
+        ProxyStatsCollector {
+            pool_size: IntGaugeVec::new(
+                opts!("pool_size", "The capacity of backend conns of each endpoint"),
+                labels,
+            )
+            .expect("Could not create POOL_SIZE"),
+            pool_idle_conns: IntGaugeVec::new(
+                opts!("pool_idle_conns", "The idle backend conns of each endpoint"),
+                labels,
+            )
+            .expect("Could not create POOL_IDLE_CONNS"),
+            plugin_rule_rejections_total: IntCounterVec::new(
+                opts!("plugin_rule_rejections_total", "The total of statements rejected by rules"),
+                &[LABEL_NAME_DOMAIN, LABEL_NAME_PLUGIN, LABEL_NAME_RULE],
+            )
+            .expect("Could not create PLUGIN_RULE_REJECTIONS_TOTAL"),
+        }
+    }
+}
+
+impl Collector for ProxyStatsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut desc = self.pool_size.desc();
+        desc.extend(self.pool_idle_conns.desc());
+        desc.extend(self.plugin_rule_rejections_total.desc());
+        desc
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        self.pool_size.reset();
+        self.pool_idle_conns.reset();
+        self.plugin_rule_rejections_total.reset();
+
+        for proxy in registry::get_proxies() {
+            let domain = proxy.name.as_str();
+            for pool in proxy.get_pools() {
+                let labels = &[domain, pool.endpoint.as_str(), pool.user.as_str()];
+                self.pool_size.with_label_values(labels).set(pool.size as i64);
+                self.pool_idle_conns.with_label_values(labels).set(pool.idle as i64);
+            }
+
+            // The rules have no name, they are identified by the regexes.
+            let stats = proxy.get_rules();
+            let rules = [
+                ("circuit_break", stats.circuit_break),
+                ("concurrency_control", stats.concurrency_control),
+            ];
+            for (plugin, rules) in rules {
+                for rule in rules {
+                    let regex = rule.regex.join(",");
+                    self.plugin_rule_rejections_total
+                        .with_label_values(&[domain, plugin, regex.as_str()])
+                        .inc_by(rule.rejects);
+                }
+            }
+
+            MySQLServerMetricsCollector::new()
+                .set_discovery_role_changes(domain, &mut proxy.route_strategy.lock());
+        }
+
+        let mut mfs = self.pool_size.collect();
+        mfs.extend(self.pool_idle_conns.collect());
+        mfs.extend(self.plugin_rule_rejections_total.collect());
+        mfs
+    }
 }
 
 macro_rules! collect_sql_processed_total {
@@ -95,3 +319,29 @@ macro_rules! collect_sql_under_processing_dec {
         $s.metrics_collector.set_sql_under_processing_dec(&[$s.name.as_str(), $x, $c]);
     };
 }
+
+macro_rules! collect_shard_fanout {
+    ($s:expr, $x:expr) => {
+        $s.metrics_collector.set_shard_fanout(&[$s.name.as_str()], $x);
+    };
+}
+
+macro_rules! collect_rewrite_errors_total {
+    ($s:expr) => {
+        $s.metrics_collector.set_rewrite_errors_total(&[$s.name.as_str()]);
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_handshake_failure_reason() {
+        let err = ProtocolError::Io(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert_eq!(handshake_failure_reason(&err), "io");
+        assert_eq!(handshake_failure_reason(&ProtocolError::Tls), "tls");
+        assert_eq!(handshake_failure_reason(&ProtocolError::AuthFailed(vec![])), "auth");
+        assert_eq!(handshake_failure_reason(&ProtocolError::ProtocolVersion(9)), "protocol");
+    }
+}
@@ -18,4 +18,11 @@ pub mod server;
 pub use server::*;
 
 mod executor;
-pub mod stmt_cache;
\ No newline at end of file
+pub mod stmt_cache;
+pub mod admin;
+pub mod backend_user;
+pub mod binlog;
+pub mod load_data;
+pub mod process;
+pub mod slow_log;
+pub mod xa;
\ No newline at end of file
@@ -0,0 +1,121 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use mysql_protocol::mysql_const::ColumnType;
+
+use super::admin::AdminResultset;
+use crate::registry::SessionView;
+
+// The length of info shown by `SHOW PROCESSLIST` without `FULL`.
+const PROCESSLIST_INFO_LEN: usize = 100;
+
+#[derive(Debug, PartialEq)]
+pub enum ProcessStmt {
+    ShowProcessList { full: bool },
+    Kill { query: bool, id: u32 },
+}
+
+// Parse `SHOW [FULL] PROCESSLIST` and `KILL [CONNECTION | QUERY] <id>`, they are answered by the proxy
+// from the sessions instead of the backend conns.
+pub fn parse_process_stmt(sql: &str) -> Option<ProcessStmt> {
+    let sql = sql.trim().trim_end_matches(';');
+    let words = sql.split_whitespace().map(|x| x.to_uppercase()).collect::<Vec<_>>();
+    let words = words.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+
+    match words[..] {
+        ["SHOW", "PROCESSLIST"] => Some(ProcessStmt::ShowProcessList { full: false }),
+        ["SHOW", "FULL", "PROCESSLIST"] => Some(ProcessStmt::ShowProcessList { full: true }),
+        ["KILL", id] | ["KILL", "CONNECTION", id] => {
+            id.parse().ok().map(|id| ProcessStmt::Kill { query: false, id })
+        }
+        ["KILL", "QUERY", id] => id.parse().ok().map(|id| ProcessStmt::Kill { query: true, id }),
+        _ => None,
+    }
+}
+
+pub fn processlist(sessions: Vec<SessionView>, full: bool) -> AdminResultset {
+    use ColumnType::{MYSQL_TYPE_LONGLONG as LONGLONG, MYSQL_TYPE_VAR_STRING as VARCHAR};
+
+    let mut rs = AdminResultset::new(vec![
+        ("Id", LONGLONG),
+        ("User", VARCHAR),
+        ("Host", VARCHAR),
+        ("db", VARCHAR),
+        ("Command", VARCHAR),
+        ("Time", LONGLONG),
+        ("State", VARCHAR),
+        ("Info", VARCHAR),
+    ]);
+
+    for session in sessions {
+        let info = match session.info {
+            Some(info) if !full => Some(info.chars().take(PROCESSLIST_INFO_LEN).collect()),
+            info => info,
+        };
+
+        rs.add_row(vec![
+            Some(session.id.to_string()),
+            Some(session.user),
+            Some(session.host),
+            session.db,
+            Some(command_name(&session.command)),
+            Some(session.time.to_string()),
+            Some(session.state),
+            info,
+        ]);
+    }
+
+    rs
+}
+
+// `query` => `Query`, `stmt_execute` => `Stmt Execute`, the same as the command of mysql.
+fn command_name(command: &str) -> String {
+    command
+        .split('_')
+        .map(|x| {
+            let mut chars = x.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_process_stmt() {
+        let cases = vec![
+            ("show processlist", Some(ProcessStmt::ShowProcessList { full: false })),
+            ("SHOW  FULL PROCESSLIST;", Some(ProcessStmt::ShowProcessList { full: true })),
+            ("kill 10", Some(ProcessStmt::Kill { query: false, id: 10 })),
+            ("KILL CONNECTION 10", Some(ProcessStmt::Kill { query: false, id: 10 })),
+            ("kill query 10;", Some(ProcessStmt::Kill { query: true, id: 10 })),
+            ("kill query a", None),
+            ("show processlist where id = 1", None),
+            ("select 1", None),
+        ];
+
+        for (sql, stmt) in cases {
+            assert_eq!(parse_process_stmt(sql), stmt, "{}", sql);
+        }
+
+        assert_eq!(command_name("query"), "Query");
+        assert_eq!(command_name("stmt_execute"), "Stmt Execute");
+    }
+}
@@ -12,42 +12,65 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{marker::PhantomData, time::Instant};
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use byteorder::{ByteOrder, LittleEndian};
 use bytes::BytesMut;
 use conn_pool::PoolConn;
-use futures::{SinkExt, StreamExt};
+use endpoint::endpoint::Endpoint;
+use futures::{FutureExt, SinkExt, StreamExt};
 use mysql_parser::ast::*;
 use mysql_protocol::{
     client::{codec::ResultsetStream, conn::{ClientConn, SessionAttr}},
+    common_packet::SessionState,
     err::ProtocolError,
     mysql_const::*,
     server::{
-        codec::{make_eof_packet, make_err_packet, ok_packet, CommonPacket, PacketSend},
+        codec::{make_eof_packet, make_eof_packet_with_status, make_err_packet, ok_packet, CommonPacket, PacketSend},
         err::MySQLError,
     },
-    session::{SessionMut, Session},
-    util::{is_eof, length_encode_int},
+    session::{is_tracked_var, quote_var_value, SessionMut, Session},
+    util::{get_eof_status, is_eof, length_encode_int},
 };
 use pisa_error::error::{Error, ErrorKind};
-use strategy::{route::RouteInputTyp, sharding_rewrite::ShardingRewriteOutput};
+use indexmap::IndexMap;
+use strategy::{
+    route::{Route, RouteInput, RouteInputTyp},
+    sharding_rewrite::{DataSource, ShardingRewriteOutput},
+};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::{Decoder, Encoder};
-use tracing::{debug, error};
-use mysql_protocol::client::stmt::Stmt;
+use tracing::{debug, error, info_span, warn, Instrument, Span};
+use mysql_protocol::client::stmt::{decode_fetch, decode_send_long_data, Stmt};
 
 use crate::{
-    mysql::{MySQLService, ReqContext, RespContext},
+    mysql::{MySQLProxy, MySQLService, ReqContext, RespContext},
+    telemetry,
     transaction_fsm::{
-        build_conn_attrs, check_get_conn, query_rewrite, route, route_sharding, TransEventName,
+        build_conn_attrs, build_conn_factory, check_get_conn, is_implicit_commit_stmt,
+        is_read_only_session, is_read_stmt, query_rewrite, raw_stmt_trans_event, route, route_command, route_primary,
+        route_sharding, set_next_transaction, set_stmt_vars, stmt_schemas, use_stmt_db, TransEventName,
     },
 };
 
 use std::sync::atomic::Ordering;
 
-use super::executor::Executor;
+use super::{
+    admin::{self, parse_admin_stmt, AdminStmt},
+    binlog::{BinlogDumpGtid, BinlogReader},
+    executor::{Executor, ShardLoad},
+    load_data::{is_load_data_local, load_data_local_file, parse_load_data, LoadData},
+    process::{self, parse_process_stmt, ProcessStmt},
+    stmt_cache::Cursor,
+    xa::{xa_sql, xa_trans_stmt, XaTransStmt},
+};
+
+// The times to retry the dump when the binlog stream of the primary is lost in GTID mode.
+const BINLOG_RETARGET_RETRIES: usize = 30;
 
 pub struct PisaMySQLService<T, C> {
     _phat: PhantomData<(T, C)>,
@@ -74,20 +97,22 @@ where
         let sess = req.framed.codec_mut().get_session();
         let attrs = build_conn_attrs(sess);
         let is_get_conn = req.fsm.trigger(state_name);
-        if is_get_conn {
+        // Keep the conn pinned while a cursor is opened on it.
+        if is_get_conn && !req.stmt_cache.has_backend_cursor() {
             return Self::fsm_get_new_conn(req, raw_sql, input_typ, &attrs).await
         }
 
-        let endpoint = route(input_typ, raw_sql, req.route_strategy.clone());
+        let endpoint = route(&req.name, input_typ, raw_sql, req.route_strategy.clone());
+        let endpoint = req.backend_user.map_endpoint(endpoint);
         req.fsm.get_conn_with_endpoint(endpoint, &attrs).await
     }
 
     async fn fsm_get_new_conn(req: &mut ReqContext<T, C>, raw_sql: &str, input_typ: RouteInputTyp, attrs: &[SessionAttr]) -> Result<PoolConn<ClientConn>, Error> {
-        let endpoint = route(input_typ, raw_sql, req.route_strategy.clone());
-        let factory =
-            ClientConn::with_opts(endpoint.user, endpoint.password, endpoint.addr.clone());
+        let endpoint = route(&req.name, input_typ, raw_sql, req.route_strategy.clone());
+        let endpoint = req.backend_user.map_endpoint(endpoint);
+        let factory = build_conn_factory(&endpoint).map_err(ErrorKind::Protocol)?;
         req.pool.set_factory(factory);
-        check_get_conn(req.pool.clone(), &endpoint.addr, attrs).await
+        check_get_conn(&req.name, req.pool.clone(), &endpoint.addr, attrs).await
     }
 
     async fn init_db_inner<'b>(
@@ -143,7 +168,7 @@ where
             return res;
         }
 
-        route_sharding(input_typ, raw_sql, req.route_strategy.clone(), &mut req.rewrite_outputs);
+        route_sharding(&req.name, input_typ, raw_sql, req.route_strategy.clone(), &mut req.rewrite_outputs)?;
         let sharding_column = req.rewrite_outputs[0].sharding_column.clone();
         debug!("prepare rewrite outputs {:?} {:?} {:?}", req.rewrite_outputs, req.rewrite_outputs.len(), is_get_conn);
 
@@ -156,7 +181,7 @@ where
         stmt.stmt_id = stmt_id;
 
         req.stmt_cache.put_sharding_column(stmt_id, sharding_column);
-        Self::prepare_stmt(req, stmt).await?;
+        Self::prepare_stmt(req, stmt, raw_sql).await?;
 
         Ok(())
     }
@@ -167,12 +192,18 @@ where
         payload: &[u8],
     ) -> Result<(), Error> {
         let stmt = client_conn.send_prepare(payload).await.map_err(ErrorKind::from)?;
-        Self::prepare_stmt(req, stmt).await?;
+        let sql = String::from_utf8_lossy(payload);
+        Self::prepare_stmt(req, stmt, sql.trim_matches(char::from(0))).await?;
 
         Ok(())
     }
 
-    async fn prepare_stmt(req: &mut ReqContext<T,C>, stmt: Stmt) -> Result<(), Error> {
+    async fn prepare_stmt(req: &mut ReqContext<T,C>, stmt: Stmt, sql: &str) -> Result<(), Error> {
+        // COM_STMT_EXECUTE is logged with the prepared sql
+        if req.slow_log.is_some() {
+            req.stmt_cache.put_sql(stmt.stmt_id, sql);
+        }
+
         let mut buf = BytesMut::with_capacity(128);
         let mut data = vec![0];
         data.extend_from_slice(&u32::to_le_bytes(stmt.stmt_id));
@@ -228,9 +259,16 @@ where
         client_conn: &mut PoolConn<ClientConn>,
         payload: &[u8],
     ) -> Result<RespContext, Error> {
+        // Executing the stmt again closes the cursor opened before.
+        let stmt_id = LittleEndian::read_u32(payload);
+        req.stmt_cache.close_cursor(stmt_id);
+
         let stream = client_conn.send_execute(payload).await.map_err(ErrorKind::from)?;
 
-        Self::handle_query_resultset(req, stream).await.map_err(ErrorKind::from)?;
+        let is_cursor = Self::handle_query_resultset(req, stream).await.map_err(ErrorKind::from)?;
+        if is_cursor {
+            req.stmt_cache.open_cursor(stmt_id);
+        }
 
         Ok(RespContext { ep: None, duration: Instant::now().elapsed() })
     }
@@ -239,19 +277,286 @@ where
         let sess = req.framed.codec_mut().get_session();
         let attrs = build_conn_attrs(sess);
         let raw_sql = std::str::from_utf8(payload).unwrap().trim_matches(char::from(0));
+        if is_load_data_local(raw_sql) {
+            return Self::shard_load_data_inner(req, payload, attrs).await;
+        }
+
         let (is_get_conn, input_typ, rewrite_outputs) = Self::query_rewrite(req, raw_sql)?;
         req.rewrite_outputs = rewrite_outputs;
 
         if req.rewrite_outputs.is_empty() {
             let mut client_conn = Self::query_inner_get_conn(req, payload).await?;
-            let res = Self::query_inner(req, &mut client_conn, payload).await;
-            
+            let ep = client_conn.get_endpoint();
+            let addr = ep.as_deref().unwrap_or_default();
+            Span::current().record("endpoint", &addr);
+            let res = Self::query_inner(req, &mut client_conn, payload)
+                .instrument(telemetry::execute_span(addr, 1))
+                .await;
+
             req.fsm.put_conn(client_conn);
             return res;
         }
 
-        route_sharding(input_typ, raw_sql, req.route_strategy.clone(), &mut req.rewrite_outputs);
+        info_span!("pisa.route", shard_count = req.rewrite_outputs.len()).in_scope(|| {
+            route_sharding(&req.name, input_typ, raw_sql, req.route_strategy.clone(), &mut req.rewrite_outputs)
+        })?;
+
+        let span = Span::current();
+        span.record("shard_count", &req.rewrite_outputs.len());
+        span.record("endpoint", &telemetry::shard_endpoints(&req.rewrite_outputs).as_str());
+
+        let mut is_xa_start = false;
+        if req.xa.is_some() {
+            match Self::get_ast(req, raw_sql).ok().and_then(|x| x.first().and_then(xa_trans_stmt)) {
+                // The branch of each shard is started on the conn which is kept until the transaction ends.
+                Some(XaTransStmt::Begin) if is_get_conn => {
+                    let gtrid = req.xa.as_ref().unwrap().next_gtrid();
+                    for (idx, o) in req.rewrite_outputs.iter_mut().enumerate() {
+                        if let DataSource::Endpoint(_) = &o.data_source {
+                            o.target_sql = xa_sql("START", &gtrid, &idx.to_string());
+                        }
+                    }
+                    req.fsm.xid = Some(gtrid);
+                    is_xa_start = true;
+                }
+                Some(stmt @ (XaTransStmt::Commit | XaTransStmt::Rollback)) if req.fsm.xid.is_some() => {
+                    return Self::finish_xa(req, stmt).await;
+                }
+                _ => {}
+            }
+        }
+
         Executor::shard_query_executor(req, attrs, is_get_conn).await?;
+        if is_xa_start {
+            req.fsm.set_xa_bquals();
+        }
+        Ok(())
+    }
+
+    // Commit or rollback the XA transaction across shards instead of sending COMMIT or ROLLBACK.
+    async fn finish_xa(req: &mut ReqContext<T, C>, stmt: XaTransStmt) -> Result<(), Error> {
+        let gtrid = req.fsm.xid.take().unwrap();
+        let xa = req.xa.clone().unwrap();
+        let mut conns = req.fsm.get_shard_conn();
+        let bquals = req.fsm.get_xa_bquals(&conns);
+        let res = match stmt {
+            XaTransStmt::Commit => xa.commit(&gtrid, &mut conns, &bquals).await,
+            _ => xa.rollback(&gtrid, &mut conns, &bquals).await.map(|_| true),
+        };
+        // The prepared branch is held by the conn, it is closed so that the branch can be committed by retry.
+        if matches!(res, Ok(false)) {
+            conns.into_iter().for_each(|conn| conn.discard());
+        } else {
+            req.fsm.put_shard_conn(conns);
+        }
+
+        match res {
+            Ok(_) => {
+                req.framed.send(PacketSend::Encode(ok_packet()[4..].into())).await.map_err(ErrorKind::from)?;
+                Ok(())
+            }
+            Err(e) if stmt == XaTransStmt::Commit => {
+                error!("xa {} commit error {:?}", gtrid, e);
+                let msg = format!("XA_RBROLLBACK: Transaction branch was rolled back: {}", e);
+                Self::send_err_packet(req, 1614, "XA100", msg).await
+            }
+            Err(e) => {
+                error!("xa {} rollback error {:?}", gtrid, e);
+                Self::send_err_packet(req, 1105, "HY000", format!("xa rollback error: {}", e)).await
+            }
+        }
+    }
+
+    // The XA transaction which is not ended by the client is rolled back.
+    async fn rollback_pending_xa(req: &mut ReqContext<T, C>) {
+        let (xa, gtrid) = match (req.xa.clone(), req.fsm.xid.take()) {
+            (Some(xa), Some(gtrid)) => (xa, gtrid),
+            _ => return,
+        };
+
+        let mut conns = req.fsm.get_shard_conn();
+        let bquals = req.fsm.get_xa_bquals(&conns);
+        match xa.rollback(&gtrid, &mut conns, &bquals).await {
+            Ok(_) => req.fsm.put_shard_conn(conns),
+            Err(e) => {
+                error!("xa {} rollback error {:?}", gtrid, e);
+                conns.into_iter().for_each(|conn| conn.discard());
+            }
+        }
+    }
+
+    // The rows of `LOAD DATA LOCAL INFILE` are split to shards by sharding column,
+    // the statement is forwarded as is when the table is not sharded.
+    async fn shard_load_data_inner(
+        req: &mut ReqContext<T, C>,
+        payload: &[u8],
+        attrs: Vec<SessionAttr>,
+    ) -> Result<(), Error> {
+        let raw_sql = std::str::from_utf8(payload).unwrap().trim_matches(char::from(0));
+        let load = match parse_load_data(raw_sql) {
+            Ok(load) => load,
+            Err(e) => return Self::send_err_packet(req, 1105, "HY000", format!("load data error: {}", e)).await,
+        };
+
+        let rewriter = req.rewriter.as_ref().unwrap();
+        let column = rewriter.get_table_sharding_column(&load.table).map(|x| x.to_string());
+        if !rewriter.is_sharding_table(&load.table) {
+            let mut client_conn = Self::query_inner_get_conn(req, payload).await?;
+            let res = Self::query_inner(req, &mut client_conn, payload).await;
+
+            req.fsm.put_conn(client_conn);
+            return res;
+        }
+
+        if !req.local_infile {
+            return Self::send_err_packet(
+                req,
+                3948,
+                "42000",
+                String::from("Loading local data is disabled; this must be enabled on both the client and server sides"),
+            )
+            .await;
+        }
+
+        let column = match column {
+            Some(column) if req.local_infile_sharding => column,
+            _ => {
+                return Self::send_err_packet(
+                    req,
+                    1235,
+                    "42000",
+                    String::from("This version of MySQL doesn't yet support 'LOAD DATA LOCAL INFILE on sharding table'"),
+                )
+                .await
+            }
+        };
+
+        let column_idx = match load.column_index(&column) {
+            Ok(idx) => idx,
+            Err(e) => return Self::send_err_packet(req, 1105, "HY000", format!("load data error: {}", e)).await,
+        };
+
+        let mut packet = vec![LOCAL_IN_FILE_HEADER];
+        packet.extend_from_slice(&load.file);
+        req.framed.send(PacketSend::Encode(packet[..].into())).await.map_err(ErrorKind::from)?;
+
+        // The rows are sent to the shards while the file is read, the row which is not terminated
+        // is kept until the next packet. The client terminates the file with an empty packet.
+        let mut file = vec![];
+        let mut ignore_lines = load.ignore_lines;
+        let mut row_count = 0;
+        let mut loads = IndexMap::<String, ShardLoad>::new();
+        let mut load_err = None;
+        req.rewrite_outputs.clear();
+
+        loop {
+            let data = match req.framed.next().await {
+                Some(Ok(data)) => Some(data),
+                _ => None,
+            };
+            let data = match data {
+                Some(data) => data,
+                None => {
+                    Executor::<T, C>::shard_load_data_abort(loads.into_iter().map(|x| x.1).collect()).await;
+                    return Err(Error::new(ErrorKind::Protocol(ProtocolError::Io(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "read local infile from client",
+                    )))));
+                }
+            };
+
+            let is_end = data.is_empty();
+            // The rest of the file is discarded after an error.
+            if load_err.is_some() {
+                if is_end {
+                    break;
+                }
+                continue;
+            }
+
+            file.extend_from_slice(&data);
+            let rewriter = req.rewriter.as_ref().unwrap();
+            let (rows, consumed) = load.split_rows(&file, is_end);
+            let mut targets = IndexMap::<String, (DataSource, Vec<u8>)>::new();
+            for row in rows.iter() {
+                if ignore_lines > 0 {
+                    ignore_lines -= 1;
+                    continue;
+                }
+                row_count += 1;
+
+                let route = load
+                    .field(row, column_idx)
+                    .and_then(|value| rewriter.route_row(load.schema.as_deref(), &load.table, &value).ok().flatten());
+                match route {
+                    Some((data_source, table)) => {
+                        let target = targets.entry(table).or_insert_with(|| (data_source, vec![]));
+                        load.write_row(row, &mut target.1);
+                    }
+                    None => {
+                        load_err = Some(format!("invalid sharding column {:?} value at row {}", column, row_count));
+                        break;
+                    }
+                }
+            }
+            file.drain(..consumed);
+
+            if load_err.is_none() {
+                load_err = Self::shard_load_data_send(req, raw_sql, &load, &column, &attrs, &mut loads, targets).await.err();
+            }
+
+            if is_end {
+                break;
+            }
+        }
+
+        let loads = loads.into_iter().map(|x| x.1).collect::<Vec<_>>();
+        if let Some(msg) = load_err {
+            Executor::<T, C>::shard_load_data_abort(loads).await;
+            return Self::send_err_packet(req, 1105, "HY000", msg).await;
+        }
+
+        if loads.is_empty() {
+            req.framed.send(PacketSend::Encode(ok_packet()[4..].into())).await.map_err(ErrorKind::from)?;
+            return Ok(());
+        }
+
+        Executor::shard_load_data_finish(req, loads).await
+    }
+
+    // Send the rows to the target tables, the load of the table is started when the first row is routed to it.
+    async fn shard_load_data_send(
+        req: &mut ReqContext<T, C>,
+        raw_sql: &str,
+        load: &LoadData,
+        column: &str,
+        attrs: &[SessionAttr],
+        loads: &mut IndexMap<String, ShardLoad>,
+        targets: IndexMap<String, (DataSource, Vec<u8>)>,
+    ) -> Result<(), String> {
+        for (table, (data_source, rows)) in targets.into_iter() {
+            if !loads.contains_key(&table) {
+                let mut outputs = vec![ShardingRewriteOutput {
+                    changes: vec![],
+                    target_sql: load.rewrite(raw_sql, &table),
+                    data_source,
+                    sharding_column: Some(column.to_string()),
+                    min_max_fields: vec![],
+                }];
+                route_sharding(&req.name, RouteInputTyp::Transaction, raw_sql, req.route_strategy.clone(), &mut outputs)
+                    .map_err(|e| format!("load data error: {}", e))?;
+
+                let shard_load = Executor::shard_load_data_open(req, &outputs[0], attrs.to_vec())
+                    .await
+                    .map_err(|e| format!("load data error: {}", e))?;
+                req.rewrite_outputs.append(&mut outputs);
+                loads.insert(table.clone(), shard_load);
+            }
+
+            // The result of the shard is returned when the load is finished.
+            let _ = loads[&table].send(rows).await;
+        }
+
         Ok(())
     }
 
@@ -267,6 +572,57 @@ where
 
         Self::handle_query_resultset(req, stream).await.map_err(ErrorKind::from)?;
 
+        if !req.read_back_vars.is_empty() {
+            Self::read_back_user_vars(req, client_conn).await?;
+        }
+
+        Ok(())
+    }
+
+    // Read back the user variables set by expressions, e.g. `SET @a = NOW()`, they are recorded
+    // as literals so that they can be replayed on another conn.
+    async fn read_back_user_vars(req: &mut ReqContext<T, C>, client_conn: &mut PoolConn<ClientConn>) -> Result<(), Error> {
+        let names = std::mem::take(&mut req.read_back_vars);
+        let sql = format!(
+            "SELECT {}",
+            names.iter().map(|x| format!("@`{}`", x[1..].replace('`', "``"))).collect::<Vec<_>>().join(", ")
+        );
+
+        let mut stream = client_conn.send_query(sql.as_bytes()).await.map_err(ErrorKind::Protocol)?;
+        let mut packets = vec![];
+        while let Some(data) = stream.next().await {
+            packets.push(data.map_err(ErrorKind::Protocol)?);
+        }
+
+        // The column count, the columns, EOF and the row.
+        let row = match packets.get(names.len() + 2) {
+            Some(row) if packets[0][4] != ERR_HEADER => row,
+            _ => {
+                warn!("read back user variables {:?} failed", names);
+                return Ok(());
+            }
+        };
+
+        let sess = req.framed.codec_mut().get_session();
+        let mut data = &row[4..];
+        for name in names.iter() {
+            if data.is_empty() {
+                break;
+            }
+
+            let (length, is_null, size) = length_encode_int(data);
+            let value = if is_null {
+                "NULL".to_string()
+            } else {
+                let value = &data[size as usize..(size + length) as usize];
+                quote_var_value(&String::from_utf8_lossy(value))
+            };
+            data = &data[(size + length) as usize..];
+
+            stream.set_session_var(name, Some(value.clone()));
+            sess.set_var(name, Some(value));
+        }
+
         Ok(())
     }
 
@@ -278,15 +634,19 @@ where
         let attrs = build_conn_attrs(sess);
         let sql = std::str::from_utf8(payload).unwrap().trim_matches(char::from(0));
         let (is_get_conn, input_typ, _rewrite_outputs) =  Self::query_rewrite(req, sql)?;
-        if is_get_conn {
-            let endpoint = route(input_typ, sql, req.route_strategy.clone());
-            let factory =
-                ClientConn::with_opts(endpoint.user, endpoint.password, endpoint.addr.clone());
+        // Keep the conn pinned while a cursor is opened on it.
+        if is_get_conn && !req.stmt_cache.has_backend_cursor() {
+            let endpoint = info_span!("pisa.route")
+                .in_scope(|| route(&req.name, input_typ, sql, req.route_strategy.clone()));
+            let endpoint = req.backend_user.map_endpoint(endpoint);
+            let factory = build_conn_factory(&endpoint).map_err(ErrorKind::Protocol)?;
             req.pool.set_factory(factory);
-            return check_get_conn(req.pool.clone(), &endpoint.addr, &attrs).await;
+            return check_get_conn(&req.name, req.pool.clone(), &endpoint.addr, &attrs)
+                .instrument(info_span!("pisa.pool_acquire", endpoint = endpoint.addr.as_str()))
+                .await;
         }
 
-        req.fsm.get_conn(&attrs).await
+        req.fsm.get_conn(&attrs).instrument(info_span!("pisa.pool_acquire")).await
     }
 
     fn query_rewrite<'a>(
@@ -297,28 +657,83 @@ where
         let ast = match ast {
             Err(err) => {
                 error!("parse sql {:?} err: {:?}", sql, err);
-                if req.rewriter.is_some() {
+                // LOAD DATA of the table which is not sharded is forwarded as is.
+                let is_load_data = is_load_data_local(sql);
+                if req.rewriter.is_some() && !is_load_data {
                     return Err(err);
                 }
+                if let Some((event, input_typ)) = raw_stmt_trans_event(sql) {
+                    return Ok((req.fsm.trigger(event), input_typ, vec![]));
+                }
                 let is_get_conn = req.fsm.trigger(TransEventName::QueryEvent);
-                return Ok((is_get_conn, RouteInputTyp::Statement, vec![]));
+                // The stmt which can't be parsed may write, e.g. CALL or a batch, it goes to the primary.
+                return Ok((is_get_conn, RouteInputTyp::Transaction, vec![]));
             }
 
-            Ok(ast) => ast[0].clone(),
+            Ok(ast) => ast,
         };
 
-        let (is_get_conn, input, can_rewrite) = match &ast {
+        // Multi-statements are routed by the strongest stmt, any write goes to the primary.
+        if ast.len() > 1 {
+            let mut is_get_conn = false;
+            let mut input = RouteInputTyp::Statement;
+            for stmt in ast.iter() {
+                let (get_conn, typ, _) = Self::trigger_stmt(req, stmt);
+                is_get_conn |= get_conn;
+                if matches!(typ, RouteInputTyp::Transaction) || !is_read_stmt(stmt) {
+                    input = RouteInputTyp::Transaction;
+                }
+            }
+            return Ok((is_get_conn, input, vec![]));
+        }
+
+        let ast = ast[0].clone();
+        let (is_get_conn, input, can_rewrite) = Self::trigger_stmt(req, &ast);
+
+        if req.rewriter.is_some() {
+            let default_db = req.framed.codec_mut().get_session().get_db();
+            let outputs = info_span!("pisa.rewrite")
+                .in_scope(|| {
+                    query_rewrite(
+                        req.rewriter.as_mut().unwrap(),
+                        sql.to_string(),
+                        ast,
+                        default_db,
+                        can_rewrite,
+                    )
+                })
+                .map_err(|e| {
+                    collect_rewrite_errors_total!(req);
+                    ErrorKind::Runtime(e.into())
+                })?;
+            debug!("rewrite outputs {:?}", outputs);
+            return Ok((is_get_conn, input, outputs));
+        }
+
+        return Ok((is_get_conn, input, vec![]));
+    }
+
+    // Trigger fsm by the stmt, returns whether to get a new conn, route input type and whether the stmt can be rewritten.
+    fn trigger_stmt(req: &mut ReqContext<T, C>, ast: &SqlStmt) -> (bool, RouteInputTyp, bool) {
+        match ast {
             SqlStmt::Set(stmt) => {
                 let (is_get_conn, input) = Self::handle_set_stmt(req, &stmt);
                 (is_get_conn, input, false)
             }
             //TODO: split sql stmt for sql audit
             SqlStmt::BeginStmt(_stmt) => {
-                (req.fsm.trigger(TransEventName::StartEvent), RouteInputTyp::Transaction, false)
+                let input = Self::start_input_typ(req, false, false);
+                (req.fsm.trigger(TransEventName::StartEvent), input, false)
+            }
+
+            SqlStmt::Start(stmt) => {
+                let input = Self::start_input_typ(req, stmt.is_read_only(), stmt.is_read_write());
+                (req.fsm.trigger(TransEventName::StartEvent), input, false)
             }
 
-            SqlStmt::Start(_stmt) => {
-                (req.fsm.trigger(TransEventName::StartEvent), RouteInputTyp::Transaction, false)
+            // `ROLLBACK TO SAVEPOINT` keeps the transaction.
+            SqlStmt::Rollback(stmt) if stmt.ident.is_some() => {
+                (req.fsm.trigger(TransEventName::SavepointEvent), RouteInputTyp::Transaction, false)
             }
 
             SqlStmt::Commit(_stmt) => {
@@ -336,28 +751,43 @@ where
                     false,
                 )
             }
+            stmt if is_implicit_commit_stmt(stmt) => {
+                (req.fsm.trigger(TransEventName::ImplicitCommitEvent), RouteInputTyp::Transaction, true)
+            }
+
             _ => {
                 (req.fsm.trigger(TransEventName::QueryEvent), RouteInputTyp::Statement, true)
             }
-        };
+        }
+    }
 
-        if req.rewriter.is_some() {
-            let default_db = req.framed.codec_mut().get_session().get_db();
-            let outputs =
-                query_rewrite(req.rewriter.as_mut().unwrap(), sql.to_string(), ast, default_db, can_rewrite)
-                    .map_err(|e| ErrorKind::Runtime(e.into()))?;
-            debug!("rewrite outputs {:?}", outputs);
-            return Ok((is_get_conn, input, outputs));
+    // The read only transaction is routed to the read endpoints.
+    fn start_input_typ(req: &mut ReqContext<T, C>, read_only: bool, read_write: bool) -> RouteInputTyp {
+        let read_only_session = is_read_only_session(&req.framed.codec_mut().get_session().get_vars());
+        if read_only || (read_only_session && !read_write) {
+            return RouteInputTyp::ReadOnlyTransaction;
         }
 
-        return Ok((is_get_conn, input, vec![]));
+        RouteInputTyp::Transaction
     }
 
-    // Set charset name
+    // Set charset name, other session variables are recorded by `track_session_state` when the stmt succeeds.
     fn handle_set_stmt<'b: 'a, 'a>(
         req: &'b mut ReqContext<T, C>,
         stmt: &'a SetOptValues,
     ) -> (bool, RouteInputTyp) {
+        req.pending_vars.extend(set_stmt_vars(stmt));
+
+        // The characteristics of the next transaction are kept on the conn until the transaction ends.
+        if let Some(trans) = set_next_transaction(stmt) {
+            let input = match &trans.mode {
+                Some(TransactionType::ReadOnly) => Self::start_input_typ(req, true, false),
+                Some(TransactionType::ReadWrite) => Self::start_input_typ(req, false, true),
+                None => Self::start_input_typ(req, false, false),
+            };
+            return (req.fsm.trigger(TransEventName::SetTransactionEvent), input);
+        }
+
         match stmt {
             SetOptValues::OptValues(vals) => match &vals.opt {
                 SetOpts::SetNames(name) => {
@@ -436,13 +866,23 @@ where
     }
 
     fn get_ast(req: &mut ReqContext<T, C>, sql: &str) -> Result<Vec<SqlStmt>, Error> {
+        let _span = info_span!("pisa.parse").entered();
+        if let Some((last_sql, err)) = &req.last_parse_err {
+            if last_sql == sql {
+                return Err(Error::from(ErrorKind::from(err.clone())));
+            }
+        }
+
         let mut ast_cache = req.ast_cache.lock();
         let try_ast = ast_cache.get(sql.to_string());
 
         match try_ast {
             Some(stmt) => Ok(stmt.to_vec()),
             None => match req.mysql_parser.parse(sql) {
-                Err(err) => Err(Error::from(ErrorKind::from(err[0].clone()))),
+                Err(err) => {
+                    req.last_parse_err = Some((sql.to_string(), err[0].clone()));
+                    Err(Error::from(ErrorKind::from(err[0].clone())))
+                }
                 Ok(stmt) => {
                     ast_cache.set(sql.to_string(), stmt.clone());
                     Ok(stmt)
@@ -451,23 +891,81 @@ where
         }
     }
 
+    // Returns true when a cursor is opened by COM_STMT_EXECUTE. Every resultset is forwarded
+    // while SERVER_MORE_RESULTS_EXISTS is set, e.g. multi-statements or CALL.
     pub async fn handle_query_resultset<'b>(
         req: &mut ReqContext<T, C>,
         mut stream: ResultsetStream<'b>,
-    ) -> Result<(), ProtocolError> {
+    ) -> Result<bool, ProtocolError> {
+        loop {
+            let is_cursor = Self::handle_one_resultset(req, &mut stream).await?;
+            if is_cursor || !stream.has_more_results() {
+                return Ok(is_cursor);
+            }
+        }
+    }
+
+    // Record the variables of succeeded SET stmt and the session state changes tracked by the backend,
+    // they are replayed when another conn is handed out.
+    fn track_session_state(req: &mut ReqContext<T, C>, stream: &mut ResultsetStream<'_>) {
+        let sess = req.framed.codec_mut().get_session();
+        for (name, value) in req.pending_vars.drain(..) {
+            if value.is_none() && name.starts_with('@') {
+                req.read_back_vars.push(name);
+                continue;
+            }
+            stream.set_session_var(&name, value.clone());
+            sess.set_var(&name, value);
+        }
+
+        for change in stream.take_session_changes() {
+            match change {
+                SessionState::SystemVariables(vars) => {
+                    for (name, value) in vars.iter() {
+                        let name = String::from_utf8_lossy(name);
+                        if is_tracked_var(&name) {
+                            sess.set_var(&name, Some(quote_var_value(&String::from_utf8_lossy(value))));
+                        }
+                    }
+                }
+                SessionState::Schema(db) => {
+                    let db = String::from_utf8_lossy(&db).to_string();
+                    sess.set_db(db.clone());
+                    req.fsm.set_db(Some(db));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn handle_one_resultset<'b>(
+        req: &mut ReqContext<T, C>,
+        stream: &mut ResultsetStream<'b>,
+    ) -> Result<bool, ProtocolError> {
         let data = stream.next().await;
 
         let header = match data {
             Some(Ok(data)) => data,
             Some(Err(e)) => return Err(e),
-            None => return Ok(()),
+            None => return Ok(false),
         };
 
         let ok_or_err = header[4];
 
+        // The OK packet keeps SERVER_MORE_RESULTS_EXISTS of the backend.
         if ok_or_err == OK_HEADER || ok_or_err == ERR_HEADER {
+            if ok_or_err == OK_HEADER {
+                Self::track_session_state(req, stream);
+            } else {
+                req.pending_vars.clear();
+            }
             req.framed.send(PacketSend::Encode(header[4..].into())).await?;
-            return Ok(());
+            return Ok(false);
+        }
+
+        if ok_or_err == LOCAL_IN_FILE_HEADER {
+            Self::handle_local_infile(req, stream, header).await?;
+            return Ok(false);
         }
 
         let (cols, ..) = length_encode_int(&header[4..]);
@@ -493,14 +991,24 @@ where
                 .encode(PacketSend::EncodeOffset(data[4..].into(), buf.len()), &mut buf);
         }
 
-        // read eof
-        let _ = stream.next().await;
+        // read eof, rows are fetched by COM_STMT_FETCH when a cursor is opened
+        if let Some(Ok(eof)) = stream.next().await {
+            if get_eof_status(&eof) & SERVER_STATUS_CURSOR_EXISTS > 0 {
+                let _ = req
+                    .framed
+                    .codec_mut()
+                    .encode(PacketSend::EncodeOffset(eof[4..].into(), buf.len()), &mut buf);
+                req.framed.send(PacketSend::Origin(buf[..].into())).await?;
+                return Ok(true);
+            }
+        }
 
         let _ = req
             .framed
             .codec_mut()
             .encode(PacketSend::EncodeOffset(make_eof_packet()[4..].into(), buf.len()), &mut buf);
 
+        let mut rows = 0u64;
         while let Some(data) = stream.next().await {
             let row = match data {
                 Ok(data) => data,
@@ -511,15 +1019,92 @@ where
                 .framed
                 .codec_mut()
                 .encode(PacketSend::EncodeOffset(row[4..].into(), buf.len()), &mut buf);
+            rows += 1;
         }
+        Span::current().record("rows", &rows);
+        req.rows += rows;
 
+        // The EOF packet keeps the status of the backend, e.g. SERVER_MORE_RESULTS_EXISTS and SERVER_STATUS_IN_TRANS.
+        let eof_packet = make_eof_packet_with_status(stream.status());
         let _ = req
             .framed
             .codec_mut()
-            .encode(PacketSend::EncodeOffset(make_eof_packet()[4..].into(), buf.len()), &mut buf);
+            .encode(PacketSend::EncodeOffset(eof_packet[4..].into(), buf.len()), &mut buf);
 
         req.framed.send(PacketSend::Origin(buf[..].into())).await?;
 
+        Ok(false)
+    }
+
+    // Relay the LOCAL INFILE request to the client and stream the file back to the backend,
+    // the OK or ERR packet of the backend is forwarded after the file is sent.
+    async fn handle_local_infile<'b>(
+        req: &mut ReqContext<T, C>,
+        stream: &mut ResultsetStream<'b>,
+        header: BytesMut,
+    ) -> Result<(), ProtocolError> {
+        // Only the file of `LOAD DATA LOCAL INFILE` sent by the client is relayed, the backend may request any file.
+        let file = req.local_infile_file.take();
+        if !req.local_infile || file.as_deref() != Some(&header[5..]) {
+            // The backend still requests the file, an empty file is sent and the result is discarded.
+            stream.send_local_infile(&[]).await?;
+            if let Some(data) = stream.next().await {
+                let _ = data?;
+            }
+
+            let err_info = if req.local_infile {
+                warn!("the backend requests the unexpected local file {:?}", String::from_utf8_lossy(&header[5..]));
+                make_err_packet(MySQLError::new(
+                    2068,
+                    "HY000".as_bytes().to_vec(),
+                    String::from("LOAD DATA LOCAL INFILE file request rejected due to restrictions on access."),
+                ))
+            } else {
+                make_err_packet(MySQLError::new(
+                    3948,
+                    "42000".as_bytes().to_vec(),
+                    String::from("Loading local data is disabled; this must be enabled on both the client and server sides"),
+                ))
+            };
+            req.framed.send(PacketSend::Encode(err_info[4..].into())).await?;
+            return Ok(());
+        }
+
+        req.framed.send(PacketSend::Encode(header[4..].into())).await?;
+
+        // The client terminates the file with an empty packet.
+        loop {
+            // The client result is not held across the awaits below, the codec error may be not Send.
+            let data = match req.framed.next().await {
+                Some(Ok(data)) => Some(data),
+                _ => None,
+            };
+            let data = match data {
+                Some(data) => data,
+                None => {
+                    // Terminate the file so that the backend conn is still usable.
+                    stream.send_local_infile(&[]).await?;
+                    if let Some(data) = stream.next().await {
+                        let _ = data?;
+                    }
+                    return Err(ProtocolError::Io(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "read local infile from client",
+                    )));
+                }
+            };
+
+            stream.send_local_infile(&data).await?;
+            if data.is_empty() {
+                break;
+            }
+        }
+
+        if let Some(data) = stream.next().await {
+            let data = data?;
+            req.framed.send(PacketSend::Encode(data[4..].into())).await?;
+        }
+
         Ok(())
     }
 
@@ -557,59 +1142,430 @@ where
         Ok(())
     }
 
-    async fn sharding_command_not_support(
-        cx: &mut ReqContext<T, C>,
-        command: &str,
+    async fn stmt_fetch_inner(
+        req: &mut ReqContext<T, C>,
+        client_conn: &mut PoolConn<ClientConn>,
+        stmt_id: u32,
+        payload: &[u8],
     ) -> Result<(), Error> {
-        let err_info = make_err_packet(MySQLError::new(
-            1047,
-            "08S01".as_bytes().to_vec(),
-            format!("command {:?} not support in sharding", command),
-        ));
-        cx.framed.send(PacketSend::Encode(err_info[4..].into())).await.map_err(ErrorKind::from)?;
-        Ok(())
-    }
-}
+        let packets = client_conn.send_fetch(payload).await.map_err(ErrorKind::Protocol)?;
 
-#[async_trait]
-impl<'a, T, C> MySQLService<T, C> for PisaMySQLService<T, C>
-where
-    T: AsyncRead + AsyncWrite + Unpin + Send,
-    C: Decoder<Item = BytesMut>
-        + Encoder<PacketSend<Box<[u8]>>, Error = ProtocolError>
-        + Send
-        + CommonPacket,
-{
-    async fn init_db(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
-        let now = Instant::now();
-        let db = std::str::from_utf8(payload).unwrap().trim_matches(char::from(0));
-        cx.framed.codec_mut().get_session().set_db(db.to_string());
+        let mut buf = BytesMut::with_capacity(128);
+        for data in packets.iter() {
+            let _ = req
+                .framed
+                .codec_mut()
+                .encode(PacketSend::EncodeOffset(data[4..].into(), buf.len()), &mut buf);
+        }
 
-        if cx.rewriter.is_some() {
-            cx.framed.send(PacketSend::Encode(ok_packet()[4..].into())).await.map_err(ErrorKind::from)?;
-            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        // The server closes the cursor after the last row is sent.
+        if let Some(data) = packets.last() {
+            if data[4] == ERR_HEADER || get_eof_status(data) & SERVER_STATUS_LAST_ROW_SEND > 0 {
+                req.stmt_cache.close_cursor(stmt_id);
+            }
         }
 
+        req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::from)?;
 
-        let mut client_conn =
-            Self::fsm_trigger(cx, TransEventName::UseEvent, RouteInputTyp::Statement, db).await?;
-        let ep = client_conn.get_endpoint();
+        Ok(())
+    }
 
-        collect_sql_processed_total!(cx, "COM_INIT_DB", ep.as_ref().unwrap());
-        collect_sql_under_processing_inc!(cx, "COM_INIT_DB", ep.as_ref().unwrap());
+    // Serve COM_STMT_FETCH of sharding stmt, rows are fetched from the shards when the cursor
+    // is opened on them, otherwise from the merged rows.
+    async fn shard_fetch_inner(req: &mut ReqContext<T, C>, payload: &[u8]) -> Result<(), Error> {
+        let (stmt_id, num_rows) = match decode_fetch(payload) {
+            Some(x) => x,
+            None => {
+                return Self::send_err_packet(req, 1835, "HY000", String::from("Malformed communication packet."))
+                    .await
+            }
+        };
 
-        Self::init_db_inner(cx, &mut client_conn, payload).await?;
+        if let Some(idx) = req.stmt_cache.get_shard_cursor(stmt_id) {
+            return Self::shard_fetch_cursor(req, stmt_id, num_rows, idx).await;
+        }
 
-        cx.fsm.put_conn(client_conn);
+        let (rows, status) = match req.stmt_cache.fetch_buffered_rows(stmt_id, num_rows) {
+            Some(x) => x,
+            None => {
+                return Self::send_err_packet(
+                    req,
+                    1421,
+                    "HY000",
+                    format!("The statement ({}) has no open cursor.", stmt_id),
+                )
+                .await
+            }
+        };
 
-        collect_sql_under_processing_dec!(cx, "COM_INIT_DB", ep.as_ref().unwrap());
-        collect_sql_processed_duration!(cx, "COM_INIT_DB", ep.as_ref().unwrap(), now.elapsed());
+        let mut buf = BytesMut::with_capacity(128);
+        for row in rows.iter() {
+            let _ = req
+                .framed
+                .codec_mut()
+                .encode(PacketSend::EncodeOffset(row[..].into(), buf.len()), &mut buf);
+        }
 
-        Ok(RespContext { ep, duration: now.elapsed() })
+        let _ = req.framed.codec_mut().encode(
+            PacketSend::EncodeOffset(make_eof_packet_with_status(status)[4..].into(), buf.len()),
+            &mut buf,
+        );
+
+        req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::from)?;
+
+        Ok(())
     }
 
-    async fn query(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
-        let now = Instant::now();
+    // Fetch the rows from the shard at `idx`, the next shard is fetched after the last row
+    // of it is sent until `num_rows` rows are fetched.
+    async fn shard_fetch_cursor(
+        req: &mut ReqContext<T, C>,
+        stmt_id: u32,
+        num_rows: u32,
+        mut idx: usize,
+    ) -> Result<(), Error> {
+        let mut conns = req.stmt_cache.get_all(stmt_id);
+        let shard_length = conns.len();
+        if idx >= shard_length {
+            req.stmt_cache.put_all(stmt_id, conns);
+            req.stmt_cache.close_cursor(stmt_id);
+            return Self::send_err_packet(
+                req,
+                1421,
+                "HY000",
+                format!("The statement ({}) has no open cursor.", stmt_id),
+            )
+            .await;
+        }
+
+        let mut buf = BytesMut::with_capacity(128);
+        let mut remain = num_rows;
+        let res = loop {
+            let (id, conn) = &mut conns[idx];
+            let mut fetch_payload = [0u8; 8];
+            LittleEndian::write_u32(&mut fetch_payload[..4], *id);
+            LittleEndian::write_u32(&mut fetch_payload[4..], remain);
+
+            let mut packets = match conn.send_fetch(&fetch_payload).await {
+                Ok(packets) => packets,
+                Err(e) => break Err(e),
+            };
+
+            // The last packet is EOF or ERR.
+            let end = packets.pop().unwrap();
+            for row in packets.iter() {
+                let _ = req
+                    .framed
+                    .codec_mut()
+                    .encode(PacketSend::EncodeOffset(row[4..].into(), buf.len()), &mut buf);
+            }
+            remain = remain.saturating_sub(packets.len() as u32);
+
+            if end[4] == ERR_HEADER || get_eof_status(&end) & SERVER_STATUS_LAST_ROW_SEND == 0 {
+                break Ok(end);
+            }
+
+            // The last row of the shard is sent, the cursor moves to the next shard.
+            idx += 1;
+            if idx == shard_length || remain == 0 {
+                break Ok(end);
+            }
+        };
+
+        req.stmt_cache.put_all(stmt_id, conns);
+        let end = match res {
+            Ok(end) => end,
+            Err(e) => {
+                req.stmt_cache.close_cursor(stmt_id);
+                return Err(Error::new(ErrorKind::Protocol(e)));
+            }
+        };
+
+        if end[4] == ERR_HEADER {
+            req.stmt_cache.close_cursor(stmt_id);
+            let _ = req.framed.codec_mut().encode(PacketSend::EncodeOffset(end[4..].into(), buf.len()), &mut buf);
+        } else {
+            let mut status = get_eof_status(&end);
+            if idx == shard_length {
+                req.stmt_cache.close_cursor(stmt_id);
+            } else {
+                status &= !SERVER_STATUS_LAST_ROW_SEND;
+                req.stmt_cache.put_cursor(stmt_id, Cursor::Shard(idx));
+            }
+            let _ = req.framed.codec_mut().encode(
+                PacketSend::EncodeOffset(make_eof_packet_with_status(status)[4..].into(), buf.len()),
+                &mut buf,
+            );
+        }
+
+        req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::from)?;
+
+        Ok(())
+    }
+
+    // Multi-statements are rejected when the client doesn't enable them or sharding is on,
+    // returns true when the err packet has been sent.
+    async fn reject_multi_statements(req: &mut ReqContext<T, C>, payload: &[u8]) -> Result<bool, Error> {
+        let sql = std::str::from_utf8(payload).unwrap().trim_matches(char::from(0));
+        let is_multi = match Self::get_ast(req, sql) {
+            Ok(ast) => ast.len() > 1,
+            // LOAD DATA of the table which is not sharded is forwarded as is.
+            Err(_) if req.rewriter.is_some() && !is_load_data_local(sql) => {
+                Self::send_err_packet(
+                    req,
+                    1064,
+                    "42000",
+                    String::from("You have an error in your SQL syntax, the sql can't be parsed in sharding mode"),
+                )
+                .await?;
+                return Ok(true);
+            }
+            Err(_) => false,
+        };
+        if !is_multi {
+            return Ok(false);
+        }
+
+        if !req.framed.codec_mut().get_session().is_multi_statements() {
+            Self::send_err_packet(
+                req,
+                1064,
+                "42000",
+                String::from("You have an error in your SQL syntax, multi-statements are not enabled by the client"),
+            )
+            .await?;
+            return Ok(true);
+        }
+
+        if req.rewriter.is_some() {
+            Self::send_err_packet(
+                req,
+                1235,
+                "42000",
+                String::from("This version of MySQL doesn't yet support 'multi-statements in sharding mode'"),
+            )
+            .await?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    // Reset the session state of the bound conns before they go back to the pool,
+    // the conn is closed when it can not be reset.
+    async fn reset_bound_conns(req: &mut ReqContext<T, C>) {
+        Self::rollback_pending_xa(req).await;
+        let mut conns = req.fsm.reset();
+        conns.extend(req.stmt_cache.drain_conns());
+
+        for mut conn in conns.into_iter() {
+            match conn.send_reset_connection().await {
+                Ok(true) => {}
+                res => {
+                    debug!("reset conn {:?} failed {:?}", conn.get_endpoint(), res);
+                    conn.discard()
+                }
+            }
+        }
+    }
+
+    // The binlog stream is forwarded when replication passthrough is enabled, it is not supported
+    // in sharding. Returns true when the command can be handled.
+    async fn check_replication(req: &mut ReqContext<T, C>, command: &str) -> Result<bool, Error> {
+        if !req.binlog.enabled {
+            Self::send_err_packet(req, 1047, "08S01", format!("command {} not support", command)).await?;
+            return Ok(false);
+        }
+
+        if req.rewriter.is_some() {
+            Self::sharding_command_not_support(req, command).await?;
+            return Ok(false);
+        }
+
+        // The binlog has the events of every database, it is refused for the users limited by `allowed_dbs`.
+        let sess = req.framed.codec_mut().get_session();
+        let user = sess.get_user().to_string();
+        if !req.runtime.is_replication_user(&user) || sess.is_db_restricted() {
+            let msg = String::from(
+                "Access denied; you need (at least one of) the REPLICATION SLAVE privilege(s) for this operation",
+            );
+            Self::send_err_packet(req, 1227, "42000", msg).await?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    // Connect to the current read-write endpoint with a dedicated conn, the replication
+    // session variables of the client are replayed on it.
+    async fn binlog_connect(req: &mut ReqContext<T, C>) -> Result<(ClientConn, Endpoint), Error> {
+        let endpoint = route_primary(&req.name, req.route_strategy.clone())
+            .ok_or_else(|| ErrorKind::Runtime("no available primary for the binlog dump".into()))?;
+        let endpoint = req.backend_user.map_endpoint(endpoint);
+        let factory = build_conn_factory(&endpoint).map_err(ErrorKind::Protocol)?;
+        let mut conn = factory.connect().await.map_err(ErrorKind::Protocol)?;
+
+        for var in req.binlog.get_vars() {
+            conn.send_query_discard_result(var).await.map_err(ErrorKind::Protocol)?;
+        }
+
+        Ok((conn, endpoint))
+    }
+
+    // Send the dump to the current read-write endpoint from the tracked gtid set.
+    async fn binlog_redump(
+        req: &mut ReqContext<T, C>,
+        dump: &BinlogDumpGtid,
+    ) -> Result<(BinlogReader, Endpoint), Error> {
+        let (mut conn, endpoint) = Self::binlog_connect(req).await?;
+
+        if let Some(register) = req.binlog.register.clone() {
+            let data = conn
+                .send_single_response_command(COM_REGISTER_SLAVE, &register)
+                .await
+                .map_err(ErrorKind::Protocol)?;
+            if data[4] != OK_HEADER {
+                return Err(Error::new(ErrorKind::Protocol(ProtocolError::PacketError(data.to_vec()))));
+            }
+        }
+
+        conn.send_no_response_command(COM_BINLOG_DUMP_GTID, &dump.encode()).await.map_err(ErrorKind::Protocol)?;
+        debug!("binlog dump is re-targeted to {:?}", endpoint.addr);
+
+        Ok((BinlogReader::new(conn), endpoint))
+    }
+
+    // The dump is retried until the new primary is elected by the discovery.
+    async fn binlog_retarget(
+        req: &mut ReqContext<T, C>,
+        dump: &BinlogDumpGtid,
+    ) -> Result<(BinlogReader, Endpoint), Error> {
+        let mut retries = 0;
+        loop {
+            match Self::binlog_redump(req, dump).await {
+                Ok(res) => return Ok(res),
+                Err(e) if retries >= BINLOG_RETARGET_RETRIES => return Err(e),
+                Err(e) => {
+                    debug!("binlog dump re-target failed {:?}", e);
+                    retries += 1;
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    // The admin statements are answered by the proxy, they are not routed to backends.
+    async fn admin_query(req: &mut ReqContext<T, C>, stmt: Result<AdminStmt, String>) -> Result<(), Error> {
+        let user = req.framed.codec_mut().get_session().get_user().to_string();
+        if !req.runtime.is_admin(&user) {
+            let msg = format!("Access denied; user '{}' is not in the admin_users of the proxy", user);
+            return Self::send_err_packet(req, 1227, "42000", msg).await;
+        }
+
+        let stmt = match stmt {
+            Ok(stmt) => stmt,
+            Err(msg) => return Self::send_err_packet(req, 1064, "42000", msg).await,
+        };
+
+        let rs = match admin::execute(&req.runtime, stmt) {
+            Ok(rs) => rs,
+            Err(e) => return Self::send_err_packet(req, 1105, "HY000", e.to_string()).await,
+        };
+
+        let mut buf = BytesMut::with_capacity(1024);
+        rs.encode(req.framed.codec_mut(), &mut buf).map_err(ErrorKind::Protocol)?;
+        req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::Protocol)?;
+        Ok(())
+    }
+
+    // The processlist is answered from the sessions of the proxy, only the admin users can see all of them.
+    async fn process_query(req: &mut ReqContext<T, C>, stmt: ProcessStmt) -> Result<(), Error> {
+        let full = match stmt {
+            ProcessStmt::ShowProcessList { full } => full,
+            ProcessStmt::Kill { query, id } => return Self::kill(req, id, query).await,
+        };
+
+        let user = req.framed.codec_mut().get_session().get_user().to_string();
+        let is_admin = req.runtime.is_admin(&user);
+        let sessions =
+            req.runtime.get_sessions().into_iter().filter(|x| is_admin || x.user == user).collect();
+
+        let rs = process::processlist(sessions, full);
+        let mut buf = BytesMut::with_capacity(1024);
+        rs.encode(req.framed.codec_mut(), &mut buf).map_err(ErrorKind::Protocol)?;
+        req.framed.send(PacketSend::Origin(buf[..].into())).await.map_err(ErrorKind::Protocol)?;
+        Ok(())
+    }
+
+    // Kill the backend threads bound to the session, including every shard conn, the session
+    // is closed as well unless only the query is killed.
+    async fn kill(req: &mut ReqContext<T, C>, id: u32, query: bool) -> Result<(), Error> {
+        let target = match req.runtime.sessions.get(id) {
+            Some(target) => target,
+            None => {
+                let msg = format!("Unknown thread id: {}", id);
+                return Self::send_err_packet(req, 1094, "HY000", msg).await;
+            }
+        };
+
+        let user = req.framed.codec_mut().get_session().get_user().to_string();
+        let is_owner = target.user == user;
+        if !is_owner && !req.runtime.is_admin(&user) {
+            let msg = format!("You are not owner of thread {}", id);
+            return Self::send_err_packet(req, 1095, "HY000", msg).await;
+        }
+
+        // The running command of the session itself is this kill.
+        let kill = if query { "KILL QUERY" } else { "KILL" };
+        if target.id != req.session.id {
+            for backend in target.get_backends() {
+                let endpoint = match req.runtime.find_endpoint(&backend.addr) {
+                    Some(endpoint) => endpoint,
+                    None => {
+                        warn!("kill backend thread {:?} error: unknown endpoint", backend);
+                        continue;
+                    }
+                };
+
+                // The backend thread is owned by the mapped user of the owner, others use the node credential.
+                let endpoint =
+                    if is_owner { req.backend_user.map_endpoint(endpoint) } else { endpoint };
+                let sql = format!("{} {}", kill, backend.thread_id);
+                let conn = match build_conn_factory(&endpoint) {
+                    Ok(factory) => factory.connect().await,
+                    Err(e) => Err(e),
+                };
+                match conn {
+                    Ok(mut conn) => {
+                        if let Err(e) = conn.send_query_discard_result(&sql).await {
+                            warn!("kill backend thread {:?} error: {:?}", backend, e);
+                        }
+                        MySQLProxy::close_conns(vec![conn]).await;
+                    }
+                    Err(e) => warn!("kill backend thread {:?} error: {:?}", backend, e),
+                }
+            }
+        }
+
+        if !query {
+            target.kill();
+        }
+
+        req.framed.send(PacketSend::Encode(ok_packet()[4..].into())).await.map_err(ErrorKind::from)?;
+        Ok(())
+    }
+
+    // The query is run in the span of it, the spans of each step are children of it.
+    async fn query_traced(
+        cx: &mut ReqContext<T, C>,
+        payload: &[u8],
+        now: Instant,
+    ) -> Result<RespContext, Error> {
+        cx.pending_vars.clear();
+
+        if Self::reject_multi_statements(cx, payload).await? {
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
 
         if cx.rewriter.is_some() {
             Self::shard_query_inner(cx, payload).await?;
@@ -622,10 +1578,14 @@ where
         let mut client_conn = Self::query_inner_get_conn(cx, payload).await?;
 
         let ep = client_conn.get_endpoint();
+        let addr = ep.as_deref().unwrap_or_default();
+        Span::current().record("endpoint", &addr);
         collect_sql_processed_total!(cx, "COM_QUERY", ep.as_ref().unwrap());
         collect_sql_under_processing_inc!(cx, "COM_QUERY", ep.as_ref().unwrap());
 
-        let _ = Self::query_inner(cx, &mut client_conn, payload).await?;
+        let _ = Self::query_inner(cx, &mut client_conn, payload)
+            .instrument(telemetry::execute_span(addr, 1))
+            .await?;
 
         cx.fsm.put_conn(client_conn);
 
@@ -635,9 +1595,191 @@ where
         Ok(RespContext { ep, duration: now.elapsed() })
     }
 
+    async fn send_err_packet(
+        req: &mut ReqContext<T, C>,
+        code: u16,
+        state: &str,
+        msg: String,
+    ) -> Result<(), Error> {
+        let err_info = make_err_packet(MySQLError::new(code, state.as_bytes().to_vec(), msg));
+        req.framed.send(PacketSend::Encode(err_info[4..].into())).await.map_err(ErrorKind::from)?;
+        Ok(())
+    }
+
+    // The stmt commands start with the stmt id, ERR is sent and None is returned when the payload is too short.
+    async fn read_stmt_id(req: &mut ReqContext<T, C>, payload: &[u8]) -> Result<Option<u32>, Error> {
+        if payload.len() < 4 {
+            Self::send_err_packet(req, 1835, "HY000", String::from("Malformed communication packet.")).await?;
+            return Ok(None);
+        }
+        Ok(Some(LittleEndian::read_u32(payload)))
+    }
+
+    // Send ERR and returns false when the user is not allowed to use the database.
+    async fn check_db_allowed(req: &mut ReqContext<T, C>, db: &str) -> Result<bool, Error> {
+        let sess = req.framed.codec_mut().get_session();
+        if sess.is_db_allowed(db) {
+            return Ok(true);
+        }
+
+        let msg = format!("Access denied for user {:?} to database {:?}", sess.get_user(), db);
+        Self::send_err_packet(req, 1044, "42000", msg).await?;
+        Ok(false)
+    }
+
+    // Send ERR and returns false when the stmt refers to a database which the user is not allowed to use,
+    // the stmt which can't be checked is rejected when the user has `allowed_dbs`.
+    async fn check_stmt_dbs(req: &mut ReqContext<T, C>, sql: &str) -> Result<bool, Error> {
+        if !req.framed.codec_mut().get_session().is_db_restricted() {
+            return Ok(true);
+        }
+
+        let dbs = match Self::stmt_dbs(req, sql) {
+            Some(dbs) => dbs,
+            None => {
+                let user = req.framed.codec_mut().get_session().get_user().to_string();
+                let msg = format!("Access denied for user {:?}, the statement can't be checked against the allowed databases", user);
+                Self::send_err_packet(req, 1044, "42000", msg).await?;
+                return Ok(false);
+            }
+        };
+
+        for db in dbs.iter() {
+            if !Self::check_db_allowed(req, db).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // The databases referred by the sql, None is returned when the sql can't be parsed.
+    fn stmt_dbs(req: &mut ReqContext<T, C>, sql: &str) -> Option<Vec<String>> {
+        let stmts = Self::get_ast(req, sql).ok()?;
+        let mut dbs = vec![];
+        for stmt in stmts.iter() {
+            match stmt {
+                SqlStmt::Prepare(stmt) => match &stmt.preparable_stmt {
+                    Value::Text { value, .. } if value.len() >= 2 => {
+                        dbs.extend(Self::stmt_dbs(req, &value[1..value.len() - 1])?)
+                    }
+                    // The stmt prepared from a user variable is unknown.
+                    _ => return None,
+                },
+                stmt => dbs.extend(stmt_schemas(stmt)?),
+            }
+        }
+        Some(dbs)
+    }
+
+    async fn sharding_command_not_support(
+        cx: &mut ReqContext<T, C>,
+        command: &str,
+    ) -> Result<(), Error> {
+        let err_info = make_err_packet(MySQLError::new(
+            1047,
+            "08S01".as_bytes().to_vec(),
+            format!("command {:?} not support in sharding", command),
+        ));
+        cx.framed.send(PacketSend::Encode(err_info[4..].into())).await.map_err(ErrorKind::from)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a, T, C> MySQLService<T, C> for PisaMySQLService<T, C>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    C: Decoder<Item = BytesMut>
+        + Encoder<PacketSend<Box<[u8]>>, Error = ProtocolError>
+        + Send
+        + CommonPacket,
+{
+    async fn init_db(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+        let db = std::str::from_utf8(payload).unwrap().trim_matches(char::from(0));
+
+        if !Self::check_db_allowed(cx, db).await? {
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        cx.framed.codec_mut().get_session().set_db(db.to_string());
+
+        if cx.rewriter.is_some() {
+            cx.framed.send(PacketSend::Encode(ok_packet()[4..].into())).await.map_err(ErrorKind::from)?;
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+
+        let mut client_conn =
+            Self::fsm_trigger(cx, TransEventName::UseEvent, RouteInputTyp::Statement, db).await?;
+        let ep = client_conn.get_endpoint();
+
+        collect_sql_processed_total!(cx, "COM_INIT_DB", ep.as_ref().unwrap());
+        collect_sql_under_processing_inc!(cx, "COM_INIT_DB", ep.as_ref().unwrap());
+
+        Self::init_db_inner(cx, &mut client_conn, payload).await?;
+
+        cx.fsm.put_conn(client_conn);
+
+        collect_sql_under_processing_dec!(cx, "COM_INIT_DB", ep.as_ref().unwrap());
+        collect_sql_processed_duration!(cx, "COM_INIT_DB", ep.as_ref().unwrap(), now.elapsed());
+
+        Ok(RespContext { ep, duration: now.elapsed() })
+    }
+
+    async fn query(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        let sql = std::str::from_utf8(payload).unwrap_or_default();
+        if let Some(stmt) = parse_admin_stmt(sql) {
+            Self::admin_query(cx, stmt).await?;
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        if let Some(stmt) = parse_process_stmt(sql) {
+            Self::process_query(cx, stmt).await?;
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        // `USE db` is forwarded to the backend, the database is checked the same as `COM_INIT_DB`.
+        let is_allowed = match use_stmt_db(sql) {
+            Some(db) => Self::check_db_allowed(cx, &db).await?,
+            None => Self::check_stmt_dbs(cx, sql.trim_matches(char::from(0))).await?,
+        };
+        if !is_allowed {
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        // `LOAD DATA LOCAL INFILE` is rejected before it is forwarded when the file can't be relayed.
+        cx.local_infile_file = load_data_local_file(sql.trim_matches(char::from(0)));
+        if cx.local_infile_file.is_some() && !(cx.local_infile && cx.framed.codec_mut().get_session().is_local_files()) {
+            cx.local_infile_file = None;
+            Self::send_err_packet(
+                cx,
+                3948,
+                "42000",
+                String::from("Loading local data is disabled; this must be enabled on both the client and server sides"),
+            )
+            .await?;
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        cx.binlog.record_query(sql);
+
+        let span = telemetry::query_span(&cx.runtime.name, sql);
+        let res = Self::query_traced(cx, payload, now).instrument(span).await;
+        cx.local_infile_file = None;
+        res
+    }
+
     async fn prepare(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
         let now = Instant::now();
 
+        let sql = std::str::from_utf8(payload).unwrap_or_default().trim_matches(char::from(0));
+        if !Self::check_stmt_dbs(cx, sql).await? {
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
         if cx.rewriter.is_some() {
             cx.fsm.trigger(TransEventName::PrepareEvent);
             let res = Self::prepare_shard_inner(cx, payload).await;
@@ -687,6 +1829,15 @@ where
     async fn execute(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
         let now = Instant::now();
 
+        let stmt_id = match Self::read_stmt_id(cx, payload).await? {
+            Some(stmt_id) => stmt_id,
+            None => return Ok(RespContext { ep: None, duration: now.elapsed() }),
+        };
+        if let Some(err) = cx.stmt_cache.take_long_data_error(stmt_id) {
+            Self::send_err_packet(cx, 1105, "HY000", format!("send long data error: {}", err)).await?;
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
         if cx.rewriter.is_some() {
             Self::execute_shard_inner(cx, payload).await?;
             return Ok(RespContext {
@@ -713,15 +1864,30 @@ where
 
     async fn stmt_close(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
         let now = Instant::now();
+        // COM_STMT_CLOSE has no response, the malformed one is ignored.
+        if payload.len() < 4 {
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
         let stmt_id = LittleEndian::read_u32(payload);
+
+        // Close the cursor on the pinned conn, COM_STMT_CLOSE has no response.
+        if cx.rewriter.is_none() && cx.stmt_cache.is_cursor_open(stmt_id) {
+            if let Some(mut client_conn) = cx.fsm.client_conn.take() {
+                let res = client_conn.send_no_response_command(COM_STMT_CLOSE, payload).await;
+                cx.fsm.put_conn(client_conn);
+                res.map_err(ErrorKind::Protocol)?;
+            }
+        }
+
         cx.stmt_cache.remove(stmt_id);
         debug!("stmt close {:?}", stmt_id);
 
         Ok(RespContext { ep: None, duration: now.elapsed() })
     }
 
-    async fn quit(_cx: &mut ReqContext<T, C>) -> Result<RespContext, Error> {
+    async fn quit(cx: &mut ReqContext<T, C>) -> Result<RespContext, Error> {
         let now = Instant::now();
+        Self::rollback_pending_xa(cx).await;
         Ok(RespContext { ep: None, duration: now.elapsed() })
     }
 
@@ -750,4 +1916,412 @@ where
 
         Ok(RespContext { ep, duration: now.elapsed() })
     }
+
+    async fn stmt_reset(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+        let stmt_id = match Self::read_stmt_id(cx, payload).await? {
+            Some(stmt_id) => stmt_id,
+            None => return Ok(RespContext { ep: None, duration: now.elapsed() }),
+        };
+        cx.stmt_cache.close_cursor(stmt_id);
+        let _ = cx.stmt_cache.take_long_data_error(stmt_id);
+
+        if cx.rewriter.is_some() {
+            let conns = cx.stmt_cache.get_all(stmt_id);
+            if conns.is_empty() {
+                Self::send_err_packet(
+                    cx,
+                    1243,
+                    "HY000",
+                    format!("Unknown prepared statement handler ({}) given to mysqld_stmt_reset", stmt_id),
+                )
+                .await?;
+                return Ok(RespContext { ep: None, duration: now.elapsed() });
+            }
+
+            // Reset the stmt of every shard, the first error is returned to client.
+            let mut err_info = None;
+            let mut reset_conns = Vec::with_capacity(conns.len());
+            for (id, mut conn) in conns.into_iter() {
+                let res = conn.send_single_response_command(COM_STMT_RESET, &id.to_le_bytes()).await;
+                reset_conns.push((id, conn));
+
+                let data = res.map_err(ErrorKind::Protocol)?;
+                if data[4] == ERR_HEADER && err_info.is_none() {
+                    err_info = Some(data);
+                }
+            }
+            cx.stmt_cache.put_all(stmt_id, reset_conns);
+
+            let ok_info = ok_packet();
+            let data = match &err_info {
+                Some(data) => &data[4..],
+                None => &ok_info[4..],
+            };
+            cx.framed.send(PacketSend::Encode(data.into())).await.map_err(ErrorKind::from)?;
+
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        let mut client_conn = match cx.fsm.client_conn.take() {
+            Some(client_conn) => client_conn,
+            None => {
+                Self::send_err_packet(
+                    cx,
+                    1243,
+                    "HY000",
+                    format!("Unknown prepared statement handler ({}) given to mysqld_stmt_reset", stmt_id),
+                )
+                .await?;
+                return Ok(RespContext { ep: None, duration: now.elapsed() });
+            }
+        };
+        let ep = client_conn.get_endpoint();
+
+        let res = client_conn.send_single_response_command(COM_STMT_RESET, payload).await;
+        cx.fsm.put_conn(client_conn);
+
+        let data = res.map_err(ErrorKind::Protocol)?;
+        cx.framed.send(PacketSend::Encode(data[4..].into())).await.map_err(ErrorKind::from)?;
+
+        Ok(RespContext { ep, duration: now.elapsed() })
+    }
+
+    async fn stmt_send_long_data(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        // There is no response, so the error is reported by the following COM_STMT_EXECUTE.
+        let stmt_id = match decode_send_long_data(payload) {
+            Some((stmt_id, param_id, data)) => {
+                debug!("stmt send long data {:?} param {:?} length {:?}", stmt_id, param_id, data.len());
+                stmt_id
+            }
+            None => {
+                error!("malformed COM_STMT_SEND_LONG_DATA packet");
+                return Ok(RespContext { ep: None, duration: now.elapsed() });
+            }
+        };
+
+        if cx.rewriter.is_some() {
+            if let Err(e) = Executor::shard_send_long_data_executor(cx, stmt_id, payload).await {
+                error!("stmt {} send long data error {:?}", stmt_id, e);
+                cx.stmt_cache.set_long_data_error(stmt_id, e.to_string());
+            }
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        let mut client_conn = match cx.fsm.client_conn.take() {
+            Some(client_conn) => client_conn,
+            None => return Ok(RespContext { ep: None, duration: now.elapsed() }),
+        };
+        let ep = client_conn.get_endpoint();
+
+        let res = client_conn.send_no_response_command(COM_STMT_SEND_LONG_DATA, payload).await;
+        cx.fsm.put_conn(client_conn);
+        if let Err(e) = res {
+            error!("stmt {} send long data error {:?}", stmt_id, e);
+            cx.stmt_cache.set_long_data_error(stmt_id, e.to_string());
+        }
+
+        Ok(RespContext { ep, duration: now.elapsed() })
+    }
+
+    async fn stmt_fetch(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+        let stmt_id = match Self::read_stmt_id(cx, payload).await? {
+            Some(stmt_id) => stmt_id,
+            None => return Ok(RespContext { ep: None, duration: now.elapsed() }),
+        };
+
+        if cx.rewriter.is_some() {
+            Self::shard_fetch_inner(cx, payload).await?;
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        let mut client_conn = match cx.fsm.client_conn.take() {
+            Some(client_conn) => client_conn,
+            None => {
+                Self::send_err_packet(
+                    cx,
+                    1243,
+                    "HY000",
+                    format!("Unknown prepared statement handler ({}) given to mysqld_stmt_fetch", stmt_id),
+                )
+                .await?;
+                return Ok(RespContext { ep: None, duration: now.elapsed() });
+            }
+        };
+        let ep = client_conn.get_endpoint();
+
+        collect_sql_processed_total!(cx, "COM_STMT_FETCH", ep.as_ref().unwrap());
+        collect_sql_under_processing_inc!(cx, "COM_STMT_FETCH", ep.as_ref().unwrap());
+
+        let res = Self::stmt_fetch_inner(cx, &mut client_conn, stmt_id, payload).await;
+        cx.fsm.put_conn(client_conn);
+
+        collect_sql_under_processing_dec!(cx, "COM_STMT_FETCH", ep.as_ref().unwrap());
+        collect_sql_processed_duration!(cx, "COM_STMT_FETCH", ep.as_ref().unwrap(), now.elapsed());
+
+        res?;
+        Ok(RespContext { ep, duration: now.elapsed() })
+    }
+
+    async fn change_user(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        // The client answers the auth switch request with the response scrambled by the fresh salt.
+        let res = match cx.framed.codec_mut().get_session().change_user(payload) {
+            Ok(switch_request) => {
+                cx.framed.send(PacketSend::Encode(switch_request[4..].into())).await.map_err(ErrorKind::from)?;
+                let auth_data = match cx.framed.next().await {
+                    Some(Ok(data)) => Some(data),
+                    _ => None,
+                };
+                match auth_data {
+                    Some(auth_data) => cx.framed.codec_mut().get_session().change_user_auth(&auth_data),
+                    None => {
+                        return Err(Error::new(ErrorKind::Protocol(ProtocolError::Io(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "read change user auth response from client",
+                        )))))
+                    }
+                }
+            }
+            Err(err) => Err(err),
+        };
+
+        let auth_more_data = match res {
+            Ok(data) => data,
+            Err(ProtocolError::AuthFailed(err_info)) => {
+                cx.framed
+                    .send(PacketSend::Encode(err_info[4..].into()))
+                    .await
+                    .map_err(ErrorKind::from)?;
+                return Ok(RespContext { ep: None, duration: now.elapsed() });
+            }
+            Err(err) => return Err(Error::new(ErrorKind::Protocol(err))),
+        };
+
+        // The backend conns are bound to the previous user, release them.
+        Self::reset_bound_conns(cx).await;
+        cx.rewrite_outputs.clear();
+
+        let sess = cx.framed.codec_mut().get_session();
+        let db = sess.get_db();
+        cx.backend_user = cx.backend_users.get(sess.get_user());
+        cx.fsm.set_db(db);
+
+        if let Some(data) = auth_more_data {
+            cx.framed.send(PacketSend::Encode(data[4..].into())).await.map_err(ErrorKind::from)?;
+        }
+        cx.framed.send(PacketSend::Encode(ok_packet()[4..].into())).await.map_err(ErrorKind::from)?;
+
+        Ok(RespContext { ep: None, duration: now.elapsed() })
+    }
+
+    async fn reset_connection(cx: &mut ReqContext<T, C>) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        // Prepared statements, user variables and transaction are discarded, the db is kept.
+        Self::reset_bound_conns(cx).await;
+        cx.rewrite_outputs.clear();
+        cx.framed.codec_mut().get_session().reset_session();
+
+        cx.framed.send(PacketSend::Encode(ok_packet()[4..].into())).await.map_err(ErrorKind::from)?;
+
+        Ok(RespContext { ep: None, duration: now.elapsed() })
+    }
+
+    async fn statistics(cx: &mut ReqContext<T, C>) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        if cx.rewriter.is_some() {
+            Self::sharding_command_not_support(cx, "COM_STATISTICS").await?;
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        // The conn of the session is reused if any, the command doesn't change the transaction state.
+        let attrs = build_conn_attrs(cx.framed.codec_mut().get_session());
+        let mut client_conn = if cx.fsm.client_conn.is_some() {
+            cx.fsm.get_conn(&attrs).await?
+        } else {
+            match route_command(&cx.name, cx.route_strategy.clone()) {
+                Some(endpoint) => {
+                    let endpoint = cx.backend_user.map_endpoint(endpoint);
+                    cx.fsm.get_conn_with_endpoint(endpoint, &attrs).await?
+                }
+                None => {
+                    Self::send_err_packet(cx, 1105, "HY000", String::from("No available backend")).await?;
+                    return Ok(RespContext { ep: None, duration: now.elapsed() });
+                }
+            }
+        };
+        let ep = client_conn.get_endpoint();
+
+        let res = client_conn.send_single_response_command(COM_STATISTICS, &[]).await;
+        cx.fsm.put_conn(client_conn);
+
+        // The response is a string packet of the server status.
+        let data = res.map_err(ErrorKind::Protocol)?;
+        cx.framed.send(PacketSend::Encode(data[4..].into())).await.map_err(ErrorKind::from)?;
+
+        Ok(RespContext { ep, duration: now.elapsed() })
+    }
+
+    async fn set_option(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        // 0: MYSQL_OPTION_MULTI_STATEMENTS_ON, 1: MYSQL_OPTION_MULTI_STATEMENTS_OFF
+        let option = if payload.len() >= 2 { Some(LittleEndian::read_u16(payload)) } else { None };
+        match option {
+            Some(x @ (0 | 1)) => {
+                cx.framed.codec_mut().get_session().set_multi_statements(x == 0);
+                cx.framed
+                    .send(PacketSend::Encode(make_eof_packet()[4..].into()))
+                    .await
+                    .map_err(ErrorKind::from)?;
+            }
+            _ => {
+                Self::send_err_packet(cx, 1047, "08S01", String::from("Unknown command")).await?;
+            }
+        }
+
+        Ok(RespContext { ep: None, duration: now.elapsed() })
+    }
+
+    async fn process_kill(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        let id = if payload.len() >= 4 { LittleEndian::read_u32(payload) } else { 0 };
+        Self::kill(cx, id, false).await?;
+
+        Ok(RespContext { ep: None, duration: now.elapsed() })
+    }
+
+    async fn register_slave(cx: &mut ReqContext<T, C>, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        if !Self::check_replication(cx, "register_slave").await? {
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        let (mut conn, endpoint) = match Self::binlog_connect(cx).await {
+            Ok(res) => res,
+            Err(e) => {
+                Self::send_err_packet(cx, 2013, "HY000", format!("Lost connection to MySQL server during query: {}", e)).await?;
+                return Ok(RespContext { ep: None, duration: now.elapsed() });
+            }
+        };
+        let ep = Some(endpoint.addr.clone());
+
+        let data = conn.send_single_response_command(COM_REGISTER_SLAVE, payload).await.map_err(ErrorKind::Protocol)?;
+        cx.framed.send(PacketSend::Encode(data[4..].into())).await.map_err(ErrorKind::from)?;
+
+        // The registered conn is pinned for the following dump.
+        if data[4] == OK_HEADER {
+            cx.binlog.register = Some(payload.to_vec());
+            cx.binlog.conn = Some((conn, endpoint));
+        }
+
+        Ok(RespContext { ep, duration: now.elapsed() })
+    }
+
+    async fn binlog_dump(cx: &mut ReqContext<T, C>, com: u8, payload: &[u8]) -> Result<RespContext, Error> {
+        let now = Instant::now();
+
+        let command = if com == COM_BINLOG_DUMP_GTID { "binlog_dump_gtid" } else { "binlog_dump" };
+        if !Self::check_replication(cx, command).await? {
+            return Ok(RespContext { ep: None, duration: now.elapsed() });
+        }
+
+        // Only the dump in GTID mode can be re-targeted to the new primary.
+        let mut dump = if com == COM_BINLOG_DUMP_GTID { BinlogDumpGtid::decode(payload) } else { None };
+
+        let (mut conn, mut endpoint) = match cx.binlog.conn.take() {
+            Some(res) => res,
+            None => match Self::binlog_connect(cx).await {
+                Ok(res) => res,
+                Err(e) => {
+                    Self::send_err_packet(cx, 2013, "HY000", format!("Lost connection to MySQL server during query: {}", e)).await?;
+                    return Ok(RespContext { ep: None, duration: now.elapsed() });
+                }
+            },
+        };
+        let ep = Some(endpoint.addr.clone());
+
+        conn.send_no_response_command(com, payload).await.map_err(ErrorKind::Protocol)?;
+        let mut reader = BinlogReader::new(conn);
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            tokio::select! {
+                packet = reader.next() => match packet {
+                    Some(Ok(packet)) => {
+                        if let Some(dump) = dump.as_mut() {
+                            dump.track(&packet);
+                        }
+
+                        // The dump ends with EOF when BINLOG_DUMP_NON_BLOCK is set, or ERR.
+                        let is_end = packet.is_empty()
+                            || packet[0] == ERR_HEADER
+                            || (packet[0] == EOF_HEADER && packet.len() < 9);
+                        cx.framed.send(PacketSend::Encode(packet[..].into())).await.map_err(ErrorKind::from)?;
+                        if is_end {
+                            break;
+                        }
+                    }
+
+                    res => {
+                        debug!("binlog stream of {:?} is lost {:?}", endpoint.addr, res);
+                        let res = match dump.as_ref() {
+                            Some(dump) => Self::binlog_retarget(cx, dump).await.ok(),
+                            None => None,
+                        };
+
+                        match res {
+                            Some((new_reader, new_endpoint)) => {
+                                reader = new_reader;
+                                endpoint = new_endpoint;
+                            }
+                            None => {
+                                Self::send_err_packet(cx, 2013, "HY000", String::from("Lost connection to MySQL server during query")).await?;
+                                break;
+                            }
+                        }
+                    }
+                },
+
+                // Re-target to the new primary when it is changed by the discovery, the probe is
+                // not counted as a route decision. The dump is not re-targeted inside a transaction,
+                // the new primary would skip the events of it.
+                _ = interval.tick(), if dump.as_ref().map_or(false, |x| !x.in_transaction()) => {
+                    let res = cx.route_strategy.lock().dispatch(&RouteInput::Transaction(""));
+                    let current = match res {
+                        Ok((Some(current), _)) => current,
+                        res => {
+                            error!("binlog dump route primary error {:?}", res);
+                            Self::send_err_packet(cx, 2013, "HY000", String::from("Lost connection to MySQL server during query")).await?;
+                            break;
+                        }
+                    };
+
+                    if current.addr != endpoint.addr {
+                        if let Ok((new_reader, new_endpoint)) = Self::binlog_redump(cx, dump.as_ref().unwrap()).await {
+                            reader = new_reader;
+                            endpoint = new_endpoint;
+                        }
+                    }
+                },
+
+                // The client doesn't send command while dumping, the dump ends when it is closed.
+                is_closed = cx.framed.next().map(|x| !matches!(x, Some(Ok(_)))) => {
+                    if is_closed {
+                        break;
+                    }
+                },
+            }
+        }
+
+        Ok(RespContext { ep, duration: now.elapsed() })
+    }
 }
@@ -0,0 +1,441 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use chrono::{SecondsFormat, Utc};
+use proxy::proxy::ProxySlowLog;
+use serde::Serialize;
+use tracing::{error, warn};
+
+// The records are dropped when the writer falls behind.
+const SLOW_LOG_QUEUE_SIZE: usize = 4096;
+
+/// A statement which takes longer than the threshold.
+pub struct SlowQuery<'a> {
+    pub proxy: &'a str,
+    pub user: &'a str,
+    pub db: Option<&'a str>,
+    // The backend endpoints, e.g. `127.0.0.1:3306,127.0.0.1:3307` for the shards
+    pub endpoints: &'a str,
+    pub sql: &'a str,
+    pub rows: u64,
+    pub elapsed: Duration,
+}
+
+#[derive(Serialize)]
+struct SlowLogRecord<'a> {
+    time: String,
+    proxy: &'a str,
+    user: &'a str,
+    db: Option<&'a str>,
+    endpoints: &'a str,
+    rows: u64,
+    elapsed_ms: f64,
+    fingerprint: String,
+    sql: String,
+}
+
+/// The slow log of a proxy, the records are written as json lines by a dedicated thread.
+#[derive(Clone)]
+pub struct SlowLog {
+    threshold: Duration,
+    redact: bool,
+    tx: SyncSender<String>,
+}
+
+impl SlowLog {
+    pub fn new(config: &ProxySlowLog) -> io::Result<Self> {
+        let mut sink = match config.output.as_str() {
+            "stdout" => Sink::Stdout,
+            path => Sink::File(RotatingFile::open(
+                PathBuf::from(path),
+                config.max_size * 1024 * 1024,
+                config.max_files,
+            )?),
+        };
+
+        // The writer exits when all senders are dropped.
+        let (tx, rx) = mpsc::sync_channel::<String>(SLOW_LOG_QUEUE_SIZE);
+        thread::Builder::new().name("pisa-slow-log".to_string()).spawn(move || {
+            for line in rx {
+                if let Err(e) = sink.write_line(&line) {
+                    error!("write slow log error {:?}", e);
+                }
+            }
+        })?;
+
+        Ok(SlowLog {
+            threshold: Duration::from_millis(config.threshold),
+            redact: config.redact,
+            tx,
+        })
+    }
+
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        elapsed >= self.threshold
+    }
+
+    pub fn log(&self, query: SlowQuery) {
+        let record = SlowLogRecord {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            proxy: query.proxy,
+            user: query.user,
+            db: query.db,
+            endpoints: query.endpoints,
+            rows: query.rows,
+            elapsed_ms: query.elapsed.as_secs_f64() * 1000.0,
+            fingerprint: fingerprint(query.sql),
+            sql: if self.redact { redact(query.sql) } else { query.sql.to_string() },
+        };
+
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => return error!("encode slow log error {:?}", e),
+        };
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(line) {
+            warn!("slow log queue is full, the record of {:?} is dropped", record.fingerprint);
+        }
+    }
+}
+
+enum Sink {
+    Stdout,
+    File(RotatingFile),
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::Stdout => writeln!(io::stdout().lock(), "{}", line),
+            Sink::File(file) => file.write_line(line),
+        }
+    }
+}
+
+// The file is renamed to `<path>.1` when it exceeds `max_size`, the older ones are shifted and
+// the ones beyond `max_files` are removed.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, max_size, max_files, file, size })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), n))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files > 0 {
+            let _ = fs::remove_file(self.rotated(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// Normalize the sql to group the same statements, the literals are replaced with `?`, the
+/// comments are removed, the spaces are collapsed and it is lower cased, the value lists are
+/// collapsed as well, e.g. `SELECT * FROM t WHERE id IN (1, 2)` is
+/// `select * from t where id in (?+)`.
+pub fn fingerprint(sql: &str) -> String {
+    let sql = scan(sql, true);
+    collapse_lists(sql.trim().trim_end_matches(';').trim_end())
+}
+
+/// Replace the literals of sql with `?`, the comments are removed as they may contain the
+/// literals as well.
+pub fn redact(sql: &str) -> String {
+    scan(sql, false)
+}
+
+fn scan(sql: &str, fingerprint: bool) -> String {
+    let chars = sql.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(sql.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\'' | '"' => {
+                i = skip_quoted(&chars, i);
+                out.push('?');
+            }
+            '`' => {
+                let end = skip_quoted(&chars, i);
+                push_str(&mut out, &chars[i..end], fingerprint);
+                i = end;
+            }
+            '/' if next == Some('*') => {
+                i = find(&chars, i + 2, &['*', '/']).map_or(chars.len(), |x| x + 2);
+                push_space(&mut out);
+            }
+            '-' if next == Some('-')
+                && chars.get(i + 2).filter(|x| !x.is_whitespace()).is_none() =>
+            {
+                i = skip_line_comment(&chars, i, &mut out);
+            }
+            '#' => {
+                i = skip_line_comment(&chars, i, &mut out);
+            }
+            c if c.is_ascii_digit() => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    // The exponent may be signed, e.g. `1e-3`.
+                    if matches!(chars[i], 'e' | 'E') && matches!(chars.get(i + 1), Some('+' | '-'))
+                    {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                out.push('?');
+            }
+            c if c.is_whitespace() => {
+                if fingerprint {
+                    push_space(&mut out);
+                } else {
+                    out.push(c);
+                }
+                i += 1;
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '$' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+                {
+                    i += 1;
+                }
+                // The prefixed strings, e.g. `X'01'`, `N'abc'` and `_utf8mb4'abc'`.
+                let word = &chars[start..i];
+                let is_prefix = matches!(word, ['x' | 'X' | 'b' | 'B' | 'n' | 'N'])
+                    || word.first() == Some(&'_');
+                if is_prefix && chars.get(i) == Some(&'\'') {
+                    i = skip_quoted(&chars, i);
+                    out.push('?');
+                } else {
+                    push_str(&mut out, word, fingerprint);
+                }
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+// Returns the index after the closing quote, the quote is escaped by backslash or doubled.
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == '\\' && quote != '`' {
+            i += 2;
+        } else if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+fn skip_line_comment(chars: &[char], start: usize, out: &mut String) -> usize {
+    push_space(out);
+    find(chars, start, &['\n']).unwrap_or(chars.len())
+}
+
+fn find(chars: &[char], start: usize, pattern: &[char]) -> Option<usize> {
+    (start..chars.len()).find(|&i| chars[i..].starts_with(pattern))
+}
+
+fn push_str(out: &mut String, chars: &[char], lowercase: bool) {
+    if lowercase {
+        out.extend(chars.iter().flat_map(|x| x.to_lowercase()));
+    } else {
+        out.extend(chars);
+    }
+}
+
+fn push_space(out: &mut String) {
+    if !out.is_empty() && !out.ends_with(' ') {
+        out.push(' ');
+    }
+}
+
+// Collapse `(?, ?)` to `(?+)`, the rows of `VALUES (?+), (?+)` are collapsed to one.
+fn collapse_lists(sql: &str) -> String {
+    let bytes = sql.as_bytes();
+    let mut out = String::with_capacity(sql.len());
+    let mut last = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let end = match bytes[i] {
+            b'(' => match_list(bytes, i),
+            _ => None,
+        };
+        match end {
+            Some(end) => {
+                out.push_str(&sql[last..i]);
+                let prev = out.trim_end().strip_suffix(',').map(|x| x.trim_end());
+                match prev.filter(|x| x.ends_with("(?+)")).map(|x| x.len()) {
+                    Some(len) => out.truncate(len),
+                    None => out.push_str("(?+)"),
+                }
+                last = end;
+                i = end;
+            }
+            None => i += 1,
+        }
+    }
+
+    out.push_str(&sql[last..]);
+    out
+}
+
+// Returns the index after `)` when the list only contains `?`.
+fn match_list(bytes: &[u8], start: usize) -> Option<usize> {
+    let skip_space = |mut i: usize| {
+        while bytes.get(i) == Some(&b' ') {
+            i += 1;
+        }
+        i
+    };
+
+    let mut i = skip_space(start + 1);
+    if bytes.get(i) != Some(&b'?') {
+        return None;
+    }
+    i += 1;
+    loop {
+        i = skip_space(i);
+        match bytes.get(i) {
+            Some(b')') => return Some(i + 1),
+            Some(b',') => {
+                i = skip_space(i + 1);
+                if bytes.get(i) != Some(&b'?') {
+                    return None;
+                }
+                i += 1;
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let cases = [
+            ("SELECT * FROM t WHERE id = 1", "select * from t where id = ?"),
+            (
+                "select  *\n from `T1`  where name='a''b' and x = \"c\\\"d\";",
+                "select * from `t1` where name=? and x = ?",
+            ),
+            (
+                "SELECT * FROM t WHERE id IN (1, 2,3) AND v > -1.5e-3",
+                "select * from t where id in (?+) and v > -?",
+            ),
+            ("INSERT INTO t VALUES (1, 'a'), (2, 'b') , (3, 'c')", "insert into t values (?+)"),
+            (
+                "/* hint */ SELECT c1 FROM t2 -- tail\nWHERE c2 = X'0A'",
+                "select c1 from t2 where c2 = ?",
+            ),
+            ("SELECT _utf8mb4'abc', N'x', 0x1F # comment", "select ?, ?, ?"),
+            ("SELECT count(c) FROM t", "select count(c) from t"),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(fingerprint(sql), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("SELECT * FROM T1 WHERE name = 'secret' AND id IN (1, 2) /* c */"),
+            "SELECT * FROM T1 WHERE name = ? AND id IN (?, ?) "
+        );
+        assert_eq!(
+            redact("SELECT /* 'secret' */ 1 -- token\nFROM t # 42"),
+            "SELECT  ? \nFROM t "
+        );
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("pisa-slow-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("slow.log");
+
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in ["aaaaaaaa", "bbbbbbbb", "cccccccc", "dddddddd"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |p: &PathBuf| fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "dddddddd\n");
+        assert_eq!(read(&file.rotated(1)), "cccccccc\n");
+        assert_eq!(read(&file.rotated(2)), "bbbbbbbb\n");
+        assert!(!file.rotated(3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
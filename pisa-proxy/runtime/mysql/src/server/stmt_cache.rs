@@ -12,9 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::VecDeque;
+
+use bytes::BytesMut;
 use conn_pool::PoolConn;
 use indexmap::IndexMap;
-use mysql_protocol::client::conn::ClientConn;
+use mysql_protocol::{client::conn::ClientConn, mysql_const::*};
+
+use crate::registry::bind_backend;
 
 #[derive(Debug)]
 struct Entry {
@@ -22,11 +27,28 @@ struct Entry {
     conn: PoolConn<ClientConn>,
 }
 
+#[derive(Debug)]
+pub enum Cursor {
+    // The cursor is opened on the backend conn, rows are fetched from it.
+    Backend,
+    // The cursor is opened on every shard conn, rows are fetched from the shards in order,
+    // the value is the index of the shard being fetched.
+    Shard(usize),
+    // The merged rows of sharding stmt and the status of the EOF packet, they are fetched from the proxy.
+    Buffered(VecDeque<BytesMut>, u16),
+}
+
 #[derive(Debug)]
 pub struct StmtCache {
     // key is generated id by pisa, value is returnd stmt id from client
     cache: IndexMap<u32, Vec<Entry>>,
-    sharding_column_cache: IndexMap<u32, Option<String>>
+    sharding_column_cache: IndexMap<u32, Option<String>>,
+    // key is the stmt id returned to client
+    cursors: IndexMap<u32, Cursor>,
+    // COM_STMT_SEND_LONG_DATA has no response, its error is returned by the next COM_STMT_EXECUTE.
+    long_data_errors: IndexMap<u32, String>,
+    // The prepared sql, it is only kept for the slow log.
+    sqls: IndexMap<u32, String>,
 }
 
 impl StmtCache {
@@ -34,6 +56,9 @@ impl StmtCache {
         Self { 
             cache: IndexMap::new(),
             sharding_column_cache: IndexMap::new(),
+            cursors: IndexMap::new(),
+            long_data_errors: IndexMap::new(),
+            sqls: IndexMap::new(),
         }
     }
 
@@ -56,7 +81,9 @@ impl StmtCache {
 
         let idx = value.iter().position(|x| x.id == stmt_id);
         if let Some(idx) = idx {
-            Some(value.remove(idx).conn)
+            let conn = value.remove(idx).conn;
+            bind_backend(&conn);
+            Some(conn)
         } else {
             None
         }
@@ -70,7 +97,10 @@ impl StmtCache {
             return vec![]
         };
 
-        value.drain(..).map(|x| (x.id, x.conn)).collect::<Vec<_>>()
+        value.drain(..).map(|x| {
+            bind_backend(&x.conn);
+            (x.id, x.conn)
+        }).collect::<Vec<_>>()
     }
 
     pub fn put_all(&mut self, server_stmt_id: u32, conns: Vec<(u32, PoolConn<ClientConn>)>) {
@@ -92,6 +122,18 @@ impl StmtCache {
 
     pub fn remove(&mut self, server_stmt_id: u32) {
         self.cache.remove(&server_stmt_id);
+        self.cursors.remove(&server_stmt_id);
+        self.long_data_errors.remove(&server_stmt_id);
+        self.sqls.remove(&server_stmt_id);
+    }
+
+    // Remove all stmts, returns the conns which hold them.
+    pub fn drain_conns(&mut self) -> Vec<PoolConn<ClientConn>> {
+        self.sharding_column_cache.clear();
+        self.cursors.clear();
+        self.long_data_errors.clear();
+        self.sqls.clear();
+        self.cache.drain(..).flat_map(|(_, entries)| entries.into_iter().map(|x| x.conn)).collect()
     }
 
     pub fn put_sharding_column(&mut self, server_stmt_id: u32, sharding_column: Option<String>) {
@@ -106,4 +148,120 @@ impl StmtCache {
             None
         }
     }
+
+    pub fn put_sql(&mut self, server_stmt_id: u32, sql: &str) {
+        let _ = self.sqls.insert(server_stmt_id, sql.to_string());
+    }
+
+    pub fn get_sql(&self, server_stmt_id: u32) -> Option<&str> {
+        self.sqls.get(&server_stmt_id).map(|x| x.as_str())
+    }
+
+    // The first error is kept until the stmt is executed or reset.
+    pub fn set_long_data_error(&mut self, server_stmt_id: u32, err: String) {
+        self.long_data_errors.entry(server_stmt_id).or_insert(err);
+    }
+
+    pub fn take_long_data_error(&mut self, server_stmt_id: u32) -> Option<String> {
+        self.long_data_errors.remove(&server_stmt_id)
+    }
+
+    pub fn open_cursor(&mut self, server_stmt_id: u32) {
+        self.cursors.insert(server_stmt_id, Cursor::Backend);
+    }
+
+    pub fn put_cursor(&mut self, server_stmt_id: u32, cursor: Cursor) {
+        self.cursors.insert(server_stmt_id, cursor);
+    }
+
+    // The index of the shard being fetched, returns None when the cursor is not opened on the shards.
+    pub fn get_shard_cursor(&self, server_stmt_id: u32) -> Option<usize> {
+        match self.cursors.get(&server_stmt_id) {
+            Some(Cursor::Shard(idx)) => Some(*idx),
+            _ => None,
+        }
+    }
+
+    pub fn close_cursor(&mut self, server_stmt_id: u32) {
+        self.cursors.remove(&server_stmt_id);
+    }
+
+    pub fn is_cursor_open(&self, server_stmt_id: u32) -> bool {
+        self.cursors.contains_key(&server_stmt_id)
+    }
+
+    // The backend conn must be pinned while any cursor is opened on it.
+    pub fn has_backend_cursor(&self) -> bool {
+        self.cursors.values().any(|x| matches!(x, Cursor::Backend))
+    }
+
+    // Fetch at most `num_rows` rows from the buffered cursor with the status of EOF packet, returns
+    // None when the cursor is not buffered. The cursor is closed after the last row is fetched.
+    pub fn fetch_buffered_rows(&mut self, server_stmt_id: u32, num_rows: u32) -> Option<(Vec<BytesMut>, u16)> {
+        let (rows, status) = match self.cursors.get_mut(&server_stmt_id) {
+            Some(Cursor::Buffered(rows, status)) => (rows, *status),
+            _ => return None,
+        };
+
+        let n = rows.len().min(num_rows as usize);
+        let fetched = rows.drain(..n).collect::<Vec<_>>();
+        if !rows.is_empty() {
+            return Some((fetched, status));
+        }
+
+        self.cursors.remove(&server_stmt_id);
+        Some((fetched, status | SERVER_STATUS_LAST_ROW_SEND))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_buffered_cursor() {
+        let mut cache = StmtCache::new();
+        let rows = (0..3u8).map(|x| BytesMut::from(&[0, x][..])).collect::<VecDeque<_>>();
+        cache.put_cursor(1, Cursor::Buffered(rows, SERVER_STATUS_CURSOR_EXISTS));
+        cache.open_cursor(2);
+        assert!(cache.has_backend_cursor());
+
+        let (rows, status) = cache.fetch_buffered_rows(1, 2).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(status, SERVER_STATUS_CURSOR_EXISTS);
+
+        let (rows, status) = cache.fetch_buffered_rows(1, 2).unwrap();
+        assert_eq!(&rows[0][..], &[0, 2]);
+        assert_eq!(status, SERVER_STATUS_CURSOR_EXISTS | SERVER_STATUS_LAST_ROW_SEND);
+        assert!(!cache.is_cursor_open(1));
+
+        assert!(cache.fetch_buffered_rows(2, 2).is_none());
+        cache.close_cursor(2);
+        assert!(!cache.has_backend_cursor());
+    }
+
+    #[test]
+    fn test_shard_cursor() {
+        let mut cache = StmtCache::new();
+        cache.put_cursor(1, Cursor::Shard(0));
+        cache.open_cursor(2);
+        assert_eq!(cache.get_shard_cursor(1), Some(0));
+        assert_eq!(cache.get_shard_cursor(2), None);
+
+        cache.put_cursor(1, Cursor::Shard(1));
+        assert_eq!(cache.get_shard_cursor(1), Some(1));
+        assert!(cache.fetch_buffered_rows(1, 2).is_none());
+        cache.remove(1);
+        assert!(!cache.is_cursor_open(1));
+    }
+
+    #[test]
+    fn test_long_data_error() {
+        let mut cache = StmtCache::new();
+        cache.set_long_data_error(1, "first".to_string());
+        cache.set_long_data_error(1, "second".to_string());
+        assert_eq!(cache.take_long_data_error(2), None);
+        assert_eq!(cache.take_long_data_error(1), Some("first".to_string()));
+        assert_eq!(cache.take_long_data_error(1), None);
+    }
 }
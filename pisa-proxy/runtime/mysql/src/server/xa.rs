@@ -0,0 +1,417 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use conn_pool::PoolConn;
+use endpoint::endpoint::Endpoint;
+use futures::{future::join_all, StreamExt};
+use indexmap::IndexSet;
+use mysql_parser::ast::SqlStmt;
+use mysql_protocol::{
+    client::conn::ClientConn, err::ProtocolError, mysql_const::ERR_HEADER, row::RowData,
+};
+use parking_lot::Mutex;
+use tracing::{error, info, warn};
+
+use crate::transaction_fsm::build_conn_factory;
+
+const XA_LOG_COMMIT: &str = "COMMIT";
+const XA_LOG_DONE: &str = "DONE";
+// Seconds between the retries of the commits failed in phase 2.
+pub const XA_RETRY_INTERVAL: u64 = 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum XaError {
+    #[error("{0:?}")]
+    Protocol(#[from] ProtocolError),
+
+    #[error("execute {sql:?} error {code}: {msg}")]
+    Exec { sql: String, code: u16, msg: String },
+
+    #[error("write xa log error {0:?}")]
+    Log(#[from] io::Error),
+}
+
+// The transaction stmts which are replaced by XA stmts on shard conns.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum XaTransStmt {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+pub fn xa_trans_stmt(stmt: &SqlStmt) -> Option<XaTransStmt> {
+    match stmt {
+        SqlStmt::BeginStmt(_) | SqlStmt::Start(_) => Some(XaTransStmt::Begin),
+        SqlStmt::Commit(_) => Some(XaTransStmt::Commit),
+        SqlStmt::Rollback(stmt) if stmt.ident.is_none() => Some(XaTransStmt::Rollback),
+        _ => None,
+    }
+}
+
+// The branch qualifier is the index of the shard when the transaction starts, so the branches of the
+// data nodes on the same endpoint are distinct.
+pub fn xa_sql(cmd: &str, gtrid: &str, bqual: &str) -> String {
+    format!("XA {} '{}','{}'", cmd, gtrid, bqual)
+}
+
+// The XID returned by `XA RECOVER` may be any bytes, it is sent as hex literal.
+fn xa_hex_sql(cmd: &str, gtrid: &[u8], bqual: &[u8], format_id: u64) -> String {
+    let hex = |data: &[u8]| data.iter().map(|x| format!("{:02X}", x)).collect::<String>();
+    format!("XA {} X'{}',X'{}',{}", cmd, hex(gtrid), hex(bqual), format_id)
+}
+
+// Returns the gtrids which are decided to commit but not finished yet.
+pub fn parse_xa_log(content: &str) -> IndexSet<String> {
+    let mut pending = IndexSet::new();
+    for line in content.lines() {
+        match line.split_once(' ') {
+            Some((XA_LOG_COMMIT, gtrid)) => {
+                pending.insert(gtrid.to_string());
+            }
+            Some((XA_LOG_DONE, gtrid)) => {
+                pending.remove(gtrid);
+            }
+            _ => {}
+        }
+    }
+    pending
+}
+
+// The code and message of the ERR packet, the packet includes 4 bytes header.
+fn decode_err_packet(data: &[u8]) -> (u16, String) {
+    if data.len() < 7 {
+        return (0, String::new());
+    }
+
+    let code = u16::from_le_bytes([data[5], data[6]]);
+    // Skip sql state marker '#' and 5 bytes sql state
+    let msg = if data.len() >= 13 && data[7] == b'#' { &data[13..] } else { &data[7..] };
+    (code, String::from_utf8_lossy(msg).into_owned())
+}
+
+// Execute the XA stmt which responds with OK or ERR packet.
+async fn xa_exec(conn: &mut ClientConn, sql: &str) -> Result<(), XaError> {
+    let mut stream = conn.send_query(sql.as_bytes()).await?;
+    match stream.next().await {
+        Some(Ok(data)) if data.len() > 4 && data[4] == ERR_HEADER => {
+            let (code, msg) = decode_err_packet(&data);
+            Err(XaError::Exec { sql: sql.to_string(), code, msg })
+        }
+        Some(Ok(_)) => Ok(()),
+        Some(Err(e)) => Err(e.into()),
+        None => Err(ProtocolError::Io(io::ErrorKind::UnexpectedEof.into()).into()),
+    }
+}
+
+// The branch qualifiers are in the order of conns.
+async fn xa_exec_all(
+    conns: &mut [PoolConn<ClientConn>],
+    bquals: &[String],
+    cmd: &str,
+    gtrid: &str,
+) -> Vec<Result<(), XaError>> {
+    let futs = conns
+        .iter_mut()
+        .zip(bquals.iter())
+        .map(|(conn, bqual)| async move { xa_exec(conn, &xa_sql(cmd, gtrid, bqual)).await });
+    join_all(futs).await
+}
+
+fn first_err(res: Vec<Result<(), XaError>>) -> Result<(), XaError> {
+    res.into_iter().collect()
+}
+
+// The id of the proxy instance is persisted next to the recovery log, so the gtrids of other instances
+// with the same name are not rolled back by recovery.
+fn load_instance_id(log_path: &str) -> Result<String, XaError> {
+    let path = format!("{}.id", log_path);
+    if let Ok(id) = std::fs::read_to_string(&path) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+
+    let id = format!("{:016x}", rand::random::<u64>());
+    let mut file = File::create(&path)?;
+    writeln!(file, "{}", id)?;
+    file.sync_data()?;
+    Ok(id)
+}
+
+// The coordinator of XA transactions across shards. The gtrid of the transaction decided to commit is
+// written to the recovery log before `XA COMMIT` is sent, the branches of other prepared transactions
+// are rolled back by recovery.
+pub struct XaCoordinator {
+    prefix: String,
+    epoch: u64,
+    seq: AtomicU64,
+    log_path: String,
+    log: Mutex<File>,
+    // The gtrids failed in phase 2, their prepared branches are committed by `retry_commits`.
+    failed: Mutex<IndexSet<String>>,
+}
+
+impl XaCoordinator {
+    pub fn new(name: &str, log_path: &str) -> Result<XaCoordinator, XaError> {
+        let name = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect::<String>();
+        let log = OpenOptions::new().create(true).append(true).open(log_path)?;
+        let id = load_instance_id(log_path)?;
+        let epoch =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or_default();
+
+        Ok(XaCoordinator {
+            prefix: format!("pisa-{}-{}-", name, id),
+            epoch,
+            seq: AtomicU64::new(0),
+            log_path: log_path.to_string(),
+            log: Mutex::new(log),
+            failed: Mutex::new(IndexSet::new()),
+        })
+    }
+
+    // The gtrid is unique in the proxy, the epoch distinguishes the gtrids after restart.
+    pub fn next_gtrid(&self) -> String {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        format!("{}{}-{}", self.prefix, self.epoch, seq)
+    }
+
+    fn write_log(&self, state: &str, gtrid: &str) -> Result<(), XaError> {
+        let mut log = self.log.lock();
+        writeln!(log, "{} {}", state, gtrid)?;
+        log.sync_data()?;
+        Ok(())
+    }
+
+    // Commit the branches by two-phase commit, one-phase commit is used when there is only one branch.
+    // Returns error when the transaction is rolled back. Returns false when a branch fails after the commit
+    // decision is logged, it is committed by `retry_commits` after the conns are closed.
+    pub async fn commit(
+        &self,
+        gtrid: &str,
+        conns: &mut [PoolConn<ClientConn>],
+        bquals: &[String],
+    ) -> Result<bool, XaError> {
+        if let Err(e) = first_err(xa_exec_all(conns, bquals, "END", gtrid).await) {
+            let _ = xa_exec_all(conns, bquals, "ROLLBACK", gtrid).await;
+            return Err(e);
+        }
+
+        if conns.len() == 1 {
+            let sql = format!("{} ONE PHASE", xa_sql("COMMIT", gtrid, &bquals[0]));
+            return xa_exec(&mut conns[0], &sql).await.map(|_| true);
+        }
+
+        if let Err(e) = first_err(xa_exec_all(conns, bquals, "PREPARE", gtrid).await) {
+            let _ = xa_exec_all(conns, bquals, "ROLLBACK", gtrid).await;
+            return Err(e);
+        }
+
+        if let Err(e) = self.write_log(XA_LOG_COMMIT, gtrid) {
+            let _ = xa_exec_all(conns, bquals, "ROLLBACK", gtrid).await;
+            return Err(e);
+        }
+
+        match first_err(xa_exec_all(conns, bquals, "COMMIT", gtrid).await) {
+            Ok(_) => {
+                if let Err(e) = self.write_log(XA_LOG_DONE, gtrid) {
+                    warn!("xa {} is committed, but write log error {:?}", gtrid, e);
+                }
+                Ok(true)
+            }
+            Err(e) => {
+                warn!("xa {} commit error {:?}, it will be committed in the background", gtrid, e);
+                self.failed.lock().insert(gtrid.to_string());
+                Ok(false)
+            }
+        }
+    }
+
+    pub fn has_failed_commits(&self) -> bool {
+        !self.failed.lock().is_empty()
+    }
+
+    // Commit the prepared branches of the transactions failed in phase 2, the transaction is logged as done
+    // when none of its branches is left. Other prepared branches may be in phase 2, they are not rolled back.
+    pub async fn retry_commits(&self, endpoints: &[Endpoint]) {
+        let failed = self.failed.lock().clone();
+        let mut unfinished = IndexSet::new();
+
+        for endpoint in endpoints {
+            if let Err(e) = self.recover_endpoint(endpoint, &failed, &mut unfinished, false).await {
+                error!("xa retry commit on {:?} error {:?}", endpoint.addr, e);
+                unfinished.extend(failed.iter().cloned());
+            }
+        }
+
+        for gtrid in failed.difference(&unfinished) {
+            if let Err(e) = self.write_log(XA_LOG_DONE, gtrid) {
+                warn!("xa {} is committed, but write log error {:?}", gtrid, e);
+            }
+            self.failed.lock().remove(gtrid);
+        }
+    }
+
+    pub async fn rollback(
+        &self,
+        gtrid: &str,
+        conns: &mut [PoolConn<ClientConn>],
+        bquals: &[String],
+    ) -> Result<(), XaError> {
+        // The branch may have been ended when the commit fails.
+        let _ = xa_exec_all(conns, bquals, "END", gtrid).await;
+        first_err(xa_exec_all(conns, bquals, "ROLLBACK", gtrid).await)
+    }
+
+    // Finish the prepared branches of this proxy on the endpoints, the branches decided to commit in the
+    // log are committed, others are rolled back. The log is compacted to the unfinished gtrids. It is only run
+    // before serving, the prepared branches of the running transactions would be rolled back.
+    pub async fn recover(&self, endpoints: &[Endpoint]) -> Result<(), XaError> {
+        let mut content = String::new();
+        File::open(&self.log_path)?.read_to_string(&mut content)?;
+        let pending = parse_xa_log(&content);
+        let mut unfinished = IndexSet::new();
+
+        for endpoint in endpoints {
+            if let Err(e) = self.recover_endpoint(endpoint, &pending, &mut unfinished, true).await {
+                error!("xa recover {:?} error {:?}", endpoint.addr, e);
+                // The branches on the endpoint are unknown, keep all commit decisions.
+                unfinished.extend(pending.iter().cloned());
+            }
+        }
+
+        let mut log = self.log.lock();
+        let mut compacted = File::create(&self.log_path)?;
+        for gtrid in unfinished.iter() {
+            writeln!(compacted, "{} {}", XA_LOG_COMMIT, gtrid)?;
+        }
+        compacted.sync_data()?;
+        *log = OpenOptions::new().append(true).open(&self.log_path)?;
+        // The unfinished commits are retried in the background.
+        self.failed.lock().extend(unfinished);
+
+        Ok(())
+    }
+
+    // The branches of `pending` are committed, others are rolled back when `rollback` is true.
+    async fn recover_endpoint(
+        &self,
+        endpoint: &Endpoint,
+        pending: &IndexSet<String>,
+        unfinished: &mut IndexSet<String>,
+        rollback: bool,
+    ) -> Result<(), XaError> {
+        let mut conn = build_conn_factory(endpoint)?.connect().await?;
+
+        let mut xids = vec![];
+        if let Some(mut rows) = conn.query_result("XA RECOVER".as_bytes()).await? {
+            while let Some(row) = rows.next().await {
+                let mut row = row?;
+                let format_id = row.decode_with_name::<u64>("formatID").ok().flatten().unwrap_or(1);
+                let gtrid_length =
+                    row.decode_with_name::<u64>("gtrid_length").ok().flatten().unwrap_or(0);
+                let data = row.get_row_data_with_name("data").ok().flatten();
+                if let Some(data) = data {
+                    let data = &data.data[data.start_part_idx..data.end_part_idx];
+                    let gtrid_length = (gtrid_length as usize).min(data.len());
+                    xids.push((
+                        data[..gtrid_length].to_vec(),
+                        data[gtrid_length..].to_vec(),
+                        format_id,
+                    ));
+                }
+            }
+        }
+
+        for (gtrid, bqual, format_id) in xids {
+            let name = String::from_utf8_lossy(&gtrid).into_owned();
+            if !name.starts_with(&self.prefix) {
+                continue;
+            }
+
+            let cmd = match pending.contains(&name) {
+                true => "COMMIT",
+                false if rollback => "ROLLBACK",
+                false => continue,
+            };
+            match xa_exec(&mut conn, &xa_hex_sql(cmd, &gtrid, &bqual, format_id)).await {
+                Ok(_) => info!("xa recover {} {} on {:?}", cmd, name, endpoint.addr),
+                Err(e) => {
+                    error!("xa recover {} {} on {:?} error {:?}", cmd, name, endpoint.addr, e);
+                    if cmd == "COMMIT" {
+                        unfinished.insert(name);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_xa_log() {
+        let content = "COMMIT pisa-p-1-0\nCOMMIT pisa-p-1-1\nDONE pisa-p-1-0\nCOMMIT pisa-p-1-2\n";
+        let pending = parse_xa_log(content);
+        assert_eq!(pending.into_iter().collect::<Vec<_>>(), vec!["pisa-p-1-1", "pisa-p-1-2"]);
+    }
+
+    #[test]
+    fn test_xa_sql() {
+        assert_eq!(
+            xa_sql("START", "pisa-p-1-0", "1"),
+            "XA START 'pisa-p-1-0','1'"
+        );
+        assert_eq!(xa_hex_sql("COMMIT", b"ab", b"\x01", 1), "XA COMMIT X'6162',X'01',1");
+    }
+
+    #[test]
+    fn test_decode_err_packet() {
+        let mut data = vec![0, 0, 0, 1, 0xff, 0x4d, 0x06, b'#'];
+        data.extend_from_slice(b"XAE07");
+        data.extend_from_slice(b"XAER_RMFAIL");
+        assert_eq!(decode_err_packet(&data), (1613, String::from("XAER_RMFAIL")));
+    }
+
+    #[test]
+    fn test_next_gtrid() {
+        let path = std::env::temp_dir().join("pisa_xa_test.log");
+        let xa = XaCoordinator::new("proxy 'a'", path.to_str().unwrap()).unwrap();
+        let gtrid = xa.next_gtrid();
+        assert!(gtrid.starts_with("pisa-proxya-"));
+        assert_ne!(gtrid, xa.next_gtrid());
+
+        // The instance id is kept after restart.
+        let restarted = XaCoordinator::new("proxy 'a'", path.to_str().unwrap()).unwrap();
+        assert_eq!(xa.prefix, restarted.prefix);
+        assert_eq!(xa.prefix.len(), "pisa-proxya-".len() + 17);
+
+        let _ = std::fs::remove_file(format!("{}.id", path.to_str().unwrap()));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright 2022 SphereEx Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use api::config::Tracing;
+use indexmap::IndexSet;
+use opentelemetry::{
+    global,
+    propagation::TextMapPropagator,
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{self, Sampler, Tracer},
+        Resource,
+    },
+    trace::{TraceContextExt, TraceError},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use strategy::sharding_rewrite::{DataSource, ShardingRewriteOutput};
+use tracing::{field, info_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// Build the OTLP tracer, spans are exported in batches on the tokio runtime.
+pub fn init_tracer(config: &Tracing) -> Result<Tracer, TraceError> {
+    let exporter: SpanExporterBuilder = match config.protocol.as_str() {
+        "http" => {
+            opentelemetry_otlp::new_exporter().http().with_endpoint(&config.otlp_endpoint).into()
+        }
+        _ => opentelemetry_otlp::new_exporter().tonic().with_endpoint(&config.otlp_endpoint).into(),
+    };
+
+    // The trace propagated from the client is sampled by its flag.
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(trace::config().with_sampler(sampler).with_resource(Resource::new(
+            vec![KeyValue::new("service.name", config.service_name.clone())],
+        )))
+        .install_batch(opentelemetry::runtime::Tokio)
+}
+
+// Flush the spans which are not exported yet.
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
+
+// The root span of a query, the fields are recorded while the query is processed.
+pub fn query_span(proxy: &str, sql: &str) -> Span {
+    let span = info_span!(
+        "pisa.query",
+        proxy = proxy,
+        statement = statement_type(sql).as_str(),
+        endpoint = field::Empty,
+        shard_count = field::Empty,
+    );
+
+    if let Some(cx) = extract_traceparent(sql) {
+        span.set_parent(cx);
+    }
+
+    span
+}
+
+// The span of sending the query to the backends and forwarding the result, `rows` is recorded
+// when the result is forwarded.
+pub fn execute_span(endpoint: &str, shard_count: usize) -> Span {
+    info_span!(
+        "pisa.backend_execute",
+        endpoint = endpoint,
+        shard_count = shard_count,
+        rows = field::Empty
+    )
+}
+
+// The endpoints of shards, e.g. `127.0.0.1:3306,127.0.0.1:3307`.
+pub fn shard_endpoints(outputs: &[ShardingRewriteOutput]) -> String {
+    let endpoints = outputs
+        .iter()
+        .filter_map(|x| match &x.data_source {
+            DataSource::Endpoint(ep) => Some(ep.addr.as_str()),
+            _ => None,
+        })
+        .collect::<IndexSet<_>>();
+    endpoints.into_iter().collect::<Vec<_>>().join(",")
+}
+
+// The first keyword of sql, e.g. `SELECT`, leading comments are skipped.
+pub fn statement_type(sql: &str) -> String {
+    let mut sql = sql.trim_start();
+    loop {
+        if let Some(rest) = sql.strip_prefix("/*") {
+            sql = rest.split_once("*/").map_or("", |x| x.1).trim_start();
+        } else if sql.starts_with("--") || sql.starts_with('#') {
+            sql = sql.split_once('\n').map_or("", |x| x.1).trim_start();
+        } else {
+            break;
+        }
+    }
+
+    sql.split(|c: char| !c.is_ascii_alphabetic()).next().unwrap_or_default().to_uppercase()
+}
+
+// Extract the W3C trace context from the comment of sql, e.g. `/* traceparent='00-<trace id>-<span id>-01' */`.
+pub fn extract_traceparent(sql: &str) -> Option<Context> {
+    let mut rest = sql;
+    while let Some(start) = rest.find("/*") {
+        let comment = &rest[start + 2..];
+        let (comment, next) = comment.split_once("*/").unwrap_or((comment, ""));
+        rest = next;
+
+        let value = match comment.find("traceparent") {
+            Some(idx) => comment[idx + "traceparent".len()..]
+                .trim_start()
+                .trim_start_matches(['=', ':'])
+                .trim_start()
+                .trim_start_matches(['\'', '"']),
+            None => continue,
+        };
+        let value =
+            value.split(|c: char| !(c.is_ascii_hexdigit() || c == '-')).next().unwrap_or_default();
+
+        let carrier = HashMap::from([("traceparent".to_string(), value.to_string())]);
+        let cx = TraceContextPropagator::new().extract(&carrier);
+        if cx.span().span_context().is_valid() {
+            return Some(cx);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use opentelemetry::trace::{SpanId, TraceId};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_statement_type() {
+        assert_eq!(statement_type("select 1"), "SELECT");
+        assert_eq!(statement_type(" /* a */ -- b\n # c\ninsert into t values(1)"), "INSERT");
+        assert_eq!(statement_type("(select 1)"), "");
+    }
+
+    #[test]
+    fn test_extract_traceparent() {
+        let sql = "select 1 /* app=a, traceparent='00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01' */";
+        let cx = extract_traceparent(sql).unwrap();
+        let span_context = cx.span().span_context().clone();
+        assert_eq!(
+            span_context.trace_id(),
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap()
+        );
+        assert_eq!(span_context.span_id(), SpanId::from_hex("b7ad6b7169203331").unwrap());
+        assert!(span_context.is_sampled());
+        assert!(span_context.is_remote());
+
+        let sql =
+            "/*traceparent=00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00*/ select 1";
+        assert!(!extract_traceparent(sql).unwrap().span().span_context().is_sampled());
+
+        assert!(extract_traceparent("select 1 /* traceparent=00-0-0-01 */").is_none());
+        assert!(extract_traceparent(
+            "select 'traceparent=00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01'"
+        )
+        .is_none());
+    }
+
+    // A collector stub which receives the OTLP/HTTP requests.
+    async fn collector() -> (String, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut req = vec![];
+                let mut buf = [0; 4096];
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    req.extend_from_slice(&buf[..n]);
+
+                    let text = String::from_utf8_lossy(&req).to_lowercase();
+                    let header_end = match text.find("\r\n\r\n") {
+                        Some(idx) => idx + 4,
+                        None => continue,
+                    };
+                    let length = text
+                        .lines()
+                        .find_map(|x| x.strip_prefix("content-length:"))
+                        .and_then(|x| x.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if n == 0 || req.len() >= header_end + length {
+                        break;
+                    }
+                }
+
+                socket.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await.unwrap();
+                let _ = tx.send(req);
+            }
+        });
+
+        (format!("http://{}/v1/traces", addr), rx)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_spans() {
+        let (endpoint, mut rx) = collector().await;
+        let config = Tracing {
+            otlp_endpoint: endpoint,
+            protocol: "http".to_string(),
+            service_name: "pisa-proxy-test".to_string(),
+            sample_ratio: 1.0,
+        };
+
+        let tracer = init_tracer(&config).unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::with_default(subscriber, || {
+            let sql = "select 1 /* traceparent='00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01' */";
+            let span = query_span("proxy-1", sql);
+            span.in_scope(|| {
+                let _route = info_span!("pisa.route").entered();
+                execute_span("127.0.0.1:3306", 1).record("rows", &1u64);
+            });
+            span.record("endpoint", &"127.0.0.1:3306");
+        });
+        tokio::task::spawn_blocking(shutdown_tracer).await.unwrap();
+
+        let req = tokio::time::timeout(Duration::from_secs(10), rx.recv()).await.unwrap().unwrap();
+        assert!(req.starts_with(b"POST /v1/traces "));
+        for expected in [
+            "pisa-proxy-test",
+            "pisa.query",
+            "pisa.route",
+            "pisa.backend_execute",
+            "proxy-1",
+            "SELECT",
+        ] {
+            assert!(req.windows(expected.len()).any(|x| x == expected.as_bytes()), "{}", expected);
+        }
+        let trace_id = TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap().to_bytes();
+        assert!(req.windows(trace_id.len()).any(|x| x == trace_id));
+    }
+}
@@ -12,24 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use conn_pool::{Pool, PoolConn};
 use endpoint::endpoint::Endpoint;
-use mysql_parser::ast::SqlStmt;
+use mysql_parser::ast::{
+    Create, CreateIndexStmt, DefinerTail, Expr, ExprOrDefault, FollowingOptType, IsolationType,
+    Node, Op, OptType, OptValue, SelectStmt, SetOptValues, SetOpts, SetVarIdentType, SqlStmt,
+    TableIdent, Transaction, TransactionType, Transformer, Value, Visitor,
+};
 use mysql_protocol::{
     client::conn::{ClientConn, SessionAttr},
+    compress::Compression,
+    err::ProtocolError,
     server::auth::ServerHandshakeCodec,
-    session::Session,
+    session::{is_tracked_var, Session, SessionVars},
 };
 use pisa_error::error::{Error, ErrorKind};
 use strategy::{
+    config::TargetRole,
     rewrite::{ShardingRewriteInput, ShardingRewriter},
-    route::{BoxError, Route, RouteInput, RouteStrategy, RouteInputTyp},
+    route::{BoxError, RouteInput, RouteStrategy, RouteInputTyp},
     sharding_rewrite::{DataSource, ShardingRewriteOutput},
 };
 use tracing::debug;
 
+use crate::{registry::bind_backend, server::metrics::MySQLServerMetricsCollector};
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TransState {
     TransDummyState,
@@ -37,6 +46,8 @@ pub enum TransState {
     TransSetSessionState,
     TransStartState,
     TransPrepareState,
+    // `SET TRANSACTION`, the characteristics is kept on the conn until the next transaction ends.
+    TransSetTransactionState,
 }
 
 impl Default for TransState {
@@ -59,6 +70,9 @@ pub enum TransEventName {
     ResetEvent,
     CommitRollBackEvent,
     DropEvent,
+    SetTransactionEvent,
+    SavepointEvent,
+    ImplicitCommitEvent,
 }
 
 impl Default for TransEventName {
@@ -67,12 +81,30 @@ impl Default for TransEventName {
     }
 }
 
-pub async fn check_get_conn(pool: Pool<ClientConn>, endpoint: &str, attrs: &[SessionAttr]) -> Result<PoolConn<ClientConn>, Error>{
+// Build the conn factory of endpoint, returns error when the compression config is invalid.
+pub fn build_conn_factory(endpoint: &Endpoint) -> Result<ClientConn, ProtocolError> {
+    let compression = endpoint
+        .compression
+        .as_ref()
+        .map(|x| Compression::new(x, endpoint.compression_level))
+        .transpose()?;
+
+    Ok(ClientConn::with_opts(endpoint.user.clone(), endpoint.password.clone(), endpoint.addr.clone())
+        .with_compression(compression))
+}
+
+pub async fn check_get_conn(domain: &str, pool: Pool<ClientConn>, endpoint: &str, attrs: &[SessionAttr]) -> Result<PoolConn<ClientConn>, Error>{
+    let now = Instant::now();
     match pool.get_conn_with_endpoint_session(endpoint, attrs).await {
         Ok(client_conn) => {
-            if !client_conn.is_ready().await {
-                return pool.rebuild_conn_with_session(attrs).await.map_err(|e| Error::new(ErrorKind::Protocol(e)))
-            }
+            let client_conn = if !client_conn.is_ready().await {
+                pool.rebuild_conn_with_session(attrs).await.map_err(|e| Error::new(ErrorKind::Protocol(e)))?
+            } else {
+                client_conn
+            };
+            MySQLServerMetricsCollector::new()
+                .set_pool_wait_duration(&[domain, endpoint], now.elapsed().as_secs_f64());
+            bind_backend(&client_conn);
             Ok(client_conn)
         }
         Err(err) => {
@@ -119,8 +151,350 @@ pub fn query_rewrite(
     Ok(outputs)
 }
 
+// Whether the stmt can be routed to the replica, the stmt which locks rows or writes into file is not.
+pub fn is_read_stmt(stmt: &SqlStmt) -> bool {
+    match stmt {
+        SqlStmt::SelectStmt(SelectStmt::Query(query)) => {
+            query.lock_clauses.is_empty() && query.into_clause.is_none()
+        }
+        SqlStmt::SelectStmt(_)
+        | SqlStmt::ShowDatabasesStmt(_)
+        | SqlStmt::ShowTablesStmt(_)
+        | SqlStmt::ShowColumnsStmt(_)
+        | SqlStmt::ShowCreateTableStmt(_)
+        | SqlStmt::ShowKeysStmt(_)
+        | SqlStmt::ShowVariablesStmt(_)
+        | SqlStmt::ShowCreateViewStmt(_)
+        | SqlStmt::ShowEnginesStmt(_)
+        | SqlStmt::ShowPluginsStmt(_)
+        | SqlStmt::ShowPrivilegesStmt(_)
+        | SqlStmt::ShowGrantsStmt(_)
+        | SqlStmt::ShowCreateProcedureStmt(_)
+        | SqlStmt::ShowCreateFunctionStmt(_)
+        | SqlStmt::ShowCreateTriggerStmt(_)
+        | SqlStmt::ShowCreateEventStmt(_)
+        | SqlStmt::ShowCreateUserStmt(_)
+        | SqlStmt::ShowStatusStmt(_) => true,
+        _ => false,
+    }
+}
+
+// The database of `USE db`, the stmt is not supported by parser.
+pub fn use_stmt_db(sql: &str) -> Option<String> {
+    let sql = sql.trim_matches(char::from(0)).trim().trim_end_matches(';').trim_end();
+    let (keyword, db) = sql.split_once(|c: char| c.is_ascii_whitespace())?;
+    if !keyword.eq_ignore_ascii_case("USE") {
+        return None;
+    }
+
+    let db = db.trim();
+    match db.strip_prefix('`').and_then(|x| x.strip_suffix('`')) {
+        Some(db) => Some(db.replace("``", "`")),
+        None => Some(db.to_string()),
+    }
+}
+
+// Collects the schemas qualifying the tables of DML.
+#[derive(Default)]
+struct SchemaCollector {
+    schemas: Vec<String>,
+}
+
+impl SchemaCollector {
+    fn push(&mut self, schema: &Option<String>) {
+        if let Some(schema) = schema {
+            self.push_schema(schema);
+        }
+    }
+
+    fn push_schema(&mut self, schema: &str) {
+        let schema = schema.trim_matches('`');
+        if !self.schemas.iter().any(|x| x == schema) {
+            self.schemas.push(schema.to_string());
+        }
+    }
+
+    fn push_table(&mut self, table: &TableIdent) {
+        self.push(&table.schema)
+    }
+
+    // The routine name is `db.name` when it is qualified.
+    fn push_routine(&mut self, name: &str) {
+        if let Some((schema, _)) = name.split_once('.') {
+            self.push_schema(schema);
+        }
+    }
+}
+
+impl Transformer for SchemaCollector {
+    fn trans(&mut self, node: &mut Node) -> bool {
+        match node {
+            Node::SingleTable(t) => self.push_table(&t.table_name),
+            Node::InsertStmt(t) => self.push_table(&t.table_name),
+            Node::DeleteStmt(t) => {
+                if let Some(table) = &t.table_name {
+                    self.push_table(table);
+                }
+            }
+            Node::Value(Value::TableIdent { schema, .. }) => self.push(schema),
+            _ => {}
+        }
+        false
+    }
+}
+
+// The schemas referred explicitly by the stmt, the unqualified names refer to the current database.
+// None is returned when the objects referred by the stmt can't be known.
+pub fn stmt_schemas(stmt: &SqlStmt) -> Option<Vec<String>> {
+    let mut collector = SchemaCollector::default();
+    match stmt {
+        SqlStmt::SelectStmt(_)
+        | SqlStmt::InsertStmt(_)
+        | SqlStmt::UpdateStmt(_)
+        | SqlStmt::DeleteStmt(_) => {
+            stmt.clone().visit(&mut collector);
+        }
+        SqlStmt::ShowTablesStmt(stmt) => {
+            collector.push(&stmt.opt_db.as_ref().map(|x| x.db.clone()));
+        }
+        SqlStmt::ShowColumnsStmt(stmt) => {
+            collector.push(&stmt.opt_db.as_ref().map(|x| x.db.clone()));
+            collector.push_routine(&stmt.from_table.table);
+        }
+        SqlStmt::ShowKeysStmt(stmt) => {
+            collector.push(&stmt.opt_db.as_ref().map(|x| x.db.clone()));
+            collector.push_routine(&stmt.from_table.table);
+        }
+        SqlStmt::ShowCreateTableStmt(stmt) => collector.push_table(&stmt.table),
+        SqlStmt::ShowCreateViewStmt(stmt) => collector.push_table(&stmt.view_name),
+        SqlStmt::ShowCreateProcedureStmt(stmt)
+        | SqlStmt::ShowCreateFunctionStmt(stmt)
+        | SqlStmt::ShowCreateTriggerStmt(stmt)
+        | SqlStmt::ShowCreateEventStmt(stmt) => collector.push_routine(&stmt.sp_name),
+        SqlStmt::CreateTableStmt(stmt) => {
+            collector.push_table(&stmt.table_ident);
+            if let Some(table) = &stmt.like_table_ident {
+                collector.push_table(table);
+            }
+        }
+        SqlStmt::CreateIndexStmt(stmt) => match stmt {
+            CreateIndexStmt::CommonIndex(x) => collector.push_table(&x.table_name),
+            CreateIndexStmt::FullTextIndex(x) => collector.push_table(&x.table_name),
+            CreateIndexStmt::SpatialIndex(x) => collector.push_table(&x.table_name),
+        },
+        SqlStmt::Create(Create::CreateDatabase(stmt)) => {
+            collector.push_schema(&stmt.database_name);
+        }
+        SqlStmt::Create(Create::CreateViewOrTriggerOrSpOrEvent(stmt)) => match &stmt.definer_tail {
+            DefinerTail::ViewTail(tail) => {
+                collector.push_table(&tail.view_name);
+                tail.view_query_block.select_stmt.clone().visit(&mut collector);
+            }
+            // The body of trigger and stored routine is not parsed.
+            DefinerTail::TriggerTail(_) | DefinerTail::SpTail(_) => return None,
+        },
+        // The stmt text of `PREPARE` is parsed and checked by the caller.
+        SqlStmt::Prepare(_) => return None,
+        _ => {}
+    }
+
+    Some(collector.schemas)
+}
+
+// The event of the stmt which is not supported by parser, e.g. `SAVEPOINT`, `XA` and most of DDL.
+pub fn raw_stmt_trans_event(sql: &str) -> Option<(TransEventName, RouteInputTyp)> {
+    let mut words = sql.split_ascii_whitespace().map(|x| x.trim_end_matches(';').to_uppercase());
+    let first = words.next()?;
+    let second = words.next().unwrap_or_default();
+
+    match first.as_str() {
+        "SAVEPOINT" => Some((TransEventName::SavepointEvent, RouteInputTyp::Transaction)),
+        "RELEASE" if second == "SAVEPOINT" => {
+            Some((TransEventName::SavepointEvent, RouteInputTyp::Transaction))
+        }
+        "XA" => {
+            let event = match second.as_str() {
+                "START" | "BEGIN" => TransEventName::StartEvent,
+                "COMMIT" | "ROLLBACK" => TransEventName::CommitRollBackEvent,
+                // `XA END`, `XA PREPARE` and `XA RECOVER`
+                _ => TransEventName::QueryEvent,
+            };
+            Some((event, RouteInputTyp::Transaction))
+        }
+        // Temporary table does not cause an implicit commit.
+        "CREATE" | "DROP" if second == "TEMPORARY" => None,
+        "ALTER" | "CREATE" | "DROP" | "RENAME" | "TRUNCATE" | "GRANT" | "REVOKE" | "INSTALL"
+        | "UNINSTALL" => Some((TransEventName::ImplicitCommitEvent, RouteInputTyp::Transaction)),
+        _ => None,
+    }
+}
+
+// DDL causes an implicit commit of the current transaction.
+pub fn is_implicit_commit_stmt(stmt: &SqlStmt) -> bool {
+    match stmt {
+        SqlStmt::CreateTableStmt(stmt) => !stmt.is_temporary,
+        SqlStmt::Create(_)
+        | SqlStmt::CreateIndexStmt(_)
+        | SqlStmt::CreateResourceGroupStmt(_)
+        | SqlStmt::CreateRoleStmt(_)
+        | SqlStmt::CreateSRSStmt(_) => true,
+        _ => false,
+    }
+}
+
+// Whether `SET SESSION TRANSACTION READ ONLY` or `SET transaction_read_only = ON` has been executed.
+pub fn is_read_only_session(vars: &SessionVars) -> bool {
+    match vars.get("transaction_read_only") {
+        Some(value) => {
+            let value = value.trim_matches('\'');
+            value == "1" || value.eq_ignore_ascii_case("ON") || value.eq_ignore_ascii_case("TRUE")
+        }
+        None => false,
+    }
+}
+
+// `SET TRANSACTION` without scope only affects the next transaction.
+pub fn set_next_transaction(stmt: &SetOptValues) -> Option<&Transaction> {
+    match stmt {
+        SetOptValues::Transaction(trans) => Some(trans),
+        _ => None,
+    }
+}
+
+// Collect the session variables changed by the SET stmt, `None` means the variable is reset to default.
+// The value of system variable which is not a literal is tracked by the OK packet, `None` of user variable
+// means its value is unknown, it is read back from the conn.
+pub fn set_stmt_vars(stmt: &SetOptValues) -> Vec<(String, Option<String>)> {
+    let mut vars = vec![];
+    // The scope of an assignment is kept by the following ones without scope.
+    let mut is_session = true;
+
+    match stmt {
+        SetOptValues::OptValues(vals) => {
+            push_set_opt(&mut vars, &vals.opt, is_session);
+            push_opt_values(&mut vars, &vals.values, &mut is_session);
+        }
+        SetOptValues::OptTypeFollowing(vals) => {
+            is_session = is_session_opt_type(&vals.opt_type);
+            match &vals.following {
+                FollowingOptType::TypeEq(eq) => {
+                    if is_session {
+                        push_system_var(&mut vars, &eq.opt_type.var, &eq.opt_type.value)
+                    }
+                    push_opt_values(&mut vars, &eq.values, &mut is_session);
+                }
+                FollowingOptType::Transaction(trans) if is_session => {
+                    push_transaction_vars(&mut vars, trans)
+                }
+                _ => {}
+            }
+        }
+        // Characteristics of the next transaction only.
+        SetOptValues::Transaction(_) => {}
+    }
+
+    vars.retain(|(name, _)| is_tracked_var(name));
+    vars
+}
+
+fn is_session_opt_type(typ: &OptType) -> bool {
+    matches!(typ, OptType::Session | OptType::Local)
+}
+
+fn push_opt_values(vars: &mut Vec<(String, Option<String>)>, values: &[OptValue], is_session: &mut bool) {
+    for value in values.iter() {
+        match value {
+            OptValue::OptValueType(val) => {
+                *is_session = is_session_opt_type(&val.opt_type);
+                if *is_session {
+                    push_system_var(vars, &val.set_var.var, &val.set_var.value)
+                }
+            }
+            OptValue::SetOpts(opt) => push_set_opt(vars, opt, *is_session),
+        }
+    }
+}
+
+fn push_set_opt(vars: &mut Vec<(String, Option<String>)>, opt: &SetOpts, is_session: bool) {
+    match opt {
+        SetOpts::SetVariable(val) if is_session => push_system_var(vars, &val.var, &val.value),
+        SetOpts::SetSystemVar(val) => {
+            if matches!(
+                val.opt_var,
+                SetVarIdentType::None | SetVarIdentType::Session | SetVarIdentType::Local
+            ) {
+                push_system_var(vars, &val.var, &val.value)
+            }
+        }
+        SetOpts::SetUserVar(val) => {
+            let name = val.var.trim_matches(|c| c == '\'' || c == '"' || c == '`');
+            vars.push((format!("@{}", name.to_lowercase()), literal_text(&val.expr)))
+        }
+        _ => {}
+    }
+}
+
+fn push_system_var(vars: &mut Vec<(String, Option<String>)>, name: &str, value: &ExprOrDefault) {
+    let value = match value {
+        ExprOrDefault::Default => None,
+        ExprOrDefault::On => Some("ON".to_string()),
+        ExprOrDefault::All => Some("ALL".to_string()),
+        ExprOrDefault::Binary => Some("BINARY".to_string()),
+        ExprOrDefault::Row => Some("ROW".to_string()),
+        ExprOrDefault::System => Some("SYSTEM".to_string()),
+        ExprOrDefault::Expr(expr) => match literal_text(expr) {
+            Some(value) => Some(value),
+            None => return,
+        },
+    };
+
+    vars.push((name.to_lowercase(), value))
+}
+
+fn push_transaction_vars(vars: &mut Vec<(String, Option<String>)>, trans: &Transaction) {
+    if let Some(level) = &trans.isolation_level {
+        let level = match level {
+            IsolationType::ReadUncommitted => "'READ-UNCOMMITTED'",
+            IsolationType::ReadCommitted => "'READ-COMMITTED'",
+            IsolationType::RepeatableRead => "'REPEATABLE-READ'",
+            IsolationType::Serializable => "'SERIALIZABLE'",
+        };
+        vars.push(("transaction_isolation".to_string(), Some(level.to_string())));
+    }
+
+    if let Some(mode) = &trans.mode {
+        let read_only = match mode {
+            TransactionType::ReadOnly => "ON",
+            TransactionType::ReadWrite => "OFF",
+        };
+        vars.push(("transaction_read_only".to_string(), Some(read_only.to_string())));
+    }
+}
+
+// The sql text of the literal, it is replayed as is.
+fn literal_text(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::LiteralExpr(value) => match value {
+            Value::Text { value, .. } | Value::TextN { value, .. } => Some(value.clone()),
+            Value::Num { value, signed, .. } | Value::FloatNum { value, signed, .. } => {
+                Some(if *signed { format!("-{}", value) } else { value.clone() })
+            }
+            Value::True => Some("TRUE".to_string()),
+            Value::False => Some("FALSE".to_string()),
+            Value::Null => Some("NULL".to_string()),
+            _ => None,
+        },
+        Expr::SimpleIdentExpr(Value::Ident { value, quoted: false, .. }) => Some(value.clone()),
+        Expr::UnaryOperationExpr { expr, operator: Op::MINUS, .. } => {
+            let value = literal_text(expr).filter(|v| !v.starts_with('-') && v.parse::<f64>().is_ok())?;
+            Some(format!("-{}", value))
+        }
+        _ => None,
+    }
+}
 
 pub fn route(
+    domain: &str,
     input_typ: RouteInputTyp,
     raw_sql: &str,
     strategy: Arc<parking_lot::Mutex<RouteStrategy>>,
@@ -129,21 +503,56 @@ pub fn route(
     let input = match input_typ {
         RouteInputTyp::Statement => RouteInput::Statement(raw_sql),
         RouteInputTyp::Transaction => RouteInput::Transaction(raw_sql),
+        RouteInputTyp::ReadOnlyTransaction => RouteInput::ReadOnlyTransaction(raw_sql),
         _ => RouteInput::None,
     };
                 
-    let dispatch_res = strategy.dispatch(&input).unwrap();
+    let dispatch_res = strategy.dispatch_with_rule(&input).unwrap();
     debug!("route_strategy rw + sharding to {:?} for input typ: {:?}, sql: {:?}", dispatch_res, input_typ, raw_sql);
+    collect_route_decision(domain, &mut strategy, &dispatch_res.2, &dispatch_res.1);
 
     return dispatch_res.0.unwrap()
 }
 
+// Route the command which carries no statement, e.g. COM_STATISTICS, to the default target of the
+// strategy, returns None when there is no available node.
+pub fn route_command(domain: &str, strategy: Arc<parking_lot::Mutex<RouteStrategy>>) -> Option<Endpoint> {
+    let mut strategy = strategy.lock();
+    let dispatch_res = strategy.dispatch_with_rule(&RouteInput::None).ok()?;
+    collect_route_decision(domain, &mut strategy, &dispatch_res.2, &dispatch_res.1);
+    dispatch_res.0
+}
+
+// Route to the read-write endpoint, returns None when there is no available primary, e.g. during failover.
+pub fn route_primary(domain: &str, strategy: Arc<parking_lot::Mutex<RouteStrategy>>) -> Option<Endpoint> {
+    let mut strategy = strategy.lock();
+    let dispatch_res = strategy.dispatch_with_rule(&RouteInput::Transaction("")).ok()?;
+    collect_route_decision(domain, &mut strategy, &dispatch_res.2, &dispatch_res.1);
+    dispatch_res.0
+}
+
+fn collect_route_decision(
+    domain: &str,
+    strategy: &mut RouteStrategy,
+    rule: &str,
+    role: &TargetRole,
+) {
+    let collector = MySQLServerMetricsCollector::new();
+    let role = match role {
+        TargetRole::Read => "read",
+        TargetRole::ReadWrite => "readwrite",
+    };
+    collector.set_route_decisions_total(&[domain, rule, role]);
+    collector.set_discovery_role_changes(domain, strategy);
+}
+
 pub fn route_sharding(
+    domain: &str,
     input_typ: RouteInputTyp,
     raw_sql: &str,
     strategy: Arc<parking_lot::Mutex<RouteStrategy>>,
     rewrite_outputs: &mut Vec<ShardingRewriteOutput>,
-) {
+) -> Result<(), Error> {
     let mut strategy = strategy.lock();
     for o in rewrite_outputs.iter_mut() {
         match &o.data_source {
@@ -152,6 +561,7 @@ pub fn route_sharding(
                 let _input = match input_typ {
                     RouteInputTyp::Statement => RouteInput::Sharding(ep.clone()),
                     RouteInputTyp::Transaction => RouteInput::Sharding(ep.clone()),
+                    RouteInputTyp::ReadOnlyTransaction => RouteInput::Sharding(ep.clone()),
                     _ => RouteInput::None,
                 };
 
@@ -161,20 +571,35 @@ pub fn route_sharding(
 
             // rewritesplitting + sharding
             DataSource::NodeGroup(group) => {
+                // The open transaction keeps its rewriter after reloading, the group may be removed.
+                if !strategy.has_node_group(group) {
+                    let err = format!("node group {:?} is not found", group);
+                    return Err(Error::new(ErrorKind::Runtime(err.into())));
+                }
+
                 let input = match input_typ {
                     RouteInputTyp::Statement => RouteInput::ShardingStatement(raw_sql, group.clone()),
                     RouteInputTyp::Transaction => RouteInput::ShardingTransaction(raw_sql, group.clone()),
+                    // The transaction of sharding is not routed to read endpoints currently.
+                    RouteInputTyp::ReadOnlyTransaction => RouteInput::ShardingTransaction(raw_sql, group.clone()),
                     _ => RouteInput::None,
                 };
                 
-                let dispatch_res = strategy.dispatch(&input).unwrap();
+                let dispatch_res = strategy.dispatch_with_rule(&input).map_err(|e| Error::new(ErrorKind::Runtime(e)))?;
                 debug!("route_strategy rw + sharding to {:?} for input typ: {:?}, sql: {:?}", dispatch_res, input_typ, raw_sql);
+                collect_route_decision(domain, &mut strategy, &dispatch_res.2, &dispatch_res.1);
                 // reassign data_source, type should is DataSource::Endpoint
-                o.data_source = DataSource::Endpoint(dispatch_res.0.unwrap());
+                let endpoint = dispatch_res.0.ok_or_else(|| {
+                    let err = format!("no endpoint of node group {:?} is available", group);
+                    Error::new(ErrorKind::Runtime(err.into()))
+                })?;
+                o.data_source = DataSource::Endpoint(endpoint);
             }
             _ => unreachable!(),
         }
     }
+
+    Ok(())
 }
 pub struct TransEvent {
     name: TransEventName,
@@ -322,9 +747,71 @@ fn init_trans_events() -> Vec<TransEvent> {
             dst_state: TransState::TransDummyState,
             //driver: Some(Box::new(Driver)),
         },
+        TransEvent {
+            name: TransEventName::SetTransactionEvent,
+            src_state: TransState::TransDummyState,
+            dst_state: TransState::TransSetTransactionState,
+        },
+        TransEvent {
+            name: TransEventName::SetTransactionEvent,
+            src_state: TransState::TransSetTransactionState,
+            dst_state: TransState::TransSetTransactionState,
+        },
+        TransEvent {
+            name: TransEventName::StartEvent,
+            src_state: TransState::TransSetTransactionState,
+            dst_state: TransState::TransStartState,
+        },
+        TransEvent {
+            name: TransEventName::PrepareEvent,
+            src_state: TransState::TransSetTransactionState,
+            dst_state: TransState::TransPrepareState,
+        },
+        // The stmt runs in the next transaction when autocommit is on.
+        TransEvent {
+            name: TransEventName::QueryEvent,
+            src_state: TransState::TransSetTransactionState,
+            dst_state: TransState::TransDummyState,
+        },
+        TransEvent {
+            name: TransEventName::CommitRollBackEvent,
+            src_state: TransState::TransSetTransactionState,
+            dst_state: TransState::TransDummyState,
+        },
+        TransEvent {
+            name: TransEventName::ImplicitCommitEvent,
+            src_state: TransState::TransSetTransactionState,
+            dst_state: TransState::TransDummyState,
+        },
+        // `SAVEPOINT`, `ROLLBACK TO` and `RELEASE SAVEPOINT` keep the pinned conn.
+        TransEvent {
+            name: TransEventName::SavepointEvent,
+            src_state: TransState::TransDummyState,
+            dst_state: TransState::TransDummyState,
+        },
+        TransEvent {
+            name: TransEventName::SavepointEvent,
+            src_state: TransState::TransStartState,
+            dst_state: TransState::TransStartState,
+        },
+        // DDL ends the transaction, other pinned states are kept.
+        TransEvent {
+            name: TransEventName::ImplicitCommitEvent,
+            src_state: TransState::TransDummyState,
+            dst_state: TransState::TransDummyState,
+        },
+        TransEvent {
+            name: TransEventName::ImplicitCommitEvent,
+            src_state: TransState::TransStartState,
+            dst_state: TransState::TransDummyState,
+        },
     ];
 }
 
+fn xa_conn_key(conn: &ClientConn) -> (String, u32) {
+    (conn.get_endpoint().unwrap_or_default(), conn.get_connection_id().unwrap_or_default())
+}
+
 pub struct TransFsm {
     pub events: Vec<TransEvent>,
     pub current_state: TransState,
@@ -336,6 +823,10 @@ pub struct TransFsm {
     pub db: Option<String>,
     pub charset: String,
     pub autocommit: Option<String>,
+    // The gtrid of the XA transaction across shards
+    pub xid: Option<String>,
+    // The branch qualifiers of the XA transaction, keyed by the endpoint and thread id of the shard conn
+    pub xa_bquals: HashMap<(String, u32), String>,
 }
 
 impl TransFsm {
@@ -353,6 +844,8 @@ impl TransFsm {
             charset: String::from("utf8mb4"),
             autocommit: None,
             shard_cache_conn: vec![],
+            xid: None,
+            xa_bquals: HashMap::new(),
         }
     }
 
@@ -373,6 +866,16 @@ impl TransFsm {
         false
     }
 
+    // The conn is in an open transaction, it is kept until the transaction ends.
+    pub fn in_transaction(&self) -> bool {
+        let autocommit_off = matches!(self.autocommit.as_deref(), Some(x) if x == "0" || x.eq_ignore_ascii_case("OFF"));
+        match self.current_state {
+            TransState::TransStartState | TransState::TransSetTransactionState => true,
+            TransState::TransSetSessionState => autocommit_off,
+            _ => self.xid.is_some(),
+        }
+    }
+
     // when autocommit=0, should be reset fsm state
     pub fn reset_fsm_state(&mut self) {
         self.current_state = TransState::TransDummyState;
@@ -402,29 +905,31 @@ impl TransFsm {
         attrs: &[SessionAttr],
     ) -> Result<PoolConn<ClientConn>, Error> {
         let conn = self.client_conn.take();
-        match conn {
-            Some(client_conn) => Ok(client_conn),
+        let client_conn = match conn {
+            Some(client_conn) => client_conn,
             None => {
-                let factory = ClientConn::with_opts(
-                    endpoint.user,
-                    endpoint.password,
-                    endpoint.addr.clone(),
-                );
+                let factory = match build_conn_factory(&endpoint) {
+                    Ok(factory) => factory,
+                    Err(err) => return Err(Error::new(ErrorKind::Protocol(err))),
+                };
                 self.pool.set_factory(factory);
                 match self.pool.get_conn_with_endpoint_session(&endpoint.addr, attrs).await {
-                    Ok(client_conn) => Ok(client_conn),
-                    Err(err) => Err(Error::new(ErrorKind::Protocol(err))),
+                    Ok(client_conn) => client_conn,
+                    Err(err) => return Err(Error::new(ErrorKind::Protocol(err))),
                 }
             },
-        }
+        };
+        bind_backend(&client_conn);
+        Ok(client_conn)
     }
 
     pub async fn get_conn(
         &mut self,
         _attrs: &[SessionAttr],
     ) -> Result<PoolConn<ClientConn>, Error> {
-        let conn = self.client_conn.take();
-        Ok(conn.unwrap())
+        let conn = self.client_conn.take().unwrap();
+        bind_backend(&conn);
+        Ok(conn)
         //let addr = self.endpoint.as_ref().unwrap().addr.as_ref();
         //match conn {
         //    Some(client_conn) => Ok(client_conn),
@@ -440,13 +945,50 @@ impl TransFsm {
     }
 
     pub fn get_shard_conn(&mut self) -> Vec<PoolConn<ClientConn>> {
-        std::mem::replace(&mut self.shard_cache_conn, Vec::new())
+        let conns = std::mem::replace(&mut self.shard_cache_conn, Vec::new());
+        conns.iter().for_each(|x| bind_backend(x));
+        conns
     }
 
     pub fn put_shard_conn(&mut self, conns: Vec<PoolConn<ClientConn>>) {
         self.shard_cache_conn = conns;
     }
 
+    pub fn merge_shard_conn(&mut self, conns: Vec<PoolConn<ClientConn>>) {
+        self.shard_cache_conn.extend(conns);
+    }
+
+    // Record the index of each shard conn as the branch qualifier, the conns are in the order of shards
+    // when the XA transaction starts.
+    pub fn set_xa_bquals(&mut self) {
+        self.xa_bquals = self
+            .shard_cache_conn
+            .iter()
+            .enumerate()
+            .map(|(idx, conn)| (xa_conn_key(conn), idx.to_string()))
+            .collect();
+    }
+
+    // The branch qualifiers in the order of conns.
+    pub fn get_xa_bquals(&self, conns: &[PoolConn<ClientConn>]) -> Vec<String> {
+        conns.iter().map(|x| self.xa_bquals.get(&xa_conn_key(x)).cloned().unwrap_or_default()).collect()
+    }
+
+    // Reset the fsm for a new session, e.g. COM_RESET_CONNECTION, the bound conns are returned.
+    pub fn reset(&mut self) -> Vec<PoolConn<ClientConn>> {
+        self.current_state = TransState::TransDummyState;
+        self.current_event = TransEventName::DummyEvent;
+        self.autocommit = None;
+        self.xid = None;
+        self.xa_bquals.clear();
+
+        let mut conns = self.get_shard_conn();
+        if let Some(conn) = self.client_conn.take() {
+            conns.push(conn);
+        }
+        conns
+    }
+
     #[inline]
     pub fn build_conn_attrs(&self) -> Vec<SessionAttr> {
         vec![
@@ -463,6 +1005,7 @@ pub fn build_conn_attrs(sess: &ServerHandshakeCodec) -> Vec<SessionAttr> {
         SessionAttr::DB(sess.get_db()),
         SessionAttr::Charset(sess.get_charset().unwrap()),
         SessionAttr::Autocommit(sess.get_autocommit()),
+        SessionAttr::Vars(sess.get_vars()),
     ]
 }
 
@@ -496,4 +1039,174 @@ mod test {
         assert_eq!(tsm.current_state, TransState::TransDummyState);
         assert_eq!(tsm.current_event, TransEventName::CommitRollBackEvent);
     }
+
+    #[test]
+    fn test_trigger_trans_stmt() {
+        let mut tsm = TransFsm::new(Pool::new(1));
+        assert!(tsm.trigger(TransEventName::StartEvent));
+        assert!(!tsm.trigger(TransEventName::SavepointEvent));
+        assert_eq!(tsm.current_state, TransState::TransStartState);
+        assert!(!tsm.trigger(TransEventName::QueryEvent));
+        assert_eq!(tsm.current_state, TransState::TransStartState);
+        assert!(!tsm.trigger(TransEventName::ImplicitCommitEvent));
+        assert_eq!(tsm.current_state, TransState::TransDummyState);
+
+        assert!(tsm.trigger(TransEventName::SetTransactionEvent));
+        assert_eq!(tsm.current_state, TransState::TransSetTransactionState);
+        assert!(!tsm.trigger(TransEventName::StartEvent));
+        assert_eq!(tsm.current_state, TransState::TransStartState);
+        assert!(!tsm.trigger(TransEventName::CommitRollBackEvent));
+        assert_eq!(tsm.current_state, TransState::TransDummyState);
+
+        assert!(tsm.trigger(TransEventName::SetTransactionEvent));
+        assert!(!tsm.trigger(TransEventName::QueryEvent));
+        assert_eq!(tsm.current_state, TransState::TransDummyState);
+
+        tsm.current_state = TransState::TransSetSessionState;
+        assert!(!tsm.trigger(TransEventName::ImplicitCommitEvent));
+        assert!(!tsm.trigger(TransEventName::SetTransactionEvent));
+        assert_eq!(tsm.current_state, TransState::TransSetSessionState);
+    }
+
+    #[test]
+    fn test_in_transaction() {
+        let mut tsm = TransFsm::new(Pool::new(1));
+        assert!(!tsm.in_transaction());
+        tsm.trigger(TransEventName::StartEvent);
+        assert!(tsm.in_transaction());
+        tsm.trigger(TransEventName::CommitRollBackEvent);
+        assert!(!tsm.in_transaction());
+
+        tsm.set_autocommit(String::from("0"));
+        tsm.trigger(TransEventName::SetSessionEvent);
+        assert!(tsm.in_transaction());
+    }
+
+    #[test]
+    fn test_raw_stmt_trans_event() {
+        let sqls = [
+            ("SAVEPOINT sp1", Some(TransEventName::SavepointEvent)),
+            ("release savepoint sp1", Some(TransEventName::SavepointEvent)),
+            ("XA START 'x'", Some(TransEventName::StartEvent)),
+            ("xa end 'x'", Some(TransEventName::QueryEvent)),
+            ("XA COMMIT 'x'", Some(TransEventName::CommitRollBackEvent)),
+            ("XA RECOVER;", Some(TransEventName::QueryEvent)),
+            ("ALTER TABLE t ADD c INT", Some(TransEventName::ImplicitCommitEvent)),
+            ("truncate t", Some(TransEventName::ImplicitCommitEvent)),
+            ("DROP TEMPORARY TABLE t", None),
+            ("select 1", None),
+        ];
+        for (sql, event) in sqls {
+            assert_eq!(raw_stmt_trans_event(sql).map(|x| x.0), event, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_is_implicit_commit_stmt() {
+        let parser = mysql_parser::parser::Parser::new();
+        let sqls = [
+            ("CREATE TABLE t (a INT)", true),
+            ("CREATE TEMPORARY TABLE t (a INT)", false),
+            ("CREATE INDEX i ON t (a)", true),
+            ("select * from t", false),
+        ];
+        for (sql, is_commit) in sqls {
+            let ast = parser.parse(sql).unwrap();
+            assert_eq!(is_implicit_commit_stmt(&ast[0]), is_commit, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_is_read_only_session() {
+        let mut vars = SessionVars::new();
+        assert!(!is_read_only_session(&vars));
+        vars.insert("transaction_read_only".to_string(), "'ON'".to_string());
+        assert!(is_read_only_session(&vars));
+        vars.insert("transaction_read_only".to_string(), "0".to_string());
+        assert!(!is_read_only_session(&vars));
+    }
+
+    #[test]
+    fn test_is_read_stmt() {
+        let parser = mysql_parser::parser::Parser::new();
+        let sqls = [
+            ("select * from t", true),
+            ("select * from t for update", false),
+            ("show tables", true),
+            ("insert into t values (1)", false),
+            ("update t set a = 1", false),
+        ];
+        for (sql, is_read) in sqls {
+            let ast = parser.parse(sql).unwrap();
+            assert_eq!(is_read_stmt(&ast[0]), is_read, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_set_stmt_vars() {
+        let parser = mysql_parser::parser::Parser::new();
+        let some = |name: &str, value: &str| (name.to_string(), Some(value.to_string()));
+        let sqls = [
+            ("SET sql_mode = 'ANSI', @a = 1, time_zone = DEFAULT", vec![
+                some("sql_mode", "'ANSI'"),
+                some("@a", "1"),
+                ("time_zone".to_string(), None),
+            ]),
+            ("SET @@SESSION.wait_timeout = -1, @@GLOBAL.max_connections = 10", vec![some("wait_timeout", "-1")]),
+            ("SET SESSION sql_safe_updates = ON, GLOBAL a = 1, b = 2", vec![some("sql_safe_updates", "ON")]),
+            ("SET GLOBAL a = 1, SESSION b = 2", vec![some("b", "2")]),
+            ("SET @b = NOW(), sql_mode = CONCAT(@@sql_mode, ',ANSI')", vec![("@b".to_string(), None)]),
+            ("SET autocommit = 0", vec![]),
+            ("SET SESSION TRANSACTION ISOLATION LEVEL READ COMMITTED", vec![
+                some("transaction_isolation", "'READ-COMMITTED'"),
+            ]),
+            ("SET TRANSACTION READ ONLY", vec![]),
+        ];
+        for (sql, vars) in sqls {
+            let ast = parser.parse(sql).unwrap();
+            match &ast[0] {
+                SqlStmt::Set(stmt) => assert_eq!(set_stmt_vars(stmt), vars, "{}", sql),
+                _ => panic!("{}", sql),
+            }
+        }
+    }
+
+    #[test]
+    fn test_use_stmt_db() {
+        assert_eq!(use_stmt_db("USE db1"), Some("db1".to_string()));
+        assert_eq!(use_stmt_db(" use `my``db` ;"), Some("my`db".to_string()));
+        assert_eq!(use_stmt_db("user db1"), None);
+        assert_eq!(use_stmt_db("select 1"), None);
+    }
+
+    #[test]
+    fn test_stmt_schemas() {
+        let parser = mysql_parser::parser::Parser::new();
+        let schemas = |sql: &str| stmt_schemas(&parser.parse(sql).unwrap()[0]);
+        let cases = vec![
+            ("SELECT * FROM t", vec![]),
+            ("SELECT * FROM db1.t", vec!["db1"]),
+            ("SELECT * FROM `db1`.t", vec!["db1"]),
+            ("SELECT * FROM t JOIN db1.t2 ON t.id = t2.id", vec!["db1"]),
+            ("SELECT * FROM t WHERE id IN (SELECT id FROM db1.t2)", vec!["db1"]),
+            ("SELECT * FROM (SELECT id FROM db1.t2) AS x", vec!["db1"]),
+            ("SELECT id FROM t UNION SELECT id FROM db1.t2", vec!["db1"]),
+            ("SELECT (SELECT max(id) FROM db1.t2) FROM t", vec!["db1"]),
+            ("SELECT db1.t.id FROM t", vec!["db1"]),
+            ("INSERT INTO db1.t VALUES (1)", vec!["db1"]),
+            ("INSERT INTO t SELECT * FROM db1.t2", vec!["db1"]),
+            ("UPDATE db1.t SET a = 1", vec!["db1"]),
+            ("DELETE FROM db1.t WHERE id = 1", vec!["db1"]),
+            ("SHOW TABLES FROM db1", vec!["db1"]),
+            ("SHOW CREATE TABLE db1.t", vec!["db1"]),
+            ("CREATE TABLE db1.t (id int)", vec!["db1"]),
+            ("CREATE DATABASE db1", vec!["db1"]),
+        ];
+        for (sql, dbs) in cases {
+            let dbs = dbs.into_iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            assert_eq!(schemas(sql), Some(dbs), "{}", sql);
+        }
+
+        assert_eq!(schemas("PREPARE s FROM 'SELECT 1'"), None);
+    }
 }
@@ -13,7 +13,10 @@
 // limitations under the License.
 
 use pisa_error::error::Error;
-use proxy::proxy::{MySQLNode, ProxyConfig};
+use proxy::{
+    proxy::{MySQLNode, ProxyConfig},
+    shutdown::Shutdown,
+};
 
 #[derive(Default)]
 pub struct ShardingSphereProxy {
@@ -24,7 +27,7 @@ pub struct ShardingSphereProxy {
 
 #[async_trait::async_trait]
 impl proxy::factory::Proxy for ShardingSphereProxy {
-    async fn start(&mut self) -> Result<(), Error> {
+    async fn start(&mut self, _shutdown: Shutdown) -> Result<(), Error> {
         Ok(())
     }
 }